@@ -0,0 +1,36 @@
+use camera_reel_service::api::render::{sign_render_params, Fit, RenderFormat, RenderParams};
+
+/// Prints a signed render URL, the way the services that know `RENDER_SIGNING_KEY` build them.
+///
+/// Usage: `cargo run --example render-url -- <image id> <width> <height>`
+fn main() {
+    let api_url = std::env::var("API_URL").unwrap_or("http://localhost:3000".to_string());
+    let signing_key =
+        std::env::var("RENDER_SIGNING_KEY").unwrap_or("local-render-signing-key".to_string());
+
+    let mut args = std::env::args().skip(1);
+    let image_id = args.next().expect("missing image id");
+    let width = args
+        .next()
+        .map(|width| width.parse().expect("invalid width"));
+    let height = args
+        .next()
+        .map(|height| height.parse().expect("invalid height"));
+
+    let params = RenderParams {
+        w: width,
+        h: height,
+        fit: Some(Fit::Cover),
+        format: Some(RenderFormat::Webp),
+    };
+    let sig = sign_render_params(&signing_key, &image_id, &params);
+
+    let mut url = format!("{api_url}/api/images/{image_id}/render?fit=cover&format=webp");
+    if let Some(width) = width {
+        url.push_str(&format!("&w={width}"));
+    }
+    if let Some(height) = height {
+        url.push_str(&format!("&h={height}"));
+    }
+    println!("{url}&sig={sig}");
+}
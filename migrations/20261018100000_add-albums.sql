@@ -0,0 +1,23 @@
+CREATE TABLE IF NOT EXISTS albums (
+    id UUID PRIMARY KEY,
+    user_address TEXT NOT NULL,
+    title TEXT NOT NULL,
+    description TEXT NOT NULL DEFAULT '',
+    is_public BOOLEAN NOT NULL DEFAULT FALSE,
+    cover_image_id UUID REFERENCES images (id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    updated_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_albums_user_address_created_at_desc ON albums (user_address, created_at DESC);
+
+CREATE TABLE IF NOT EXISTS album_images (
+    album_id UUID NOT NULL REFERENCES albums (id) ON DELETE CASCADE,
+    image_id UUID NOT NULL REFERENCES images (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    added_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (album_id, image_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_album_images_album_id_position ON album_images (album_id, position);
+CREATE INDEX IF NOT EXISTS idx_album_images_image_id ON album_images (image_id);
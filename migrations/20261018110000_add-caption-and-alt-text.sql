@@ -0,0 +1,2 @@
+ALTER TABLE images ADD COLUMN caption TEXT;
+ALTER TABLE images ADD COLUMN alt_text TEXT;
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS image_tags (
+    image_id UUID NOT NULL REFERENCES images (id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    is_explicit BOOLEAN NOT NULL DEFAULT FALSE,
+    PRIMARY KEY (image_id, tag)
+);
+
+CREATE INDEX IF NOT EXISTS idx_image_tags_tag ON image_tags (tag);
+CREATE INDEX IF NOT EXISTS idx_is_public_created_at_desc ON images (is_public, created_at DESC);
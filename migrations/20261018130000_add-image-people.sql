@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS image_people (
+    image_id UUID NOT NULL REFERENCES images (id) ON DELETE CASCADE,
+    user_address TEXT NOT NULL,
+    PRIMARY KEY (image_id, user_address)
+);
+
+CREATE INDEX IF NOT EXISTS idx_image_people_user_address ON image_people (user_address);
+
+-- Backfill from the people recorded in the metadata of the existing images
+INSERT INTO image_people (image_id, user_address)
+SELECT images.id, lower(person->>'userAddress')
+FROM images,
+    jsonb_array_elements(
+        CASE WHEN jsonb_typeof(images.metadata->'visiblePeople') = 'array'
+            THEN images.metadata->'visiblePeople'
+            ELSE '[]'::jsonb
+        END
+    ) AS person
+WHERE COALESCE(person->>'userAddress', '') <> ''
+ON CONFLICT (image_id, user_address) DO NOTHING;
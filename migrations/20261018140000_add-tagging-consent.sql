@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS user_preferences (
+    user_address TEXT PRIMARY KEY,
+    allow_tagging BOOLEAN NOT NULL DEFAULT TRUE,
+    updated_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS image_flags (
+    image_id UUID NOT NULL REFERENCES images (id) ON DELETE CASCADE,
+    user_address TEXT NOT NULL,
+    reason TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (image_id, user_address)
+);
+
+CREATE INDEX IF NOT EXISTS idx_image_flags_created_at ON image_flags (created_at);
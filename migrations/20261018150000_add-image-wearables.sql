@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS image_wearables (
+    image_id UUID NOT NULL REFERENCES images (id) ON DELETE CASCADE,
+    user_address TEXT NOT NULL,
+    urn TEXT NOT NULL,
+    PRIMARY KEY (image_id, user_address, urn)
+);
+
+CREATE INDEX IF NOT EXISTS idx_image_wearables_urn ON image_wearables (urn);
+
+-- Backfill from the wearables of the people recorded in the metadata of the existing images
+INSERT INTO image_wearables (image_id, user_address, urn)
+SELECT images.id, lower(COALESCE(person->>'userAddress', '')), lower(wearable)
+FROM images,
+    jsonb_array_elements(
+        CASE WHEN jsonb_typeof(images.metadata->'visiblePeople') = 'array'
+            THEN images.metadata->'visiblePeople'
+            ELSE '[]'::jsonb
+        END
+    ) AS person,
+    jsonb_array_elements_text(
+        CASE WHEN jsonb_typeof(person->'wearables') = 'array'
+            THEN person->'wearables'
+            ELSE '[]'::jsonb
+        END
+    ) AS wearable
+WHERE wearable <> ''
+ON CONFLICT (image_id, user_address, urn) DO NOTHING;
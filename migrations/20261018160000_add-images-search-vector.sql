@@ -0,0 +1,34 @@
+ALTER TABLE images ADD COLUMN IF NOT EXISTS search_vector TSVECTOR;
+
+-- Scene names weigh the most, then captions, then the people in the photo. Uses the `simple`
+-- configuration since names and captions are written in many languages.
+CREATE OR REPLACE FUNCTION images_search_vector(metadata JSONB, caption TEXT) RETURNS TSVECTOR AS $$
+    SELECT setweight(to_tsvector('simple', COALESCE(metadata->'scene'->>'name', '')), 'A')
+        || setweight(to_tsvector('simple', COALESCE(caption, '')), 'B')
+        || setweight(to_tsvector('simple', COALESCE(metadata->>'userName', '')), 'C')
+        || setweight(to_tsvector('simple', COALESCE((
+            SELECT string_agg(person->>'userName', ' ')
+            FROM jsonb_array_elements(
+                CASE WHEN jsonb_typeof(metadata->'visiblePeople') = 'array'
+                    THEN metadata->'visiblePeople'
+                    ELSE '[]'::jsonb
+                END
+            ) AS person
+        ), '')), 'C')
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION images_search_vector_trigger() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.search_vector := images_search_vector(NEW.metadata, NEW.caption);
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS images_search_vector_update ON images;
+CREATE TRIGGER images_search_vector_update
+    BEFORE INSERT OR UPDATE OF metadata, caption ON images
+    FOR EACH ROW EXECUTE FUNCTION images_search_vector_trigger();
+
+UPDATE images SET search_vector = images_search_vector(metadata, caption);
+
+CREATE INDEX IF NOT EXISTS idx_images_search_vector ON images USING GIN (search_vector);
@@ -0,0 +1,30 @@
+DO $$ BEGIN
+    CREATE TYPE image_visibility AS ENUM ('private', 'unlisted', 'public', 'shared');
+EXCEPTION
+    WHEN duplicate_object THEN NULL;
+END $$;
+
+ALTER TABLE images ADD COLUMN IF NOT EXISTS visibility image_visibility NOT NULL DEFAULT 'private';
+
+-- Non-public images become private. Anyone with the link could view them until now, but
+-- their owners never chose to share them, so they are restricted rather than `unlisted`.
+UPDATE images SET visibility = CASE WHEN is_public THEN 'public'::image_visibility ELSE 'private'::image_visibility END;
+
+DROP INDEX IF EXISTS idx_user_address_is_public;
+DROP INDEX IF EXISTS idx_place_id_is_public_created_at_desc;
+DROP INDEX IF EXISTS idx_is_public_created_at_desc;
+
+ALTER TABLE images DROP COLUMN IF EXISTS is_public;
+
+CREATE INDEX IF NOT EXISTS idx_user_address_visibility ON images (user_address, visibility);
+CREATE INDEX IF NOT EXISTS idx_place_id_visibility_created_at_desc ON images ((metadata->>'placeId'), visibility, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_visibility_created_at_desc ON images (visibility, created_at DESC);
+
+CREATE TABLE IF NOT EXISTS image_shares (
+    image_id UUID NOT NULL REFERENCES images (id) ON DELETE CASCADE,
+    user_address TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (image_id, user_address)
+);
+
+CREATE INDEX IF NOT EXISTS idx_image_shares_user_address ON image_shares (user_address);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS share_links (
+    token TEXT PRIMARY KEY,
+    image_id UUID NOT NULL REFERENCES images (id) ON DELETE CASCADE,
+    user_address TEXT NOT NULL,
+    expires_at TIMESTAMP,
+    max_views INTEGER,
+    views INTEGER NOT NULL DEFAULT 0,
+    revoked_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_share_links_image_id_created_at_desc ON share_links (image_id, created_at DESC);
@@ -0,0 +1,21 @@
+-- Rows keep the keys of their files instead of URLs, which are built when responding so the
+-- domains serving them can change. Keys never include the `private/` prefix, it's derived
+-- from the visibility.
+ALTER TABLE images ADD COLUMN IF NOT EXISTS image_key TEXT;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS thumbnail_key TEXT;
+
+-- Files uploaded so far sit at the root of the bucket, named like the end of their URLs.
+-- The `migrate-storage-keys` command moves them to the partitioned layout.
+UPDATE images SET
+    image_key = substring(url FROM '[^/]+$'),
+    thumbnail_key = NULLIF(substring(thumbnail_url FROM '[^/]+$'), '')
+WHERE image_key IS NULL;
+
+ALTER TABLE images ALTER COLUMN image_key SET NOT NULL;
+
+-- The URLs stay, still written on upload, so the previous release keeps working while this
+-- one rolls out and it can be rolled back. A later release drops them.
+ALTER TABLE images ALTER COLUMN url DROP NOT NULL;
+
+-- Lets the migration command find the files still in the legacy layout
+CREATE INDEX IF NOT EXISTS idx_images_legacy_keys ON images (created_at) WHERE image_key NOT LIKE '%/%';
@@ -0,0 +1,24 @@
+-- Originals of old images are moved under the `archive/` prefix, which a bucket lifecycle rule
+-- keeps in a colder storage class read instantly, like Glacier Instant Retrieval. Thumbnails
+-- stay where they are.
+ALTER TABLE images ADD COLUMN IF NOT EXISTS archived_at TIMESTAMP;
+
+CREATE INDEX IF NOT EXISTS idx_images_not_archived_created_at ON images (created_at) WHERE archived_at IS NULL;
+
+DO $$ BEGIN
+    CREATE TYPE restore_status AS ENUM ('pending', 'completed', 'failed');
+EXCEPTION
+    WHEN duplicate_object THEN NULL;
+END $$;
+
+-- Originals requested while archived, moved back by the archival worker. An image has at most
+-- one restore, requested again when it's archived again.
+CREATE TABLE IF NOT EXISTS image_restores (
+    image_id UUID PRIMARY KEY REFERENCES images (id) ON DELETE CASCADE,
+    status restore_status NOT NULL DEFAULT 'pending',
+    error TEXT,
+    requested_at TIMESTAMP NOT NULL DEFAULT now(),
+    completed_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_image_restores_pending ON image_restores (requested_at) WHERE status = 'pending';
@@ -0,0 +1,47 @@
+-- Endpoints notified of the public photos taken in a place, or in any place of a world when
+-- `place_id` is a world name. Subscriptions are disabled after repeated failed deliveries.
+CREATE TABLE IF NOT EXISTS webhook_subscriptions (
+    id UUID PRIMARY KEY,
+    user_address TEXT NOT NULL,
+    place_id TEXT NOT NULL,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    -- Places of the world, refreshed by the webhook worker so events are matched without
+    -- calling the places API
+    world_place_ids TEXT[] NOT NULL DEFAULT '{}',
+    world_resolved_at TIMESTAMP,
+    consecutive_failures INTEGER NOT NULL DEFAULT 0,
+    disabled_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_subscriptions_user_address ON webhook_subscriptions (user_address, created_at);
+CREATE INDEX IF NOT EXISTS idx_webhook_subscriptions_place_id ON webhook_subscriptions (place_id) WHERE disabled_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_webhook_subscriptions_world_place_ids ON webhook_subscriptions USING GIN (world_place_ids) WHERE disabled_at IS NULL;
+
+DO $$ BEGIN
+    CREATE TYPE webhook_delivery_status AS ENUM ('pending', 'delivered', 'failed');
+EXCEPTION
+    WHEN duplicate_object THEN NULL;
+END $$;
+
+-- Each event sent to each subscription, kept as the delivery log. An event is queued at most
+-- once per subscription, even when the outbox relay publishes it again.
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id UUID PRIMARY KEY,
+    subscription_id UUID NOT NULL REFERENCES webhook_subscriptions (id) ON DELETE CASCADE,
+    event_id TEXT NOT NULL,
+    event JSONB NOT NULL,
+    status webhook_delivery_status NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    response_status INTEGER,
+    last_error TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    next_attempt_at TIMESTAMP NOT NULL DEFAULT now(),
+    completed_at TIMESTAMP,
+    UNIQUE (subscription_id, event_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_pending ON webhook_deliveries (next_attempt_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_subscription_created_at ON webhook_deliveries (subscription_id, created_at);
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_completed_at ON webhook_deliveries (completed_at) WHERE completed_at IS NOT NULL;
@@ -0,0 +1,8 @@
+-- Moderation and account events consumed from SQS, recorded once handled so a redelivered
+-- message isn't handled twice.
+CREATE TABLE IF NOT EXISTS processed_moderation_events (
+    event_id TEXT PRIMARY KEY,
+    event_type TEXT NOT NULL,
+    affected_images INTEGER NOT NULL,
+    processed_at TIMESTAMP NOT NULL DEFAULT now()
+);
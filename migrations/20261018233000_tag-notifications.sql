@@ -0,0 +1,21 @@
+-- Addresses each user blocked, whose photos don't notify them when they appear in them.
+CREATE TABLE IF NOT EXISTS user_blocks (
+    user_address TEXT NOT NULL,
+    blocked_address TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_address, blocked_address)
+);
+
+-- Inbox of the people tagged in photos, filled from the photo-tagged events and keyed by
+-- their id so an event published again isn't notified twice.
+CREATE TABLE IF NOT EXISTS notifications (
+    id UUID PRIMARY KEY,
+    user_address TEXT NOT NULL,
+    image_id UUID NOT NULL REFERENCES images (id) ON DELETE CASCADE,
+    actor_address TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    read_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_notifications_user_created_at ON notifications (user_address, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_notifications_unread ON notifications (user_address) WHERE read_at IS NULL;
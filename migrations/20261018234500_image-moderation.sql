@@ -0,0 +1,12 @@
+-- Images taken down by moderation stay private while `moderated_at` is set, and their owners
+-- can't change their visibility. The visibility the owner had chosen is kept apart.
+ALTER TABLE images ADD COLUMN IF NOT EXISTS moderated_at TIMESTAMP;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS moderation_reason TEXT;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS owner_visibility image_visibility;
+
+-- Users banned by moderation, who can't upload images anymore
+CREATE TABLE IF NOT EXISTS banned_users (
+    user_address TEXT PRIMARY KEY,
+    reason TEXT NOT NULL,
+    banned_at TIMESTAMP NOT NULL DEFAULT now()
+);
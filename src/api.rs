@@ -3,50 +3,193 @@ use actix_web::web::{scope, ServiceConfig};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
-use crate::database::DBImage;
+use crate::{database::DBImage, storage::Rendition, urls::UrlBuilder};
 
 use self::{
+    admin::get_outbox_status,
+    albums::{
+        add_album_images, create_album, delete_album, get_album, get_album_images, get_user_albums,
+        remove_album_image, reorder_album_images, update_album,
+    },
     delete::delete_image,
-    docs::generate_docs,
+    docs::{generate_docs, get_event_schemas},
+    embed::{get_oembed, get_share_page},
+    files::get_file,
     get::{
-        get_image, get_metadata, get_multiple_places_images, get_place_images, get_user_data,
-        get_user_images,
+        get_image, get_image_file, get_metadata, get_multiple_places_images, get_place_images,
+        get_user_appearances, get_user_data, get_user_images,
+    },
+    notifications::{get_user_notifications, mark_notifications_read},
+    people::{
+        block_user, get_blocked_users, get_user_preferences, unblock_user, untag_image,
+        update_user_preferences,
     },
-    update::update_image_visibility,
+    render::render_image,
+    restore::{get_image_restore, restore_image},
+    search::search_images,
+    share_links::{create_share_link, get_share_links, get_shared_image, revoke_share_link},
+    stream::stream_place_images,
+    tags::{get_tag_images, get_trending_tags},
+    update::{update_image, update_image_visibility},
     upload::upload_image,
+    wearables::{get_wearable_images, get_wearables_usage},
+    webhooks::{
+        create_webhook_subscription, delete_webhook_subscription, enable_webhook_subscription,
+        get_webhook_deliveries, get_webhook_subscriptions,
+    },
 };
 
+pub mod admin;
+pub mod albums;
 pub mod auth;
 pub mod delete;
 mod docs;
+pub mod embed;
+pub mod files;
 pub mod get;
 pub mod middlewares;
+pub mod notifications;
+pub mod people;
+mod proxy;
+pub mod render;
+pub mod restore;
+pub mod search;
+pub mod share_links;
+pub mod stream;
+pub mod tags;
 pub mod update;
 pub mod upload;
+pub mod wearables;
+pub mod webhooks;
 
 pub fn services(config: &mut ServiceConfig) {
     let cors = Cors::default()
         .allow_any_origin()
         .allow_any_header()
         .expose_any_header()
-        .allowed_methods(vec!["GET", "POST", "PATCH", "DELETE"])
+        .allowed_methods(vec!["GET", "POST", "PATCH", "PUT", "DELETE"])
         .max_age(300);
 
     let docs = generate_docs();
 
-    config.service(docs).service(
-        scope("/api")
-            .service(upload_image)
-            .service(delete_image)
-            .service(get_image)
-            .service(update_image_visibility)
-            .service(get_metadata)
-            .service(get_user_images)
-            .service(get_user_data)
-            .service(get_place_images)
-            .service(get_multiple_places_images)
-            .wrap(cors),
-    );
+    config
+        .service(docs)
+        .service(get_share_page)
+        .service(get_oembed)
+        .service(get_file)
+        .service(
+            scope("/api")
+                .service(upload_image)
+                .service(delete_image)
+                .service(get_image)
+                .service(get_image_file)
+                .service(update_image_visibility)
+                .service(update_image)
+                .service(untag_image)
+                .service(get_metadata)
+                .service(render_image)
+                .service(restore_image)
+                .service(get_image_restore)
+                .service(get_user_images)
+                .service(get_user_appearances)
+                .service(get_user_preferences)
+                .service(update_user_preferences)
+                .service(get_blocked_users)
+                .service(block_user)
+                .service(unblock_user)
+                .service(get_user_notifications)
+                .service(mark_notifications_read)
+                .service(get_user_data)
+                .service(get_place_images)
+                .service(stream_place_images)
+                .service(get_multiple_places_images)
+                .service(create_album)
+                .service(get_user_albums)
+                .service(get_album)
+                .service(update_album)
+                .service(delete_album)
+                .service(add_album_images)
+                .service(remove_album_image)
+                .service(reorder_album_images)
+                .service(get_album_images)
+                .service(get_trending_tags)
+                .service(get_tag_images)
+                .service(get_wearable_images)
+                .service(get_wearables_usage)
+                .service(search_images)
+                .service(create_share_link)
+                .service(get_share_links)
+                .service(revoke_share_link)
+                .service(get_shared_image)
+                .service(create_webhook_subscription)
+                .service(get_webhook_subscriptions)
+                .service(delete_webhook_subscription)
+                .service(enable_webhook_subscription)
+                .service(get_webhook_deliveries)
+                .service(get_outbox_status)
+                .service(get_event_schemas)
+                .wrap(cors),
+        );
+}
+
+/// Who can see an image.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    ToSchema,
+    schemars::JsonSchema,
+    sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "image_visibility", rename_all = "lowercase")]
+pub enum Visibility {
+    /// Only the owner.
+    #[default]
+    Private,
+    /// Anyone with the link, but it isn't listed in galleries.
+    Unlisted,
+    /// Anyone, and it is listed in galleries.
+    Public,
+    /// The owner and the addresses the image is shared with.
+    Shared,
+}
+
+impl Visibility {
+    /// Maps the legacy `isPublic` flag. `false` means `private`, widening who can see an image
+    /// always takes an explicit `visibility`.
+    pub fn from_is_public(is_public: bool) -> Self {
+        if is_public {
+            Self::Public
+        } else {
+            Self::Private
+        }
+    }
+
+    pub fn is_public(&self) -> bool {
+        *self == Self::Public
+    }
+
+    /// Whether only some users can see the image, as opposed to anyone with the link.
+    pub fn is_restricted(&self) -> bool {
+        matches!(self, Self::Private | Self::Shared)
+    }
+}
+
+impl std::fmt::Display for Visibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Visibility::Private => write!(f, "private"),
+            Visibility::Unlisted => write!(f, "unlisted"),
+            Visibility::Public => write!(f, "public"),
+            Visibility::Shared => write!(f, "shared"),
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
@@ -55,8 +198,20 @@ pub struct Image {
     pub id: String,
     pub url: String,
     pub thumbnail_url: String,
+    /// Kept for older clients, `true` only when the visibility is `public`.
     pub is_public: bool,
+    #[serde(default)]
+    pub visibility: Visibility,
     pub metadata: Metadata,
+    #[serde(default)]
+    pub caption: Option<String>,
+    /// User-supplied alternative text. Images without one get a description generated from
+    /// the scene and the people in it.
+    #[serde(default)]
+    pub alt_text: Option<String>,
+    /// Normalized tags, both the explicit ones and the `#hashtags` of the caption.
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
@@ -66,6 +221,8 @@ pub struct GalleryImage {
     pub url: String,
     pub thumbnail_url: String,
     pub is_public: bool,
+    #[serde(default)]
+    pub visibility: Visibility,
     pub date_time: String,
 }
 
@@ -76,6 +233,8 @@ pub struct GalleryImageWithPlace {
     pub url: String,
     pub thumbnail_url: String,
     pub is_public: bool,
+    #[serde(default)]
+    pub visibility: Visibility,
     pub date_time: String,
     pub place_id: String,
 }
@@ -92,6 +251,44 @@ pub struct Metadata {
     pub place_id: String,
 }
 
+/// Maximum number of people named in a generated alternative text.
+const MAX_ALT_TEXT_PEOPLE: usize = 5;
+
+impl Metadata {
+    /// Describes the photo from the scene name and the people visible in it, used as
+    /// alternative text when the user didn't write one.
+    pub fn default_alt_text(&self) -> String {
+        let mut alt_text = match self.scene.name.trim() {
+            "" => "Photo taken in Decentraland".to_string(),
+            scene_name => format!("Photo taken at {scene_name}"),
+        };
+
+        let names = self
+            .visible_people
+            .iter()
+            .map(|user| user.user_name.trim())
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>();
+
+        match names.as_slice() {
+            [] => {}
+            [name] => alt_text.push_str(&format!(" with {name}")),
+            [rest @ .., last] if names.len() <= MAX_ALT_TEXT_PEOPLE => {
+                alt_text.push_str(&format!(" with {} and {last}", rest.join(", ")));
+            }
+            names => {
+                let others = names.len() - MAX_ALT_TEXT_PEOPLE;
+                alt_text.push_str(&format!(
+                    " with {} and {others} others",
+                    names[..MAX_ALT_TEXT_PEOPLE].join(", ")
+                ));
+            }
+        }
+
+        alt_text
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Default, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Scene {
@@ -118,37 +315,55 @@ pub struct User {
     pub is_emoting: Option<bool>,
 }
 
-impl From<DBImage> for Image {
-    fn from(value: DBImage) -> Self {
+impl Image {
+    pub fn from_db(value: DBImage, urls: &UrlBuilder) -> Self {
+        let url = urls.image_file_url(&value, Rendition::Original);
+        let thumbnail_url = urls.image_file_url(&value, Rendition::Thumbnail);
+        let alt_text = value
+            .alt_text
+            .unwrap_or_else(|| value.metadata.default_alt_text());
+
         Self {
             id: value.id.to_string(),
-            url: value.url,
-            thumbnail_url: value.thumbnail_url,
-            is_public: value.is_public,
+            url,
+            thumbnail_url,
+            is_public: value.visibility.is_public(),
+            visibility: value.visibility,
             metadata: value.metadata.0,
+            caption: value.caption,
+            alt_text: Some(alt_text),
+            tags: value.tags,
         }
     }
 }
 
-impl From<DBImage> for GalleryImage {
-    fn from(value: DBImage) -> Self {
+impl GalleryImage {
+    pub fn from_db(value: DBImage, urls: &UrlBuilder) -> Self {
+        let url = urls.image_file_url(&value, Rendition::Original);
+        let thumbnail_url = urls.image_file_url(&value, Rendition::Thumbnail);
+
         Self {
             id: value.id.to_string(),
-            url: value.url,
-            thumbnail_url: value.thumbnail_url,
-            is_public: value.is_public,
+            url,
+            thumbnail_url,
+            is_public: value.visibility.is_public(),
+            visibility: value.visibility,
             date_time: value.metadata.0.date_time,
         }
     }
 }
 
-impl From<DBImage> for GalleryImageWithPlace {
-    fn from(value: DBImage) -> Self {
+impl GalleryImageWithPlace {
+    pub fn from_db(value: DBImage, urls: &UrlBuilder) -> Self {
+        let url = urls.image_file_url(&value, Rendition::Original);
+        let thumbnail_url = urls.image_file_url(&value, Rendition::Thumbnail);
+
         Self {
             id: value.id.to_string(),
-            url: value.url,
-            thumbnail_url: value.thumbnail_url,
-            is_public: value.is_public,
+            url,
+            thumbnail_url,
+            is_public: value.visibility.is_public(),
+            visibility: value.visibility,
             date_time: value.metadata.0.date_time,
             place_id: value.metadata.0.place_id,
         }
@@ -194,3 +409,65 @@ impl ForbiddenError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(scene_name: &str, people: &[&str]) -> Metadata {
+        Metadata {
+            scene: Scene {
+                name: scene_name.to_string(),
+                ..Default::default()
+            },
+            visible_people: people
+                .iter()
+                .map(|name| User {
+                    user_name: name.to_string(),
+                    user_address: "0x0000000000000000000000000000000000000000".to_string(),
+                    wearables: vec![],
+                    is_guest: false,
+                    is_emoting: None,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_default_alt_text_without_scene_or_people() {
+        assert_eq!(
+            metadata("", &[]).default_alt_text(),
+            "Photo taken in Decentraland"
+        );
+    }
+
+    #[test]
+    fn test_default_alt_text_with_people() {
+        assert_eq!(
+            metadata("Genesis Plaza", &["alice"]).default_alt_text(),
+            "Photo taken at Genesis Plaza with alice"
+        );
+        assert_eq!(
+            metadata("Genesis Plaza", &["alice", "bob", "carol"]).default_alt_text(),
+            "Photo taken at Genesis Plaza with alice, bob and carol"
+        );
+    }
+
+    #[test]
+    fn test_default_alt_text_truncates_long_lists() {
+        let people = ["a", "b", "c", "d", "e", "f", "g"];
+        assert_eq!(
+            metadata("Wonderland", &people).default_alt_text(),
+            "Photo taken at Wonderland with a, b, c, d, e and 2 others"
+        );
+    }
+
+    #[test]
+    fn test_default_alt_text_skips_unnamed_people() {
+        assert_eq!(
+            metadata("Wonderland", &["", "alice"]).default_alt_text(),
+            "Photo taken at Wonderland with alice"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use actix_web::{get, http::header, web::Data, HttpRequest, HttpResponse, Responder};
+use actix_web_lab::extract::Query;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    api::ResponseError,
+    database::{DBOutboxEvent, Database},
+    Settings,
+};
+
+/// Upper bound for the client-supplied `limit`.
+const MAX_LIMIT: i64 = 500;
+
+/// Checks the request carries the admin bearer token. Admin endpoints answer as missing when
+/// no token is configured.
+fn authorize_admin(settings: &Settings, request: &HttpRequest) -> Result<(), HttpResponse> {
+    let Some(admin_token) = &settings.admin_bearer_token else {
+        return Err(HttpResponse::NotFound().finish());
+    };
+
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match token {
+        Some(token) if token == admin_token => Ok(()),
+        _ => Err(HttpResponse::Unauthorized().json(ResponseError::new("invalid admin token"))),
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[serde(rename_all = "camelCase")]
+struct OutboxQuery {
+    /// Only events waiting for longer than this many seconds, `OUTBOX_STUCK_AFTER_SECONDS`
+    /// by default.
+    stuck_after_seconds: Option<u64>,
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+fn default_limit() -> i64 {
+    100
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxEntry {
+    pub id: String,
+    pub sub_type: String,
+    /// Id of the photo the event is about.
+    pub key: String,
+    /// Failed attempts to publish it so far.
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: String,
+    pub next_attempt_at: String,
+    /// When the event was set aside because it couldn't be decoded. It isn't tried again.
+    pub parked_at: Option<String>,
+    /// The event as it is stored.
+    #[schema(value_type = Object)]
+    pub event: serde_json::Value,
+}
+
+impl From<DBOutboxEvent> for OutboxEntry {
+    fn from(value: DBOutboxEvent) -> Self {
+        Self {
+            id: value.id.to_string(),
+            sub_type: value.event["subType"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            key: value.event["key"].as_str().unwrap_or_default().to_string(),
+            attempts: value.attempts,
+            last_error: value.last_error,
+            created_at: value.created_at.and_utc().to_rfc3339(),
+            next_attempt_at: value.next_attempt_at.and_utc().to_rfc3339(),
+            parked_at: value
+                .parked_at
+                .map(|parked_at| parked_at.and_utc().to_rfc3339()),
+            event: value.event.0,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxStatus {
+    /// Events waiting to be published.
+    pub pending: i64,
+    /// Seconds the oldest waiting event has been waiting.
+    pub lag_seconds: f64,
+    /// Waiting events older than the threshold, the oldest first.
+    pub stuck: Vec<OutboxEntry>,
+}
+
+#[tracing::instrument(skip(settings, database, request))]
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/api",
+    params(
+        OutboxQuery
+    ),
+    responses(
+        (status = 200, description = "Events waiting to be published", body = OutboxStatus),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseError),
+        (status = 404, description = "Admin endpoints are disabled"),
+        (status = 500, description = "Failed to get the outbox status", body = ResponseError)
+    )
+)]
+#[get("/admin/outbox")]
+pub async fn get_outbox_status(
+    settings: Data<Settings>,
+    database: Data<Database>,
+    query: Query<OutboxQuery>,
+    request: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = authorize_admin(&settings, &request) {
+        return response;
+    }
+
+    let stuck_after = query
+        .stuck_after_seconds
+        .unwrap_or(settings.outbox.stuck_after_seconds);
+    let limit = query.limit.clamp(1, MAX_LIMIT);
+
+    let lag = database.get_outbox_lag().await;
+    let stuck = database
+        .get_stuck_outbox_events(stuck_after as i64, limit)
+        .await;
+    match (lag, stuck) {
+        (Ok((pending, lag_seconds)), Ok(stuck)) => HttpResponse::Ok().json(OutboxStatus {
+            pending,
+            lag_seconds,
+            stuck: stuck.into_iter().map(OutboxEntry::from).collect(),
+        }),
+        (Err(error), _) | (_, Err(error)) => {
+            tracing::error!("failed to get outbox status: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to get outbox status"))
+        }
+    }
+}
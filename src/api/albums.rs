@@ -0,0 +1,709 @@
+use std::collections::HashSet;
+
+use actix_web::{
+    delete, get, patch, post, put,
+    web::{Data, Json, Path},
+    FromRequest, HttpRequest, HttpResponse, Responder,
+};
+use actix_web_lab::extract::Query;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    api::{auth::AuthUser, GalleryImage, ResponseError},
+    database::{DBAlbum, DBImage, Database},
+    urls::UrlBuilder,
+};
+
+/// Maximum length, in characters, of an album title.
+const MAX_TITLE_LENGTH: usize = 100;
+
+/// Maximum length, in characters, of an album description.
+const MAX_DESCRIPTION_LENGTH: usize = 1000;
+
+/// Maximum number of images an album can hold.
+const MAX_ALBUM_IMAGES: usize = 500;
+
+/// Maximum number of image IDs accepted in a single add or reorder request.
+const MAX_IMAGES_IDS: usize = 100;
+
+/// Upper bound for the client-supplied pagination `limit`.
+const MAX_LIMIT: u64 = 100;
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Album {
+    pub id: String,
+    pub user_address: String,
+    pub title: String,
+    pub description: String,
+    pub is_public: bool,
+    pub cover_image: Option<GalleryImage>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl Album {
+    fn new(album: DBAlbum, cover: Option<DBImage>, urls: &UrlBuilder) -> Self {
+        Self {
+            id: album.id.to_string(),
+            user_address: album.user_address,
+            title: album.title,
+            description: album.description,
+            is_public: album.is_public,
+            cover_image: cover.map(|image| GalleryImage::from_db(image, urls)),
+            created_at: album.created_at.and_utc().to_rfc3339(),
+            updated_at: album.updated_at.and_utc().to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateAlbum {
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub is_public: bool,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateAlbum {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub is_public: Option<bool>,
+    /// Image of the album to show as its cover.
+    pub cover_image_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumImagesBody {
+    pub image_ids: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GetAlbumsResponse {
+    pub albums: Vec<Album>,
+    pub total: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GetAlbumImagesResponse {
+    pub images: Vec<GalleryImage>,
+    /// Cursor to request the next page, absent when there are no more images.
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+struct GetAlbumsQuery {
+    #[serde(default = "default_offset")]
+    offset: u64,
+    #[serde(default = "default_limit")]
+    limit: u64,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+struct GetAlbumImagesQuery {
+    /// Opaque cursor returned as `nextCursor` by the previous page.
+    cursor: Option<String>,
+    #[serde(default = "default_limit")]
+    limit: u64,
+}
+
+fn default_offset() -> u64 {
+    0
+}
+
+fn default_limit() -> u64 {
+    20
+}
+
+fn is_owner(album: &DBAlbum, address: &str) -> bool {
+    album.user_address.eq_ignore_ascii_case(address)
+}
+
+/// An album is visible to its owner and, when public, to everyone else.
+fn can_view(album: &DBAlbum, viewer: Option<&str>) -> bool {
+    album.is_public || viewer.is_some_and(|viewer| is_owner(album, viewer))
+}
+
+fn validate_album_fields(title: &str, description: &str) -> Result<(), HttpResponse> {
+    if title.trim().is_empty() {
+        return Err(HttpResponse::BadRequest().json(ResponseError::new("title can't be empty")));
+    }
+
+    if title.chars().count() > MAX_TITLE_LENGTH {
+        return Err(HttpResponse::BadRequest().json(ResponseError::new(&format!(
+            "title is too long, maximum is {MAX_TITLE_LENGTH} characters"
+        ))));
+    }
+
+    if description.chars().count() > MAX_DESCRIPTION_LENGTH {
+        return Err(HttpResponse::BadRequest().json(ResponseError::new(&format!(
+            "description is too long, maximum is {MAX_DESCRIPTION_LENGTH} characters"
+        ))));
+    }
+
+    Ok(())
+}
+
+fn parse_image_ids(image_ids: &[String]) -> Result<Vec<Uuid>, HttpResponse> {
+    if image_ids.is_empty() {
+        return Err(HttpResponse::BadRequest().json(ResponseError::new("no image IDs provided")));
+    }
+
+    if image_ids.len() > MAX_IMAGES_IDS {
+        return Err(HttpResponse::BadRequest().json(ResponseError::new(&format!(
+            "too many image IDs provided, maximum is {MAX_IMAGES_IDS}"
+        ))));
+    }
+
+    let mut ids = Vec::with_capacity(image_ids.len());
+    for image_id in image_ids {
+        let Ok(id) = Uuid::parse_str(image_id) else {
+            return Err(HttpResponse::BadRequest().json(ResponseError::new("invalid image ID")));
+        };
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+
+    Ok(ids)
+}
+
+async fn get_owned_album(
+    database: &Database,
+    album_id: &str,
+    address: &str,
+) -> Result<DBAlbum, HttpResponse> {
+    let album = match database.get_album(album_id).await {
+        Ok(album) => album,
+        Err(_) => return Err(HttpResponse::NotFound().json(ResponseError::new("album not found"))),
+    };
+
+    if !is_owner(&album, address) {
+        return Err(HttpResponse::Forbidden().json(ResponseError::new("forbidden")));
+    }
+
+    Ok(album)
+}
+
+#[tracing::instrument(skip(database, urls))]
+#[utoipa::path(
+    tag = "albums",
+    context_path = "/api",
+    request_body(content = CreateAlbum, description = "Album title, description and visibility", content_type = "application/json"),
+    responses(
+        (status = 200, description = "Created album", body = Album),
+        (status = 400, description = "Bad Request", body = ResponseError),
+        (status = 500, description = "Internal Server Error", body = ResponseError),
+    )
+)]
+#[post("/albums")]
+pub async fn create_album(
+    auth_user: AuthUser,
+    database: Data<Database>,
+    body: Json<CreateAlbum>,
+    urls: Data<UrlBuilder>,
+) -> impl Responder {
+    let CreateAlbum {
+        title,
+        description,
+        is_public,
+    } = body.into_inner();
+
+    if let Err(response) = validate_album_fields(&title, &description) {
+        return response;
+    }
+
+    let album_id = Uuid::new_v4().to_string();
+    match database
+        .insert_album(
+            &album_id,
+            &auth_user.address,
+            title.trim(),
+            &description,
+            is_public,
+        )
+        .await
+    {
+        Ok(album) => HttpResponse::Ok().json(Album::new(album, None, &urls)),
+        Err(error) => {
+            tracing::error!("failed to create album: {}", error);
+            HttpResponse::InternalServerError().json(ResponseError::new("failed to create album"))
+        }
+    }
+}
+
+#[tracing::instrument(skip(database, urls))]
+#[utoipa::path(
+    tag = "albums",
+    context_path = "/api",
+    params(
+        GetAlbumsQuery
+    ),
+    responses(
+        (status = 200, description = "List albums for a given user, private ones are only listed to their owner", body = GetAlbumsResponse),
+        (status = 404, description = "Not found")
+    )
+)]
+#[get("/users/{user_address}/albums")]
+pub async fn get_user_albums(
+    user_address: Path<String>,
+    query_params: Query<GetAlbumsQuery>,
+    request: HttpRequest,
+    database: Data<Database>,
+    urls: Data<UrlBuilder>,
+) -> impl Responder {
+    let user_address = user_address.into_inner();
+    let viewer = AuthUser::extract(&request)
+        .await
+        .ok()
+        .map(|AuthUser { address }| address);
+    let only_public_albums = !viewer
+        .as_deref()
+        .is_some_and(|viewer| viewer.eq_ignore_ascii_case(&user_address));
+
+    let GetAlbumsQuery { offset, limit } = query_params.into_inner();
+    let limit = limit.min(MAX_LIMIT);
+
+    let Ok(total) = database
+        .get_user_albums_count(&user_address, only_public_albums)
+        .await
+    else {
+        return HttpResponse::NotFound().json(ResponseError::new("user not found"));
+    };
+
+    let Ok(db_albums) = database
+        .get_user_albums(
+            &user_address,
+            offset as i64,
+            limit as i64,
+            only_public_albums,
+        )
+        .await
+    else {
+        return HttpResponse::NotFound().json(ResponseError::new("user not found"));
+    };
+
+    let mut albums = Vec::with_capacity(db_albums.len());
+    for album in db_albums {
+        let cover = database
+            .get_album_cover(&album, viewer.as_deref())
+            .await
+            .unwrap_or_default();
+        albums.push(Album::new(album, cover, &urls));
+    }
+
+    HttpResponse::Ok().json(GetAlbumsResponse { albums, total })
+}
+
+#[tracing::instrument(skip(database, urls))]
+#[utoipa::path(
+    tag = "albums",
+    context_path = "/api",
+    responses(
+        (status = 200, description = "Get album", body = Album),
+        (status = 404, description = "Not found")
+    )
+)]
+#[get("/albums/{album_id}")]
+pub async fn get_album(
+    album_id: Path<String>,
+    request: HttpRequest,
+    database: Data<Database>,
+    urls: Data<UrlBuilder>,
+) -> impl Responder {
+    let viewer = AuthUser::extract(&request)
+        .await
+        .ok()
+        .map(|AuthUser { address }| address);
+
+    let album = match database.get_album(&album_id).await {
+        Ok(album) if can_view(&album, viewer.as_deref()) => album,
+        _ => return HttpResponse::NotFound().json(ResponseError::new("album not found")),
+    };
+
+    let cover = database
+        .get_album_cover(&album, viewer.as_deref())
+        .await
+        .unwrap_or_default();
+
+    HttpResponse::Ok().json(Album::new(album, cover, &urls))
+}
+
+#[tracing::instrument(skip(database, urls))]
+#[utoipa::path(
+    tag = "albums",
+    context_path = "/api",
+    request_body(content = UpdateAlbum, description = "Album fields to update", content_type = "application/json"),
+    responses(
+        (status = 200, description = "Updated album", body = Album),
+        (status = 400, description = "Bad Request", body = ResponseError),
+        (status = NOT_FOUND, description = "Album was not found"),
+        (status = FORBIDDEN, description = "Forbidden"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to update album"),
+    )
+)]
+#[patch("/albums/{album_id}")]
+pub async fn update_album(
+    auth_user: AuthUser,
+    album_id: Path<String>,
+    database: Data<Database>,
+    body: Json<UpdateAlbum>,
+    urls: Data<UrlBuilder>,
+) -> impl Responder {
+    let album_id = album_id.into_inner();
+    let album = match get_owned_album(&database, &album_id, &auth_user.address).await {
+        Ok(album) => album,
+        Err(response) => return response,
+    };
+
+    let UpdateAlbum {
+        title,
+        description,
+        is_public,
+        cover_image_id,
+    } = body.into_inner();
+
+    let title = title.unwrap_or(album.title);
+    let description = description.unwrap_or(album.description);
+    let is_public = is_public.unwrap_or(album.is_public);
+
+    if let Err(response) = validate_album_fields(&title, &description) {
+        return response;
+    }
+
+    let cover_image_id = match cover_image_id {
+        Some(cover_image_id) => {
+            let Ok(cover_image_id) = Uuid::parse_str(&cover_image_id) else {
+                return HttpResponse::BadRequest().json(ResponseError::new("invalid image ID"));
+            };
+
+            match database.get_album_image_ids(&album_id).await {
+                Ok(image_ids) if image_ids.contains(&cover_image_id) => Some(cover_image_id),
+                Ok(_) => {
+                    return HttpResponse::BadRequest()
+                        .json(ResponseError::new("cover image is not part of the album"))
+                }
+                Err(error) => {
+                    tracing::error!("failed to get album images: {}", error);
+                    return HttpResponse::InternalServerError()
+                        .json(ResponseError::new("failed to update album"));
+                }
+            }
+        }
+        None => album.cover_image_id,
+    };
+
+    let album = match database
+        .update_album(
+            &album_id,
+            title.trim(),
+            &description,
+            is_public,
+            cover_image_id,
+        )
+        .await
+    {
+        Ok(album) => album,
+        Err(error) => {
+            tracing::error!("failed to update album: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to update album"));
+        }
+    };
+
+    let cover = database
+        .get_album_cover(&album, Some(&auth_user.address))
+        .await
+        .unwrap_or_default();
+
+    HttpResponse::Ok().json(Album::new(album, cover, &urls))
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "albums",
+    context_path = "/api",
+    responses(
+        (status = 200, description = "Album deleted, its images are kept"),
+        (status = NOT_FOUND, description = "Album was not found"),
+        (status = FORBIDDEN, description = "Forbidden"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to delete album"),
+    )
+)]
+#[delete("/albums/{album_id}")]
+pub async fn delete_album(
+    auth_user: AuthUser,
+    album_id: Path<String>,
+    database: Data<Database>,
+) -> impl Responder {
+    let album_id = album_id.into_inner();
+    if let Err(response) = get_owned_album(&database, &album_id, &auth_user.address).await {
+        return response;
+    }
+
+    if let Err(error) = database.delete_album(&album_id).await {
+        tracing::error!("failed to delete album: {}", error);
+        return HttpResponse::InternalServerError()
+            .json(ResponseError::new("failed to delete album"));
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "albums",
+    context_path = "/api",
+    request_body(content = AlbumImagesBody, description = "Images to append at the end of the album. Only your own images or public images can be added", content_type = "application/json"),
+    responses(
+        (status = 200, description = "Images added to the album"),
+        (status = 400, description = "Bad Request", body = ResponseError),
+        (status = NOT_FOUND, description = "Album was not found"),
+        (status = FORBIDDEN, description = "Forbidden"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to add images"),
+    )
+)]
+#[post("/albums/{album_id}/images")]
+pub async fn add_album_images(
+    auth_user: AuthUser,
+    album_id: Path<String>,
+    database: Data<Database>,
+    body: Json<AlbumImagesBody>,
+) -> impl Responder {
+    let album_id = album_id.into_inner();
+    if let Err(response) = get_owned_album(&database, &album_id, &auth_user.address).await {
+        return response;
+    }
+
+    let image_ids = match parse_image_ids(&body.image_ids) {
+        Ok(image_ids) => image_ids,
+        Err(response) => return response,
+    };
+
+    let images = match database.get_images_by_ids(&image_ids).await {
+        Ok(images) => images,
+        Err(error) => {
+            tracing::error!("failed to get images: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to add images"));
+        }
+    };
+
+    let addable = images.iter().filter(|image| {
+        image.visibility.is_public() || image.user_address.eq_ignore_ascii_case(&auth_user.address)
+    });
+    if addable.count() != image_ids.len() {
+        return HttpResponse::BadRequest().json(ResponseError::new("image not found"));
+    }
+
+    let current_ids = match database.get_album_image_ids(&album_id).await {
+        Ok(ids) => ids,
+        Err(error) => {
+            tracing::error!("failed to get album images: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to add images"));
+        }
+    };
+
+    let new_images = image_ids
+        .iter()
+        .filter(|id| !current_ids.contains(id))
+        .count();
+    if current_ids.len() + new_images > MAX_ALBUM_IMAGES {
+        return HttpResponse::BadRequest().json(ResponseError::new(&format!(
+            "albums can't hold more than {MAX_ALBUM_IMAGES} images"
+        )));
+    }
+
+    if let Err(error) = database.add_album_images(&album_id, &image_ids).await {
+        tracing::error!("failed to add album images: {}", error);
+        return HttpResponse::InternalServerError()
+            .json(ResponseError::new("failed to add images"));
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "albums",
+    context_path = "/api",
+    responses(
+        (status = 200, description = "Image removed from the album"),
+        (status = 400, description = "Bad Request", body = ResponseError),
+        (status = NOT_FOUND, description = "Album was not found, or the image isn't in it"),
+        (status = FORBIDDEN, description = "Forbidden"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to remove image"),
+    )
+)]
+#[delete("/albums/{album_id}/images/{image_id}")]
+pub async fn remove_album_image(
+    auth_user: AuthUser,
+    path: Path<(String, String)>,
+    database: Data<Database>,
+) -> impl Responder {
+    let (album_id, image_id) = path.into_inner();
+    if let Err(response) = get_owned_album(&database, &album_id, &auth_user.address).await {
+        return response;
+    }
+
+    match database.remove_album_image(&album_id, &image_id).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().json(ResponseError::new("image not found in album")),
+        Err(sqlx::Error::Protocol(_)) => {
+            HttpResponse::BadRequest().json(ResponseError::new("invalid image id"))
+        }
+        Err(error) => {
+            tracing::error!("failed to remove album image: {}", error);
+            HttpResponse::InternalServerError().json(ResponseError::new("failed to remove image"))
+        }
+    }
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "albums",
+    context_path = "/api",
+    request_body(content = AlbumImagesBody, description = "Every image of the album in the new order", content_type = "application/json"),
+    responses(
+        (status = 200, description = "Album images reordered"),
+        (status = 400, description = "Bad Request", body = ResponseError),
+        (status = NOT_FOUND, description = "Album was not found"),
+        (status = FORBIDDEN, description = "Forbidden"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to reorder images"),
+    )
+)]
+#[put("/albums/{album_id}/order")]
+pub async fn reorder_album_images(
+    auth_user: AuthUser,
+    album_id: Path<String>,
+    database: Data<Database>,
+    body: Json<AlbumImagesBody>,
+) -> impl Responder {
+    let album_id = album_id.into_inner();
+    if let Err(response) = get_owned_album(&database, &album_id, &auth_user.address).await {
+        return response;
+    }
+
+    if body.image_ids.len() > MAX_ALBUM_IMAGES {
+        return HttpResponse::BadRequest().json(ResponseError::new(&format!(
+            "albums can't hold more than {MAX_ALBUM_IMAGES} images"
+        )));
+    }
+
+    let mut image_ids = Vec::with_capacity(body.image_ids.len());
+    for image_id in &body.image_ids {
+        let Ok(id) = Uuid::parse_str(image_id) else {
+            return HttpResponse::BadRequest().json(ResponseError::new("invalid image ID"));
+        };
+        image_ids.push(id);
+    }
+
+    let current_ids = match database.get_album_image_ids(&album_id).await {
+        Ok(ids) => ids,
+        Err(error) => {
+            tracing::error!("failed to get album images: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to reorder images"));
+        }
+    };
+
+    let requested = image_ids.iter().collect::<HashSet<_>>();
+    if requested.len() != image_ids.len() || requested != current_ids.iter().collect::<HashSet<_>>()
+    {
+        return HttpResponse::BadRequest().json(ResponseError::new(
+            "the new order must contain every image of the album exactly once",
+        ));
+    }
+
+    if let Err(error) = database.reorder_album_images(&album_id, &image_ids).await {
+        tracing::error!("failed to reorder album images: {}", error);
+        return HttpResponse::InternalServerError()
+            .json(ResponseError::new("failed to reorder images"));
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[tracing::instrument(skip(database, urls))]
+#[utoipa::path(
+    tag = "albums",
+    context_path = "/api",
+    params(
+        GetAlbumImagesQuery
+    ),
+    responses(
+        (status = 200, description = "List the album images in album order. Private images are only listed to their owner", body = GetAlbumImagesResponse),
+        (status = 400, description = "Invalid cursor", body = ResponseError),
+        (status = 404, description = "Not found")
+    )
+)]
+#[get("/albums/{album_id}/images")]
+pub async fn get_album_images(
+    album_id: Path<String>,
+    query_params: Query<GetAlbumImagesQuery>,
+    request: HttpRequest,
+    database: Data<Database>,
+    urls: Data<UrlBuilder>,
+) -> impl Responder {
+    let album_id = album_id.into_inner();
+    let viewer = AuthUser::extract(&request)
+        .await
+        .ok()
+        .map(|AuthUser { address }| address);
+
+    match database.get_album(&album_id).await {
+        Ok(album) if can_view(&album, viewer.as_deref()) => {}
+        _ => return HttpResponse::NotFound().json(ResponseError::new("album not found")),
+    };
+
+    let GetAlbumImagesQuery { cursor, limit } = query_params.into_inner();
+    let limit = limit.clamp(1, MAX_LIMIT);
+
+    let after_position = match cursor.map(|cursor| cursor.parse::<i32>()) {
+        Some(Ok(position)) => Some(position),
+        Some(Err(_)) => {
+            return HttpResponse::BadRequest().json(ResponseError::new("invalid cursor"));
+        }
+        None => None,
+    };
+
+    // Fetch one extra row to know whether there is a next page
+    let Ok(mut images) = database
+        .get_album_images(
+            &album_id,
+            viewer.as_deref(),
+            after_position,
+            limit as i64 + 1,
+        )
+        .await
+    else {
+        return HttpResponse::NotFound().json(ResponseError::new("album not found"));
+    };
+
+    let next_cursor = if images.len() > limit as usize {
+        images.truncate(limit as usize);
+        images.last().map(|image| image.position.to_string())
+    } else {
+        None
+    };
+
+    let images = images
+        .into_iter()
+        .map(|album_image| GalleryImage::from_db(album_image.image, &urls))
+        .collect::<Vec<GalleryImage>>();
+
+    HttpResponse::Ok().json(GetAlbumImagesResponse {
+        images,
+        next_cursor,
+    })
+}
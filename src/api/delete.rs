@@ -3,17 +3,47 @@ use actix_web::{
     web::{Data, Path},
     HttpResponse, Responder,
 };
-use s3::Bucket;
 
 use crate::{
     api::{auth::AuthUser, ResponseError},
-    database::Database,
+    database::{DBImage, Database},
+    events::{Event, EventPayload, EventUser, PhotoDeletedMetadata, SchemaVersion},
+    storage::{delete_renders, object_key, ObjectStorage, Rendition},
     Settings,
 };
 
 use super::get::UserDataResponse;
 
-#[tracing::instrument(skip(bucket, database))]
+/// Event telling downstream services a photo is gone, with what they need to undo what they
+/// derived from it, like counts by owner, place or visible person.
+pub fn photo_deleted_event(image: &DBImage) -> Event {
+    let metadata = &image.metadata.0;
+    let photo_id = image.id.to_string();
+    Event::new(
+        &photo_id,
+        EventPayload::PhotoDeleted(PhotoDeletedMetadata {
+            schema_version: SchemaVersion,
+            photo_id: photo_id.clone(),
+            user_address: image.user_address.to_lowercase(),
+            realm: metadata.realm.clone(),
+            place_id: metadata.place_id.clone(),
+            is_public: image.visibility.is_public(),
+            visibility: image.visibility,
+            tags: image.tags.clone(),
+            // Same format as in `photo-taken`
+            users: metadata
+                .visible_people
+                .iter()
+                .map(|user| EventUser {
+                    address: user.user_address.clone(),
+                    is_emoting: user.is_emoting.unwrap_or(false),
+                })
+                .collect(),
+        }),
+    )
+}
+
+#[tracing::instrument(skip(storage, database))]
 #[utoipa::path(
     tag = "images",
     context_path = "/api",
@@ -31,7 +61,7 @@ use super::get::UserDataResponse;
 pub async fn delete_image(
     user_address: AuthUser,
     image_id: Path<String>,
-    bucket: Data<Bucket>,
+    storage: Data<dyn ObjectStorage>,
     database: Data<Database>,
     settings: Data<Settings>,
 ) -> impl Responder {
@@ -52,28 +82,26 @@ pub async fn delete_image(
     }
 
     let image_id = image_id.into_inner();
-    if let Err(error) = database.delete_image(&image_id).await {
+    if let Err(error) = database
+        .delete_image(&image_id, &photo_deleted_event(&image))
+        .await
+    {
         tracing::error!("failed to delete image metadata: {}", error);
         return HttpResponse::InternalServerError()
             .json(ResponseError::new("failed to delete image"));
     };
 
-    match image.url.split('/').last() {
-        Some(image_file_name) => {
-            if let Err(error) = bucket.delete_object(image_file_name).await {
-                tracing::error!("failed to delete image from bucket: {}", error);
-                return HttpResponse::InternalServerError()
-                    .json(ResponseError::new("failed to delete image"));
-            };
-        }
-        None => {
-            tracing::debug!("No image to delete");
-        }
-    }
+    let key = object_key(&image.stored_key(Rendition::Original), image.visibility);
+    if let Err(error) = storage.delete(&key).await {
+        tracing::error!("failed to delete image from bucket: {}", error);
+        return HttpResponse::InternalServerError()
+            .json(ResponseError::new("failed to delete image"));
+    };
 
-    match image.thumbnail_url.split('/').last() {
-        Some(thumbnail_file_name) => {
-            if let Err(error) = bucket.delete_object(thumbnail_file_name).await {
+    match &image.thumbnail_key {
+        Some(thumbnail_key) => {
+            let key = object_key(thumbnail_key, image.visibility);
+            if let Err(error) = storage.delete(&key).await {
                 tracing::error!("failed to delete thumbnail image from bucket: {}", error);
                 return HttpResponse::InternalServerError()
                     .json(ResponseError::new("failed to delete thumbnail image"));
@@ -84,6 +112,10 @@ pub async fn delete_image(
         }
     }
 
+    if let Err(error) = delete_renders(storage.get_ref(), &image_id, image.visibility).await {
+        tracing::error!("failed to delete image renders: {}", error);
+    }
+
     let current_images = database
         .get_user_images_count(&image.user_address, false)
         .await
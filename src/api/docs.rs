@@ -1,8 +1,24 @@
+use super::admin::*;
+use super::albums::*;
 use super::delete::*;
+use super::embed::*;
+use super::files::*;
 use super::get::*;
+use super::notifications::*;
+use super::people::*;
+use super::render::*;
+use super::restore::*;
+use super::search::*;
+use super::share_links::*;
+use super::stream::*;
+use super::tags::*;
 use super::update::*;
 use super::upload::*;
+use super::wearables::*;
+use super::webhooks::*;
 use super::*;
+use crate::events::event_schemas;
+use actix_web::{get, HttpResponse, Responder};
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
@@ -15,18 +31,63 @@ use utoipa_swagger_ui::SwaggerUi;
     paths(
         delete_image,
         get_image,
+        get_image_file,
         get_metadata,
+        render_image,
+        restore_image,
+        get_image_restore,
         get_user_data,
         get_user_images,
+        get_user_appearances,
         get_place_images,
+        stream_place_images,
         get_multiple_places_images,
         upload_image,
-        update_image_visibility
+        update_image_visibility,
+        update_image,
+        untag_image,
+        get_user_preferences,
+        update_user_preferences,
+        get_blocked_users,
+        block_user,
+        unblock_user,
+        get_user_notifications,
+        mark_notifications_read,
+        create_album,
+        get_user_albums,
+        get_album,
+        update_album,
+        delete_album,
+        add_album_images,
+        remove_album_image,
+        reorder_album_images,
+        get_album_images,
+        get_tag_images,
+        get_trending_tags,
+        get_wearable_images,
+        get_wearables_usage,
+        search_images,
+        create_share_link,
+        get_share_links,
+        revoke_share_link,
+        get_shared_image,
+        get_share_page,
+        get_oembed,
+        get_file,
+        create_webhook_subscription,
+        get_webhook_subscriptions,
+        delete_webhook_subscription,
+        enable_webhook_subscription,
+        get_webhook_deliveries,
+        get_outbox_status,
+        get_event_schemas
     ),
     components(
         schemas(
             Image,
+            Visibility,
             GalleryImage,
+            GalleryImageWithPlace,
             Metadata,
             Scene,
             Location,
@@ -34,8 +95,19 @@ use utoipa_swagger_ui::SwaggerUi;
             Upload,
             UploadResponse,
             UpdateVisibility,
+            UpdateImage,
+            UntagImage,
+            UserPreferences,
+            GetBlockedUsersResponse,
+            Notification,
+            GetNotificationsResponse,
+            MarkNotificationsRead,
+            MarkNotificationsReadResponse,
             GetImagesResponse,
             GetGalleryImagesResponse,
+            GetAppearancesResponse,
+            GetGalleryAppearancesResponse,
+            AppearancesDataResponse,
             GetPlaceImagesResponse,
             GetMultiplePlacesImagesBody,
             GetMultiplePlacesImagesResponse,
@@ -43,13 +115,69 @@ use utoipa_swagger_ui::SwaggerUi;
             PlaceDataResponse,
             ResponseError,
             ForbiddenError,
-            ForbiddenReason
+            ForbiddenReason,
+            Album,
+            CreateAlbum,
+            UpdateAlbum,
+            AlbumImagesBody,
+            GetAlbumsResponse,
+            GetAlbumImagesResponse,
+            GetTagImagesResponse,
+            TagDataResponse,
+            TagCount,
+            GetTrendingTagsResponse,
+            GetWearableImagesResponse,
+            WearableDataResponse,
+            GetWearablesUsageBody,
+            WearableUsage,
+            GetWearablesUsageResponse,
+            SearchImagesResponse,
+            SearchDataResponse,
+            ShareLink,
+            CreateShareLink,
+            GetShareLinksResponse,
+            SharedImageResponse,
+            OEmbedResponse,
+            Fit,
+            RenderFormat,
+            ImageRestore,
+            RestoreStatus,
+            WebhookSubscription,
+            CreatedWebhookSubscription,
+            CreateWebhookSubscription,
+            GetWebhookSubscriptionsResponse,
+            WebhookDelivery,
+            WebhookDeliveryStatus,
+            GetWebhookDeliveriesResponse,
+            OutboxStatus,
+            OutboxEntry
         )
     ),
-    tags((name = "images",description = "Images management endpoints.")),
+    tags(
+        (name = "images",description = "Images management endpoints."),
+        (name = "albums",description = "User-curated collections of images."),
+        (name = "tags",description = "Hashtag galleries and trending tags."),
+        (name = "notifications",description = "Inbox of the photos a user appears in, and the users whose photos don't notify them."),
+        (name = "events",description = "Schemas of the events published to SNS, SQS and webhooks."),
+        (name = "webhooks",description = "Webhooks notified of the public photos taken in a place or world."),
+        (name = "admin",description = "Operations endpoints, behind the admin bearer token.")
+    ),
 )]
 pub struct ApiDoc;
 
 pub fn generate_docs() -> SwaggerUi {
     SwaggerUi::new("/api/docs/ui/{_:.*}").url("/api/docs/openapi.json", ApiDoc::openapi())
 }
+
+#[tracing::instrument]
+#[utoipa::path(
+    tag = "events",
+    context_path = "/api",
+    responses(
+        (status = 200, description = "JSON Schema of each published event, by `subType`. The `metadata` of an event carries its `schemaVersion`, bumped on breaking changes", content_type = "application/json"),
+    )
+)]
+#[get("/docs/events.json")]
+pub async fn get_event_schemas() -> impl Responder {
+    HttpResponse::Ok().json(event_schemas())
+}
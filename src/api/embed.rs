@@ -0,0 +1,405 @@
+use actix_web::{
+    get,
+    http::header,
+    web::{Data, Path},
+    HttpResponse, Responder,
+};
+use actix_web_lab::extract::Query;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    api::{Image, ResponseError},
+    database::Database,
+    storage::{presign_image, ObjectStorage},
+    urls::UrlBuilder,
+    Settings,
+};
+
+const PROVIDER_NAME: &str = "Decentraland Camera Reel";
+
+/// Bounds of the thumbnails generated on upload, which is what embeds show.
+const THUMBNAIL_WIDTH: u32 = 640;
+const THUMBNAIL_HEIGHT: u32 = 360;
+
+#[derive(Deserialize, Debug, IntoParams)]
+struct SharePageQuery {
+    /// Share link token, required to preview private and shared images.
+    token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+struct OEmbedQuery {
+    /// Share page URL, as `{api_url}/share/{image_id}`, optionally with its `token`.
+    url: String,
+    /// Only `json` is supported.
+    format: Option<String>,
+    maxwidth: Option<u32>,
+    maxheight: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct OEmbedResponse {
+    /// `photo` for viewable images, `link` for the generic card of hidden ones.
+    #[serde(rename = "type")]
+    pub embed_type: String,
+    pub version: String,
+    pub title: String,
+    pub provider_name: String,
+    pub provider_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+/// Escapes a text to be placed in HTML content or attribute values.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        match char {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            char => escaped.push(char),
+        }
+    }
+    escaped
+}
+
+/// Title of the card, from the scene and the photographer.
+fn card_title(image: &Image) -> String {
+    let scene_name = image.metadata.scene.name.trim();
+    let user_name = image.metadata.user_name.trim();
+    match (scene_name, user_name) {
+        ("", "") => "Photo taken in Decentraland".to_string(),
+        ("", user_name) => format!("Photo by {user_name}"),
+        (scene_name, "") => format!("Photo taken at {scene_name}"),
+        (scene_name, user_name) => format!("{scene_name} by {user_name}"),
+    }
+}
+
+/// Scales the thumbnail bounds down to fit the maximum size requested by the consumer.
+fn fit_size(max_width: Option<u32>, max_height: Option<u32>) -> (u32, u32) {
+    let scale = [
+        max_width.map(|width| width as f64 / THUMBNAIL_WIDTH as f64),
+        max_height.map(|height| height as f64 / THUMBNAIL_HEIGHT as f64),
+    ]
+    .into_iter()
+    .flatten()
+    .fold(1.0_f64, f64::min);
+
+    (
+        (THUMBNAIL_WIDTH as f64 * scale).round() as u32,
+        (THUMBNAIL_HEIGHT as f64 * scale).round() as u32,
+    )
+}
+
+/// Returns the image id and share token of a share page URL.
+fn parse_share_url(url: &str, api_url: &str) -> Option<(String, Option<String>)> {
+    let url = Url::parse(url).ok()?;
+    let image_id = url
+        .as_str()
+        .strip_prefix(&format!("{}/share/", api_url.trim_end_matches('/')))?
+        .split(['?', '#', '/'])
+        .next()
+        .filter(|image_id| !image_id.is_empty())?
+        .to_string();
+    let token = url
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, token)| token.into_owned());
+
+    Some((image_id, token))
+}
+
+/// Returns the image when it can be previewed: public and unlisted images always, private and
+/// shared ones only with a usable share link of the image, unless moderation took it down.
+/// The files of restricted images get presigned URLs. Previews don't count as views of the
+/// share link, since link unfurlers fetch them without anyone opening the photo.
+async fn get_embeddable_image(
+    database: &Database,
+    storage: &dyn ObjectStorage,
+    urls: &UrlBuilder,
+    image_id: &str,
+    token: Option<&str>,
+) -> Option<Image> {
+    let image = database.get_image(image_id).await.ok()?;
+
+    if image.visibility.is_restricted() {
+        if image.moderated_at.is_some() {
+            return None;
+        }
+        let share_link = database.get_usable_share_link(token?).await.ok()??;
+        if share_link.image_id != image.id {
+            return None;
+        }
+    }
+
+    match presign_image(storage, urls, image) {
+        Ok(image) => Some(image),
+        Err(error) => {
+            tracing::error!("failed to presign image url: {}", error);
+            None
+        }
+    }
+}
+
+fn render_share_page(image: Option<&Image>, page_url: &str, oembed_url: &str) -> String {
+    let Some(image) = image else {
+        return format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{PROVIDER_NAME}</title>
+<meta name="robots" content="noindex">
+<meta property="og:site_name" content="{PROVIDER_NAME}">
+<meta property="og:type" content="website">
+<meta property="og:title" content="{PROVIDER_NAME}">
+<meta property="og:description" content="This photo is private.">
+<meta name="twitter:card" content="summary">
+<meta name="twitter:title" content="{PROVIDER_NAME}">
+<meta name="twitter:description" content="This photo is private.">
+</head>
+<body>
+<p>This photo is private.</p>
+</body>
+</html>
+"#
+        );
+    };
+
+    let title = escape_html(&card_title(image));
+    let description = escape_html(
+        image
+            .caption
+            .as_deref()
+            .or(image.alt_text.as_deref())
+            .unwrap_or_default(),
+    );
+    let alt_text = escape_html(image.alt_text.as_deref().unwrap_or_default());
+    let image_url = escape_html(&image.url);
+    let page_url = escape_html(page_url);
+    let oembed_url = escape_html(oembed_url);
+    let robots = if image.visibility.is_public() {
+        ""
+    } else {
+        "\n<meta name=\"robots\" content=\"noindex\">"
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{title}</title>{robots}
+<link rel="alternate" type="application/json+oembed" href="{oembed_url}" title="{title}">
+<meta property="og:site_name" content="{PROVIDER_NAME}">
+<meta property="og:type" content="website">
+<meta property="og:url" content="{page_url}">
+<meta property="og:title" content="{title}">
+<meta property="og:description" content="{description}">
+<meta property="og:image" content="{image_url}">
+<meta property="og:image:alt" content="{alt_text}">
+<meta name="twitter:card" content="summary_large_image">
+<meta name="twitter:title" content="{title}">
+<meta name="twitter:description" content="{description}">
+<meta name="twitter:image" content="{image_url}">
+<meta name="twitter:image:alt" content="{alt_text}">
+</head>
+<body>
+<h1>{title}</h1>
+<img src="{image_url}" alt="{alt_text}">
+<p>{description}</p>
+</body>
+</html>
+"#
+    )
+}
+
+#[tracing::instrument(skip(query_params, settings, storage, database, urls))]
+#[utoipa::path(
+    tag = "images",
+    params(
+        SharePageQuery
+    ),
+    responses(
+        (status = 200, description = "HTML page with the Open Graph and Twitter card tags of the image. Private and shared images without a share token, and missing ones, get a generic card", content_type = "text/html"),
+    )
+)]
+#[get("/share/{image_id}")]
+pub async fn get_share_page(
+    image_id: Path<String>,
+    query_params: Query<SharePageQuery>,
+    settings: Data<Settings>,
+    storage: Data<dyn ObjectStorage>,
+    database: Data<Database>,
+    urls: Data<UrlBuilder>,
+) -> impl Responder {
+    let image_id = image_id.into_inner();
+    let SharePageQuery { token } = query_params.into_inner();
+
+    let image = get_embeddable_image(
+        &database,
+        storage.get_ref(),
+        &urls,
+        &image_id,
+        token.as_deref(),
+    )
+    .await;
+
+    let mut page_url = Url::parse(&format!("{}/share/{image_id}", settings.api_url)).ok();
+    if let (Some(page_url), Some(token)) = (page_url.as_mut(), token.as_deref()) {
+        page_url.query_pairs_mut().append_pair("token", token);
+    }
+    let page_url = page_url.map(String::from).unwrap_or_default();
+
+    let oembed_url = Url::parse_with_params(
+        &format!("{}/oembed", settings.api_url),
+        &[("url", page_url.as_str()), ("format", "json")],
+    )
+    .map(String::from)
+    .unwrap_or_default();
+
+    let cache_control = match &image {
+        Some(image) if image.visibility.is_public() => "public, max-age=300",
+        _ => "private, no-store",
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .insert_header((header::CACHE_CONTROL, cache_control))
+        .body(render_share_page(image.as_ref(), &page_url, &oembed_url))
+}
+
+#[tracing::instrument(skip(query_params, settings, storage, database, urls))]
+#[utoipa::path(
+    tag = "images",
+    params(
+        OEmbedQuery
+    ),
+    responses(
+        (status = 200, description = "oEmbed of a share page. Private and shared images without a share token, and missing ones, get a generic `link` embed", body = OEmbedResponse),
+        (status = 404, description = "The URL isn't a share page", body = ResponseError),
+        (status = 501, description = "Unsupported format", body = ResponseError),
+    )
+)]
+#[get("/oembed")]
+pub async fn get_oembed(
+    query_params: Query<OEmbedQuery>,
+    settings: Data<Settings>,
+    storage: Data<dyn ObjectStorage>,
+    database: Data<Database>,
+    urls: Data<UrlBuilder>,
+) -> impl Responder {
+    let OEmbedQuery {
+        url,
+        format,
+        maxwidth,
+        maxheight,
+    } = query_params.into_inner();
+
+    if format.is_some_and(|format| format != "json") {
+        return HttpResponse::NotImplemented().json(ResponseError::new("unsupported format"));
+    }
+
+    let Some((image_id, token)) = parse_share_url(&url, &settings.api_url) else {
+        return HttpResponse::NotFound().json(ResponseError::new("url not found"));
+    };
+
+    let image = get_embeddable_image(
+        &database,
+        storage.get_ref(),
+        &urls,
+        &image_id,
+        token.as_deref(),
+    )
+    .await;
+
+    let mut embed = OEmbedResponse {
+        embed_type: "link".to_string(),
+        version: "1.0".to_string(),
+        title: PROVIDER_NAME.to_string(),
+        provider_name: PROVIDER_NAME.to_string(),
+        provider_url: settings.api_url.clone(),
+        author_name: None,
+        url: None,
+        width: None,
+        height: None,
+    };
+
+    let cache_control = match image {
+        Some(image) => {
+            let (width, height) = fit_size(maxwidth, maxheight);
+            let cache_control = if image.visibility.is_public() {
+                "public, max-age=300"
+            } else {
+                "private, no-store"
+            };
+            embed = OEmbedResponse {
+                embed_type: "photo".to_string(),
+                title: card_title(&image),
+                author_name: Some(image.metadata.user_name),
+                url: Some(image.thumbnail_url),
+                width: Some(width),
+                height: Some(height),
+                ..embed
+            };
+            cache_control
+        }
+        None => "private, no-store",
+    };
+
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, cache_control))
+        .json(embed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escapes_html() {
+        assert_eq!(
+            escape_html(r#"<script>"Tom & Jerry's"</script>"#),
+            "&lt;script&gt;&quot;Tom &amp; Jerry&#39;s&quot;&lt;/script&gt;"
+        );
+    }
+
+    #[test]
+    fn test_fits_the_requested_size() {
+        assert_eq!(fit_size(None, None), (640, 360));
+        assert_eq!(fit_size(Some(1000), None), (640, 360));
+        assert_eq!(fit_size(Some(320), None), (320, 180));
+        assert_eq!(fit_size(Some(320), Some(90)), (160, 90));
+    }
+
+    #[test]
+    fn test_parses_share_urls() {
+        let api_url = "https://camera-reel.decentraland.org";
+        let image_id = "f5f7b3c5-7f43-4a8e-9b3b-0c9b6a1b2c3d";
+
+        assert_eq!(
+            parse_share_url(&format!("{api_url}/share/{image_id}"), api_url),
+            Some((image_id.to_string(), None))
+        );
+        assert_eq!(
+            parse_share_url(&format!("{api_url}/share/{image_id}?token=abc"), api_url),
+            Some((image_id.to_string(), Some("abc".to_string())))
+        );
+        assert_eq!(
+            parse_share_url(&format!("https://example.com/share/{image_id}"), api_url),
+            None
+        );
+        assert_eq!(parse_share_url(&format!("{api_url}/share/"), api_url), None);
+    }
+}
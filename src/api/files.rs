@@ -0,0 +1,89 @@
+use actix_web::{
+    get,
+    web::{Data, Path},
+    HttpRequest, HttpResponse, Responder,
+};
+use actix_web_lab::extract::Query;
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::{
+    api::{
+        get::{PRIVATE_CACHE_CONTROL, PUBLIC_CACHE_CONTROL},
+        proxy::{proxy_file, ProxiedFile},
+        ResponseError,
+    },
+    image_cache::ImageCache,
+    storage::{file_name, ObjectStorage, PRIVATE_PREFIX},
+    Settings, StorageBackend,
+};
+
+#[derive(Deserialize, Debug, IntoParams)]
+pub struct GetFileQuery {
+    /// Expiration of a presigned URL, as seconds since the Unix epoch.
+    expires: Option<u64>,
+    /// Signature of a presigned URL.
+    signature: Option<String>,
+}
+
+#[tracing::instrument(skip(settings, storage, image_cache, request))]
+#[utoipa::path(
+    tag = "images",
+    params(
+        GetFileQuery
+    ),
+    responses(
+        (status = 200, description = "A stored file, when the files are kept in a local directory instead of a bucket"),
+        (status = 403, description = "Private file without a valid presigned signature", body = ResponseError),
+        (status = 404, description = "Not found")
+    )
+)]
+#[get("/files/{key:.*}")]
+pub async fn get_file(
+    key: Path<String>,
+    query_params: Query<GetFileQuery>,
+    settings: Data<Settings>,
+    storage: Data<dyn ObjectStorage>,
+    image_cache: Data<ImageCache>,
+    request: HttpRequest,
+) -> impl Responder {
+    // Buckets serve their own files
+    if settings.storage_backend != StorageBackend::Local {
+        return HttpResponse::NotFound().json(ResponseError::new("file not found"));
+    }
+
+    let key = key.into_inner();
+    let Some(name) = file_name(&key) else {
+        return HttpResponse::NotFound().json(ResponseError::new("file not found"));
+    };
+
+    // Like the bucket policy, private files are only readable through presigned URLs
+    let cache_control = if key.starts_with(PRIVATE_PREFIX) {
+        let GetFileQuery { expires, signature } = query_params.into_inner();
+        let is_signed = match (expires, signature) {
+            (Some(expires), Some(signature)) => storage.verify_presigned(&key, expires, &signature),
+            _ => false,
+        };
+        if !is_signed {
+            return HttpResponse::Forbidden()
+                .json(ResponseError::new("invalid or expired signature"));
+        }
+        PRIVATE_CACHE_CONTROL
+    } else {
+        PUBLIC_CACHE_CONTROL
+    };
+
+    proxy_file(
+        &request,
+        storage.get_ref(),
+        &image_cache,
+        ProxiedFile {
+            key: &key,
+            file_name: name,
+            rendition: None,
+            cache_control,
+            download: false,
+        },
+    )
+    .await
+}
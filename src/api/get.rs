@@ -1,6 +1,8 @@
 use actix_web::{
-    get, post,
-    web::{Data, Json, Path, Redirect},
+    get,
+    http::header,
+    post,
+    web::{Data, Json, Path},
     FromRequest, HttpRequest, HttpResponse, Responder,
 };
 use actix_web_lab::extract::Query;
@@ -8,10 +10,18 @@ use serde::{Deserialize, Serialize};
 use utoipa::{IntoParams, ToSchema};
 
 use crate::{
-    api::{auth::AuthUser, GalleryImage, GalleryImageWithPlace, Image, ResponseError},
-    database::Database,
+    api::{
+        auth::AuthUser,
+        proxy::{proxy_file, ProxiedFile},
+        restore::archived_original_response,
+        GalleryImage, GalleryImageWithPlace, Image, ResponseError, Visibility,
+    },
+    database::{DBImage, Database},
+    image_cache::ImageCache,
     places_client::PlacesClient,
-    Settings,
+    storage::{file_name, object_key, presigned_url, ObjectStorage, Rendition},
+    urls::UrlBuilder,
+    ImageDelivery, Settings,
 };
 
 /// Maximum number of place IDs accepted in a single `POST /places/images` request.
@@ -23,20 +33,198 @@ const MAX_PLACES_IDS: usize = 100;
 /// so a request can't drive an oversized DB query / response.
 const MAX_LIMIT: u64 = 100;
 
-#[tracing::instrument(skip(settings))]
+/// Public and unlisted images can be viewed by anyone who has the link, sharing a photo is a
+/// first-class feature. Private images are only visible to their owner, and shared ones also
+/// to the addresses they are shared with.
+pub async fn can_view_image(database: &Database, image: &DBImage, request: &HttpRequest) -> bool {
+    if !image.visibility.is_restricted() {
+        return true;
+    }
+
+    match AuthUser::extract(request).await {
+        Ok(AuthUser { address }) if address.eq_ignore_ascii_case(&image.user_address) => true,
+        Ok(AuthUser { address }) if image.visibility == Visibility::Shared => database
+            .is_image_shared_with(&image.id.to_string(), &address)
+            .await
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// Public and unlisted files may be cached by shared caches for a while.
+pub const PUBLIC_CACHE_CONTROL: &str = "public, max-age=3600";
+
+/// Private and shared files are only cached by the browser of the viewer.
+pub const PRIVATE_CACHE_CONTROL: &str = "private, max-age=300";
+
+#[derive(Deserialize, Debug, IntoParams)]
+struct GetImageQuery {
+    /// Serve the file as an attachment, so browsers save it. Only honored in proxy mode.
+    #[serde(default)]
+    download: bool,
+}
+
+/// Serves a file of an image, redirecting to the storage or streaming it depending on the
+/// delivery mode.
+async fn serve_image_file(
+    request: &HttpRequest,
+    settings: &Settings,
+    storage: &dyn ObjectStorage,
+    database: &Database,
+    image_cache: &ImageCache,
+    (image_id, rendition): (&str, Rendition),
+    download: bool,
+) -> HttpResponse {
+    // Hidden images look like missing ones so their existence isn't leaked
+    let image = match database.get_image(image_id).await {
+        Ok(image) if can_view_image(database, &image, request).await => image,
+        _ => return HttpResponse::NotFound().json(ResponseError::new("image not found")),
+    };
+    if image.is_archived(rendition) {
+        return archived_original_response(database, settings, &image).await;
+    }
+    let key = image.key(rendition);
+
+    if settings.image_delivery == ImageDelivery::Proxy {
+        let cache_control = if image.visibility.is_restricted() {
+            PRIVATE_CACHE_CONTROL
+        } else {
+            PUBLIC_CACHE_CONTROL
+        };
+
+        return proxy_file(
+            request,
+            storage,
+            image_cache,
+            ProxiedFile {
+                key: &object_key(key, image.visibility),
+                file_name: file_name(key).unwrap_or(key),
+                rendition: Some(rendition),
+                cache_control,
+                download,
+            },
+        )
+        .await;
+    }
+
+    if image.visibility.is_public() {
+        return HttpResponse::TemporaryRedirect()
+            .insert_header((header::LOCATION, storage.public_url(key)))
+            .finish();
+    }
+
+    match presigned_url(storage, key, image.visibility) {
+        Ok(url) => HttpResponse::TemporaryRedirect()
+            .insert_header((header::LOCATION, url))
+            .insert_header((header::CACHE_CONTROL, "private, no-store"))
+            .finish(),
+        Err(error) => {
+            tracing::error!("failed to presign image url: {}", error);
+            HttpResponse::InternalServerError().json(ResponseError::new("failed to get image"))
+        }
+    }
+}
+
+#[tracing::instrument(skip(settings, storage, database, image_cache, request))]
 #[utoipa::path(
     tag = "images",
-    context_path = "/api", 
+    context_path = "/api",
+    params(
+        ("image_id" = String, Path, description = "Image id"),
+        ("rendition" = String, Path, description = "`original` or `thumbnail`"),
+        GetImageQuery
+    ),
     responses(
-        (status = 200, description = "Get image", body = Image),
+        (status = 200, description = "The image file, when the service proxies the files"),
+        (status = 202, description = "The original is archived, its restore was queued. Retry after the `Retry-After` seconds", body = ImageRestore),
+        (status = 206, description = "Part of the image file, for range requests in proxy mode"),
+        (status = 304, description = "The image file didn't change, in proxy mode"),
+        (status = 307, description = "Redirects to the image file. Images that aren't public get a short-lived presigned URL"),
+        (status = 404, description = "Not found")
+    )
+)]
+#[get("/images/{image_id}/{rendition:original|thumbnail}")]
+async fn get_image_file(
+    settings: Data<Settings>,
+    storage: Data<dyn ObjectStorage>,
+    database: Data<Database>,
+    image_cache: Data<ImageCache>,
+    path: Path<(String, Rendition)>,
+    query_params: Query<GetImageQuery>,
+    request: HttpRequest,
+) -> impl Responder {
+    let (image_id, rendition) = path.into_inner();
+
+    serve_image_file(
+        &request,
+        &settings,
+        storage.get_ref(),
+        &database,
+        &image_cache,
+        (&image_id, rendition),
+        query_params.download,
+    )
+    .await
+}
+
+/// Rendition of a file named like the URLs handed out before the keys were partitioned,
+/// `{image_id}-{file name}` and `{image_id}-thumbnail-{file name}`.
+fn legacy_file_rendition(file_name: &str) -> Option<(&str, Rendition)> {
+    let image_id = file_name.get(..36)?;
+    let rendition = match file_name.get(36..)? {
+        name if name.starts_with("-thumbnail-") => Rendition::Thumbnail,
+        name if name.starts_with('-') => Rendition::Original,
+        _ => return None,
+    };
+
+    Some((image_id, rendition))
+}
+
+#[tracing::instrument(skip(settings, storage, database, image_cache, request))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    params(
+        ("image_id" = String, Path, description = "File name of the image, as `{image_id}-{file name}` or `{image_id}-thumbnail-{file name}`. Kept for URLs handed out before `/images/{image_id}/{rendition}`"),
+        GetImageQuery
+    ),
+    responses(
+        (status = 200, description = "The image file, when the service proxies the files"),
+        (status = 202, description = "The original is archived, its restore was queued. Retry after the `Retry-After` seconds", body = ImageRestore),
+        (status = 206, description = "Part of the image file, for range requests in proxy mode"),
+        (status = 304, description = "The image file didn't change, in proxy mode"),
+        (status = 307, description = "Redirects to the image file. Images that aren't public get a short-lived presigned URL"),
+        (status = 404, description = "Not found")
     )
 )]
 #[get("/images/{image_id}")]
-async fn get_image(settings: Data<Settings>, image_id: Path<String>) -> impl Responder {
-    Redirect::to(format!("{}/{}", settings.bucket_url, image_id))
+async fn get_image(
+    settings: Data<Settings>,
+    storage: Data<dyn ObjectStorage>,
+    database: Data<Database>,
+    image_cache: Data<ImageCache>,
+    image_id: Path<String>,
+    query_params: Query<GetImageQuery>,
+    request: HttpRequest,
+) -> impl Responder {
+    let file_name = image_id.into_inner();
+    let Some(file) = legacy_file_rendition(&file_name) else {
+        return HttpResponse::NotFound().json(ResponseError::new("image not found"));
+    };
+
+    serve_image_file(
+        &request,
+        &settings,
+        storage.get_ref(),
+        &database,
+        &image_cache,
+        file,
+        query_params.download,
+    )
+    .await
 }
 
-#[tracing::instrument(skip(database))]
+#[tracing::instrument(skip(database, request, urls))]
 #[utoipa::path(
     tag = "images",
     context_path = "/api",
@@ -46,27 +234,33 @@ async fn get_image(settings: Data<Settings>, image_id: Path<String>) -> impl Res
     )
 )]
 #[get("/images/{image_id}/metadata")]
-async fn get_metadata(database: Data<Database>, image_id: Path<String>) -> impl Responder {
+async fn get_metadata(
+    database: Data<Database>,
+    image_id: Path<String>,
+    request: HttpRequest,
+    urls: Data<UrlBuilder>,
+) -> impl Responder {
     let image_id = image_id.into_inner();
-    // A single image's metadata is public for every image. The image bytes are already
-    // served publicly (`GET /images/{id}` redirects to the public bucket) and sharing a
-    // photo is a first-class feature, so the photo and its metadata must be viewable by
-    // anyone who has the link. `is_public` only controls whether the image is listed in
-    // the user's public gallery (see `get_user_images`), not whether it can be viewed.
-    match database.get_image(&image_id).await {
-        Ok(image) => {
-            let image: Image = image.into();
-            HttpResponse::Ok().json(image)
-        }
+    let image = match database.get_image(&image_id).await {
+        Ok(image) => image,
         Err(sqlx::Error::ColumnDecode { source, .. }) => {
             tracing::debug!("Couldn't decode image metadata: {source:?}");
-            HttpResponse::InternalServerError().json(ResponseError::new("couldn't decode image"))
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("couldn't decode image"));
         }
         Err(e) => {
             tracing::debug!("Image not found: {e:?}");
-            HttpResponse::NotFound().json(ResponseError::new("image not found"))
+            return HttpResponse::NotFound().json(ResponseError::new("image not found"));
         }
+    };
+
+    // Hidden images look like missing ones so their existence isn't leaked
+    if !can_view_image(&database, &image, &request).await {
+        return HttpResponse::NotFound().json(ResponseError::new("image not found"));
     }
+
+    let image = Image::from_db(image, &urls);
+    HttpResponse::Ok().json(image)
 }
 
 #[derive(Deserialize, Debug, IntoParams)]
@@ -156,7 +350,7 @@ pub struct GetGalleryImagesResponse {
     pub user_data: UserDataResponse,
 }
 
-#[tracing::instrument(skip(database, settings))]
+#[tracing::instrument(skip(database, settings, urls))]
 #[utoipa::path(
     tag = "images",
     context_path = "/api", 
@@ -176,6 +370,7 @@ async fn get_user_images(
     request: HttpRequest,
     settings: Data<Settings>,
     database: Data<Database>,
+    urls: Data<UrlBuilder>,
 ) -> impl Responder {
     let user_address = user_address.into_inner();
     let mut only_public_images: bool = false;
@@ -221,15 +416,123 @@ async fn get_user_images(
     if compact {
         let images = images
             .into_iter()
-            .map(GalleryImage::from)
+            .map(|image| GalleryImage::from_db(image, &urls))
             .collect::<Vec<GalleryImage>>();
         return HttpResponse::Ok().json(GetGalleryImagesResponse { images, user_data });
     } else {
-        let images = images.into_iter().map(Image::from).collect::<Vec<Image>>();
+        let images = images
+            .into_iter()
+            .map(|image| Image::from_db(image, &urls))
+            .collect::<Vec<Image>>();
         return HttpResponse::Ok().json(GetImagesResponse { images, user_data });
     };
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AppearancesDataResponse {
+    pub max_images: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GetAppearancesResponse {
+    pub images: Vec<Image>,
+    #[serde(flatten)]
+    pub appearances_data: AppearancesDataResponse,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GetGalleryAppearancesResponse {
+    pub images: Vec<GalleryImage>,
+    #[serde(flatten)]
+    pub appearances_data: AppearancesDataResponse,
+}
+
+#[tracing::instrument(skip(database, urls))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    params(
+        GetImagesQuery
+    ),
+    responses(
+        (status = 200, description = "List images a given user appears in", body = GetAppearancesResponse),
+        (status = 210, description = "List gallery images a given user appears in if `compact=true` (status code is 200, but was not possible to list multiple responses for one status code)", body = GetGalleryAppearancesResponse),
+        (status = 404, description = "Not found")
+    )
+)]
+#[get("/users/{user_address}/appearances")]
+async fn get_user_appearances(
+    user_address: Path<String>,
+    query_params: Query<GetImagesQuery>,
+    request: HttpRequest,
+    database: Data<Database>,
+    urls: Data<UrlBuilder>,
+) -> impl Responder {
+    let user_address = user_address.into_inner();
+    let mut only_public_images: bool = false;
+
+    // Authenticated users also get the private images they took themselves and appear in
+    match AuthUser::extract(&request).await {
+        Ok(AuthUser { address }) if address.eq_ignore_ascii_case(&user_address) => {}
+        _ => {
+            only_public_images = true;
+        }
+    }
+
+    let GetImagesQuery {
+        offset,
+        limit,
+        compact,
+    } = query_params.into_inner();
+    let limit = limit.min(MAX_LIMIT);
+
+    let Ok(images_count) = database
+        .get_user_appearances_count(&user_address, only_public_images)
+        .await
+    else {
+        return HttpResponse::NotFound().json(ResponseError::new("user not found"));
+    };
+
+    let Ok(images) = database
+        .get_user_appearances(
+            &user_address,
+            offset as i64,
+            limit as i64,
+            only_public_images,
+        )
+        .await
+    else {
+        return HttpResponse::NotFound().json(ResponseError::new("user not found"));
+    };
+
+    let appearances_data = AppearancesDataResponse {
+        max_images: images_count,
+    };
+
+    if compact {
+        let images = images
+            .into_iter()
+            .map(|image| GalleryImage::from_db(image, &urls))
+            .collect::<Vec<GalleryImage>>();
+        HttpResponse::Ok().json(GetGalleryAppearancesResponse {
+            images,
+            appearances_data,
+        })
+    } else {
+        let images = images
+            .into_iter()
+            .map(|image| Image::from_db(image, &urls))
+            .collect::<Vec<Image>>();
+        HttpResponse::Ok().json(GetAppearancesResponse {
+            images,
+            appearances_data,
+        })
+    }
+}
+
 #[derive(Deserialize, Debug, IntoParams)]
 struct GetPlaceImagesQuery {
     #[serde(default = "default_offset")]
@@ -252,7 +555,7 @@ pub struct GetPlaceImagesResponse {
     pub place_data: PlaceDataResponse,
 }
 
-#[tracing::instrument(skip(database, places_client))]
+#[tracing::instrument(skip(database, places_client, urls))]
 #[utoipa::path(
     tag = "images",
     context_path = "/api",
@@ -272,6 +575,7 @@ async fn get_place_images(
     request: HttpRequest,
     database: Data<Database>,
     places_client: Data<PlacesClient>,
+    urls: Data<UrlBuilder>,
 ) -> impl Responder {
     let place_id = place_id.into_inner();
     let GetPlaceImagesQuery { offset, limit } = query_params.into_inner();
@@ -313,7 +617,7 @@ async fn get_place_images(
 
         let images = images
             .into_iter()
-            .map(GalleryImage::from)
+            .map(|image| GalleryImage::from_db(image, &urls))
             .collect::<Vec<GalleryImage>>();
 
         return HttpResponse::Ok().json(GetPlaceImagesResponse { images, place_data });
@@ -336,7 +640,7 @@ async fn get_place_images(
 
     let images = images
         .into_iter()
-        .map(GalleryImage::from)
+        .map(|image| GalleryImage::from_db(image, &urls))
         .collect::<Vec<GalleryImage>>();
 
     HttpResponse::Ok().json(GetPlaceImagesResponse { images, place_data })
@@ -364,7 +668,7 @@ pub struct GetMultiplePlacesImagesResponse {
     pub place_data: PlaceDataResponse,
 }
 
-#[tracing::instrument(skip(database, places_client))]
+#[tracing::instrument(skip(database, places_client, urls))]
 #[utoipa::path(
     tag = "images",
     context_path = "/api",
@@ -384,6 +688,7 @@ async fn get_multiple_places_images(
     request: Json<GetMultiplePlacesImagesBody>,
     database: Data<Database>,
     places_client: Data<PlacesClient>,
+    urls: Data<UrlBuilder>,
 ) -> impl Responder {
     let GetMultiplePlacesImagesQuery { offset, limit } = query_params.into_inner();
     let limit = limit.min(MAX_LIMIT);
@@ -444,8 +749,28 @@ async fn get_multiple_places_images(
 
     let images = images
         .into_iter()
-        .map(GalleryImageWithPlace::from)
+        .map(|image| GalleryImageWithPlace::from_db(image, &urls))
         .collect::<Vec<GalleryImageWithPlace>>();
 
     HttpResponse::Ok().json(GetMultiplePlacesImagesResponse { images, place_data })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_legacy_file_names() {
+        let image_id = "0b5b7f3e-5a4e-4f8e-9a57-1f6c1a4f2d3b";
+        assert_eq!(
+            legacy_file_rendition(&format!("{image_id}-photo.png")),
+            Some((image_id, Rendition::Original))
+        );
+        assert_eq!(
+            legacy_file_rendition(&format!("{image_id}-thumbnail-photo.png")),
+            Some((image_id, Rendition::Thumbnail))
+        );
+        assert_eq!(legacy_file_rendition(image_id), None);
+        assert_eq!(legacy_file_rendition("photo.png"), None);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use actix_web::{
+    get, post,
+    web::{Data, Json, Path},
+    HttpResponse, Responder,
+};
+use actix_web_lab::extract::Query;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    api::{auth::AuthUser, GalleryImage, ResponseError},
+    database::{DBNotification, Database},
+    urls::UrlBuilder,
+};
+
+/// Upper bound for the client-supplied `limit` of the inbox.
+const MAX_LIMIT: u64 = 100;
+
+/// Maximum number of notifications marked as read by id at once.
+const MAX_READ_IDS: usize = 100;
+
+/// Tells the user they appear in a photo of someone else.
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    /// Id of the `photo-tagged` event the notification comes from.
+    pub id: String,
+    pub image_id: String,
+    /// Address of the user who took the photo.
+    pub user_address: String,
+    pub read: bool,
+    pub created_at: String,
+    pub image: GalleryImage,
+}
+
+impl Notification {
+    fn new(notification: DBNotification, image: GalleryImage) -> Self {
+        Self {
+            id: notification.id.to_string(),
+            image_id: notification.image_id.to_string(),
+            user_address: notification.actor_address,
+            read: notification.read_at.is_some(),
+            created_at: notification.created_at.and_utc().to_rfc3339(),
+            image,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GetNotificationsResponse {
+    pub notifications: Vec<Notification>,
+    /// Notifications matching the query.
+    pub total: u64,
+    /// Unread notifications of the user.
+    pub unread: u64,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+struct GetNotificationsQuery {
+    /// Only the unread notifications.
+    #[serde(default)]
+    unread: bool,
+    #[serde(default = "default_offset")]
+    offset: u64,
+    #[serde(default = "default_limit")]
+    limit: u64,
+}
+
+fn default_offset() -> u64 {
+    0
+}
+
+fn default_limit() -> u64 {
+    20
+}
+
+#[derive(Deserialize, Debug, Default, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkNotificationsRead {
+    /// Notifications to mark as read, every notification of the user when absent.
+    #[serde(default)]
+    ids: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkNotificationsReadResponse {
+    /// Notifications that were unread.
+    pub updated: u64,
+}
+
+#[tracing::instrument(skip(database, urls))]
+#[utoipa::path(
+    tag = "notifications",
+    context_path = "/api",
+    params(
+        GetNotificationsQuery
+    ),
+    responses(
+        (status = 200, description = "Photos of other users the authenticated user appears in, newest first. Photos the user can no longer see, was untagged from or whose owner the user blocked are left out", body = GetNotificationsResponse),
+        (status = FORBIDDEN, description = "Forbidden"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to get the notifications"),
+    )
+)]
+#[get("/users/{user_address}/notifications")]
+pub async fn get_user_notifications(
+    user: AuthUser,
+    user_address: Path<String>,
+    database: Data<Database>,
+    urls: Data<UrlBuilder>,
+    query: Query<GetNotificationsQuery>,
+) -> impl Responder {
+    let user_address = user_address.into_inner();
+    if !user.address.eq_ignore_ascii_case(&user_address) {
+        return HttpResponse::Forbidden().json(ResponseError::new("forbidden"));
+    }
+
+    let limit = query.limit.clamp(1, MAX_LIMIT);
+    let result = async {
+        let notifications = database
+            .get_user_notifications(&user_address, query.unread, query.offset, limit)
+            .await?;
+        let total = database
+            .get_user_notifications_count(&user_address, query.unread)
+            .await?;
+        let unread = database
+            .get_user_notifications_count(&user_address, true)
+            .await?;
+
+        Ok::<_, sqlx::Error>((notifications, total, unread))
+    };
+    let (notifications, total, unread) = match result.await {
+        Ok(result) => result,
+        Err(error) => {
+            tracing::error!("failed to get notifications: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to get notifications"));
+        }
+    };
+
+    let image_ids = notifications
+        .iter()
+        .map(|notification| notification.image_id)
+        .collect::<Vec<_>>();
+    let mut images = match database.get_images_by_ids(&image_ids).await {
+        Ok(images) => images
+            .into_iter()
+            .map(|image| (image.id, image))
+            .collect::<HashMap<_, _>>(),
+        Err(error) => {
+            tracing::error!("failed to get notification images: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to get notifications"));
+        }
+    };
+
+    // A user is notified once per image. Images deleted since the notifications were listed
+    // are left out
+    let notifications = notifications
+        .into_iter()
+        .filter_map(|notification| {
+            let image = images.remove(&notification.image_id)?;
+            Some(Notification::new(
+                notification,
+                GalleryImage::from_db(image, &urls),
+            ))
+        })
+        .collect();
+
+    HttpResponse::Ok().json(GetNotificationsResponse {
+        notifications,
+        total,
+        unread,
+    })
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "notifications",
+    context_path = "/api",
+    request_body(content = MarkNotificationsRead, description = "Notifications to mark as read, all of them when no ids are given", content_type = "application/json"),
+    responses(
+        (status = 200, description = "Notifications marked as read", body = MarkNotificationsReadResponse),
+        (status = 400, description = "Bad Request", body = ResponseError),
+        (status = FORBIDDEN, description = "Forbidden"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to mark the notifications as read"),
+    )
+)]
+#[post("/users/{user_address}/notifications/read")]
+pub async fn mark_notifications_read(
+    user: AuthUser,
+    user_address: Path<String>,
+    database: Data<Database>,
+    body: Option<Json<MarkNotificationsRead>>,
+) -> impl Responder {
+    let user_address = user_address.into_inner();
+    if !user.address.eq_ignore_ascii_case(&user_address) {
+        return HttpResponse::Forbidden().json(ResponseError::new("forbidden"));
+    }
+
+    let MarkNotificationsRead { ids } = body.map(Json::into_inner).unwrap_or_default();
+    if ids.as_ref().is_some_and(|ids| ids.len() > MAX_READ_IDS) {
+        return HttpResponse::BadRequest().json(ResponseError::new(&format!(
+            "too many ids, maximum is {MAX_READ_IDS}"
+        )));
+    }
+
+    match database
+        .mark_notifications_read(&user_address, ids.as_deref())
+        .await
+    {
+        Ok(updated) => HttpResponse::Ok().json(MarkNotificationsReadResponse { updated }),
+        Err(sqlx::Error::Protocol(_)) => {
+            HttpResponse::BadRequest().json(ResponseError::new("invalid notification id"))
+        }
+        Err(error) => {
+            tracing::error!("failed to mark notifications as read: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to mark notifications as read"))
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use actix_web::{
+    delete, get, post, put,
+    web::{Data, Json, Path},
+    HttpResponse, Responder,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    api::{auth::AuthUser, update::normalize_text, ResponseError},
+    database::{DBUserPreferences, Database},
+    events::{Event, EventPayload, PhotoUntaggedMetadata, SchemaVersion},
+};
+
+/// Maximum length, in characters, of the reason given when flagging an image.
+pub const MAX_FLAG_REASON_LENGTH: usize = 500;
+
+#[derive(Deserialize, Debug, Default, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UntagImage {
+    /// Also flags the image so it gets reviewed by the moderators.
+    #[serde(default)]
+    flag_for_review: bool,
+    /// Why the image is flagged, only used when `flagForReview` is set.
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    request_body(content = UntagImage, description = "Remove the authenticated user from the people visible in the image", content_type = "application/json"),
+    responses(
+        (status = 200, description = "User removed from the image"),
+        (status = 400, description = "Bad Request", body = ResponseError),
+        (status = NOT_FOUND, description = "Image was not found, or the user doesn't appear in it"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to remove the user from the image"),
+    )
+)]
+#[post("/images/{id}/untag")]
+pub async fn untag_image(
+    user_address: AuthUser,
+    image_id: Path<String>,
+    database: Data<Database>,
+    untag: Option<Json<UntagImage>>,
+) -> impl Responder {
+    let image_id = image_id.into_inner();
+
+    let AuthUser {
+        address: request_user_address,
+    } = user_address;
+
+    let UntagImage {
+        flag_for_review,
+        reason,
+    } = untag.map(Json::into_inner).unwrap_or_default();
+
+    let reason = normalize_text(reason.as_deref()).unwrap_or_default();
+    if reason.chars().count() > MAX_FLAG_REASON_LENGTH {
+        return HttpResponse::BadRequest().json(ResponseError::new(&format!(
+            "reason is too long, maximum is {MAX_FLAG_REASON_LENGTH} characters"
+        )));
+    }
+
+    let image = match database.get_image(&image_id).await {
+        Ok(image) => image,
+        Err(_) => return HttpResponse::NotFound().json(ResponseError::new("image not found")),
+    };
+
+    // Queued with the removal, so the consumers of photo-taken stop listing the user in the photo
+    let sns_event = Event::new(
+        &image_id,
+        EventPayload::PhotoUntagged(PhotoUntaggedMetadata {
+            schema_version: SchemaVersion,
+            photo_id: image_id.clone(),
+            user_address: image.user_address.to_lowercase(),
+            untagged_address: request_user_address.to_lowercase(),
+            is_public: image.visibility.is_public(),
+            visibility: image.visibility,
+            flagged_for_review: flag_for_review,
+        }),
+    );
+
+    let flag_reason = flag_for_review.then_some(reason.as_str());
+    match database
+        .remove_image_person(&image_id, &request_user_address, flag_reason, &sns_event)
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return HttpResponse::NotFound()
+                .json(ResponseError::new("user doesn't appear in the image"))
+        }
+        Err(error) => {
+            tracing::error!("failed to remove user from image: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to remove user from image"));
+        }
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserPreferences {
+    /// When `false`, the user is removed from the visible people of every new upload.
+    pub allow_tagging: bool,
+}
+
+impl Default for UserPreferences {
+    fn default() -> Self {
+        Self {
+            allow_tagging: true,
+        }
+    }
+}
+
+impl From<DBUserPreferences> for UserPreferences {
+    fn from(value: DBUserPreferences) -> Self {
+        Self {
+            allow_tagging: value.allow_tagging,
+        }
+    }
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    responses(
+        (status = 200, description = "Get the preferences of the authenticated user", body = UserPreferences),
+        (status = FORBIDDEN, description = "Forbidden"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to get the preferences"),
+    )
+)]
+#[get("/users/{user_address}/preferences")]
+pub async fn get_user_preferences(
+    user: AuthUser,
+    user_address: Path<String>,
+    database: Data<Database>,
+) -> impl Responder {
+    let user_address = user_address.into_inner();
+    if !user.address.eq_ignore_ascii_case(&user_address) {
+        return HttpResponse::Forbidden().json(ResponseError::new("forbidden"));
+    }
+
+    match database.get_user_preferences(&user_address).await {
+        Ok(preferences) => {
+            HttpResponse::Ok().json(preferences.map(UserPreferences::from).unwrap_or_default())
+        }
+        Err(error) => {
+            tracing::error!("failed to get user preferences: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to get user preferences"))
+        }
+    }
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    request_body(content = UserPreferences, description = "Update the preferences of the authenticated user", content_type = "application/json"),
+    responses(
+        (status = 200, description = "Updated preferences", body = UserPreferences),
+        (status = FORBIDDEN, description = "Forbidden"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to update the preferences"),
+    )
+)]
+#[put("/users/{user_address}/preferences")]
+pub async fn update_user_preferences(
+    user: AuthUser,
+    user_address: Path<String>,
+    database: Data<Database>,
+    preferences: Json<UserPreferences>,
+) -> impl Responder {
+    let user_address = user_address.into_inner();
+    if !user.address.eq_ignore_ascii_case(&user_address) {
+        return HttpResponse::Forbidden().json(ResponseError::new("forbidden"));
+    }
+
+    match database
+        .upsert_user_preferences(&user_address, preferences.allow_tagging)
+        .await
+    {
+        Ok(preferences) => HttpResponse::Ok().json(UserPreferences::from(preferences)),
+        Err(error) => {
+            tracing::error!("failed to update user preferences: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to update user preferences"))
+        }
+    }
+}
+
+/// Maximum number of addresses a user can block.
+const MAX_BLOCKED_USERS: u64 = 1000;
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GetBlockedUsersResponse {
+    /// Most recently blocked first.
+    pub blocked_addresses: Vec<String>,
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "notifications",
+    context_path = "/api",
+    responses(
+        (status = 200, description = "Addresses blocked by the authenticated user, whose photos don't notify the user", body = GetBlockedUsersResponse),
+        (status = FORBIDDEN, description = "Forbidden"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to get the blocked users"),
+    )
+)]
+#[get("/users/{user_address}/blocks")]
+pub async fn get_blocked_users(
+    user: AuthUser,
+    user_address: Path<String>,
+    database: Data<Database>,
+) -> impl Responder {
+    let user_address = user_address.into_inner();
+    if !user.address.eq_ignore_ascii_case(&user_address) {
+        return HttpResponse::Forbidden().json(ResponseError::new("forbidden"));
+    }
+
+    match database.get_user_blocks(&user_address).await {
+        Ok(blocked_addresses) => {
+            HttpResponse::Ok().json(GetBlockedUsersResponse { blocked_addresses })
+        }
+        Err(error) => {
+            tracing::error!("failed to get blocked users: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to get blocked users"))
+        }
+    }
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "notifications",
+    context_path = "/api",
+    responses(
+        (status = 204, description = "Address blocked, its new photos no longer notify the authenticated user and its past ones leave the inbox"),
+        (status = 400, description = "Bad Request", body = ResponseError),
+        (status = FORBIDDEN, description = "Forbidden"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to block the user"),
+    )
+)]
+#[put("/users/{user_address}/blocks/{blocked_address}")]
+pub async fn block_user(
+    user: AuthUser,
+    path: Path<(String, String)>,
+    database: Data<Database>,
+) -> impl Responder {
+    let (user_address, blocked_address) = path.into_inner();
+    if !user.address.eq_ignore_ascii_case(&user_address) {
+        return HttpResponse::Forbidden().json(ResponseError::new("forbidden"));
+    }
+    if blocked_address.eq_ignore_ascii_case(&user_address) {
+        return HttpResponse::BadRequest().json(ResponseError::new("users can't block themselves"));
+    }
+
+    match database.get_user_blocks_count(&user_address).await {
+        Ok(count) if count >= MAX_BLOCKED_USERS => {
+            return HttpResponse::BadRequest().json(ResponseError::new(&format!(
+                "too many blocked users, maximum is {MAX_BLOCKED_USERS}"
+            )))
+        }
+        Ok(_) => {}
+        Err(error) => {
+            tracing::error!("failed to count blocked users: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to block user"));
+        }
+    }
+
+    match database
+        .insert_user_block(&user_address, &blocked_address)
+        .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(error) => {
+            tracing::error!("failed to block user: {}", error);
+            HttpResponse::InternalServerError().json(ResponseError::new("failed to block user"))
+        }
+    }
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "notifications",
+    context_path = "/api",
+    responses(
+        (status = 204, description = "Address unblocked"),
+        (status = FORBIDDEN, description = "Forbidden"),
+        (status = NOT_FOUND, description = "Address wasn't blocked"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to unblock the user"),
+    )
+)]
+#[delete("/users/{user_address}/blocks/{blocked_address}")]
+pub async fn unblock_user(
+    user: AuthUser,
+    path: Path<(String, String)>,
+    database: Data<Database>,
+) -> impl Responder {
+    let (user_address, blocked_address) = path.into_inner();
+    if !user.address.eq_ignore_ascii_case(&user_address) {
+        return HttpResponse::Forbidden().json(ResponseError::new("forbidden"));
+    }
+
+    match database
+        .delete_user_block(&user_address, &blocked_address)
+        .await
+    {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().json(ResponseError::new("user isn't blocked")),
+        Err(error) => {
+            tracing::error!("failed to unblock user: {}", error);
+            HttpResponse::InternalServerError().json(ResponseError::new("failed to unblock user"))
+        }
+    }
+}
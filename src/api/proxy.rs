@@ -0,0 +1,302 @@
+use std::str::FromStr;
+
+use crate::{
+    api::ResponseError,
+    image_cache::{CachedImage, ImageCache},
+    storage::{ObjectStorage, Rendition, StorageError},
+};
+use actix_web::{
+    http::{
+        header::{
+            self, ByteRangeSpec, ContentDisposition, DispositionParam, DispositionType, Range,
+        },
+        StatusCode,
+    },
+    web::Bytes,
+    HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+
+/// How the file of an image is sent back to the client.
+pub struct ProxiedFile<'a> {
+    /// Key of the object in the storage.
+    pub key: &'a str,
+    /// Name the file is served with.
+    pub file_name: &'a str,
+    /// Which file of an image it is, `None` for files served by their key alone.
+    pub rendition: Option<Rendition>,
+    pub cache_control: &'a str,
+    /// Whether the browser should save the file instead of displaying it.
+    pub download: bool,
+}
+
+/// Whether the `If-None-Match` header matches the current entity tag of the file.
+fn matches_e_tag(request: &HttpRequest, e_tag: Option<&str>) -> bool {
+    let (Some(if_none_match), Some(e_tag)) = (
+        request
+            .headers()
+            .get(header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok()),
+        e_tag,
+    ) else {
+        return false;
+    };
+
+    if_none_match.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.trim_start_matches("W/") == e_tag.trim_start_matches("W/")
+    })
+}
+
+/// Returns the requested byte range, inclusive, when the client asked for a single range.
+/// `If-Range` requests only get a range while the file didn't change, and multiple ranges are
+/// answered with the whole file, as allowed by RFC 9110.
+fn requested_range(
+    request: &HttpRequest,
+    e_tag: Option<&str>,
+    length: u64,
+) -> Option<Result<(u64, u64), ()>> {
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())?;
+
+    if let Some(if_range) = request
+        .headers()
+        .get(header::IF_RANGE)
+        .and_then(|value| value.to_str().ok())
+    {
+        if e_tag != Some(if_range) {
+            return None;
+        }
+    }
+
+    match Range::from_str(range) {
+        Ok(Range::Bytes(ranges)) if ranges.len() == 1 => Some(
+            ranges
+                .first()
+                .and_then(|range: &ByteRangeSpec| range.to_satisfiable_range(length))
+                .ok_or(()),
+        ),
+        _ => None,
+    }
+}
+
+fn response_builder(
+    status: StatusCode,
+    file: &ProxiedFile,
+    e_tag: Option<&str>,
+) -> HttpResponseBuilder {
+    let mut response = HttpResponse::build(status);
+    response
+        .insert_header((header::CACHE_CONTROL, file.cache_control))
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header(ContentDisposition {
+            disposition: if file.download {
+                DispositionType::Attachment
+            } else {
+                DispositionType::Inline
+            },
+            parameters: vec![DispositionParam::Filename(file.file_name.to_string())],
+        });
+    if let Some(e_tag) = e_tag {
+        response.insert_header((header::ETAG, e_tag));
+    }
+    response
+}
+
+fn not_modified(file: &ProxiedFile, e_tag: Option<&str>) -> HttpResponse {
+    response_builder(StatusCode::NOT_MODIFIED, file, e_tag).finish()
+}
+
+fn range_not_satisfiable(file: &ProxiedFile, e_tag: Option<&str>, length: u64) -> HttpResponse {
+    response_builder(StatusCode::RANGE_NOT_SATISFIABLE, file, e_tag)
+        .insert_header((header::CONTENT_RANGE, format!("bytes */{length}")))
+        .finish()
+}
+
+fn partial_content(
+    file: &ProxiedFile,
+    e_tag: Option<&str>,
+    content_type: Option<&str>,
+    (start, end): (u64, u64),
+    length: u64,
+    bytes: Bytes,
+) -> HttpResponse {
+    let mut response = response_builder(StatusCode::PARTIAL_CONTENT, file, e_tag);
+    response.insert_header((
+        header::CONTENT_RANGE,
+        format!("bytes {start}-{end}/{length}"),
+    ));
+    if let Some(content_type) = content_type {
+        response.content_type(content_type);
+    }
+    response.body(bytes)
+}
+
+fn storage_error(error: StorageError) -> HttpResponse {
+    match error {
+        StorageError::NotFound => {
+            HttpResponse::NotFound().json(ResponseError::new("image not found"))
+        }
+        error => {
+            tracing::error!("failed to get image from the storage: {}", error);
+            HttpResponse::InternalServerError().json(ResponseError::new("failed to get image"))
+        }
+    }
+}
+
+/// Serves a file that is already in memory.
+fn serve_bytes(request: &HttpRequest, file: &ProxiedFile, image: CachedImage) -> HttpResponse {
+    let e_tag = image.e_tag.as_deref();
+    let content_type = image.content_type.as_deref();
+
+    if matches_e_tag(request, e_tag) {
+        return not_modified(file, e_tag);
+    }
+
+    let length = image.bytes.len() as u64;
+    match requested_range(request, e_tag, length) {
+        Some(Ok((start, end))) => partial_content(
+            file,
+            e_tag,
+            content_type,
+            (start, end),
+            length,
+            image.bytes.slice(start as usize..=end as usize),
+        ),
+        Some(Err(())) => range_not_satisfiable(file, e_tag, length),
+        None => {
+            let mut response = response_builder(StatusCode::OK, file, e_tag);
+            if let Some(content_type) = content_type {
+                response.content_type(content_type);
+            }
+            response.body(image.bytes)
+        }
+    }
+}
+
+async fn fetch_cached_image(
+    storage: &dyn ObjectStorage,
+    image_cache: &ImageCache,
+    key: &str,
+) -> Result<CachedImage, StorageError> {
+    if let Some(image) = image_cache.get(key).await {
+        return Ok(image);
+    }
+
+    let object = storage.get(key).await?;
+    let image = CachedImage {
+        e_tag: object.e_tag,
+        content_type: object.content_type,
+        bytes: object.bytes,
+    };
+    image_cache.insert(key.to_string(), image.clone()).await;
+
+    Ok(image)
+}
+
+/// Streams a file from the storage, honoring conditional and range requests. Thumbnails are
+/// served from the in-process cache.
+pub async fn proxy_file(
+    request: &HttpRequest,
+    storage: &dyn ObjectStorage,
+    image_cache: &ImageCache,
+    file: ProxiedFile<'_>,
+) -> HttpResponse {
+    // Thumbnails are small and requested by every gallery, so they are worth caching
+    if file.rendition == Some(Rendition::Thumbnail) {
+        return match fetch_cached_image(storage, image_cache, file.key).await {
+            Ok(image) => serve_bytes(request, &file, image),
+            Err(error) => storage_error(error),
+        };
+    }
+
+    let head = match storage.head(file.key).await {
+        Ok(head) => head,
+        Err(error) => return storage_error(error),
+    };
+    let e_tag = head.e_tag.as_deref();
+    let content_type = head.content_type.as_deref();
+    let length = head.content_length;
+
+    if matches_e_tag(request, e_tag) {
+        return not_modified(&file, e_tag);
+    }
+
+    match requested_range(request, e_tag, length) {
+        Some(Ok((start, end))) => match storage.get_range(file.key, start, end).await {
+            Ok(bytes) => partial_content(&file, e_tag, content_type, (start, end), length, bytes),
+            Err(error) => storage_error(error),
+        },
+        Some(Err(())) => range_not_satisfiable(&file, e_tag, length),
+        None => {
+            let stream = match storage.get_stream(file.key).await {
+                Ok(stream) => stream,
+                Err(error) => return storage_error(error),
+            };
+            let mut response = response_builder(StatusCode::OK, &file, e_tag);
+            if let Some(content_type) = content_type {
+                response.content_type(content_type);
+            }
+            response.no_chunking(length).streaming(stream)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn test_matches_e_tags() {
+        let e_tag = Some("\"abc\"");
+        let request = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, "\"xyz\", W/\"abc\""))
+            .to_http_request();
+        assert!(matches_e_tag(&request, e_tag));
+
+        let request = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, "\"xyz\""))
+            .to_http_request();
+        assert!(!matches_e_tag(&request, e_tag));
+        assert!(!matches_e_tag(
+            &TestRequest::default().to_http_request(),
+            e_tag
+        ));
+    }
+
+    #[test]
+    fn test_parses_single_ranges() {
+        let e_tag = Some("\"abc\"");
+        let range = |value: &str| {
+            TestRequest::default()
+                .insert_header((header::RANGE, value))
+                .to_http_request()
+        };
+
+        assert_eq!(
+            requested_range(&range("bytes=0-9"), e_tag, 100),
+            Some(Ok((0, 9)))
+        );
+        assert_eq!(
+            requested_range(&range("bytes=-10"), e_tag, 100),
+            Some(Ok((90, 99)))
+        );
+        assert_eq!(
+            requested_range(&range("bytes=200-"), e_tag, 100),
+            Some(Err(()))
+        );
+        assert_eq!(requested_range(&range("bytes=0-1,5-6"), e_tag, 100), None);
+        assert_eq!(
+            requested_range(&TestRequest::default().to_http_request(), e_tag, 100),
+            None
+        );
+
+        let request = TestRequest::default()
+            .insert_header((header::RANGE, "bytes=0-9"))
+            .insert_header((header::IF_RANGE, "\"old\""))
+            .to_http_request();
+        assert_eq!(requested_range(&request, e_tag, 100), None);
+    }
+}
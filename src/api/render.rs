@@ -0,0 +1,495 @@
+use std::io::Cursor;
+
+use actix_web::{
+    get,
+    http::header,
+    web::{self, Data, Path},
+    HttpRequest, HttpResponse, Responder,
+};
+use actix_web_lab::extract::Query;
+use hmac::{Hmac, Mac};
+use image::{imageops::FilterType, DynamicImage, GenericImageView, ImageOutputFormat};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    api::{
+        get::{can_view_image, PRIVATE_CACHE_CONTROL, PUBLIC_CACHE_CONTROL},
+        restore::archived_original_response,
+        ResponseError,
+    },
+    database::Database,
+    storage::{object_key, render_key, ObjectStorage, Rendition, StorageError},
+    Settings,
+};
+
+/// Largest width or height a variant can be rendered at.
+const MAX_DIMENSION: u32 = 4096;
+
+/// Quality of the rendered JPEG variants.
+const JPEG_QUALITY: u8 = 85;
+
+/// How the image is fitted into the requested size.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Scaled down to fit inside the size, keeping its aspect ratio.
+    #[default]
+    Contain,
+    /// Scaled and cropped to fill the whole size.
+    Cover,
+    /// Stretched to the exact size.
+    Fill,
+}
+
+impl std::fmt::Display for Fit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fit::Contain => write!(f, "contain"),
+            Fit::Cover => write!(f, "cover"),
+            Fit::Fill => write!(f, "fill"),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderFormat {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl RenderFormat {
+    /// Format of an uploaded file, from its name.
+    fn from_file_name(file_name: &str) -> Self {
+        let file_name = file_name.to_lowercase();
+        if file_name.ends_with(".jpg") || file_name.ends_with(".jpeg") {
+            RenderFormat::Jpeg
+        } else {
+            RenderFormat::Png
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            RenderFormat::Png => "png",
+            RenderFormat::Jpeg => "jpg",
+            RenderFormat::Webp => "webp",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            RenderFormat::Png => "image/png",
+            RenderFormat::Jpeg => "image/jpeg",
+            RenderFormat::Webp => "image/webp",
+        }
+    }
+}
+
+impl std::fmt::Display for RenderFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderFormat::Png => write!(f, "png"),
+            RenderFormat::Jpeg => write!(f, "jpeg"),
+            RenderFormat::Webp => write!(f, "webp"),
+        }
+    }
+}
+
+/// Parameters of a rendered variant.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RenderParams {
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    pub fit: Option<Fit>,
+    pub format: Option<RenderFormat>,
+}
+
+impl RenderParams {
+    fn validate(&self) -> Result<(), String> {
+        if self.w.is_none() && self.h.is_none() {
+            return Err("missing width or height".to_string());
+        }
+
+        let valid = |dimension: Option<u32>| {
+            dimension.is_none_or(|dimension| (1..=MAX_DIMENSION).contains(&dimension))
+        };
+        if !valid(self.w) || !valid(self.h) {
+            return Err(format!(
+                "width and height must be between 1 and {MAX_DIMENSION}"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Text covered by the signature, every parameter in a fixed order.
+    fn signing_payload(&self, image_id: &str) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        format!(
+            "{image_id}:{}:{}:{}:{}",
+            optional(self.w.map(|w| w.to_string())),
+            optional(self.h.map(|h| h.to_string())),
+            self.fit.unwrap_or_default(),
+            optional(self.format.map(|format| format.to_string())),
+        )
+    }
+
+    /// Name of the variant in the bucket, the same for every equivalent set of parameters.
+    fn variant_name(&self, format: RenderFormat) -> String {
+        let dimension = |dimension: Option<u32>| {
+            dimension.map_or("auto".to_string(), |dimension| dimension.to_string())
+        };
+        format!(
+            "{}x{}-{}.{}",
+            dimension(self.w),
+            dimension(self.h),
+            self.fit.unwrap_or_default(),
+            format.extension()
+        )
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+struct RenderQuery {
+    /// Width in pixels, computed from the height when absent.
+    w: Option<u32>,
+    /// Height in pixels, computed from the width when absent.
+    h: Option<u32>,
+    /// `contain` (default), `cover` or `fill`.
+    fit: Option<Fit>,
+    /// `png`, `jpeg` or `webp`. Defaults to the format of the original image.
+    format: Option<RenderFormat>,
+    /// Hex HMAC-SHA256 of the parameters, see `sign_render_params`.
+    sig: Option<String>,
+}
+
+fn signer(key: &str, payload: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Signs the render parameters of an image. Only the services that know the signing key can
+/// build render URLs, so clients can't make the service render unlimited variants.
+pub fn sign_render_params(key: &str, image_id: &str, params: &RenderParams) -> String {
+    hex::encode(
+        signer(key, &params.signing_payload(image_id))
+            .finalize()
+            .into_bytes(),
+    )
+}
+
+fn verify_render_params(key: &str, image_id: &str, params: &RenderParams, sig: &str) -> bool {
+    let Ok(sig) = hex::decode(sig) else {
+        return false;
+    };
+    signer(key, &params.signing_payload(image_id))
+        .verify_slice(&sig)
+        .is_ok()
+}
+
+/// Shrinks a size that doesn't fit in the image, keeping its aspect ratio.
+fn fit_within(w: u32, h: u32, width: u32, height: u32) -> (u32, u32) {
+    let scale = (width as f64 / w as f64)
+        .min(height as f64 / h as f64)
+        .min(1.0);
+
+    (
+        ((w as f64 * scale).round() as u32).max(1),
+        ((h as f64 * scale).round() as u32).max(1),
+    )
+}
+
+/// Resizes, crops and converts an image. Images are never scaled up to fit a size: covered
+/// and filled sizes larger than the image are shrunk to fit in it, keeping their aspect ratio.
+fn render(bytes: &[u8], params: RenderParams, format: RenderFormat) -> image::ImageResult<Vec<u8>> {
+    let image = image::load_from_memory(bytes)?;
+    let (width, height) = image.dimensions();
+
+    let rendered = match (params.w, params.h, params.fit.unwrap_or_default()) {
+        (Some(w), Some(h), Fit::Cover) => {
+            let (w, h) = fit_within(w, h, width, height);
+            image.resize_to_fill(w, h, FilterType::CatmullRom)
+        }
+        (Some(w), Some(h), Fit::Fill) => {
+            let (w, h) = fit_within(w, h, width, height);
+            image.resize_exact(w, h, FilterType::CatmullRom)
+        }
+        // A missing dimension keeps the aspect ratio of the original, whatever the fit
+        (w, h, _) => image.resize(
+            w.unwrap_or(u32::MAX).min(width),
+            h.unwrap_or(u32::MAX).min(height),
+            FilterType::CatmullRom,
+        ),
+    };
+
+    let mut buffer = Cursor::new(vec![]);
+    match format {
+        RenderFormat::Png => rendered.write_to(&mut buffer, ImageOutputFormat::Png)?,
+        // JPEG has no alpha channel
+        RenderFormat::Jpeg => DynamicImage::ImageRgb8(rendered.to_rgb8())
+            .write_to(&mut buffer, ImageOutputFormat::Jpeg(JPEG_QUALITY))?,
+        RenderFormat::Webp => DynamicImage::ImageRgba8(rendered.to_rgba8())
+            .write_to(&mut buffer, ImageOutputFormat::WebP)?,
+    }
+
+    Ok(buffer.into_inner())
+}
+
+#[tracing::instrument(skip(settings, storage, database, request))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    params(
+        RenderQuery
+    ),
+    responses(
+        (status = 200, description = "The image resized, cropped or converted. Variants are cached in the bucket"),
+        (status = 202, description = "The original is archived, its restore was queued. Retry after the `Retry-After` seconds", body = ImageRestore),
+        (status = 400, description = "Bad Request", body = ResponseError),
+        (status = 403, description = "Missing or invalid signature", body = ResponseError),
+        (status = 404, description = "Not found"),
+        (status = 500, description = "Internal Server Error", body = ResponseError)
+    )
+)]
+#[get("/images/{image_id}/render")]
+pub async fn render_image(
+    image_id: Path<String>,
+    query_params: Query<RenderQuery>,
+    settings: Data<Settings>,
+    storage: Data<dyn ObjectStorage>,
+    database: Data<Database>,
+    request: HttpRequest,
+) -> impl Responder {
+    let image_id = image_id.into_inner();
+    let RenderQuery {
+        w,
+        h,
+        fit,
+        format,
+        sig,
+    } = query_params.into_inner();
+    let params = RenderParams { w, h, fit, format };
+
+    if let Err(message) = params.validate() {
+        return HttpResponse::BadRequest().json(ResponseError::new(&message));
+    }
+
+    if !sig.is_some_and(|sig| {
+        verify_render_params(&settings.render_signing_key, &image_id, &params, &sig)
+    }) {
+        return HttpResponse::Forbidden().json(ResponseError::new("invalid signature"));
+    }
+
+    let image = match database.get_image(&image_id).await {
+        Ok(image) => image,
+        Err(_) => return HttpResponse::NotFound().json(ResponseError::new("image not found")),
+    };
+
+    // Hidden images look like missing ones so their existence isn't leaked
+    if !can_view_image(&database, &image, &request).await {
+        return HttpResponse::NotFound().json(ResponseError::new("image not found"));
+    }
+
+    let format = params
+        .format
+        .unwrap_or_else(|| RenderFormat::from_file_name(&image.image_key));
+    let key = render_key(&image_id, &params.variant_name(format), image.visibility);
+    let cache_control = if image.visibility.is_restricted() {
+        PRIVATE_CACHE_CONTROL
+    } else {
+        PUBLIC_CACHE_CONTROL
+    };
+
+    let rendered = match storage.get(&key).await {
+        Ok(cached) => cached.bytes.to_vec(),
+        Err(error) => {
+            if !matches!(error, StorageError::NotFound) {
+                tracing::error!("failed to get cached render: {}", error);
+            }
+
+            // Variants rendered before the original was archived are still served
+            if image.is_archived(Rendition::Original) {
+                return archived_original_response(&database, &settings, &image).await;
+            }
+
+            let source = match storage
+                .get(&object_key(&image.image_key, image.visibility))
+                .await
+            {
+                Ok(source) => source.bytes,
+                Err(error) => {
+                    tracing::error!("failed to get image from the storage: {}", error);
+                    return HttpResponse::InternalServerError()
+                        .json(ResponseError::new("failed to render image"));
+                }
+            };
+
+            // Decoding and resizing are CPU bound, so they don't run on the request thread
+            let rendered = match web::block(move || render(&source, params, format)).await {
+                Ok(Ok(rendered)) => rendered,
+                Ok(Err(error)) => {
+                    tracing::error!("failed to render image: {}", error);
+                    return HttpResponse::InternalServerError()
+                        .json(ResponseError::new("failed to render image"));
+                }
+                Err(error) => {
+                    tracing::error!("failed to render image: {}", error);
+                    return HttpResponse::InternalServerError()
+                        .json(ResponseError::new("failed to render image"));
+                }
+            };
+
+            if let Err(error) = storage.put(&key, &rendered, format.content_type()).await {
+                tracing::error!("failed to cache render: {}", error);
+            }
+
+            rendered
+        }
+    };
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((header::CACHE_CONTROL, cache_control))
+        .body(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE_ID: &str = "f5f7b3c5-7f43-4a8e-9b3b-0c9b6a1b2c3d";
+
+    #[test]
+    fn test_verifies_signed_params() {
+        let params = RenderParams {
+            w: Some(320),
+            h: Some(180),
+            fit: Some(Fit::Cover),
+            format: Some(RenderFormat::Webp),
+        };
+        let sig = sign_render_params("key", IMAGE_ID, &params);
+
+        assert!(verify_render_params("key", IMAGE_ID, &params, &sig));
+        assert!(!verify_render_params("other", IMAGE_ID, &params, &sig));
+        assert!(!verify_render_params(
+            "key",
+            IMAGE_ID,
+            &RenderParams {
+                w: Some(4096),
+                ..params
+            },
+            &sig
+        ));
+        assert!(!verify_render_params("key", IMAGE_ID, &params, "not-hex"));
+    }
+
+    #[test]
+    fn test_names_variants_deterministically() {
+        let params = RenderParams {
+            w: Some(320),
+            ..Default::default()
+        };
+        assert_eq!(
+            params.variant_name(RenderFormat::Jpeg),
+            "320xauto-contain.jpg"
+        );
+        assert_eq!(
+            RenderParams {
+                fit: Some(Fit::Contain),
+                ..params
+            }
+            .variant_name(RenderFormat::Jpeg),
+            "320xauto-contain.jpg"
+        );
+    }
+
+    #[test]
+    fn test_validates_dimensions() {
+        assert!(RenderParams::default().validate().is_err());
+        assert!(RenderParams {
+            w: Some(0),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+        assert!(RenderParams {
+            h: Some(MAX_DIMENSION + 1),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+        assert!(RenderParams {
+            w: Some(640),
+            h: Some(MAX_DIMENSION),
+            ..Default::default()
+        }
+        .validate()
+        .is_ok());
+    }
+
+    #[test]
+    fn test_renders_variants() {
+        let source = include_bytes!("../../tests/resources/image.png");
+        let (width, height) = image::load_from_memory(source).unwrap().dimensions();
+
+        let rendered = render(
+            source,
+            RenderParams {
+                w: Some(50),
+                h: Some(50),
+                fit: Some(Fit::Cover),
+                ..Default::default()
+            },
+            RenderFormat::Jpeg,
+        )
+        .unwrap();
+        let rendered = image::load_from_memory(&rendered).unwrap();
+        assert_eq!(rendered.dimensions(), (50, 50));
+
+        // Contained images are never scaled up
+        let rendered = render(
+            source,
+            RenderParams {
+                w: Some(width * 2),
+                ..Default::default()
+            },
+            RenderFormat::Webp,
+        )
+        .unwrap();
+        let rendered = image::load_from_memory(&rendered).unwrap();
+        assert_eq!(rendered.dimensions(), (width, height));
+    }
+
+    #[test]
+    fn test_never_scales_up_covered_and_filled_images() {
+        let source = include_bytes!("../../tests/resources/image.png");
+        let (width, height) = image::load_from_memory(source).unwrap().dimensions();
+
+        for fit in [Fit::Cover, Fit::Fill] {
+            let rendered = render(
+                source,
+                RenderParams {
+                    w: Some(width * 4),
+                    h: Some(width * 2),
+                    fit: Some(fit),
+                    ..Default::default()
+                },
+                RenderFormat::Png,
+            )
+            .unwrap();
+            let (rendered_width, rendered_height) =
+                image::load_from_memory(&rendered).unwrap().dimensions();
+
+            // Shrunk to fit in the original, with the requested aspect ratio
+            assert!(rendered_width <= width && rendered_height <= height);
+            assert!(rendered_width == width || rendered_height == height);
+            assert_eq!(rendered_width, rendered_height * 2);
+        }
+    }
+}
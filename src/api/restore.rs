@@ -0,0 +1,145 @@
+use actix_web::{
+    get,
+    http::header,
+    post,
+    web::{Data, Path},
+    HttpRequest, HttpResponse, Responder,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    api::{get::can_view_image, ResponseError},
+    database::{DBImage, DBImageRestore, Database},
+    Settings,
+};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "restore_status", rename_all = "lowercase")]
+pub enum RestoreStatus {
+    /// Queued, the original is moved back by the next run of the archival worker.
+    Pending,
+    Completed,
+    Failed,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageRestore {
+    pub image_id: String,
+    pub status: RestoreStatus,
+    pub error: Option<String>,
+    pub requested_at: String,
+    pub completed_at: Option<String>,
+    /// Seconds to wait before checking again, while the restore is pending.
+    pub retry_after: Option<u64>,
+}
+
+impl ImageRestore {
+    fn new(restore: DBImageRestore, settings: &Settings) -> Self {
+        Self {
+            image_id: restore.image_id.to_string(),
+            retry_after: (restore.status == RestoreStatus::Pending)
+                .then_some(settings.archive.interval_seconds),
+            status: restore.status,
+            error: restore.error,
+            requested_at: restore.requested_at.and_utc().to_rfc3339(),
+            completed_at: restore
+                .completed_at
+                .map(|completed_at| completed_at.and_utc().to_rfc3339()),
+        }
+    }
+}
+
+/// Answers the requests for an archived original, queueing its restore so retrying later
+/// eventually gets it.
+pub async fn archived_original_response(
+    database: &Database,
+    settings: &Settings,
+    image: &DBImage,
+) -> HttpResponse {
+    match database.request_image_restore(&image.id.to_string()).await {
+        Ok(restore) => HttpResponse::Accepted()
+            .insert_header((header::RETRY_AFTER, settings.archive.interval_seconds))
+            .insert_header((header::CACHE_CONTROL, "no-store"))
+            .json(ImageRestore::new(restore, settings)),
+        Err(error) => {
+            tracing::error!("failed to request image restore: {}", error);
+            HttpResponse::InternalServerError().json(ResponseError::new("failed to get image"))
+        }
+    }
+}
+
+async fn get_viewable_image(
+    database: &Database,
+    image_id: &str,
+    request: &HttpRequest,
+) -> Result<DBImage, HttpResponse> {
+    // Hidden images look like missing ones so their existence isn't leaked
+    match database.get_image(image_id).await {
+        Ok(image) if can_view_image(database, &image, request).await => Ok(image),
+        _ => Err(HttpResponse::NotFound().json(ResponseError::new("image not found"))),
+    }
+}
+
+#[tracing::instrument(skip(settings, database, request))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    responses(
+        (status = 202, description = "The restore of the archived original was queued", body = ImageRestore),
+        (status = 404, description = "Not found"),
+        (status = 409, description = "The original isn't archived", body = ResponseError),
+        (status = 500, description = "Failed to queue the restore")
+    )
+)]
+#[post("/images/{image_id}/restore")]
+pub async fn restore_image(
+    settings: Data<Settings>,
+    database: Data<Database>,
+    image_id: Path<String>,
+    request: HttpRequest,
+) -> impl Responder {
+    let image = match get_viewable_image(&database, &image_id, &request).await {
+        Ok(image) => image,
+        Err(response) => return response,
+    };
+
+    if image.archived_at.is_none() {
+        return HttpResponse::Conflict().json(ResponseError::new("image isn't archived"));
+    }
+
+    archived_original_response(&database, &settings, &image).await
+}
+
+#[tracing::instrument(skip(settings, database, request))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    responses(
+        (status = 200, description = "Status of the last restore of the original", body = ImageRestore),
+        (status = 404, description = "Not found, or the original was never restored")
+    )
+)]
+#[get("/images/{image_id}/restore")]
+pub async fn get_image_restore(
+    settings: Data<Settings>,
+    database: Data<Database>,
+    image_id: Path<String>,
+    request: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = get_viewable_image(&database, &image_id, &request).await {
+        return response;
+    }
+
+    match database.get_image_restore(&image_id).await {
+        Ok(Some(restore)) => HttpResponse::Ok().json(ImageRestore::new(restore, &settings)),
+        Ok(None) => HttpResponse::NotFound().json(ResponseError::new("restore not found")),
+        Err(error) => {
+            tracing::error!("failed to get image restore: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to get image restore"))
+        }
+    }
+}
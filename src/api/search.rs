@@ -0,0 +1,157 @@
+use actix_web::{get, web::Data, HttpResponse, Responder};
+use actix_web_lab::extract::Query;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::NaiveDate;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    api::{GalleryImageWithPlace, ResponseError},
+    database::{Database, SearchFilter},
+    urls::UrlBuilder,
+};
+
+/// Maximum length, in characters, of the search terms.
+const MAX_QUERY_LENGTH: usize = 200;
+
+/// Upper bound for the client-supplied pagination `limit`.
+const MAX_LIMIT: u64 = 100;
+
+#[derive(Deserialize, Debug, IntoParams)]
+struct SearchImagesQuery {
+    /// Search terms. Supports quoted phrases, `or` and `-` to exclude words.
+    q: String,
+    /// Only images taken in this realm.
+    realm: Option<String>,
+    /// Only images uploaded on or after this date, as `YYYY-MM-DD`.
+    from: Option<String>,
+    /// Only images uploaded on or before this date, as `YYYY-MM-DD`.
+    to: Option<String>,
+    #[serde(default = "default_offset")]
+    offset: u64,
+    #[serde(default = "default_limit")]
+    limit: u64,
+}
+
+fn default_offset() -> u64 {
+    0
+}
+
+fn default_limit() -> u64 {
+    20
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchDataResponse {
+    pub max_images: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchImagesResponse {
+    pub images: Vec<GalleryImageWithPlace>,
+    #[serde(flatten)]
+    pub search_data: SearchDataResponse,
+}
+
+fn parse_date(date: Option<&str>, name: &str) -> Result<Option<NaiveDate>, String> {
+    date.filter(|date| !date.is_empty())
+        .map(|date| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| format!("invalid {name} date, expected YYYY-MM-DD"))
+        })
+        .transpose()
+}
+
+#[tracing::instrument(skip(database, urls))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    params(
+        SearchImagesQuery
+    ),
+    responses(
+        (status = 200, description = "Public images matching the search terms in their scene name, caption or people, most relevant first", body = SearchImagesResponse),
+        (status = 400, description = "Bad Request", body = ResponseError),
+        (status = 500, description = "Internal Server Error", body = ResponseError)
+    )
+)]
+#[get("/search/images")]
+pub async fn search_images(
+    query_params: Query<SearchImagesQuery>,
+    database: Data<Database>,
+    urls: Data<UrlBuilder>,
+) -> impl Responder {
+    let SearchImagesQuery {
+        q,
+        realm,
+        from,
+        to,
+        offset,
+        limit,
+    } = query_params.into_inner();
+    let limit = limit.min(MAX_LIMIT);
+
+    let query = q.trim().to_string();
+    if query.is_empty() {
+        return HttpResponse::BadRequest().json(ResponseError::new("missing search terms"));
+    }
+    if query.chars().count() > MAX_QUERY_LENGTH {
+        return HttpResponse::BadRequest().json(ResponseError::new(&format!(
+            "search terms are too long, maximum is {MAX_QUERY_LENGTH} characters"
+        )));
+    }
+
+    let (from, to) = match (
+        parse_date(from.as_deref(), "from"),
+        parse_date(to.as_deref(), "to"),
+    ) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(message), _) | (_, Err(message)) => {
+            return HttpResponse::BadRequest().json(ResponseError::new(&message))
+        }
+    };
+
+    let filter = SearchFilter {
+        query,
+        realm: realm.filter(|realm| !realm.is_empty()),
+        from: from.and_then(|from| from.and_hms_opt(0, 0, 0)),
+        // The whole `to` day is included
+        to: to
+            .and_then(|to| to.succ_opt())
+            .and_then(|to| to.and_hms_opt(0, 0, 0)),
+    };
+
+    let images_count = match database.search_images_count(&filter).await {
+        Ok(count) => count,
+        Err(error) => {
+            tracing::error!("failed to search images: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to search images"));
+        }
+    };
+
+    let images = match database
+        .search_images(&filter, offset as i64, limit as i64)
+        .await
+    {
+        Ok(images) => images,
+        Err(error) => {
+            tracing::error!("failed to search images: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to search images"));
+        }
+    };
+
+    let images = images
+        .into_iter()
+        .map(|image| GalleryImageWithPlace::from_db(image, &urls))
+        .collect::<Vec<GalleryImageWithPlace>>();
+
+    HttpResponse::Ok().json(SearchImagesResponse {
+        images,
+        search_data: SearchDataResponse {
+            max_images: images_count,
+        },
+    })
+}
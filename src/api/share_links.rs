@@ -0,0 +1,316 @@
+use actix_web::{
+    delete, get,
+    http::header,
+    post,
+    web::{Data, Json, Path},
+    HttpResponse, Responder,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use utoipa::ToSchema;
+
+use crate::{
+    api::{auth::AuthUser, Image, ResponseError},
+    database::{DBImage, DBShareLink, Database},
+    storage::{presign_image, ObjectStorage},
+    urls::UrlBuilder,
+    Settings,
+};
+
+/// Longest time a share link can stay valid, 30 days.
+const MAX_EXPIRES_IN_SECS: u64 = 30 * 24 * 60 * 60;
+
+/// Maximum number of usable links an image can have at the same time.
+const MAX_ACTIVE_SHARE_LINKS: u64 = 50;
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareLink {
+    pub token: String,
+    pub image_id: String,
+    /// Link to send, resolving the token to the image.
+    pub url: String,
+    pub expires_at: Option<String>,
+    pub max_views: Option<u32>,
+    pub views: u32,
+    pub revoked: bool,
+    pub created_at: String,
+}
+
+impl ShareLink {
+    fn new(share_link: DBShareLink, api_url: &str) -> Self {
+        Self {
+            url: format!("{api_url}/api/s/{}", share_link.token),
+            token: share_link.token,
+            image_id: share_link.image_id.to_string(),
+            expires_at: share_link
+                .expires_at
+                .map(|expires_at| expires_at.and_utc().to_rfc3339()),
+            max_views: share_link.max_views.map(|max_views| max_views as u32),
+            views: share_link.views as u32,
+            revoked: share_link.revoked_at.is_some(),
+            created_at: share_link.created_at.and_utc().to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateShareLink {
+    /// Seconds until the link expires, it never expires when absent.
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+    /// Number of times the link can be opened, unlimited when absent.
+    #[serde(default)]
+    pub max_views: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GetShareLinksResponse {
+    pub share_links: Vec<ShareLink>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedImageResponse {
+    /// The shared image. The URLs of private and shared images are short-lived presigned URLs.
+    pub image: Image,
+    pub expires_at: Option<String>,
+    /// Views left before the link stops working, absent when unlimited.
+    pub remaining_views: Option<u32>,
+}
+
+async fn get_owned_image(
+    database: &Database,
+    image_id: &str,
+    address: &str,
+) -> Result<DBImage, HttpResponse> {
+    let image = match database.get_image(image_id).await {
+        Ok(image) => image,
+        Err(_) => return Err(HttpResponse::NotFound().json(ResponseError::new("image not found"))),
+    };
+
+    if !image.user_address.eq_ignore_ascii_case(address) {
+        return Err(HttpResponse::Forbidden().json(ResponseError::new("forbidden")));
+    }
+
+    Ok(image)
+}
+
+/// Tokens are 64 hex characters, long enough that they can't be guessed.
+fn generate_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+#[tracing::instrument(skip(settings, database))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    request_body(content = CreateShareLink, description = "Optional expiry and view limit of the link", content_type = "application/json"),
+    responses(
+        (status = 200, description = "Created share link", body = ShareLink),
+        (status = 400, description = "Bad Request", body = ResponseError),
+        (status = FORBIDDEN, description = "Forbidden, or the image was taken down by moderation"),
+        (status = NOT_FOUND, description = "Image was not found"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to create the share link"),
+    )
+)]
+#[post("/images/{image_id}/share-links")]
+pub async fn create_share_link(
+    auth_user: AuthUser,
+    image_id: Path<String>,
+    settings: Data<Settings>,
+    database: Data<Database>,
+    body: Option<Json<CreateShareLink>>,
+) -> impl Responder {
+    let image_id = image_id.into_inner();
+    let CreateShareLink {
+        expires_in,
+        max_views,
+    } = body.map(Json::into_inner).unwrap_or_default();
+
+    if expires_in.is_some_and(|expires_in| expires_in == 0 || expires_in > MAX_EXPIRES_IN_SECS) {
+        return HttpResponse::BadRequest().json(ResponseError::new(&format!(
+            "expiresIn must be between 1 and {MAX_EXPIRES_IN_SECS} seconds"
+        )));
+    }
+
+    if max_views.is_some_and(|max_views| max_views == 0 || max_views > i32::MAX as u32) {
+        return HttpResponse::BadRequest().json(ResponseError::new("invalid maxViews"));
+    }
+
+    match get_owned_image(&database, &image_id, &auth_user.address).await {
+        Ok(image) if image.moderated_at.is_some() => {
+            return HttpResponse::Forbidden().json(ResponseError::new("image was taken down"))
+        }
+        Ok(_) => {}
+        Err(response) => return response,
+    }
+
+    match database.get_active_share_links_count(&image_id).await {
+        Ok(count) if count >= MAX_ACTIVE_SHARE_LINKS => {
+            return HttpResponse::BadRequest().json(ResponseError::new(&format!(
+                "too many share links, maximum is {MAX_ACTIVE_SHARE_LINKS}"
+            )))
+        }
+        Ok(_) => {}
+        Err(error) => {
+            tracing::error!("failed to count share links: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to create share link"));
+        }
+    }
+
+    match database
+        .insert_share_link(
+            &generate_token(),
+            &image_id,
+            &auth_user.address,
+            expires_in.map(|expires_in| expires_in as i64),
+            max_views.map(|max_views| max_views as i32),
+        )
+        .await
+    {
+        Ok(share_link) => HttpResponse::Ok().json(ShareLink::new(share_link, &settings.api_url)),
+        Err(error) => {
+            tracing::error!("failed to create share link: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to create share link"))
+        }
+    }
+}
+
+#[tracing::instrument(skip(settings, database))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    responses(
+        (status = 200, description = "Share links of the image, newest first, including the revoked and expired ones", body = GetShareLinksResponse),
+        (status = FORBIDDEN, description = "Forbidden"),
+        (status = NOT_FOUND, description = "Image was not found"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to get the share links"),
+    )
+)]
+#[get("/images/{image_id}/share-links")]
+pub async fn get_share_links(
+    auth_user: AuthUser,
+    image_id: Path<String>,
+    settings: Data<Settings>,
+    database: Data<Database>,
+) -> impl Responder {
+    let image_id = image_id.into_inner();
+    if let Err(response) = get_owned_image(&database, &image_id, &auth_user.address).await {
+        return response;
+    }
+
+    match database.get_image_share_links(&image_id).await {
+        Ok(share_links) => HttpResponse::Ok().json(GetShareLinksResponse {
+            share_links: share_links
+                .into_iter()
+                .map(|share_link| ShareLink::new(share_link, &settings.api_url))
+                .collect(),
+        }),
+        Err(error) => {
+            tracing::error!("failed to get share links: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to get share links"))
+        }
+    }
+}
+
+// Share tokens are bearer credentials, so they are kept out of the spans
+#[tracing::instrument(skip(path, settings, database), fields(image_id = %path.0))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    responses(
+        (status = 200, description = "Revoked share link", body = ShareLink),
+        (status = FORBIDDEN, description = "Forbidden"),
+        (status = NOT_FOUND, description = "Image or share link was not found"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to revoke the share link"),
+    )
+)]
+#[delete("/images/{image_id}/share-links/{token}")]
+pub async fn revoke_share_link(
+    auth_user: AuthUser,
+    path: Path<(String, String)>,
+    settings: Data<Settings>,
+    database: Data<Database>,
+) -> impl Responder {
+    let (image_id, token) = path.into_inner();
+    if let Err(response) = get_owned_image(&database, &image_id, &auth_user.address).await {
+        return response;
+    }
+
+    match database.revoke_share_link(&image_id, &token).await {
+        Ok(Some(share_link)) => {
+            HttpResponse::Ok().json(ShareLink::new(share_link, &settings.api_url))
+        }
+        Ok(None) => HttpResponse::NotFound().json(ResponseError::new("share link not found")),
+        Err(error) => {
+            tracing::error!("failed to revoke share link: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to revoke share link"))
+        }
+    }
+}
+
+#[tracing::instrument(skip(token, storage, database, urls))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    responses(
+        (status = 200, description = "The image of the share link, whatever its visibility. Every request counts as a view", body = SharedImageResponse),
+        (status = NOT_FOUND, description = "Share link was not found, revoked, expired or used up"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to get the shared image"),
+    )
+)]
+#[get("/s/{token}")]
+pub async fn get_shared_image(
+    token: Path<String>,
+    storage: Data<dyn ObjectStorage>,
+    database: Data<Database>,
+    urls: Data<UrlBuilder>,
+) -> impl Responder {
+    let share_link = match database.use_share_link(&token).await {
+        Ok(Some(share_link)) => share_link,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(ResponseError::new("share link not found"))
+        }
+        Err(error) => {
+            tracing::error!("failed to use share link: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to get shared image"));
+        }
+    };
+
+    // Links created before the image was taken down don't reach it anymore
+    let image = match database.get_image(&share_link.image_id.to_string()).await {
+        Ok(image) if image.moderated_at.is_none() => image,
+        _ => return HttpResponse::NotFound().json(ResponseError::new("image not found")),
+    };
+
+    // The link holder can't authenticate as a viewer of restricted images, so their files
+    // are handed out directly
+    let image = match presign_image(storage.get_ref(), &urls, image) {
+        Ok(image) => image,
+        Err(error) => {
+            tracing::error!("failed to presign image url: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to get shared image"));
+        }
+    };
+
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "private, no-store"))
+        .json(SharedImageResponse {
+            image,
+            expires_at: share_link
+                .expires_at
+                .map(|expires_at| expires_at.and_utc().to_rfc3339()),
+            remaining_views: share_link
+                .max_views
+                .map(|max_views| (max_views - share_link.views).max(0) as u32),
+        })
+}
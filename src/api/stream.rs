@@ -0,0 +1,179 @@
+use std::{collections::HashSet, convert::Infallible, time::Duration};
+
+use actix_http::ws;
+use actix_web::{
+    get,
+    http::header,
+    web::{Bytes, BytesMut, Data, Path, Payload},
+    HttpRequest, HttpResponse, Responder,
+};
+use actix_web_lab::sse;
+use futures_util::StreamExt;
+use tokio::sync::broadcast;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{
+    api::{GalleryImageWithPlace, ResponseError},
+    image_feed::ImageFeed,
+    places_client::PlacesClient,
+};
+
+/// How often the streams send a keep-alive, a comment with SSE and a ping with WebSocket.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Waits for the next image taken in one of the places, `None` once the feed is closed.
+async fn next_image(
+    images: &mut broadcast::Receiver<GalleryImageWithPlace>,
+    place_ids: &HashSet<String>,
+) -> Option<GalleryImageWithPlace> {
+    loop {
+        match images.recv().await {
+            Ok(image) if place_ids.contains(&image.place_id) => return Some(image),
+            Ok(_) => {}
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("live stream fell behind, skipped {} images", skipped)
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+fn is_websocket(request: &HttpRequest) -> bool {
+    request
+        .headers()
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+}
+
+/// Streams the images as `image` events, with their id as event id.
+fn sse_response(
+    request: &HttpRequest,
+    images: broadcast::Receiver<GalleryImageWithPlace>,
+    place_ids: HashSet<String>,
+) -> HttpResponse {
+    let events =
+        futures_util::stream::unfold((images, place_ids), |(mut images, place_ids)| async move {
+            let image = next_image(&mut images, &place_ids).await?;
+            let event = sse::Data::new_json(&image)
+                .map(|data| sse::Event::from(data.id(image.id.clone()).event("image")));
+            Some((event, (images, place_ids)))
+        });
+
+    sse::Sse::from_stream(events)
+        .with_keep_alive(KEEP_ALIVE)
+        .respond_to(request)
+        .map_into_boxed_body()
+}
+
+/// Streams the images as JSON text messages. Messages from the client are ignored, except
+/// pings and closes.
+fn websocket_response(
+    request: &HttpRequest,
+    mut payload: Payload,
+    mut images: broadcast::Receiver<GalleryImageWithPlace>,
+    place_ids: HashSet<String>,
+) -> HttpResponse {
+    let mut response = match ws::handshake(request.head()) {
+        Ok(response) => response,
+        Err(error) => return HttpResponse::from_error(error),
+    };
+    let (mut sender, body) = actix_web_lab::body::channel::<Infallible>();
+
+    actix_web::rt::spawn(async move {
+        let mut codec = ws::Codec::new();
+        let mut received = BytesMut::new();
+        let mut keep_alive = actix_web::rt::time::interval_at(
+            actix_web::rt::time::Instant::now() + KEEP_ALIVE,
+            KEEP_ALIVE,
+        );
+        let mut send = move |codec: &mut ws::Codec, message: ws::Message| {
+            let mut frame = BytesMut::new();
+            codec.encode(message, &mut frame).is_ok() && sender.send(frame.freeze()).is_ok()
+        };
+
+        loop {
+            let sent = tokio::select! {
+                chunk = payload.next() => {
+                    let Some(Ok(chunk)) = chunk else { break };
+                    received.extend_from_slice(&chunk);
+                    let mut sent = true;
+                    while let Ok(Some(frame)) = codec.decode(&mut received) {
+                        match frame {
+                            ws::Frame::Ping(data) => sent &= send(&mut codec, ws::Message::Pong(data)),
+                            ws::Frame::Close(reason) => {
+                                send(&mut codec, ws::Message::Close(reason));
+                                sent = false;
+                            }
+                            _ => {}
+                        }
+                    }
+                    sent
+                }
+                image = next_image(&mut images, &place_ids) => {
+                    let Some(image) = image else { break };
+                    match serde_json::to_string(&image) {
+                        Ok(json) => send(&mut codec, ws::Message::Text(json.into())),
+                        Err(error) => {
+                            tracing::error!("failed to serialize live image: {}", error);
+                            true
+                        }
+                    }
+                }
+                _ = keep_alive.tick() => send(&mut codec, ws::Message::Ping(Bytes::new())),
+            };
+
+            // The client is gone or closed the stream
+            if !sent {
+                break;
+            }
+        }
+    });
+
+    match response.message_body(body) {
+        Ok(response) => HttpResponse::from(response.map_into_boxed_body()),
+        Err(error) => HttpResponse::from_error(error),
+    }
+}
+
+#[tracing::instrument(skip(request, payload, feed, places_client))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    responses(
+        (status = 200, description = "Server-sent `image` events with the public images of the place, as they are uploaded or made public. World names stream every place of the world", content_type = "text/event-stream", body = GalleryImageWithPlace),
+        (status = 101, description = "Same images as JSON text messages, when the request asks for a WebSocket upgrade"),
+        (status = 502, description = "Failed to resolve the world name", body = ResponseError),
+    )
+)]
+#[get("/places/{place_id}/images/stream")]
+pub async fn stream_place_images(
+    place_id: Path<String>,
+    request: HttpRequest,
+    payload: Payload,
+    feed: Data<ImageFeed>,
+    places_client: Data<PlacesClient>,
+) -> impl Responder {
+    let place_id = place_id.into_inner();
+
+    let place_ids = if place_id.ends_with(".eth") {
+        match places_client.get_world_place_ids(&place_id).await {
+            Ok(ids) => ids.into_iter().collect(),
+            Err(e) => {
+                tracing::error!("Failed to resolve world name '{}': {}", place_id, e);
+                return HttpResponse::BadGateway().json(ResponseError::new(&format!(
+                    "failed to resolve world name: {e}"
+                )));
+            }
+        }
+    } else {
+        HashSet::from([place_id])
+    };
+
+    let images = feed.subscribe();
+    if is_websocket(&request) {
+        websocket_response(&request, payload, images, place_ids)
+    } else {
+        sse_response(&request, images, place_ids)
+    }
+}
@@ -0,0 +1,325 @@
+use actix_web::{
+    get,
+    web::{Data, Path},
+    HttpResponse, Responder,
+};
+use actix_web_lab::extract::Query;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    api::{GalleryImageWithPlace, ResponseError},
+    database::Database,
+    urls::UrlBuilder,
+};
+
+/// Maximum length, in characters, of a single tag.
+pub const MAX_TAG_LENGTH: usize = 50;
+
+/// Maximum number of tags an image can have, counting the ones parsed from its caption.
+pub const MAX_TAGS_PER_IMAGE: usize = 20;
+
+/// Upper bound for the client-supplied pagination `limit`.
+const MAX_LIMIT: u64 = 100;
+
+/// Largest time window, in hours, used to compute trending tags.
+const MAX_TRENDING_HOURS: u32 = 24 * 30;
+
+/// Upper bound for the number of trending tags returned.
+const MAX_TRENDING_LIMIT: u64 = 50;
+
+fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Normalizes a user-supplied tag: drops the leading `#` and lowercases it. Returns `None`
+/// when the result is empty, too long or has characters other than letters, digits and `_`.
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim();
+    let tag = tag.strip_prefix('#').unwrap_or(tag).to_lowercase();
+
+    if tag.is_empty() || tag.chars().count() > MAX_TAG_LENGTH || !tag.chars().all(is_tag_char) {
+        return None;
+    }
+
+    Some(tag)
+}
+
+/// Extracts the normalized `#hashtags` of a caption. A hashtag starts with `#` at the
+/// beginning of the text or after a character that can't be part of a tag.
+pub fn parse_hashtags(caption: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    let mut previous = None;
+    let mut chars = caption.char_indices().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        if c == '#' && !previous.is_some_and(is_tag_char) {
+            let start = index + c.len_utf8();
+            let mut end = start;
+            while let Some((next_index, next)) = chars.peek().copied() {
+                if !is_tag_char(next) {
+                    break;
+                }
+                end = next_index + next.len_utf8();
+                chars.next();
+            }
+
+            if let Some(tag) = normalize_tag(&caption[start..end]) {
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+            previous = caption[..end].chars().last();
+            continue;
+        }
+
+        previous = Some(c);
+    }
+
+    tags
+}
+
+/// Normalizes the explicit tags, which may come comma or space separated, and merges them with
+/// the hashtags of the caption. Returns the explicit tags and the full sorted set of tags.
+pub fn collect_tags(
+    caption: Option<&str>,
+    explicit_tags: &[String],
+) -> Result<(Vec<String>, Vec<String>), String> {
+    let mut explicit = Vec::new();
+    for tag in explicit_tags
+        .iter()
+        .flat_map(|tags| tags.split(|c: char| c == ',' || c.is_whitespace()))
+        .filter(|tag| !tag.is_empty())
+    {
+        let Some(normalized) = normalize_tag(tag) else {
+            return Err(format!("invalid tag: {tag}"));
+        };
+        if !explicit.contains(&normalized) {
+            explicit.push(normalized);
+        }
+    }
+
+    let mut tags = explicit.clone();
+    for tag in caption.map(parse_hashtags).unwrap_or_default() {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+
+    if tags.len() > MAX_TAGS_PER_IMAGE {
+        return Err(format!(
+            "too many tags, maximum is {MAX_TAGS_PER_IMAGE} including the caption hashtags"
+        ));
+    }
+
+    explicit.sort();
+    tags.sort();
+
+    Ok((explicit, tags))
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+struct GetTagImagesQuery {
+    #[serde(default = "default_offset")]
+    offset: u64,
+    #[serde(default = "default_limit")]
+    limit: u64,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+struct GetTrendingTagsQuery {
+    /// Time window, in hours, to count tag usage over.
+    #[serde(default = "default_trending_hours")]
+    hours: u32,
+    #[serde(default = "default_trending_limit")]
+    limit: u64,
+}
+
+fn default_offset() -> u64 {
+    0
+}
+
+fn default_limit() -> u64 {
+    20
+}
+
+fn default_trending_hours() -> u32 {
+    24
+}
+
+fn default_trending_limit() -> u64 {
+    10
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TagDataResponse {
+    pub max_images: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GetTagImagesResponse {
+    pub images: Vec<GalleryImageWithPlace>,
+    #[serde(flatten)]
+    pub tag_data: TagDataResponse,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TagCount {
+    pub tag: String,
+    pub count: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GetTrendingTagsResponse {
+    pub tags: Vec<TagCount>,
+}
+
+#[tracing::instrument(skip(database, urls))]
+#[utoipa::path(
+    tag = "tags",
+    context_path = "/api",
+    params(
+        GetTagImagesQuery
+    ),
+    responses(
+        (status = 200, description = "List public images with a given tag across every place", body = GetTagImagesResponse),
+        (status = 400, description = "Invalid tag", body = ResponseError),
+        (status = 404, description = "Not found")
+    )
+)]
+#[get("/tags/{tag}/images")]
+pub async fn get_tag_images(
+    tag: Path<String>,
+    query_params: Query<GetTagImagesQuery>,
+    database: Data<Database>,
+    urls: Data<UrlBuilder>,
+) -> impl Responder {
+    let Some(tag) = normalize_tag(&tag) else {
+        return HttpResponse::BadRequest().json(ResponseError::new("invalid tag"));
+    };
+
+    let GetTagImagesQuery { offset, limit } = query_params.into_inner();
+    let limit = limit.min(MAX_LIMIT);
+
+    let Ok(images_count) = database.get_tag_images_count(&tag).await else {
+        return HttpResponse::NotFound().json(ResponseError::new("tag not found"));
+    };
+
+    let Ok(images) = database
+        .get_tag_images(&tag, offset as i64, limit as i64)
+        .await
+    else {
+        return HttpResponse::NotFound().json(ResponseError::new("tag not found"));
+    };
+
+    let tag_data = TagDataResponse {
+        max_images: images_count,
+    };
+
+    let images = images
+        .into_iter()
+        .map(|image| GalleryImageWithPlace::from_db(image, &urls))
+        .collect::<Vec<GalleryImageWithPlace>>();
+
+    HttpResponse::Ok().json(GetTagImagesResponse { images, tag_data })
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "tags",
+    context_path = "/api",
+    params(
+        GetTrendingTagsQuery
+    ),
+    responses(
+        (status = 200, description = "Most used tags in public images uploaded within the time window", body = GetTrendingTagsResponse),
+        (status = 500, description = "Internal Server Error", body = ResponseError)
+    )
+)]
+#[get("/tags/trending")]
+pub async fn get_trending_tags(
+    query_params: Query<GetTrendingTagsQuery>,
+    database: Data<Database>,
+) -> impl Responder {
+    let GetTrendingTagsQuery { hours, limit } = query_params.into_inner();
+    let hours = hours.clamp(1, MAX_TRENDING_HOURS);
+    let limit = limit.min(MAX_TRENDING_LIMIT);
+
+    match database.get_trending_tags(hours as i32, limit as i64).await {
+        Ok(tags) => {
+            let tags = tags
+                .into_iter()
+                .map(|tag| TagCount {
+                    tag: tag.tag,
+                    count: tag.count as u64,
+                })
+                .collect();
+            HttpResponse::Ok().json(GetTrendingTagsResponse { tags })
+        }
+        Err(error) => {
+            tracing::error!("failed to get trending tags: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to get trending tags"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_tag() {
+        assert_eq!(
+            normalize_tag("#MusicFestival2026"),
+            Some("musicfestival2026".to_string())
+        );
+        assert_eq!(normalize_tag(" art_week "), Some("art_week".to_string()));
+        assert_eq!(normalize_tag("#"), None);
+        assert_eq!(normalize_tag("two words"), None);
+        assert_eq!(normalize_tag(&"a".repeat(MAX_TAG_LENGTH + 1)), None);
+    }
+
+    #[test]
+    fn test_parse_hashtags() {
+        assert_eq!(
+            parse_hashtags("Dancing at #MusicFestival2026 with friends! #party,#Party"),
+            vec!["musicfestival2026", "party"]
+        );
+    }
+
+    #[test]
+    fn test_parse_hashtags_ignores_hashes_inside_words() {
+        assert_eq!(
+            parse_hashtags("issue#42 and C# are not tags"),
+            Vec::<String>::new()
+        );
+        assert_eq!(parse_hashtags("##double"), vec!["double"]);
+    }
+
+    #[test]
+    fn test_collect_tags_merges_explicit_and_caption_tags() {
+        let (explicit, tags) = collect_tags(
+            Some("Best night #Party"),
+            &["#MusicFestival2026, party".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(explicit, vec!["musicfestival2026", "party"]);
+        assert_eq!(tags, vec!["musicfestival2026", "party"]);
+    }
+
+    #[test]
+    fn test_collect_tags_rejects_invalid_tags() {
+        assert!(collect_tags(None, &["not-valid".to_string()]).is_err());
+
+        let too_many = (0..=MAX_TAGS_PER_IMAGE)
+            .map(|i| format!("tag{i}"))
+            .collect::<Vec<_>>();
+        assert!(collect_tags(None, &too_many).is_err());
+    }
+}
@@ -3,21 +3,47 @@ use actix_web::{
     web::{Data, Json, Path},
     HttpResponse, Responder,
 };
-use actix_web_lab::__reexports::serde_json;
 use serde::Deserialize;
-use sqlx::types::chrono;
-use std::collections::HashMap;
 use utoipa::ToSchema;
 
 use crate::{
-    api::{auth::AuthUser, ResponseError},
-    database::Database,
-    sns::{Event, EventSubtype, EventType, SNSPublisher},
+    api::{auth::AuthUser, tags::collect_tags, Image, ResponseError, Visibility},
+    database::{DBImage, Database},
+    events::{
+        Event, EventPayload, PhotoPrivacyChangedMetadata, PhotoUpdatedMetadata, SchemaVersion,
+    },
+    storage::{delete_renders, move_image_objects, ObjectStorage, StorageError},
+    urls::UrlBuilder,
 };
 
-#[derive(Deserialize, ToSchema)]
+/// Maximum length, in characters, of an image caption.
+pub const MAX_CAPTION_LENGTH: usize = 500;
+
+/// Maximum length, in characters, of an image alternative text.
+pub const MAX_ALT_TEXT_LENGTH: usize = 250;
+
+/// Maximum number of addresses an image can be shared with.
+pub const MAX_SHARED_WITH: usize = 100;
+
+#[derive(Deserialize, Debug, ToSchema)]
 pub struct UpdateVisibility {
-    is_public: bool,
+    /// Legacy flag, `true` means `public` and `false` means `private`. Ignored when
+    /// `visibility` is set.
+    #[serde(default, alias = "isPublic")]
+    is_public: Option<bool>,
+    #[serde(default)]
+    visibility: Option<Visibility>,
+    /// Addresses that can see the image when its visibility is `shared`. The current ones are
+    /// kept when absent.
+    #[serde(default, alias = "sharedWith")]
+    shared_with: Option<Vec<String>>,
+}
+
+/// Checks the address looks like an Ethereum address, `0x` followed by 40 hex digits.
+pub fn is_valid_address(address: &str) -> bool {
+    address
+        .strip_prefix("0x")
+        .is_some_and(|hex| hex.len() == 40 && hex.chars().all(|c| c.is_ascii_hexdigit()))
 }
 
 #[utoipa::path(
@@ -26,8 +52,9 @@ pub struct UpdateVisibility {
     request_body(content = UpdateVisibility, description = "Update image visibility", content_type = "application/json"),
     responses(
         (status = 200, description = "Image visibility updated successfully"),
+        (status = 400, description = "Bad Request", body = ResponseError),
         (status = NOT_FOUND, description = "Image was not found"),
-        (status = FORBIDDEN, description = "Forbidden"),
+        (status = FORBIDDEN, description = "Forbidden, or the image was taken down by moderation"),
         (status = INTERNAL_SERVER_ERROR, description = "Failed to update image visibility"),
     )
 )]
@@ -35,8 +62,8 @@ pub struct UpdateVisibility {
 pub async fn update_image_visibility(
     user_address: AuthUser,
     image_id: Path<String>,
+    storage: Data<dyn ObjectStorage>,
     database: Data<Database>,
-    sns_publisher: Data<SNSPublisher>,
     update: Json<UpdateVisibility>,
 ) -> impl Responder {
     let image_id = image_id.into_inner();
@@ -45,6 +72,37 @@ pub async fn update_image_visibility(
         address: request_user_address,
     } = user_address;
 
+    let UpdateVisibility {
+        is_public,
+        visibility,
+        shared_with,
+    } = update.into_inner();
+
+    let Some(visibility) = visibility.or(is_public.map(Visibility::from_is_public)) else {
+        return HttpResponse::BadRequest().json(ResponseError::new("missing visibility"));
+    };
+
+    let shared_with = shared_with.map(|addresses| {
+        let mut addresses = addresses
+            .iter()
+            .map(|address| address.trim().to_lowercase())
+            .collect::<Vec<_>>();
+        addresses.sort();
+        addresses.dedup();
+        addresses
+    });
+    if let Some(addresses) = &shared_with {
+        if addresses.len() > MAX_SHARED_WITH {
+            return HttpResponse::BadRequest().json(ResponseError::new(&format!(
+                "too many addresses, maximum is {MAX_SHARED_WITH}"
+            )));
+        }
+        if let Some(address) = addresses.iter().find(|address| !is_valid_address(address)) {
+            return HttpResponse::BadRequest()
+                .json(ResponseError::new(&format!("invalid address: {address}")));
+        }
+    }
+
     let image = match database.get_image(&image_id).await {
         Ok(image) => image,
         Err(_) => return HttpResponse::NotFound().json(ResponseError::new("image not found")),
@@ -57,40 +115,271 @@ pub async fn update_image_visibility(
         return HttpResponse::Forbidden().json(ResponseError::new("forbidden"));
     }
 
-    if image.is_public == update.is_public {
+    if image.moderated_at.is_some() {
+        return HttpResponse::Forbidden().json(ResponseError::new("image was taken down"));
+    }
+
+    if image.visibility == visibility && shared_with.is_none() {
         return HttpResponse::Ok().finish();
     }
 
+    // The addresses the image ends up shared with, the current ones are kept when none are given
+    let shares = match (visibility, &shared_with) {
+        (Visibility::Shared, Some(addresses)) => addresses.clone(),
+        (Visibility::Shared, None) => database
+            .get_image_shares(&image_id)
+            .await
+            .unwrap_or_default(),
+        _ => vec![],
+    };
+
+    // Queued with the privacy settings change, published by the outbox relay
+    let sns_event = Event::new(
+        &image_id,
+        EventPayload::PhotoPrivacyChanged(PhotoPrivacyChangedMetadata {
+            schema_version: SchemaVersion,
+            photo_id: image_id.clone(),
+            user_address: request_user_address.to_lowercase(),
+            is_public: visibility.is_public(),
+            visibility,
+            previous_visibility: image.visibility,
+            shared_with: shares,
+            moderation_reason: None,
+        }),
+    );
+
+    match change_image_visibility(
+        storage.get_ref(),
+        &database,
+        &image,
+        visibility,
+        shared_with.as_deref(),
+        &sns_event,
+    )
+    .await
+    {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(VisibilityChangeError::Storage(error)) => {
+            tracing::error!("failed to move image files: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to update image visibility"))
+        }
+        Err(VisibilityChangeError::Database(error)) => {
+            tracing::error!("failed to update image metadata: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to update image metadata"))
+        }
+    }
+}
+
+/// Why the visibility of an image couldn't be changed.
+#[derive(Debug)]
+pub enum VisibilityChangeError {
+    /// The files couldn't be moved, nothing changed.
+    Storage(StorageError),
+    /// The change couldn't be stored, the files were moved back.
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for VisibilityChangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VisibilityChangeError::Storage(error) => write!(f, "failed to move files: {error}"),
+            VisibilityChangeError::Database(error) => write!(f, "failed to store: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for VisibilityChangeError {}
+
+/// Changes the visibility of an image and queues `event` in the outbox. Private and shared
+/// files live under the private prefix, so they are moved first and moved back if the change
+/// can't be stored.
+pub async fn change_image_visibility(
+    storage: &dyn ObjectStorage,
+    database: &Database,
+    image: &DBImage,
+    visibility: Visibility,
+    shared_with: Option<&[String]>,
+    event: &Event,
+) -> Result<(), VisibilityChangeError> {
+    let image_id = image.id.to_string();
+    let keys = image.stored_keys();
+    move_image_objects(storage, &keys, image.visibility, visibility)
+        .await
+        .map_err(VisibilityChangeError::Storage)?;
+
     if let Err(error) = database
-        .update_image_visibility(&image_id, &update.is_public)
+        .update_image_visibility(&image_id, visibility, shared_with, event)
         .await
     {
-        tracing::error!("failed to update image metadata: {}", error);
-        return HttpResponse::InternalServerError()
-            .json(ResponseError::new("failed to update image metadata"));
+        if let Err(error) = move_image_objects(storage, &keys, visibility, image.visibility).await {
+            tracing::error!("failed to move image files back: {}", error);
+        }
+        return Err(VisibilityChangeError::Database(error));
     }
 
-    // Publish SNS event for privacy settings change
-    let mut event_metadata = HashMap::new();
-    event_metadata.insert("photoId".to_string(), serde_json::json!(image_id));
-    event_metadata.insert(
-        "userAddress".to_string(),
-        serde_json::json!(request_user_address.to_lowercase().to_string()),
-    );
-    event_metadata.insert("isPublic".to_string(), serde_json::json!(update.is_public));
-
-    let sns_event = Event {
-        event_type: EventType::Camera,
-        sub_type: EventSubtype::PhotoPrivacyChanged,
-        key: image_id.clone(),
-        timestamp: chrono::Utc::now().timestamp() as u64,
-        metadata: event_metadata,
+    // Rendered variants are dropped instead of moved, they are rendered again when requested
+    if image.visibility.is_public() != visibility.is_public() {
+        if let Err(error) = delete_renders(storage, &image_id, image.visibility).await {
+            tracing::error!("failed to delete image renders: {}", error);
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateImage {
+    /// New caption, an empty string removes it. The caption is left as is when absent.
+    caption: Option<String>,
+    /// New alternative text, an empty string goes back to the generated one. The alternative
+    /// text is left as is when absent.
+    alt_text: Option<String>,
+    /// New explicit tags, replacing the previous ones. The `#hashtags` of the caption are
+    /// always added on top. The explicit tags are left as is when absent.
+    tags: Option<Vec<String>>,
+}
+
+/// Trims the user-supplied text, turning blank values into `None`.
+pub fn normalize_text(text: Option<&str>) -> Option<String> {
+    text.map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(str::to_string)
+}
+
+/// Checks the caption and alternative text are within their length limits.
+pub fn validate_image_texts(caption: Option<&str>, alt_text: Option<&str>) -> Result<(), String> {
+    if caption.is_some_and(|caption| caption.chars().count() > MAX_CAPTION_LENGTH) {
+        return Err(format!(
+            "caption is too long, maximum is {MAX_CAPTION_LENGTH} characters"
+        ));
+    }
+
+    if alt_text.is_some_and(|alt_text| alt_text.chars().count() > MAX_ALT_TEXT_LENGTH) {
+        return Err(format!(
+            "alt text is too long, maximum is {MAX_ALT_TEXT_LENGTH} characters"
+        ));
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(database, urls))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    request_body(content = UpdateImage, description = "Update the image caption and alternative text", content_type = "application/json"),
+    responses(
+        (status = 200, description = "Updated image", body = Image),
+        (status = 400, description = "Bad Request", body = ResponseError),
+        (status = NOT_FOUND, description = "Image was not found"),
+        (status = FORBIDDEN, description = "Forbidden"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to update image"),
+    )
+)]
+#[patch("/images/{id}")]
+pub async fn update_image(
+    user_address: AuthUser,
+    image_id: Path<String>,
+    database: Data<Database>,
+    update: Json<UpdateImage>,
+    urls: Data<UrlBuilder>,
+) -> impl Responder {
+    let image_id = image_id.into_inner();
+
+    let AuthUser {
+        address: request_user_address,
+    } = user_address;
+
+    let image = match database.get_image(&image_id).await {
+        Ok(image) => image,
+        Err(_) => return HttpResponse::NotFound().json(ResponseError::new("image not found")),
     };
 
-    if let Err(error) = sns_publisher.publish(&sns_event).await {
-        tracing::error!("failed to publish SNS event: {}", error);
-        // Don't return error here as the update was successful
+    if !image
+        .user_address
+        .eq_ignore_ascii_case(&request_user_address)
+    {
+        return HttpResponse::Forbidden().json(ResponseError::new("forbidden"));
     }
 
-    HttpResponse::Ok().finish()
+    let UpdateImage {
+        caption,
+        alt_text,
+        tags,
+    } = update.into_inner();
+    let caption = match caption {
+        Some(caption) => normalize_text(Some(&caption)),
+        None => image.caption.clone(),
+    };
+    let alt_text = match alt_text {
+        Some(alt_text) => normalize_text(Some(&alt_text)),
+        None => image.alt_text.clone(),
+    };
+
+    if let Err(message) = validate_image_texts(caption.as_deref(), alt_text.as_deref()) {
+        return HttpResponse::BadRequest().json(ResponseError::new(&message));
+    }
+
+    let explicit_tags = match tags {
+        Some(tags) => tags,
+        None => match database.get_explicit_image_tags(&image_id).await {
+            Ok(tags) => tags,
+            Err(error) => {
+                tracing::error!("failed to get image tags: {}", error);
+                return HttpResponse::InternalServerError()
+                    .json(ResponseError::new("failed to update image metadata"));
+            }
+        },
+    };
+
+    let (explicit_tags, tags) = match collect_tags(caption.as_deref(), &explicit_tags) {
+        Ok(tags) => tags,
+        Err(message) => return HttpResponse::BadRequest().json(ResponseError::new(&message)),
+    };
+
+    // Queued with the edited image details, published by the outbox relay unless nothing
+    // consumers see changed
+    let mut sorted_tags = tags.clone();
+    sorted_tags.sort();
+    let changed =
+        caption != image.caption || alt_text != image.alt_text || sorted_tags != image.tags;
+    let sns_event = changed.then(|| {
+        Event::new(
+            &image_id,
+            EventPayload::PhotoUpdated(PhotoUpdatedMetadata {
+                schema_version: SchemaVersion,
+                photo_id: image_id.clone(),
+                user_address: request_user_address.to_lowercase(),
+                is_public: image.visibility.is_public(),
+                visibility: image.visibility,
+                caption: caption.clone(),
+                alt_text: alt_text.clone(),
+                tags: sorted_tags,
+            }),
+        )
+    });
+
+    let image = match database
+        .update_image_details(
+            &image_id,
+            caption.as_deref(),
+            alt_text.as_deref(),
+            &tags,
+            &explicit_tags,
+            sns_event.as_ref(),
+        )
+        .await
+    {
+        Ok(image) => Image::from_db(image, &urls),
+        Err(error) => {
+            tracing::error!("failed to update image metadata: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to update image metadata"));
+        }
+    };
+
+    HttpResponse::Ok().json(image)
 }
@@ -3,20 +3,28 @@ use std::io::Cursor;
 use actix_multipart::form::{bytes::Bytes, text::Text, MultipartForm};
 use actix_web::{post, web::Data, HttpResponse, Responder};
 use actix_web_lab::__reexports::serde_json;
-use image::guess_format;
-use s3::Bucket;
+use image::{guess_format, ImageFormat};
 use serde::{Deserialize, Serialize};
 use sqlx::types::{chrono, Uuid};
 use utoipa::ToSchema;
 
 use crate::{
     api::Image,
-    api::{auth::AuthUser, get::UserDataResponse, ForbiddenError, Metadata, ResponseError},
+    api::{
+        auth::AuthUser,
+        get::UserDataResponse,
+        tags::collect_tags,
+        update::{normalize_text, validate_image_texts},
+        ForbiddenError, Metadata, ResponseError, User, Visibility,
+    },
     database::Database,
-    sns::{Event, EventSubtype, EventType, SNSPublisher},
+    events::{
+        Event, EventPayload, EventUser, PhotoTaggedMetadata, PhotoTakenMetadata, SchemaVersion,
+    },
+    storage::{image_key, object_key, ObjectStorage, Rendition},
+    urls::UrlBuilder,
     Settings,
 };
-use std::collections::HashMap;
 
 #[derive(MultipartForm, Debug, ToSchema)]
 pub struct Upload {
@@ -25,8 +33,19 @@ pub struct Upload {
     image: Bytes,
     #[schema(value_type = String, format = Binary)]
     metadata: Bytes,
+    /// Legacy flag, `true` means `public` and `false` means `private`. Ignored when
+    /// `visibility` is set, images are `private` when neither is.
     #[schema(value_type = bool)]
     is_public: Option<Text<bool>>,
+    #[schema(value_type = Visibility)]
+    visibility: Option<Text<Visibility>>,
+    #[schema(value_type = String)]
+    caption: Option<Text<String>>,
+    #[schema(value_type = String)]
+    alt_text: Option<Text<String>>,
+    /// Explicit tags, comma or space separated. The field can be repeated.
+    #[schema(value_type = Vec<String>)]
+    tags: Vec<Text<String>>,
 }
 #[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
@@ -36,7 +55,26 @@ pub struct UploadResponse {
     pub user_data: UserDataResponse,
 }
 
-#[tracing::instrument(skip(upload, bucket, database, settings, sns_publisher))]
+/// Event telling a person they appear in a new photo, keyed by the photo like `photo-taken`.
+fn photo_tagged_event(image: &Image, user: &User) -> Event {
+    let metadata = &image.metadata;
+    Event::new(
+        &image.id,
+        EventPayload::PhotoTagged(PhotoTaggedMetadata {
+            schema_version: SchemaVersion,
+            photo_id: image.id.clone(),
+            user_address: metadata.user_address.to_lowercase(),
+            tagged_address: user.user_address.to_lowercase(),
+            is_emoting: user.is_emoting.unwrap_or(false),
+            place_id: metadata.place_id.clone(),
+            is_public: image.visibility.is_public(),
+            visibility: image.visibility,
+            thumbnail_url: image.thumbnail_url.clone(),
+        }),
+    )
+}
+
+#[tracing::instrument(skip(upload, storage, database, settings, urls))]
 #[utoipa::path(
     tag = "images",
     context_path = "/api", 
@@ -44,19 +82,31 @@ pub struct UploadResponse {
     responses(
         (status = 200, description = "Uploaded image with its metadata", body = UploadResponse),
         (status = 400, description = "Bad Request", body = ResponseError),
-        (status = 403, description = "Forbidden", body = ForbiddenError),
+        (status = 403, description = "Forbidden, the user reached the image limit or is banned", body = ForbiddenError),
         (status = 500, description = "Internal Server Error", body = ResponseError),
     )
 )]
 #[post("/images")]
 pub async fn upload_image(
     auth_user: AuthUser,
-    bucket: Data<Bucket>,
+    storage: Data<dyn ObjectStorage>,
     database: Data<Database>,
     settings: Data<Settings>,
-    sns_publisher: Data<SNSPublisher>,
+    urls: Data<UrlBuilder>,
     upload: MultipartForm<Upload>,
 ) -> impl Responder {
+    match database.is_user_banned(&auth_user.address).await {
+        Ok(false) => {}
+        Ok(true) => {
+            return HttpResponse::Forbidden().json(ResponseError::new("user is banned"));
+        }
+        Err(error) => {
+            tracing::error!("failed to check if the user is banned: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to upload image"));
+        }
+    }
+
     let images_count = database
         .get_user_images_count(&auth_user.address, false)
         .await
@@ -70,13 +120,14 @@ pub async fn upload_image(
         return HttpResponse::Forbidden().json(ForbiddenError::new(&message));
     }
 
-    let (image_bytes, metadata_bytes, is_public) = (
-        &upload.image.data,
-        &upload.metadata.data,
-        upload.is_public.as_ref().map_or(false, |val| val.0),
-    );
+    let (image_bytes, metadata_bytes) = (&upload.image.data, &upload.metadata.data);
+    let visibility = match (&upload.visibility, &upload.is_public) {
+        (Some(visibility), _) => visibility.0,
+        (None, Some(is_public)) => Visibility::from_is_public(is_public.0),
+        (None, None) => Visibility::Private,
+    };
 
-    let metadata: Metadata = match serde_json::from_slice(metadata_bytes) {
+    let mut metadata: Metadata = match serde_json::from_slice(metadata_bytes) {
         Ok(metadata) => metadata,
         Err(error) => {
             tracing::error!("failed to parse metadata: {}", error);
@@ -88,6 +139,82 @@ pub async fn upload_image(
         return HttpResponse::BadRequest().json(ResponseError::new("invalid user address"));
     }
 
+    // People who don't want to be tagged are removed from the photo, unless they took it
+    let tagged_addresses = metadata
+        .visible_people
+        .iter()
+        .map(|user| user.user_address.clone())
+        .collect::<Vec<_>>();
+    match database.get_untaggable_addresses(&tagged_addresses).await {
+        Ok(untaggable) => metadata.visible_people.retain(|user| {
+            user.user_address
+                .eq_ignore_ascii_case(&metadata.user_address)
+                || !untaggable
+                    .iter()
+                    .any(|address| address.eq_ignore_ascii_case(&user.user_address))
+        }),
+        Err(error) => {
+            tracing::error!("failed to get tagging preferences: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to store image metadata"));
+        }
+    }
+
+    // The people who can see the photo get a photo-tagged event each, except its owner and the
+    // people who blocked the owner
+    let mut notified_people: Vec<User> = Vec::new();
+    if !visibility.is_restricted() {
+        for user in &metadata.visible_people {
+            if !user.user_address.is_empty()
+                && !user
+                    .user_address
+                    .eq_ignore_ascii_case(&metadata.user_address)
+                && !notified_people.iter().any(|notified| {
+                    notified
+                        .user_address
+                        .eq_ignore_ascii_case(&user.user_address)
+                })
+            {
+                notified_people.push(user.clone());
+            }
+        }
+    }
+    let notified_addresses = notified_people
+        .iter()
+        .map(|user| user.user_address.clone())
+        .collect::<Vec<_>>();
+    match database
+        .get_blocking_addresses(&notified_addresses, &metadata.user_address)
+        .await
+    {
+        Ok(blocking) => notified_people.retain(|user| {
+            !blocking
+                .iter()
+                .any(|address| address.eq_ignore_ascii_case(&user.user_address))
+        }),
+        Err(error) => {
+            tracing::error!("failed to get blocking users: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to store image metadata"));
+        }
+    }
+
+    let caption = normalize_text(upload.caption.as_ref().map(|caption| caption.as_str()));
+    let alt_text = normalize_text(upload.alt_text.as_ref().map(|alt_text| alt_text.as_str()));
+    if let Err(message) = validate_image_texts(caption.as_deref(), alt_text.as_deref()) {
+        return HttpResponse::BadRequest().json(ResponseError::new(&message));
+    }
+
+    let explicit_tags = upload
+        .tags
+        .iter()
+        .map(|tags| tags.to_string())
+        .collect::<Vec<_>>();
+    let (explicit_tags, tags) = match collect_tags(caption.as_deref(), &explicit_tags) {
+        Ok(tags) => tags,
+        Err(message) => return HttpResponse::BadRequest().json(ResponseError::new(&message)),
+    };
+
     let Some(content_type) = upload
         .image
         .content_type
@@ -137,11 +264,28 @@ pub async fn upload_image(
         return HttpResponse::BadRequest().json(ResponseError::new("invalid file name"));
     }
 
-    let image_name = format!("{image_id}-{image_file_name}");
-    let thumbnail_name = format!("{image_id}-thumbnail-{image_file_name}");
+    let extension = match format {
+        ImageFormat::Jpeg => "jpg",
+        _ => "png",
+    };
+    let uploaded_at = chrono::Utc::now().naive_utc();
+    let key = |rendition| {
+        image_key(
+            &metadata.user_address,
+            uploaded_at,
+            &image_id,
+            rendition,
+            extension,
+        )
+    };
+    let (image_key, thumbnail_key) = (key(Rendition::Original), key(Rendition::Thumbnail));
 
-    if let Err(error) = bucket
-        .put_object_with_content_type(image_name.clone(), image_bytes, content_type.as_str())
+    if let Err(error) = storage
+        .put(
+            &object_key(&image_key, visibility),
+            image_bytes,
+            content_type.as_str(),
+        )
         .await
     {
         tracing::error!("failed to upload image: {}", error);
@@ -149,9 +293,9 @@ pub async fn upload_image(
             .json(ResponseError::new("failed to upload image"));
     }
 
-    if let Err(error) = bucket
-        .put_object_with_content_type(
-            thumbnail_name.clone(),
+    if let Err(error) = storage
+        .put(
+            &object_key(&thumbnail_key, visibility),
             thumbnail.get_ref(),
             content_type.as_str(),
         )
@@ -162,63 +306,63 @@ pub async fn upload_image(
             .json(ResponseError::new("failed to upload image"));
     }
 
-    let http_url = &settings.api_url;
-
     let image = Image {
         id: image_id.clone(),
-        url: format!("{http_url}/api/images/{image_name}"),
-        thumbnail_url: format!("{http_url}/api/images/{thumbnail_name}"),
-        is_public,
+        url: urls.file_url(&image_id, &image_key, visibility, Rendition::Original),
+        thumbnail_url: urls.file_url(&image_id, &thumbnail_key, visibility, Rendition::Thumbnail),
+        is_public: visibility.is_public(),
+        visibility,
         metadata: metadata.clone(),
+        caption,
+        alt_text,
+        tags,
     };
 
-    if let Err(error) = database.insert_image(&image).await {
+    // Queued with the image, published by the outbox relay
+    let sns_event = Event::new(
+        &image_id,
+        EventPayload::PhotoTaken(PhotoTakenMetadata {
+            schema_version: SchemaVersion,
+            photo_id: image_id.clone(),
+            user_address: metadata.user_address.to_lowercase(),
+            realm: metadata.realm.clone(),
+            scene_id: metadata.scene.name.clone(),
+            place_id: metadata.place_id.clone(),
+            is_public: visibility.is_public(),
+            visibility,
+            tags: image.tags.clone(),
+            users: metadata
+                .visible_people
+                .iter()
+                .map(|user| EventUser {
+                    address: user.user_address.clone(),
+                    is_emoting: user.is_emoting.unwrap_or(false),
+                })
+                .collect(),
+        }),
+    );
+
+    let mut events = vec![sns_event];
+    events.extend(
+        notified_people
+            .iter()
+            .map(|user| photo_tagged_event(&image, user)),
+    );
+
+    if let Err(error) = database
+        .insert_image(&image, &image_key, &thumbnail_key, &explicit_tags, &events)
+        .await
+    {
         tracing::error!("failed to store image metadata: {}", error);
         return HttpResponse::InternalServerError()
             .json(ResponseError::new("failed to store image metadata"));
     };
 
-    // Publish SNS event
-    let mut event_metadata = HashMap::new();
-    event_metadata.insert(
-        "sceneId".to_string(),
-        serde_json::json!(metadata.scene.name),
-    );
-    event_metadata.insert("realm".to_string(), serde_json::json!(metadata.realm));
-    event_metadata.insert(
-        "userAddress".to_string(),
-        serde_json::json!(metadata.user_address.to_lowercase().to_string()),
-    );
-    event_metadata.insert("isPublic".to_string(), serde_json::json!(is_public));
-    event_metadata.insert("photoId".to_string(), serde_json::json!(image_id));
-    event_metadata.insert("placeId".to_string(), serde_json::json!(metadata.place_id));
-
-    // Convert visible_people to the required format
-    let users: Vec<serde_json::Value> = metadata
-        .visible_people
-        .iter()
-        .map(|user| {
-            serde_json::json!({
-                "address": user.user_address,
-                "isEmoting": user.is_emoting.unwrap_or(false)
-            })
-        })
-        .collect();
-    event_metadata.insert("users".to_string(), serde_json::json!(users));
-
-    let sns_event = Event {
-        event_type: EventType::Camera,
-        sub_type: EventSubtype::PhotoTaken,
-        key: image_id.clone(),
-        timestamp: chrono::Utc::now().timestamp() as u64,
-        metadata: event_metadata,
+    let image = Image {
+        alt_text: image.alt_text.or_else(|| Some(metadata.default_alt_text())),
+        ..image
     };
 
-    if let Err(error) = sns_publisher.publish(&sns_event).await {
-        tracing::error!("failed to publish SNS event: {}", error);
-        // Don't return error here as the image was successfully uploaded
-    }
-
     let user_data = UserDataResponse {
         current_images: images_count + 1,
         max_images: settings.max_images_per_user,
@@ -0,0 +1,174 @@
+use actix_web::{
+    get, post,
+    web::{Data, Json, Path},
+    HttpResponse, Responder,
+};
+use actix_web_lab::extract::Query;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    api::{GalleryImageWithPlace, ResponseError},
+    database::Database,
+    urls::UrlBuilder,
+};
+
+/// Maximum number of wearable URNs accepted in a single `POST /wearables/usage` request.
+const MAX_WEARABLES_URNS: usize = 100;
+
+/// Upper bound for the client-supplied pagination `limit`.
+const MAX_LIMIT: u64 = 100;
+
+#[derive(Deserialize, Debug, IntoParams)]
+struct GetWearableImagesQuery {
+    #[serde(default = "default_offset")]
+    offset: u64,
+    #[serde(default = "default_limit")]
+    limit: u64,
+}
+
+fn default_offset() -> u64 {
+    0
+}
+
+fn default_limit() -> u64 {
+    20
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WearableDataResponse {
+    pub max_images: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GetWearableImagesResponse {
+    pub images: Vec<GalleryImageWithPlace>,
+    #[serde(flatten)]
+    pub wearable_data: WearableDataResponse,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GetWearablesUsageBody {
+    pub urns: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WearableUsage {
+    pub urn: String,
+    /// Number of public images the wearable appears in.
+    pub images: u64,
+    /// Number of different people wearing it across those images.
+    pub people: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GetWearablesUsageResponse {
+    pub wearables: Vec<WearableUsage>,
+}
+
+#[tracing::instrument(skip(database, urls))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    params(
+        GetWearableImagesQuery
+    ),
+    responses(
+        (status = 200, description = "List public images where a visible person wears the given wearable", body = GetWearableImagesResponse),
+        (status = 404, description = "Not found")
+    )
+)]
+#[get("/wearables/{urn}/images")]
+pub async fn get_wearable_images(
+    urn: Path<String>,
+    query_params: Query<GetWearableImagesQuery>,
+    database: Data<Database>,
+    urls: Data<UrlBuilder>,
+) -> impl Responder {
+    let urn = urn.into_inner();
+    let GetWearableImagesQuery { offset, limit } = query_params.into_inner();
+    let limit = limit.min(MAX_LIMIT);
+
+    let Ok(images_count) = database.get_wearable_images_count(&urn).await else {
+        return HttpResponse::NotFound().json(ResponseError::new("wearable not found"));
+    };
+
+    let Ok(images) = database
+        .get_wearable_images(&urn, offset as i64, limit as i64)
+        .await
+    else {
+        return HttpResponse::NotFound().json(ResponseError::new("wearable not found"));
+    };
+
+    let wearable_data = WearableDataResponse {
+        max_images: images_count,
+    };
+
+    let images = images
+        .into_iter()
+        .map(|image| GalleryImageWithPlace::from_db(image, &urls))
+        .collect::<Vec<GalleryImageWithPlace>>();
+
+    HttpResponse::Ok().json(GetWearableImagesResponse {
+        images,
+        wearable_data,
+    })
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "images",
+    context_path = "/api",
+    request_body = GetWearablesUsageBody,
+    responses(
+        (status = 200, description = "Public usage counts of the given wearables, in the requested order", body = GetWearablesUsageResponse),
+        (status = 400, description = "Bad Request", body = ResponseError),
+        (status = 500, description = "Internal Server Error", body = ResponseError)
+    )
+)]
+#[post("/wearables/usage")]
+pub async fn get_wearables_usage(
+    body: Json<GetWearablesUsageBody>,
+    database: Data<Database>,
+) -> impl Responder {
+    let GetWearablesUsageBody { urns } = body.into_inner();
+
+    if urns.len() > MAX_WEARABLES_URNS {
+        return HttpResponse::BadRequest().json(ResponseError::new(&format!(
+            "too many urns, maximum is {MAX_WEARABLES_URNS}"
+        )));
+    }
+
+    let usage = match database.get_wearables_usage(&urns).await {
+        Ok(usage) => usage,
+        Err(error) => {
+            tracing::error!("failed to get wearables usage: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to get wearables usage"));
+        }
+    };
+
+    // Every requested wearable is listed, the unused ones with zero counts
+    let wearables = urns
+        .into_iter()
+        .map(|urn| {
+            let (images, people) = usage
+                .iter()
+                .find(|usage| usage.urn.eq_ignore_ascii_case(&urn))
+                .map(|usage| (usage.images as u64, usage.people as u64))
+                .unwrap_or_default();
+            WearableUsage {
+                urn,
+                images,
+                people,
+            }
+        })
+        .collect();
+
+    HttpResponse::Ok().json(GetWearablesUsageResponse { wearables })
+}
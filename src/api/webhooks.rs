@@ -0,0 +1,411 @@
+use actix_web::{
+    delete, get, post,
+    web::{Data, Json, Path},
+    HttpResponse, Responder,
+};
+use actix_web_lab::extract::Query;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    api::{auth::AuthUser, ResponseError},
+    database::{DBWebhookDelivery, DBWebhookSubscription, Database},
+    places_client::PlacesClient,
+    webhooks::resolve_webhook_url,
+    Environment, Settings,
+};
+
+/// Maximum number of webhook subscriptions of a user.
+const MAX_SUBSCRIPTIONS_PER_USER: u64 = 25;
+
+/// Upper bound for the client-supplied `limit` of the delivery log.
+const MAX_LIMIT: i64 = 100;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "webhook_delivery_status", rename_all = "lowercase")]
+pub enum WebhookDeliveryStatus {
+    /// Waiting for its first attempt or for a retry.
+    Pending,
+    Delivered,
+    /// Given up after too many failed attempts.
+    Failed,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookSubscription {
+    pub id: String,
+    /// Place ID, or world name for every place of the world.
+    pub place_id: String,
+    pub url: String,
+    /// Failed deliveries in a row, the subscription is disabled after too many.
+    pub consecutive_failures: u32,
+    pub disabled_at: Option<String>,
+    pub created_at: String,
+}
+
+impl From<DBWebhookSubscription> for WebhookSubscription {
+    fn from(value: DBWebhookSubscription) -> Self {
+        Self {
+            id: value.id.to_string(),
+            place_id: value.place_id,
+            url: value.url,
+            consecutive_failures: value.consecutive_failures as u32,
+            disabled_at: value
+                .disabled_at
+                .map(|disabled_at| disabled_at.and_utc().to_rfc3339()),
+            created_at: value.created_at.and_utc().to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedWebhookSubscription {
+    #[serde(flatten)]
+    pub subscription: WebhookSubscription,
+    /// Key of the `X-Webhook-Signature` HMAC. Only returned on creation.
+    pub secret: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateWebhookSubscription {
+    /// Place ID, or world name (ending in `.eth`) for every place of the world.
+    pub place_id: String,
+    pub url: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GetWebhookSubscriptionsResponse {
+    pub subscriptions: Vec<WebhookSubscription>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDelivery {
+    pub id: String,
+    pub event_id: String,
+    pub event_type: String,
+    /// Id of the photo the event is about.
+    pub key: String,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: u32,
+    /// Status of the last response, absent when the webhook couldn't be reached.
+    pub response_status: Option<u16>,
+    pub last_error: Option<String>,
+    pub created_at: String,
+    /// Next attempt, while the delivery is pending.
+    pub next_attempt_at: Option<String>,
+    pub completed_at: Option<String>,
+}
+
+impl From<DBWebhookDelivery> for WebhookDelivery {
+    fn from(value: DBWebhookDelivery) -> Self {
+        Self {
+            id: value.id.to_string(),
+            event_id: value.event_id,
+            event_type: value.event.sub_type().to_string(),
+            key: value.event.key.clone(),
+            status: value.status,
+            attempts: value.attempts as u32,
+            response_status: value.response_status.map(|status| status as u16),
+            last_error: value.last_error,
+            created_at: value.created_at.and_utc().to_rfc3339(),
+            next_attempt_at: (value.status == WebhookDeliveryStatus::Pending)
+                .then(|| value.next_attempt_at.and_utc().to_rfc3339()),
+            completed_at: value
+                .completed_at
+                .map(|completed_at| completed_at.and_utc().to_rfc3339()),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GetWebhookDeliveriesResponse {
+    pub deliveries: Vec<WebhookDelivery>,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+struct GetWebhookDeliveriesQuery {
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+fn default_limit() -> i64 {
+    20
+}
+
+/// Secrets are 64 hex characters, long enough that they can't be guessed.
+fn generate_secret() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Webhooks must be absolute HTTP URLs, HTTPS only in production.
+fn validate_url(url: &str, env: &Environment) -> Result<reqwest::Url, String> {
+    let parsed = reqwest::Url::parse(url).map_err(|_| "invalid url".to_string())?;
+    match (parsed.scheme(), env) {
+        ("https", _) | ("http", Environment::Dev) => {}
+        _ => return Err("url must use https".to_string()),
+    }
+    if parsed.host_str().is_none() {
+        return Err("invalid url".to_string());
+    }
+
+    Ok(parsed)
+}
+
+#[tracing::instrument(skip(settings, database, places_client))]
+#[utoipa::path(
+    tag = "webhooks",
+    context_path = "/api",
+    request_body(content = CreateWebhookSubscription, description = "Place or world to watch and URL to notify", content_type = "application/json"),
+    responses(
+        (status = 200, description = "Created subscription, along with its signing secret", body = CreatedWebhookSubscription),
+        (status = 400, description = "Bad Request", body = ResponseError),
+        (status = 502, description = "Failed to resolve the world name", body = ResponseError),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to create the subscription"),
+    )
+)]
+#[post("/webhooks")]
+pub async fn create_webhook_subscription(
+    auth_user: AuthUser,
+    settings: Data<Settings>,
+    database: Data<Database>,
+    places_client: Data<PlacesClient>,
+    body: Json<CreateWebhookSubscription>,
+) -> impl Responder {
+    let CreateWebhookSubscription { place_id, url } = body.into_inner();
+    let place_id = place_id.trim().to_string();
+
+    if place_id.is_empty() {
+        return HttpResponse::BadRequest().json(ResponseError::new("placeId is required"));
+    }
+
+    let parsed_url = match validate_url(&url, &settings.env) {
+        Ok(parsed_url) => parsed_url,
+        Err(error) => return HttpResponse::BadRequest().json(ResponseError::new(&error)),
+    };
+    if let Err(error) =
+        resolve_webhook_url(&parsed_url, settings.webhooks.allow_private_networks).await
+    {
+        return HttpResponse::BadRequest().json(ResponseError::new(&error));
+    }
+
+    let mut world_place_ids = Vec::new();
+    if place_id.ends_with(".eth") {
+        match places_client.get_world_place_ids(&place_id).await {
+            Ok(ids) if ids.is_empty() => {
+                return HttpResponse::BadRequest().json(ResponseError::new("world not found"))
+            }
+            Ok(ids) => world_place_ids = ids,
+            Err(e) => {
+                tracing::error!("Failed to resolve world name '{}': {}", place_id, e);
+                return HttpResponse::BadGateway().json(ResponseError::new(&format!(
+                    "failed to resolve world name: {e}"
+                )));
+            }
+        }
+    }
+
+    match database
+        .get_user_webhook_subscriptions_count(&auth_user.address)
+        .await
+    {
+        Ok(count) if count >= MAX_SUBSCRIPTIONS_PER_USER => {
+            return HttpResponse::BadRequest().json(ResponseError::new(&format!(
+                "too many webhook subscriptions, maximum is {MAX_SUBSCRIPTIONS_PER_USER}"
+            )))
+        }
+        Ok(_) => {}
+        Err(error) => {
+            tracing::error!("failed to count webhook subscriptions: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to create webhook subscription"));
+        }
+    }
+
+    match database
+        .insert_webhook_subscription(
+            &auth_user.address,
+            &place_id,
+            &world_place_ids,
+            &url,
+            &generate_secret(),
+        )
+        .await
+    {
+        Ok(subscription) => HttpResponse::Ok().json(CreatedWebhookSubscription {
+            secret: subscription.secret.clone(),
+            subscription: subscription.into(),
+        }),
+        Err(error) => {
+            tracing::error!("failed to create webhook subscription: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to create webhook subscription"))
+        }
+    }
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "webhooks",
+    context_path = "/api",
+    responses(
+        (status = 200, description = "Webhook subscriptions of the user, oldest first", body = GetWebhookSubscriptionsResponse),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to get the subscriptions"),
+    )
+)]
+#[get("/webhooks")]
+pub async fn get_webhook_subscriptions(
+    auth_user: AuthUser,
+    database: Data<Database>,
+) -> impl Responder {
+    match database
+        .get_user_webhook_subscriptions(&auth_user.address)
+        .await
+    {
+        Ok(subscriptions) => HttpResponse::Ok().json(GetWebhookSubscriptionsResponse {
+            subscriptions: subscriptions.into_iter().map(Into::into).collect(),
+        }),
+        Err(error) => {
+            tracing::error!("failed to get webhook subscriptions: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to get webhook subscriptions"))
+        }
+    }
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "webhooks",
+    context_path = "/api",
+    responses(
+        (status = 204, description = "Deleted subscription, along with its delivery log"),
+        (status = NOT_FOUND, description = "Subscription was not found"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to delete the subscription"),
+    )
+)]
+#[delete("/webhooks/{subscription_id}")]
+pub async fn delete_webhook_subscription(
+    auth_user: AuthUser,
+    subscription_id: Path<String>,
+    database: Data<Database>,
+) -> impl Responder {
+    match database
+        .delete_webhook_subscription(&subscription_id, &auth_user.address)
+        .await
+    {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        // Subscriptions of other users look like missing ones
+        Ok(false) | Err(sqlx::Error::Protocol(_)) => {
+            HttpResponse::NotFound().json(ResponseError::new("webhook subscription not found"))
+        }
+        Err(error) => {
+            tracing::error!("failed to delete webhook subscription: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to delete webhook subscription"))
+        }
+    }
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "webhooks",
+    context_path = "/api",
+    responses(
+        (status = 200, description = "Enabled subscription, its failures reset and its pending deliveries resumed", body = WebhookSubscription),
+        (status = NOT_FOUND, description = "Subscription was not found"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to enable the subscription"),
+    )
+)]
+#[post("/webhooks/{subscription_id}/enable")]
+pub async fn enable_webhook_subscription(
+    auth_user: AuthUser,
+    subscription_id: Path<String>,
+    database: Data<Database>,
+) -> impl Responder {
+    match database
+        .enable_webhook_subscription(&subscription_id, &auth_user.address)
+        .await
+    {
+        Ok(Some(subscription)) => HttpResponse::Ok().json(WebhookSubscription::from(subscription)),
+        Ok(None) | Err(sqlx::Error::Protocol(_)) => {
+            HttpResponse::NotFound().json(ResponseError::new("webhook subscription not found"))
+        }
+        Err(error) => {
+            tracing::error!("failed to enable webhook subscription: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to enable webhook subscription"))
+        }
+    }
+}
+
+#[tracing::instrument(skip(database))]
+#[utoipa::path(
+    tag = "webhooks",
+    context_path = "/api",
+    params(
+        GetWebhookDeliveriesQuery
+    ),
+    responses(
+        (status = 200, description = "Last deliveries of the subscription, newest first", body = GetWebhookDeliveriesResponse),
+        (status = NOT_FOUND, description = "Subscription was not found"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to get the deliveries"),
+    )
+)]
+#[get("/webhooks/{subscription_id}/deliveries")]
+pub async fn get_webhook_deliveries(
+    auth_user: AuthUser,
+    subscription_id: Path<String>,
+    database: Data<Database>,
+    query: Query<GetWebhookDeliveriesQuery>,
+) -> impl Responder {
+    let subscription = match database
+        .get_user_webhook_subscription(&subscription_id, &auth_user.address)
+        .await
+    {
+        Ok(Some(subscription)) => subscription,
+        Ok(None) | Err(sqlx::Error::Protocol(_)) => {
+            return HttpResponse::NotFound()
+                .json(ResponseError::new("webhook subscription not found"))
+        }
+        Err(error) => {
+            tracing::error!("failed to get webhook subscription: {}", error);
+            return HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to get webhook deliveries"));
+        }
+    };
+
+    match database
+        .get_webhook_deliveries(subscription.id, query.limit.clamp(1, MAX_LIMIT))
+        .await
+    {
+        Ok(deliveries) => HttpResponse::Ok().json(GetWebhookDeliveriesResponse {
+            deliveries: deliveries.into_iter().map(Into::into).collect(),
+        }),
+        Err(error) => {
+            tracing::error!("failed to get webhook deliveries: {}", error);
+            HttpResponse::InternalServerError()
+                .json(ResponseError::new("failed to get webhook deliveries"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_accepts_https_urls_in_production() {
+        assert!(validate_url("https://example.com/hook", &Environment::Prod).is_ok());
+        assert!(validate_url("http://example.com/hook", &Environment::Prod).is_err());
+        assert!(validate_url("http://localhost:3000/hook", &Environment::Dev).is_ok());
+        assert!(validate_url("ftp://example.com/hook", &Environment::Dev).is_err());
+        assert!(validate_url("not a url", &Environment::Dev).is_err());
+    }
+}
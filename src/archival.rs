@@ -0,0 +1,165 @@
+use std::time::Duration;
+
+use actix_web::web::Data;
+
+use crate::{
+    database::{DBImage, Database},
+    storage::{archive_key, copy_checked, object_key, ObjectStorage},
+};
+
+/// Images handled at once by each pass of the archival worker.
+const BATCH_SIZE: i64 = 100;
+
+/// When old originals move to the archive.
+#[derive(Debug, Clone)]
+pub struct ArchiveSettings {
+    /// Originals uploaded more than this many months ago are archived. Nothing is archived
+    /// when `None`, but the restores are still processed.
+    pub after_months: Option<u32>,
+    /// Seconds between the runs of the worker, also the retry hint given to clients waiting
+    /// for a restore.
+    pub interval_seconds: u64,
+}
+
+impl Default for ArchiveSettings {
+    fn default() -> Self {
+        Self {
+            after_months: None,
+            interval_seconds: 60,
+        }
+    }
+}
+
+/// Moves the original of an image between its hot and archived keys, and updates the row
+/// once the file was copied. The previous file is only deleted afterwards, so a failure in
+/// between leaves the image readable. Returns whether the image was moved, which it isn't when
+/// it changed in the meantime.
+async fn move_original(
+    storage: &dyn ObjectStorage,
+    database: &Database,
+    image: &DBImage,
+    archive: bool,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let hot_key = object_key(&image.image_key, image.visibility);
+    let archived_key = object_key(&archive_key(&image.image_key), image.visibility);
+    let (from, to) = if archive {
+        (hot_key, archived_key)
+    } else {
+        (archived_key, hot_key)
+    };
+
+    // Objects a lifecycle rule moved to a class read asynchronously can't be copied until
+    // they are restored, which isn't supported
+    if !archive && storage.head(&from).await?.needs_restore {
+        return Err(format!(
+            "{from} is in a storage class that needs a restore, only instant retrieval classes are supported"
+        )
+        .into());
+    }
+
+    // Incomplete copies are overwritten when trying again
+    copy_checked(storage, &from, &to).await?;
+
+    if !database.set_image_archived(image, archive).await? {
+        if let Err(error) = storage.delete(&to).await {
+            tracing::error!("failed to delete {}: {}", to, error);
+        }
+        return Ok(false);
+    }
+
+    if let Err(error) = storage.delete(&from).await {
+        tracing::error!("failed to delete {}: {}", from, error);
+    }
+
+    Ok(true)
+}
+
+/// Archives the originals uploaded more than `months` ago. Returns how many were archived.
+pub async fn archive_old_originals(
+    storage: &dyn ObjectStorage,
+    database: &Database,
+    months: u32,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut archived = 0;
+    let mut last = None;
+    loop {
+        let images = database
+            .get_archivable_images(months as i32, last.as_ref(), BATCH_SIZE)
+            .await?;
+        if images.is_empty() {
+            return Ok(archived);
+        }
+
+        for image in &images {
+            match move_original(storage, database, image, true).await {
+                Ok(true) => archived += 1,
+                Ok(false) => {}
+                Err(error) => tracing::error!("failed to archive image {}: {}", image.id, error),
+            }
+        }
+
+        last = images.into_iter().next_back();
+    }
+}
+
+/// Moves back the archived originals whose restore was requested, the oldest requests first.
+/// Returns how many restores were processed, failed ones included.
+pub async fn process_restores(
+    storage: &dyn ObjectStorage,
+    database: &Database,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut processed = 0;
+    for image in database.get_pending_restore_images(BATCH_SIZE).await? {
+        // Images made hot again some other way don't need anything
+        let result = match image.archived_at {
+            Some(_) => move_original(storage, database, &image, false).await,
+            None => Ok(true),
+        };
+        let error = match result {
+            Ok(true) => None,
+            // Changed while being restored, it's picked up again by the next run
+            Ok(false) => continue,
+            Err(error) => {
+                tracing::error!("failed to restore image {}: {}", image.id, error);
+                Some(error.to_string())
+            }
+        };
+        database
+            .complete_image_restore(image.id, error.as_deref())
+            .await?;
+        processed += 1;
+    }
+
+    Ok(processed)
+}
+
+/// Runs the archival worker in the background, processing the restores and archiving old
+/// originals every `interval_seconds`.
+pub fn spawn_archival_worker(
+    settings: ArchiveSettings,
+    storage: Data<dyn ObjectStorage>,
+    database: Data<Database>,
+) {
+    actix_web::rt::spawn(async move {
+        let mut interval =
+            actix_web::rt::time::interval(Duration::from_secs(settings.interval_seconds.max(1)));
+        loop {
+            interval.tick().await;
+
+            match process_restores(storage.get_ref(), &database).await {
+                Ok(0) => {}
+                Ok(restored) => tracing::info!("processed {} image restores", restored),
+                Err(error) => tracing::error!("failed to process image restores: {}", error),
+            }
+
+            let Some(months) = settings.after_months else {
+                continue;
+            };
+            match archive_old_originals(storage.get_ref(), &database, months).await {
+                Ok(0) => {}
+                Ok(archived) => tracing::info!("archived {} image originals", archived),
+                Err(error) => tracing::error!("failed to archive image originals: {}", error),
+            }
+        }
+    });
+}
@@ -2,17 +2,51 @@ use serde::__private::fmt::Debug;
 use sqlx::pool::PoolConnection;
 use sqlx::types::{chrono, Uuid};
 use sqlx::{
-    postgres::{PgConnectOptions, PgPoolOptions},
+    postgres::{PgConnectOptions, PgListener, PgPoolOptions},
     PgPool,
 };
-use sqlx::{Error as DBError, Postgres, QueryBuilder};
+use sqlx::{Error as DBError, Postgres, QueryBuilder, Transaction};
 
 use std::str::FromStr;
 
-use crate::api::{Image, Metadata};
+use crate::api::restore::RestoreStatus;
+use crate::api::webhooks::WebhookDeliveryStatus;
+use crate::api::{Image, Metadata, Visibility};
+use crate::events::Event;
+use crate::storage::{archive_key, Rendition};
 
 pub type DBResult<V> = Result<V, DBError>;
 
+/// Selects every image column plus the image tags, sorted alphabetically.
+const SELECT_IMAGES: &str = "SELECT images.*, ARRAY(SELECT tag FROM image_tags WHERE image_tags.image_id = images.id ORDER BY tag) AS tags FROM images";
+
+/// Matches the share links that haven't been revoked, expired or used up.
+const SHARE_LINK_IS_USABLE: &str = "revoked_at IS NULL
+    AND (expires_at IS NULL OR expires_at > now())
+    AND (max_views IS NULL OR views < max_views)";
+
+/// Matches the notifications the user can still see: the image is viewable by the user, who
+/// still appears in it and hasn't blocked its owner since.
+const NOTIFICATION_IS_VISIBLE: &str = "EXISTS (
+        SELECT 1 FROM images WHERE images.id = notifications.image_id
+        AND (images.visibility IN ('public', 'unlisted')
+            OR (images.visibility = 'shared' AND EXISTS (
+                SELECT 1 FROM image_shares WHERE image_shares.image_id = images.id
+                AND image_shares.user_address = notifications.user_address)))
+    )
+    AND EXISTS (
+        SELECT 1 FROM image_people WHERE image_people.image_id = notifications.image_id
+        AND image_people.user_address = notifications.user_address
+    )
+    AND NOT EXISTS (
+        SELECT 1 FROM user_blocks WHERE user_blocks.user_address = notifications.user_address
+        AND user_blocks.blocked_address = notifications.actor_address
+    )";
+
+/// Postgres channel notified with the id of each image that becomes public, once the change is
+/// committed.
+const PUBLIC_IMAGES_CHANNEL: &str = "public_images";
+
 #[derive(Debug, Clone)]
 pub struct Database {
     pool: PgPool,
@@ -48,6 +82,15 @@ impl Database {
         self.pool.acquire().await
     }
 
+    /// Listens to the images becoming public on any replica, each notification carries the id
+    /// of the image.
+    pub async fn listen_public_images(&self) -> DBResult<PgListener> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(PUBLIC_IMAGES_CHANNEL).await?;
+
+        Ok(listener)
+    }
+
     async fn migrate(&self) -> Result<(), sqlx::migrate::MigrateError> {
         sqlx::migrate!("./migrations").run(&self.pool).await
     }
@@ -68,7 +111,7 @@ impl Database {
     }
 
     pub async fn get_image(&self, id: &str) -> DBResult<DBImage> {
-        let image = sqlx::query_as::<_, DBImage>("SELECT * FROM images WHERE id = $1")
+        let image = sqlx::query_as::<_, DBImage>(&format!("{SELECT_IMAGES} WHERE id = $1"))
             .bind(parse_uuid(id)?)
             .fetch_one(&self.pool)
             .await?;
@@ -101,6 +144,26 @@ impl Database {
                 query_builder.push_bind(filter_value);
                 query_builder.push(")");
             }
+            "appearances" => {
+                // Private images are only listed when they were taken by the person appearing
+                // in them, other users' private images stay private.
+                query_builder
+                    .push("id IN (SELECT image_id FROM image_people WHERE user_address = ");
+                query_builder.push_bind(filter_value[0].to_lowercase());
+                query_builder.push(") AND (visibility = 'public' OR user_address = ");
+                query_builder.push_bind(filter_value[0].to_lowercase());
+                query_builder.push(")");
+            }
+            "wearable" => {
+                query_builder.push("id IN (SELECT image_id FROM image_wearables WHERE urn = ");
+                query_builder.push_bind(filter_value[0].to_lowercase());
+                query_builder.push(")");
+            }
+            "tag" => {
+                query_builder.push("id IN (SELECT image_id FROM image_tags WHERE tag = ");
+                query_builder.push_bind(&filter_value[0]);
+                query_builder.push(")");
+            }
             _ => {
                 tracing::error!("Unsupported filter field: {}", filter_field);
                 return Err(DBError::Protocol(format!(
@@ -111,7 +174,7 @@ impl Database {
         }
 
         if public_only {
-            query_builder.push(" AND is_public = true");
+            query_builder.push(" AND visibility = 'public'");
         }
 
         Ok(query_builder)
@@ -125,12 +188,8 @@ impl Database {
         limit: i64,
         public_only: bool,
     ) -> DBResult<Vec<DBImage>> {
-        let mut query_builder = self.build_images_query(
-            filter_field,
-            filter_value,
-            public_only,
-            "SELECT * FROM images",
-        )?;
+        let mut query_builder =
+            self.build_images_query(filter_field, filter_value, public_only, SELECT_IMAGES)?;
 
         query_builder
             .push(" ORDER BY created_at DESC LIMIT ")
@@ -201,11 +260,53 @@ impl Database {
             .await
     }
 
+    pub async fn get_user_appearances(
+        &self,
+        user: &str,
+        offset: i64,
+        limit: i64,
+        public_only: bool,
+    ) -> DBResult<Vec<DBImage>> {
+        self.get_images(
+            "appearances",
+            &[user.to_string()],
+            offset,
+            limit,
+            public_only,
+        )
+        .await
+    }
+
+    pub async fn get_wearable_images(
+        &self,
+        urn: &str,
+        offset: i64,
+        limit: i64,
+    ) -> DBResult<Vec<DBImage>> {
+        self.get_images("wearable", &[urn.to_string()], offset, limit, true)
+            .await
+    }
+
+    pub async fn get_tag_images(
+        &self,
+        tag: &str,
+        offset: i64,
+        limit: i64,
+    ) -> DBResult<Vec<DBImage>> {
+        self.get_images("tag", &[tag.to_string()], offset, limit, true)
+            .await
+    }
+
     pub async fn get_user_images_count(&self, user: &str, public_only: bool) -> DBResult<u64> {
         self.get_images_count("user_address", &[user.to_string()], public_only)
             .await
     }
 
+    pub async fn get_user_appearances_count(&self, user: &str, public_only: bool) -> DBResult<u64> {
+        self.get_images_count("appearances", &[user.to_string()], public_only)
+            .await
+    }
+
     pub async fn get_place_images_count(&self, place_id: &str) -> DBResult<u64> {
         self.get_images_count("place_id", &[place_id.to_string()], true)
             .await
@@ -215,51 +316,1984 @@ impl Database {
         self.get_images_count("places_ids", places_ids, true).await
     }
 
-    pub async fn delete_image(&self, id: &str) -> DBResult<()> {
+    pub async fn get_tag_images_count(&self, tag: &str) -> DBResult<u64> {
+        self.get_images_count("tag", &[tag.to_string()], true).await
+    }
+
+    pub async fn get_wearable_images_count(&self, urn: &str) -> DBResult<u64> {
+        self.get_images_count("wearable", &[urn.to_string()], true)
+            .await
+    }
+
+    /// Counts the public images each of the given wearables, lowercased, appears in. Wearables
+    /// that don't appear in any public image are left out.
+    pub async fn get_wearables_usage(&self, urns: &[String]) -> DBResult<Vec<DBWearableUsage>> {
+        let urns = urns
+            .iter()
+            .map(|urn| urn.to_lowercase())
+            .collect::<Vec<_>>();
+
+        let usage = sqlx::query_as::<_, DBWearableUsage>(
+            "SELECT image_wearables.urn, COUNT(DISTINCT image_wearables.image_id) AS images,
+                COUNT(DISTINCT image_wearables.user_address) AS people
+            FROM image_wearables
+            JOIN images ON images.id = image_wearables.image_id
+            WHERE image_wearables.urn = ANY($1) AND images.visibility = 'public'
+            GROUP BY image_wearables.urn",
+        )
+        .bind(urns)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(usage)
+    }
+
+    fn build_search_query<'a>(
+        &self,
+        filter: &'a SearchFilter,
+        initial_clause: &'a str,
+    ) -> QueryBuilder<'a, Postgres> {
+        let mut query_builder = QueryBuilder::new(initial_clause);
+
+        query_builder.push(", websearch_to_tsquery('simple', ");
+        query_builder.push_bind(&filter.query);
+        query_builder.push(") AS query WHERE search_vector @@ query AND visibility = 'public'");
+
+        if let Some(realm) = &filter.realm {
+            query_builder.push(" AND metadata->>'realm' = ");
+            query_builder.push_bind(realm);
+        }
+        if let Some(from) = filter.from {
+            query_builder.push(" AND created_at >= ");
+            query_builder.push_bind(from);
+        }
+        if let Some(to) = filter.to {
+            query_builder.push(" AND created_at < ");
+            query_builder.push_bind(to);
+        }
+
+        query_builder
+    }
+
+    /// Full-text search over public images, ranked by relevance and then by recency.
+    pub async fn search_images(
+        &self,
+        filter: &SearchFilter,
+        offset: i64,
+        limit: i64,
+    ) -> DBResult<Vec<DBImage>> {
+        let initial_clause = SELECT_IMAGES.replacen(
+            " FROM images",
+            ", ts_rank(search_vector, query) AS rank FROM images",
+            1,
+        );
+        let mut query_builder = self.build_search_query(filter, &initial_clause);
+
+        query_builder
+            .push(" ORDER BY rank DESC, created_at DESC LIMIT ")
+            .push_bind(limit)
+            .push(" OFFSET ")
+            .push_bind(offset);
+
+        let images = query_builder
+            .build_query_as::<DBImage>()
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(images)
+    }
+
+    pub async fn search_images_count(&self, filter: &SearchFilter) -> DBResult<u64> {
+        let mut query_builder = self.build_search_query(filter, "SELECT COUNT(*) FROM images");
+
+        let count = query_builder
+            .build_query_scalar::<i64>()
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(count as u64)
+    }
+
+    /// Counts the public images of each tag uploaded since `since_hours` ago, most used
+    /// tags first.
+    pub async fn get_trending_tags(
+        &self,
+        since_hours: i32,
+        limit: i64,
+    ) -> DBResult<Vec<DBTagCount>> {
+        let tags = sqlx::query_as::<_, DBTagCount>(
+            "SELECT image_tags.tag, COUNT(*) AS count FROM image_tags
+            JOIN images ON images.id = image_tags.image_id
+            WHERE images.visibility = 'public' AND images.created_at >= now() - make_interval(hours => $1)
+            GROUP BY image_tags.tag
+            ORDER BY count DESC, image_tags.tag ASC LIMIT $2",
+        )
+        .bind(since_hours)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tags)
+    }
+
+    pub async fn get_explicit_image_tags(&self, id: &str) -> DBResult<Vec<String>> {
+        let tags = sqlx::query_scalar::<_, String>(
+            "SELECT tag FROM image_tags WHERE image_id = $1 AND is_explicit = true ORDER BY tag",
+        )
+        .bind(parse_uuid(id)?)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tags)
+    }
+
+    /// Deletes the image and queues `event` in the outbox.
+    pub async fn delete_image(&self, id: &str, event: &Event) -> DBResult<()> {
+        let mut transaction = self.pool.begin().await?;
+
         sqlx::query("DELETE FROM images WHERE id = $1")
             .bind(parse_uuid(id)?)
-            .execute(&self.pool)
+            .execute(&mut *transaction)
             .await?;
 
-        Ok(())
+        insert_outbox_event(&mut transaction, event).await?;
+
+        transaction.commit().await
     }
 
-    pub async fn insert_image(&self, image: &Image) -> DBResult<()> {
-        sqlx::query("INSERT INTO images (id, user_address, url, thumbnail_url, is_public, metadata) VALUES ($1, $2, $3, $4, $5, $6)")
-            .bind(parse_uuid(&image.id)?)
+    /// Stores the image and its tags, the ones in `explicit_tags` are flagged as chosen by
+    /// the user rather than parsed from the caption, and queues `events` in the outbox. Public
+    /// images are announced to the listeners of `listen_public_images`.
+    pub async fn insert_image(
+        &self,
+        image: &Image,
+        image_key: &str,
+        thumbnail_key: &str,
+        explicit_tags: &[String],
+        events: &[Event],
+    ) -> DBResult<()> {
+        let image_id = parse_uuid(&image.id)?;
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query("INSERT INTO images (id, user_address, image_key, thumbnail_key, url, thumbnail_url, visibility, metadata, caption, alt_text) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)")
+            .bind(image_id)
             .bind(image.metadata.user_address.to_lowercase())
+            .bind(image_key)
+            .bind(thumbnail_key)
             .bind(&image.url)
             .bind(&image.thumbnail_url)
-            .bind(image.is_public)
+            .bind(image.visibility)
             .bind(sqlx::types::Json(&image.metadata))
-            .execute(&self.pool)
+            .bind(&image.caption)
+            .bind(&image.alt_text)
+            .execute(&mut *transaction)
+            .await?;
+
+        insert_image_tags(&mut transaction, image_id, &image.tags, explicit_tags).await?;
+
+        let people = image
+            .metadata
+            .visible_people
+            .iter()
+            .map(|user| user.user_address.to_lowercase())
+            .filter(|address| !address.is_empty())
+            .collect::<Vec<_>>();
+        insert_image_people(&mut transaction, image_id, &people).await?;
+
+        let (wearers, wearables): (Vec<_>, Vec<_>) = image
+            .metadata
+            .visible_people
+            .iter()
+            .flat_map(|user| {
+                user.wearables
+                    .iter()
+                    .filter(|urn| !urn.is_empty())
+                    .map(|urn| (user.user_address.to_lowercase(), urn.to_lowercase()))
+            })
+            .unzip();
+        insert_image_wearables(&mut transaction, image_id, &wearers, &wearables).await?;
+
+        for event in events {
+            insert_outbox_event(&mut transaction, event).await?;
+        }
+
+        if image.visibility.is_public() {
+            notify_public_image(&mut transaction, image_id).await?;
+        }
+
+        transaction.commit().await
+    }
+
+    /// Returns the oldest images whose files are still at the root of the bucket, uploaded after
+    /// the `after` image so the ones that failed to migrate can be skipped.
+    pub async fn get_legacy_key_images(
+        &self,
+        after: Option<&DBImage>,
+        limit: i64,
+    ) -> DBResult<Vec<DBImage>> {
+        sqlx::query_as::<_, DBImage>(&format!(
+            "{SELECT_IMAGES} WHERE image_key NOT LIKE '%/%'
+             AND ($1::timestamp IS NULL OR (created_at, id) > ($1, $2))
+             ORDER BY created_at, id LIMIT $3"
+        ))
+        .bind(after.map(|image| image.created_at))
+        .bind(after.map(|image| image.id))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Returns the oldest images that aren't public, uploaded after the `after` image.
+    pub async fn get_non_public_images(
+        &self,
+        after: Option<&DBImage>,
+        limit: i64,
+    ) -> DBResult<Vec<DBImage>> {
+        sqlx::query_as::<_, DBImage>(&format!(
+            "{SELECT_IMAGES} WHERE visibility <> 'public'
+             AND ($1::timestamp IS NULL OR (created_at, id) > ($1, $2))
+             ORDER BY created_at, id LIMIT $3"
+        ))
+        .bind(after.map(|image| image.created_at))
+        .bind(after.map(|image| image.id))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Points an image to new keys, unless its files or visibility changed since it was read.
+    /// Returns whether the image was updated.
+    pub async fn update_image_keys(
+        &self,
+        image: &DBImage,
+        image_key: &str,
+        thumbnail_key: Option<&str>,
+    ) -> DBResult<bool> {
+        let result = sqlx::query(
+            "UPDATE images SET image_key = $2, thumbnail_key = $3
+             WHERE id = $1 AND image_key = $4 AND thumbnail_key IS NOT DISTINCT FROM $5 AND visibility = $6",
+        )
+        .bind(image.id)
+        .bind(image_key)
+        .bind(thumbnail_key)
+        .bind(&image.image_key)
+        .bind(&image.thumbnail_key)
+        .bind(image.visibility)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns the oldest images whose originals are still hot and were uploaded more than
+    /// `months` ago, after the `after` image. Images restored in that time, the ones without a
+    /// thumbnail to show meanwhile and the ones still in the legacy key layout are left alone.
+    pub async fn get_archivable_images(
+        &self,
+        months: i32,
+        after: Option<&DBImage>,
+        limit: i64,
+    ) -> DBResult<Vec<DBImage>> {
+        sqlx::query_as::<_, DBImage>(&format!(
+            "{SELECT_IMAGES} WHERE archived_at IS NULL AND image_key LIKE '%/%' AND thumbnail_key IS NOT NULL
+             AND created_at < now() - make_interval(months => $1)
+             AND NOT EXISTS (
+                 SELECT 1 FROM image_restores WHERE image_restores.image_id = images.id
+                 AND image_restores.completed_at > now() - make_interval(months => $1)
+             )
+             AND ($2::timestamp IS NULL OR (created_at, id) > ($2, $3))
+             ORDER BY created_at, id LIMIT $4"
+        ))
+        .bind(months)
+        .bind(after.map(|image| image.created_at))
+        .bind(after.map(|image| image.id))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Marks the original of an image as archived, or as hot again, unless its files or
+    /// visibility changed since it was read. Returns whether the image was updated.
+    pub async fn set_image_archived(&self, image: &DBImage, archived: bool) -> DBResult<bool> {
+        let result = sqlx::query(
+            "UPDATE images SET archived_at = CASE WHEN $2 THEN now() END
+             WHERE id = $1 AND (archived_at IS NULL) = $2 AND image_key = $3 AND visibility = $4",
+        )
+        .bind(image.id)
+        .bind(archived)
+        .bind(&image.image_key)
+        .bind(image.visibility)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Queues the restore of an archived original. Restores already pending are kept as they
+    /// are, finished ones are requested again.
+    pub async fn request_image_restore(&self, image_id: &str) -> DBResult<DBImageRestore> {
+        let restore = sqlx::query_as::<_, DBImageRestore>(
+            "INSERT INTO image_restores (image_id) VALUES ($1)
+             ON CONFLICT (image_id) DO UPDATE SET
+                 status = CASE WHEN image_restores.status = 'pending' THEN image_restores.status ELSE 'pending' END,
+                 error = CASE WHEN image_restores.status = 'pending' THEN image_restores.error END,
+                 requested_at = CASE WHEN image_restores.status = 'pending' THEN image_restores.requested_at ELSE now() END,
+                 completed_at = CASE WHEN image_restores.status = 'pending' THEN image_restores.completed_at END
+             RETURNING *",
+        )
+        .bind(parse_uuid(image_id)?)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(restore)
+    }
+
+    pub async fn get_image_restore(&self, image_id: &str) -> DBResult<Option<DBImageRestore>> {
+        let restore =
+            sqlx::query_as::<_, DBImageRestore>("SELECT * FROM image_restores WHERE image_id = $1")
+                .bind(parse_uuid(image_id)?)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(restore)
+    }
+
+    /// Returns the images with a pending restore, the oldest requests first.
+    pub async fn get_pending_restore_images(&self, limit: i64) -> DBResult<Vec<DBImage>> {
+        sqlx::query_as::<_, DBImage>(&format!(
+            "{SELECT_IMAGES} JOIN image_restores ON image_restores.image_id = images.id
+             WHERE image_restores.status = 'pending'
+             ORDER BY image_restores.requested_at LIMIT $1"
+        ))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Finishes the pending restore of an image, as failed when there's an error.
+    pub async fn complete_image_restore(
+        &self,
+        image_id: Uuid,
+        error: Option<&str>,
+    ) -> DBResult<()> {
+        sqlx::query(
+            "UPDATE image_restores SET
+                 status = CASE WHEN $2::text IS NULL THEN 'completed' ELSE 'failed' END::restore_status,
+                 error = $2, completed_at = now()
+             WHERE image_id = $1 AND status = 'pending'",
+        )
+        .bind(image_id)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sets the visibility of an image and queues `event` in the outbox. The addresses it is
+    /// shared with are replaced when `shared_with` is given, and removed when it is no longer
+    /// `shared`. Images made public are announced to the listeners of `listen_public_images`.
+    pub async fn update_image_visibility(
+        &self,
+        id: &str,
+        visibility: Visibility,
+        shared_with: Option<&[String]>,
+        event: &Event,
+    ) -> DBResult<()> {
+        let image_id = parse_uuid(id)?;
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query("UPDATE images SET visibility = $1 WHERE id = $2")
+            .bind(visibility)
+            .bind(image_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        if visibility != Visibility::Shared || shared_with.is_some() {
+            sqlx::query("DELETE FROM image_shares WHERE image_id = $1")
+                .bind(image_id)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        if let (Visibility::Shared, Some(shared_with)) = (visibility, shared_with) {
+            let addresses = shared_with
+                .iter()
+                .map(|address| address.to_lowercase())
+                .collect::<Vec<_>>();
+            sqlx::query(
+                "INSERT INTO image_shares (image_id, user_address)
+                SELECT $1, user_address FROM unnest($2::TEXT[]) AS user_address
+                ON CONFLICT (image_id, user_address) DO NOTHING",
+            )
+            .bind(image_id)
+            .bind(addresses)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        insert_outbox_event(&mut transaction, event).await?;
+
+        if visibility.is_public() {
+            notify_public_image(&mut transaction, image_id).await?;
+        }
+
+        transaction.commit().await
+    }
+
+    pub async fn get_image_shares(&self, id: &str) -> DBResult<Vec<String>> {
+        let addresses = sqlx::query_scalar::<_, String>(
+            "SELECT user_address FROM image_shares WHERE image_id = $1 ORDER BY user_address",
+        )
+        .bind(parse_uuid(id)?)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(addresses)
+    }
+
+    pub async fn is_image_shared_with(&self, id: &str, user_address: &str) -> DBResult<bool> {
+        let shared = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM image_shares WHERE image_id = $1 AND user_address = $2)",
+        )
+        .bind(parse_uuid(id)?)
+        .bind(user_address.to_lowercase())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(shared)
+    }
+
+    /// Sets the user-supplied caption and alternative text, `None` clears them, replaces the
+    /// image tags and queues `event` in the outbox when there is one.
+    pub async fn update_image_details(
+        &self,
+        id: &str,
+        caption: Option<&str>,
+        alt_text: Option<&str>,
+        tags: &[String],
+        explicit_tags: &[String],
+        event: Option<&Event>,
+    ) -> DBResult<DBImage> {
+        let image_id = parse_uuid(id)?;
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query("UPDATE images SET caption = $1, alt_text = $2 WHERE id = $3")
+            .bind(caption)
+            .bind(alt_text)
+            .bind(image_id)
+            .execute(&mut *transaction)
             .await?;
 
+        sqlx::query("DELETE FROM image_tags WHERE image_id = $1")
+            .bind(image_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        insert_image_tags(&mut transaction, image_id, tags, explicit_tags).await?;
+
+        let image = sqlx::query_as::<_, DBImage>(&format!("{SELECT_IMAGES} WHERE id = $1"))
+            .bind(image_id)
+            .fetch_one(&mut *transaction)
+            .await?;
+
+        if let Some(event) = event {
+            insert_outbox_event(&mut transaction, event).await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(image)
+    }
+
+    /// Removes a person from an image and flags it when a reason is given. Returns `false` when
+    /// the person doesn't appear in it.
+    pub async fn remove_image_person(
+        &self,
+        id: &str,
+        user_address: &str,
+        flag_reason: Option<&str>,
+        event: &Event,
+    ) -> DBResult<bool> {
+        let image_id = parse_uuid(id)?;
+        let user_address = user_address.to_lowercase();
+        let mut transaction = self.pool.begin().await?;
+
+        let removed =
+            sqlx::query("DELETE FROM image_people WHERE image_id = $1 AND user_address = $2")
+                .bind(image_id)
+                .bind(&user_address)
+                .execute(&mut *transaction)
+                .await?
+                .rows_affected();
+
+        if removed == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query("DELETE FROM image_wearables WHERE image_id = $1 AND user_address = $2")
+            .bind(image_id)
+            .bind(&user_address)
+            .execute(&mut *transaction)
+            .await?;
+
+        sqlx::query(
+            "UPDATE images SET metadata = jsonb_set(metadata, '{visiblePeople}', (
+                SELECT COALESCE(jsonb_agg(person), '[]'::jsonb)
+                FROM jsonb_array_elements(metadata->'visiblePeople') AS person
+                WHERE COALESCE(lower(person->>'userAddress'), '') <> $2
+            ))
+            WHERE id = $1 AND jsonb_typeof(metadata->'visiblePeople') = 'array'",
+        )
+        .bind(image_id)
+        .bind(&user_address)
+        .execute(&mut *transaction)
+        .await?;
+
+        if let Some(reason) = flag_reason {
+            sqlx::query(
+                "INSERT INTO image_flags (image_id, user_address, reason) VALUES ($1, $2, $3)
+                ON CONFLICT (image_id, user_address) DO UPDATE SET reason = $3, created_at = now()",
+            )
+            .bind(image_id)
+            .bind(&user_address)
+            .bind(reason)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        insert_outbox_event(&mut transaction, event).await?;
+
+        transaction.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Claims the outbox events due for delivery, up to `limit` of the oldest ones, and holds
+    /// them back for `lease_secs` so other relays skip them meanwhile.
+    pub async fn claim_outbox_events(
+        &self,
+        limit: i64,
+        lease_secs: i64,
+    ) -> DBResult<Vec<DBOutboxEvent>> {
+        let mut events = sqlx::query_as::<_, DBOutboxEvent>(
+            "UPDATE outbox SET next_attempt_at = now() + $2 * INTERVAL '1 second'
+             WHERE id IN (
+                 SELECT id FROM outbox
+                 WHERE delivered_at IS NULL AND parked_at IS NULL AND next_attempt_at <= now()
+                 ORDER BY created_at LIMIT $1 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING *",
+        )
+        .bind(limit)
+        .bind(lease_secs)
+        .fetch_all(&self.pool)
+        .await?;
+
+        // RETURNING doesn't keep the order of the subquery
+        events.sort_by_key(|event| event.created_at);
+
+        Ok(events)
+    }
+
+    pub async fn mark_outbox_event_delivered(&self, id: Uuid) -> DBResult<()> {
+        sqlx::query(
+            "UPDATE outbox SET delivered_at = now(), attempts = attempts + 1, last_error = NULL
+             WHERE id = $1",
+        )
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+
         Ok(())
     }
 
-    pub async fn update_image_visibility(&self, id: &str, is_public: &bool) -> DBResult<()> {
-        sqlx::query("UPDATE images SET is_public = $1 WHERE id = $2")
-            .bind(is_public)
-            .bind(parse_uuid(id)?)
+    /// Records a failed delivery, the event is tried again in `retry_in_secs`.
+    pub async fn mark_outbox_event_failed(
+        &self,
+        id: Uuid,
+        error: &str,
+        retry_in_secs: i64,
+    ) -> DBResult<()> {
+        sqlx::query(
+            "UPDATE outbox SET attempts = attempts + 1, last_error = $2,
+                 next_attempt_at = now() + $3 * INTERVAL '1 second'
+             WHERE id = $1",
+        )
+        .bind(id)
+        .bind(error)
+        .bind(retry_in_secs)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sets aside an outbox event that can't be decoded, so it isn't claimed again. It stays
+    /// reported as stuck.
+    pub async fn park_outbox_event(&self, id: Uuid, error: &str) -> DBResult<()> {
+        sqlx::query("UPDATE outbox SET parked_at = now(), last_error = $2 WHERE id = $1")
+            .bind(id)
+            .bind(error)
             .execute(&self.pool)
             .await?;
 
         Ok(())
     }
-}
 
-fn parse_uuid(uuid: &str) -> Result<Uuid, DBError> {
-    Uuid::parse_str(uuid).map_err(|_| DBError::Protocol("Invalid UUID".to_string()))
-}
+    /// Returns how many outbox events are waiting for delivery, and how many seconds ago the
+    /// oldest one was queued.
+    pub async fn get_outbox_lag(&self) -> DBResult<(i64, f64)> {
+        sqlx::query_as::<_, (i64, f64)>(
+            "SELECT count(*), COALESCE(EXTRACT(EPOCH FROM now() - min(created_at)), 0)::float8
+             FROM outbox WHERE delivered_at IS NULL AND parked_at IS NULL",
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
 
-#[derive(sqlx::FromRow, Debug)]
-pub struct DBImage {
-    pub id: Uuid,
-    pub user_address: String,
-    pub url: String,
-    pub thumbnail_url: String,
-    pub is_public: bool,
-    pub created_at: chrono::NaiveDateTime,
-    pub metadata: sqlx::types::Json<Metadata>,
+    /// Returns the outbox events still waiting for delivery after `older_than_secs`, parked
+    /// ones included, the oldest first.
+    pub async fn get_stuck_outbox_events(
+        &self,
+        older_than_secs: i64,
+        limit: i64,
+    ) -> DBResult<Vec<DBOutboxEvent>> {
+        sqlx::query_as::<_, DBOutboxEvent>(
+            "SELECT * FROM outbox
+             WHERE delivered_at IS NULL AND created_at < now() - $1 * INTERVAL '1 second'
+             ORDER BY created_at LIMIT $2",
+        )
+        .bind(older_than_secs)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Deletes the outbox events delivered more than `older_than_secs` ago. Returns how many
+    /// were deleted.
+    pub async fn delete_delivered_outbox_events(&self, older_than_secs: i64) -> DBResult<u64> {
+        let result =
+            sqlx::query("DELETE FROM outbox WHERE delivered_at < now() - $1 * INTERVAL '1 second'")
+                .bind(older_than_secs)
+                .execute(&self.pool)
+                .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn insert_share_link(
+        &self,
+        token: &str,
+        image_id: &str,
+        user_address: &str,
+        expires_in_secs: Option<i64>,
+        max_views: Option<i32>,
+    ) -> DBResult<DBShareLink> {
+        let share_link = sqlx::query_as::<_, DBShareLink>(
+            "INSERT INTO share_links (token, image_id, user_address, expires_at, max_views)
+            VALUES ($1, $2, $3, now() + $4 * INTERVAL '1 second', $5) RETURNING *",
+        )
+        .bind(token)
+        .bind(parse_uuid(image_id)?)
+        .bind(user_address.to_lowercase())
+        .bind(expires_in_secs)
+        .bind(max_views)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(share_link)
+    }
+
+    pub async fn get_image_share_links(&self, image_id: &str) -> DBResult<Vec<DBShareLink>> {
+        let share_links = sqlx::query_as::<_, DBShareLink>(
+            "SELECT * FROM share_links WHERE image_id = $1 ORDER BY created_at DESC",
+        )
+        .bind(parse_uuid(image_id)?)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(share_links)
+    }
+
+    /// Counts the links of an image that haven't been revoked, expired or used up.
+    pub async fn get_active_share_links_count(&self, image_id: &str) -> DBResult<u64> {
+        let count = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT COUNT(*) FROM share_links WHERE image_id = $1 AND {SHARE_LINK_IS_USABLE}"
+        ))
+        .bind(parse_uuid(image_id)?)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count as u64)
+    }
+
+    /// Revokes a link of the image, returning `None` when the image has no such link.
+    pub async fn revoke_share_link(
+        &self,
+        image_id: &str,
+        token: &str,
+    ) -> DBResult<Option<DBShareLink>> {
+        let share_link = sqlx::query_as::<_, DBShareLink>(
+            "UPDATE share_links SET revoked_at = COALESCE(revoked_at, now())
+            WHERE image_id = $1 AND token = $2 RETURNING *",
+        )
+        .bind(parse_uuid(image_id)?)
+        .bind(token)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(share_link)
+    }
+
+    /// Returns the link when it can still be used, without counting a view.
+    pub async fn get_usable_share_link(&self, token: &str) -> DBResult<Option<DBShareLink>> {
+        let share_link = sqlx::query_as::<_, DBShareLink>(&format!(
+            "SELECT * FROM share_links WHERE token = $1 AND {SHARE_LINK_IS_USABLE}"
+        ))
+        .bind(token)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(share_link)
+    }
+
+    /// Counts a view of the link, returning `None` when it doesn't exist or can't be used
+    /// anymore because it was revoked, expired or reached its view limit.
+    pub async fn use_share_link(&self, token: &str) -> DBResult<Option<DBShareLink>> {
+        let share_link = sqlx::query_as::<_, DBShareLink>(&format!(
+            "UPDATE share_links SET views = views + 1
+            WHERE token = $1 AND {SHARE_LINK_IS_USABLE} RETURNING *"
+        ))
+        .bind(token)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(share_link)
+    }
+
+    /// Creates a subscription. `world_place_ids` are the places of the world when `place_id`
+    /// is a world name.
+    pub async fn insert_webhook_subscription(
+        &self,
+        user_address: &str,
+        place_id: &str,
+        world_place_ids: &[String],
+        url: &str,
+        secret: &str,
+    ) -> DBResult<DBWebhookSubscription> {
+        sqlx::query_as::<_, DBWebhookSubscription>(
+            "INSERT INTO webhook_subscriptions (id, user_address, place_id, world_place_ids, world_resolved_at, url, secret)
+            VALUES ($1, $2, $3, $4, CASE WHEN $3 LIKE '%.eth' THEN now() END, $5, $6) RETURNING *",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_address.to_lowercase())
+        .bind(place_id)
+        .bind(world_place_ids)
+        .bind(url)
+        .bind(secret)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    pub async fn get_user_webhook_subscriptions(
+        &self,
+        user_address: &str,
+    ) -> DBResult<Vec<DBWebhookSubscription>> {
+        sqlx::query_as::<_, DBWebhookSubscription>(
+            "SELECT * FROM webhook_subscriptions WHERE user_address = $1 ORDER BY created_at",
+        )
+        .bind(user_address.to_lowercase())
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn get_user_webhook_subscriptions_count(&self, user_address: &str) -> DBResult<u64> {
+        let count = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM webhook_subscriptions WHERE user_address = $1",
+        )
+        .bind(user_address.to_lowercase())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count as u64)
+    }
+
+    /// Returns a subscription of the user, `None` when the user has no such subscription.
+    pub async fn get_user_webhook_subscription(
+        &self,
+        id: &str,
+        user_address: &str,
+    ) -> DBResult<Option<DBWebhookSubscription>> {
+        sqlx::query_as::<_, DBWebhookSubscription>(
+            "SELECT * FROM webhook_subscriptions WHERE id = $1 AND user_address = $2",
+        )
+        .bind(parse_uuid(id)?)
+        .bind(user_address.to_lowercase())
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Deletes a subscription of the user and its delivery log. Returns `false` when the user
+    /// has no such subscription.
+    pub async fn delete_webhook_subscription(
+        &self,
+        id: &str,
+        user_address: &str,
+    ) -> DBResult<bool> {
+        let result =
+            sqlx::query("DELETE FROM webhook_subscriptions WHERE id = $1 AND user_address = $2")
+                .bind(parse_uuid(id)?)
+                .bind(user_address.to_lowercase())
+                .execute(&self.pool)
+                .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Enables a subscription of the user again, resuming its pending deliveries. Returns
+    /// `None` when the user has no such subscription.
+    pub async fn enable_webhook_subscription(
+        &self,
+        id: &str,
+        user_address: &str,
+    ) -> DBResult<Option<DBWebhookSubscription>> {
+        sqlx::query_as::<_, DBWebhookSubscription>(
+            "UPDATE webhook_subscriptions SET disabled_at = NULL, consecutive_failures = 0
+            WHERE id = $1 AND user_address = $2 RETURNING *",
+        )
+        .bind(parse_uuid(id)?)
+        .bind(user_address.to_lowercase())
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Returns the enabled subscriptions to the place, or to the world it was last known in.
+    pub async fn get_place_webhook_subscriptions(
+        &self,
+        place_id: &str,
+    ) -> DBResult<Vec<DBWebhookSubscription>> {
+        sqlx::query_as::<_, DBWebhookSubscription>(
+            "SELECT * FROM webhook_subscriptions
+            WHERE disabled_at IS NULL AND (place_id = $1 OR world_place_ids @> ARRAY[$1])",
+        )
+        .bind(place_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Returns the enabled subscriptions to a world whose places were resolved more than
+    /// `older_than_secs` ago, the least recently resolved first.
+    pub async fn get_stale_world_webhook_subscriptions(
+        &self,
+        older_than_secs: i64,
+        limit: i64,
+    ) -> DBResult<Vec<DBWebhookSubscription>> {
+        sqlx::query_as::<_, DBWebhookSubscription>(
+            "SELECT * FROM webhook_subscriptions
+            WHERE disabled_at IS NULL AND place_id LIKE '%.eth'
+                AND (world_resolved_at IS NULL OR world_resolved_at < now() - $1 * INTERVAL '1 second')
+            ORDER BY world_resolved_at NULLS FIRST LIMIT $2",
+        )
+        .bind(older_than_secs)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn set_webhook_world_place_ids(
+        &self,
+        id: Uuid,
+        world_place_ids: &[String],
+    ) -> DBResult<()> {
+        sqlx::query(
+            "UPDATE webhook_subscriptions SET world_place_ids = $2, world_resolved_at = now()
+            WHERE id = $1",
+        )
+        .bind(id)
+        .bind(world_place_ids)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Queues the delivery of an event to each subscription, skipping the subscriptions it was
+    /// already queued for.
+    pub async fn queue_webhook_deliveries(
+        &self,
+        subscription_ids: &[Uuid],
+        event_id: &str,
+        event: &Event,
+    ) -> DBResult<u64> {
+        let ids = subscription_ids
+            .iter()
+            .map(|_| Uuid::new_v4())
+            .collect::<Vec<_>>();
+        let result = sqlx::query(
+            "INSERT INTO webhook_deliveries (id, subscription_id, event_id, event)
+            SELECT id, subscription_id, $3, $4 FROM unnest($1::UUID[], $2::UUID[]) AS t (id, subscription_id)
+            ON CONFLICT (subscription_id, event_id) DO NOTHING",
+        )
+        .bind(ids)
+        .bind(subscription_ids)
+        .bind(event_id)
+        .bind(sqlx::types::Json(event))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Claims the pending deliveries due of the enabled subscriptions, up to `limit` of the
+    /// oldest ones, and holds them back for `lease_secs` so other workers skip them meanwhile.
+    pub async fn claim_webhook_deliveries(
+        &self,
+        limit: i64,
+        lease_secs: i64,
+    ) -> DBResult<Vec<DBClaimedWebhookDelivery>> {
+        let mut deliveries = sqlx::query_as::<_, DBClaimedWebhookDelivery>(
+            "WITH claimed AS (
+                UPDATE webhook_deliveries SET next_attempt_at = now() + $2 * INTERVAL '1 second'
+                WHERE id IN (
+                    SELECT webhook_deliveries.id FROM webhook_deliveries
+                    JOIN webhook_subscriptions ON webhook_subscriptions.id = webhook_deliveries.subscription_id
+                    WHERE webhook_deliveries.status = 'pending' AND webhook_deliveries.next_attempt_at <= now()
+                    AND webhook_subscriptions.disabled_at IS NULL
+                    ORDER BY webhook_deliveries.created_at LIMIT $1
+                    FOR UPDATE OF webhook_deliveries SKIP LOCKED
+                )
+                RETURNING *
+            )
+            SELECT claimed.*, webhook_subscriptions.url, webhook_subscriptions.secret FROM claimed
+            JOIN webhook_subscriptions ON webhook_subscriptions.id = claimed.subscription_id",
+        )
+        .bind(limit)
+        .bind(lease_secs)
+        .fetch_all(&self.pool)
+        .await?;
+
+        deliveries.sort_by_key(|delivery| delivery.delivery.created_at);
+
+        Ok(deliveries)
+    }
+
+    /// Records a successful delivery, which also resets the failures of the subscription.
+    pub async fn complete_webhook_delivery(
+        &self,
+        delivery: &DBWebhookDelivery,
+        response_status: i32,
+    ) -> DBResult<()> {
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query(
+            "UPDATE webhook_deliveries SET status = 'delivered', attempts = attempts + 1,
+                response_status = $2, last_error = NULL, completed_at = now()
+            WHERE id = $1",
+        )
+        .bind(delivery.id)
+        .bind(response_status)
+        .execute(&mut *transaction)
+        .await?;
+
+        sqlx::query("UPDATE webhook_subscriptions SET consecutive_failures = 0 WHERE id = $1")
+            .bind(delivery.subscription_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await
+    }
+
+    /// Records a failed delivery, tried again in `retry_in_secs` or given up when `None`. The
+    /// subscription is disabled once it failed `max_consecutive_failures` times in a row.
+    /// Returns whether it was disabled.
+    pub async fn fail_webhook_delivery(
+        &self,
+        delivery: &DBWebhookDelivery,
+        response_status: Option<i32>,
+        error: &str,
+        retry_in_secs: Option<i64>,
+        max_consecutive_failures: i32,
+    ) -> DBResult<bool> {
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query(
+            "UPDATE webhook_deliveries SET attempts = attempts + 1, response_status = $2, last_error = $3,
+                status = CASE WHEN $4::bigint IS NULL THEN 'failed' ELSE 'pending' END::webhook_delivery_status,
+                next_attempt_at = now() + COALESCE($4, 0) * INTERVAL '1 second',
+                completed_at = CASE WHEN $4::bigint IS NULL THEN now() END
+            WHERE id = $1",
+        )
+        .bind(delivery.id)
+        .bind(response_status)
+        .bind(error)
+        .bind(retry_in_secs)
+        .execute(&mut *transaction)
+        .await?;
+
+        let disabled = sqlx::query_scalar::<_, bool>(
+            "UPDATE webhook_subscriptions SET consecutive_failures = consecutive_failures + 1,
+                disabled_at = CASE WHEN consecutive_failures + 1 >= $2 THEN COALESCE(disabled_at, now()) END
+            WHERE id = $1 RETURNING disabled_at IS NOT NULL",
+        )
+        .bind(delivery.subscription_id)
+        .bind(max_consecutive_failures)
+        .fetch_optional(&mut *transaction)
+        .await?
+        .unwrap_or_default();
+
+        transaction.commit().await?;
+
+        Ok(disabled)
+    }
+
+    /// Returns the last deliveries to a subscription, newest first.
+    pub async fn get_webhook_deliveries(
+        &self,
+        subscription_id: Uuid,
+        limit: i64,
+    ) -> DBResult<Vec<DBWebhookDelivery>> {
+        sqlx::query_as::<_, DBWebhookDelivery>(
+            "SELECT * FROM webhook_deliveries WHERE subscription_id = $1
+            ORDER BY created_at DESC LIMIT $2",
+        )
+        .bind(subscription_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Deletes the deliveries that finished more than `older_than_secs` ago. Returns how many
+    /// were deleted.
+    pub async fn delete_completed_webhook_deliveries(&self, older_than_secs: i64) -> DBResult<u64> {
+        let result = sqlx::query(
+            "DELETE FROM webhook_deliveries WHERE completed_at < now() - $1 * INTERVAL '1 second'",
+        )
+        .bind(older_than_secs)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Returns up to `limit` of the oldest images of the user not taken down by moderation
+    /// yet, private ones included since their owner could publish them later.
+    pub async fn get_unmoderated_user_images(
+        &self,
+        user_address: &str,
+        limit: i64,
+    ) -> DBResult<Vec<DBImage>> {
+        sqlx::query_as::<_, DBImage>(&format!(
+            "{SELECT_IMAGES} WHERE user_address = $1 AND moderated_at IS NULL
+            ORDER BY created_at LIMIT $2"
+        ))
+        .bind(user_address.to_lowercase())
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Returns up to `limit` of the oldest images taken in the places not taken down by
+    /// moderation yet, private ones included.
+    pub async fn get_unmoderated_places_images(
+        &self,
+        place_ids: &[String],
+        limit: i64,
+    ) -> DBResult<Vec<DBImage>> {
+        sqlx::query_as::<_, DBImage>(&format!(
+            "{SELECT_IMAGES} WHERE metadata->>'placeId' = ANY($1) AND moderated_at IS NULL
+            ORDER BY created_at LIMIT $2"
+        ))
+        .bind(place_ids)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Returns up to `limit` of the oldest images of the user, whatever their visibility.
+    pub async fn get_oldest_user_images(
+        &self,
+        user_address: &str,
+        limit: i64,
+    ) -> DBResult<Vec<DBImage>> {
+        sqlx::query_as::<_, DBImage>(&format!(
+            "{SELECT_IMAGES} WHERE user_address = $1 ORDER BY created_at LIMIT $2"
+        ))
+        .bind(user_address.to_lowercase())
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Takes an image down: it becomes private, keeping the visibility its owner had chosen,
+    /// and its owner can't change it anymore. `event` is queued along, when there's one.
+    /// Returns `false` when the image was taken down already.
+    pub async fn moderate_image(
+        &self,
+        image_id: Uuid,
+        reason: &str,
+        event: Option<&Event>,
+    ) -> DBResult<bool> {
+        let mut transaction = self.pool.begin().await?;
+
+        let result = sqlx::query(
+            "UPDATE images SET owner_visibility = visibility, visibility = 'private',
+                moderated_at = now(), moderation_reason = $2
+            WHERE id = $1 AND moderated_at IS NULL",
+        )
+        .bind(image_id)
+        .bind(reason)
+        .execute(&mut *transaction)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        if let Some(event) = event {
+            insert_outbox_event(&mut transaction, event).await?;
+        }
+
+        transaction.commit().await?;
+        Ok(true)
+    }
+
+    /// Records that the user was banned, so their uploads are rejected.
+    pub async fn ban_user(&self, user_address: &str, reason: &str) -> DBResult<()> {
+        sqlx::query(
+            "INSERT INTO banned_users (user_address, reason) VALUES ($1, $2)
+            ON CONFLICT (user_address) DO NOTHING",
+        )
+        .bind(user_address.to_lowercase())
+        .bind(reason)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn is_user_banned(&self, user_address: &str) -> DBResult<bool> {
+        sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM banned_users WHERE user_address = $1)",
+        )
+        .bind(user_address.to_lowercase())
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    pub async fn is_moderation_event_processed(&self, event_id: &str) -> DBResult<bool> {
+        sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM processed_moderation_events WHERE event_id = $1)",
+        )
+        .bind(event_id)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    pub async fn mark_moderation_event_processed(
+        &self,
+        event_id: &str,
+        event_type: &str,
+        affected_images: u64,
+    ) -> DBResult<()> {
+        sqlx::query(
+            "INSERT INTO processed_moderation_events (event_id, event_type, affected_images)
+            VALUES ($1, $2, $3) ON CONFLICT (event_id) DO NOTHING",
+        )
+        .bind(event_id)
+        .bind(event_type)
+        .bind(affected_images as i32)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_user_preferences(
+        &self,
+        user_address: &str,
+    ) -> DBResult<Option<DBUserPreferences>> {
+        let preferences = sqlx::query_as::<_, DBUserPreferences>(
+            "SELECT * FROM user_preferences WHERE user_address = $1",
+        )
+        .bind(user_address.to_lowercase())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(preferences)
+    }
+
+    pub async fn upsert_user_preferences(
+        &self,
+        user_address: &str,
+        allow_tagging: bool,
+    ) -> DBResult<DBUserPreferences> {
+        let preferences = sqlx::query_as::<_, DBUserPreferences>(
+            "INSERT INTO user_preferences (user_address, allow_tagging) VALUES ($1, $2)
+            ON CONFLICT (user_address) DO UPDATE SET allow_tagging = $2, updated_at = now()
+            RETURNING *",
+        )
+        .bind(user_address.to_lowercase())
+        .bind(allow_tagging)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(preferences)
+    }
+
+    /// Returns which of the given addresses, lowercased, asked not to be tagged in photos.
+    pub async fn get_untaggable_addresses(&self, addresses: &[String]) -> DBResult<Vec<String>> {
+        let addresses = addresses
+            .iter()
+            .map(|address| address.to_lowercase())
+            .collect::<Vec<_>>();
+
+        let untaggable = sqlx::query_scalar::<_, String>(
+            "SELECT user_address FROM user_preferences
+            WHERE user_address = ANY($1) AND allow_tagging = false",
+        )
+        .bind(addresses)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(untaggable)
+    }
+
+    /// Returns which of the given addresses, lowercased, blocked `blocked_address`.
+    pub async fn get_blocking_addresses(
+        &self,
+        addresses: &[String],
+        blocked_address: &str,
+    ) -> DBResult<Vec<String>> {
+        let addresses = addresses
+            .iter()
+            .map(|address| address.to_lowercase())
+            .collect::<Vec<_>>();
+
+        let blocking = sqlx::query_scalar::<_, String>(
+            "SELECT user_address FROM user_blocks
+            WHERE user_address = ANY($1) AND blocked_address = $2",
+        )
+        .bind(addresses)
+        .bind(blocked_address.to_lowercase())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(blocking)
+    }
+
+    /// Returns the addresses the user blocked, the most recently blocked first.
+    pub async fn get_user_blocks(&self, user_address: &str) -> DBResult<Vec<String>> {
+        let blocked = sqlx::query_scalar::<_, String>(
+            "SELECT blocked_address FROM user_blocks WHERE user_address = $1
+            ORDER BY created_at DESC, blocked_address",
+        )
+        .bind(user_address.to_lowercase())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(blocked)
+    }
+
+    pub async fn get_user_blocks_count(&self, user_address: &str) -> DBResult<u64> {
+        let count = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM user_blocks WHERE user_address = $1",
+        )
+        .bind(user_address.to_lowercase())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count as u64)
+    }
+
+    pub async fn insert_user_block(
+        &self,
+        user_address: &str,
+        blocked_address: &str,
+    ) -> DBResult<()> {
+        sqlx::query(
+            "INSERT INTO user_blocks (user_address, blocked_address) VALUES ($1, $2)
+            ON CONFLICT (user_address, blocked_address) DO NOTHING",
+        )
+        .bind(user_address.to_lowercase())
+        .bind(blocked_address.to_lowercase())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_user_block(
+        &self,
+        user_address: &str,
+        blocked_address: &str,
+    ) -> DBResult<bool> {
+        let result =
+            sqlx::query("DELETE FROM user_blocks WHERE user_address = $1 AND blocked_address = $2")
+                .bind(user_address.to_lowercase())
+                .bind(blocked_address.to_lowercase())
+                .execute(&self.pool)
+                .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Stores the notification of `user_address` appearing in a photo of `actor_address`,
+    /// unless one with the same id was stored already or the image is gone.
+    pub async fn insert_tag_notification(
+        &self,
+        id: &str,
+        user_address: &str,
+        image_id: &str,
+        actor_address: &str,
+    ) -> DBResult<()> {
+        sqlx::query(
+            "INSERT INTO notifications (id, user_address, image_id, actor_address)
+            SELECT $1, $2, id, $4 FROM images WHERE id = $3
+            ON CONFLICT (id) DO NOTHING",
+        )
+        .bind(parse_uuid(id)?)
+        .bind(user_address.to_lowercase())
+        .bind(parse_uuid(image_id)?)
+        .bind(actor_address.to_lowercase())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the notifications the user can still see, the newest first, only the unread
+    /// ones when `unread_only` is set.
+    pub async fn get_user_notifications(
+        &self,
+        user_address: &str,
+        unread_only: bool,
+        offset: u64,
+        limit: u64,
+    ) -> DBResult<Vec<DBNotification>> {
+        let notifications = sqlx::query_as::<_, DBNotification>(&format!(
+            "SELECT * FROM notifications WHERE user_address = $1
+            AND ($2 = false OR read_at IS NULL) AND {NOTIFICATION_IS_VISIBLE}
+            ORDER BY created_at DESC, id OFFSET $3 LIMIT $4"
+        ))
+        .bind(user_address.to_lowercase())
+        .bind(unread_only)
+        .bind(offset as i64)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(notifications)
+    }
+
+    pub async fn get_user_notifications_count(
+        &self,
+        user_address: &str,
+        unread_only: bool,
+    ) -> DBResult<u64> {
+        let count = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT COUNT(*) FROM notifications WHERE user_address = $1
+            AND ($2 = false OR read_at IS NULL) AND {NOTIFICATION_IS_VISIBLE}"
+        ))
+        .bind(user_address.to_lowercase())
+        .bind(unread_only)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count as u64)
+    }
+
+    /// Marks notifications of the user as read, the given ones or all of them when `ids` is
+    /// `None`. Returns how many were unread.
+    pub async fn mark_notifications_read(
+        &self,
+        user_address: &str,
+        ids: Option<&[String]>,
+    ) -> DBResult<u64> {
+        let ids = ids
+            .map(|ids| {
+                ids.iter()
+                    .map(|id| parse_uuid(id))
+                    .collect::<DBResult<Vec<_>>>()
+            })
+            .transpose()?;
+
+        let result = sqlx::query(
+            "UPDATE notifications SET read_at = now()
+            WHERE user_address = $1 AND read_at IS NULL AND ($2::UUID[] IS NULL OR id = ANY($2))",
+        )
+        .bind(user_address.to_lowercase())
+        .bind(ids)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn get_images_by_ids(&self, ids: &[Uuid]) -> DBResult<Vec<DBImage>> {
+        let images = sqlx::query_as::<_, DBImage>(&format!("{SELECT_IMAGES} WHERE id = ANY($1)"))
+            .bind(ids)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(images)
+    }
+
+    pub async fn insert_album(
+        &self,
+        id: &str,
+        user_address: &str,
+        title: &str,
+        description: &str,
+        is_public: bool,
+    ) -> DBResult<DBAlbum> {
+        let album = sqlx::query_as::<_, DBAlbum>(
+            "INSERT INTO albums (id, user_address, title, description, is_public) VALUES ($1, $2, $3, $4, $5) RETURNING *",
+        )
+        .bind(parse_uuid(id)?)
+        .bind(user_address.to_lowercase())
+        .bind(title)
+        .bind(description)
+        .bind(is_public)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(album)
+    }
+
+    pub async fn get_album(&self, id: &str) -> DBResult<DBAlbum> {
+        let album = sqlx::query_as::<_, DBAlbum>("SELECT * FROM albums WHERE id = $1")
+            .bind(parse_uuid(id)?)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(album)
+    }
+
+    pub async fn get_user_albums(
+        &self,
+        user: &str,
+        offset: i64,
+        limit: i64,
+        public_only: bool,
+    ) -> DBResult<Vec<DBAlbum>> {
+        let albums = sqlx::query_as::<_, DBAlbum>(
+            "SELECT * FROM albums WHERE user_address = $1 AND (is_public = true OR $2 = false) ORDER BY created_at DESC LIMIT $3 OFFSET $4",
+        )
+        .bind(user.to_lowercase())
+        .bind(public_only)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(albums)
+    }
+
+    pub async fn get_user_albums_count(&self, user: &str, public_only: bool) -> DBResult<u64> {
+        let count = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM albums WHERE user_address = $1 AND (is_public = true OR $2 = false)",
+        )
+        .bind(user.to_lowercase())
+        .bind(public_only)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count as u64)
+    }
+
+    pub async fn update_album(
+        &self,
+        id: &str,
+        title: &str,
+        description: &str,
+        is_public: bool,
+        cover_image_id: Option<Uuid>,
+    ) -> DBResult<DBAlbum> {
+        let album = sqlx::query_as::<_, DBAlbum>(
+            "UPDATE albums SET title = $1, description = $2, is_public = $3, cover_image_id = $4, updated_at = now() WHERE id = $5 RETURNING *",
+        )
+        .bind(title)
+        .bind(description)
+        .bind(is_public)
+        .bind(cover_image_id)
+        .bind(parse_uuid(id)?)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(album)
+    }
+
+    pub async fn delete_album(&self, id: &str) -> DBResult<()> {
+        sqlx::query("DELETE FROM albums WHERE id = $1")
+            .bind(parse_uuid(id)?)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns the ids of every image in the album, in album order, regardless of visibility.
+    pub async fn get_album_image_ids(&self, album_id: &str) -> DBResult<Vec<Uuid>> {
+        let ids = sqlx::query_scalar::<_, Uuid>(
+            "SELECT image_id FROM album_images WHERE album_id = $1 ORDER BY position ASC",
+        )
+        .bind(parse_uuid(album_id)?)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(ids)
+    }
+
+    /// Appends the images at the end of the album keeping the given order. Images that are
+    /// already part of the album are left where they are.
+    pub async fn add_album_images(&self, album_id: &str, image_ids: &[Uuid]) -> DBResult<()> {
+        let album_id = parse_uuid(album_id)?;
+        let mut transaction = self.pool.begin().await?;
+
+        // Concurrent additions would otherwise get the same positions
+        lock_album(&mut transaction, album_id).await?;
+
+        sqlx::query(
+            "INSERT INTO album_images (album_id, image_id, position)
+            SELECT $1, ids.image_id, ((SELECT COALESCE(MAX(position), -1) FROM album_images WHERE album_id = $1) + ids.ord)::INTEGER
+            FROM unnest($2::UUID[]) WITH ORDINALITY AS ids(image_id, ord)
+            ON CONFLICT (album_id, image_id) DO NOTHING",
+        )
+        .bind(album_id)
+        .bind(image_ids)
+        .execute(&mut *transaction)
+        .await?;
+
+        sqlx::query("UPDATE albums SET updated_at = now() WHERE id = $1")
+            .bind(album_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await
+    }
+
+    /// Returns `false` when the image isn't in the album.
+    pub async fn remove_album_image(&self, album_id: &str, image_id: &str) -> DBResult<bool> {
+        let (album_id, image_id) = (parse_uuid(album_id)?, parse_uuid(image_id)?);
+        let mut transaction = self.pool.begin().await?;
+
+        let removed = sqlx::query("DELETE FROM album_images WHERE album_id = $1 AND image_id = $2")
+            .bind(album_id)
+            .bind(image_id)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected();
+        if removed == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query(
+            "UPDATE albums SET cover_image_id = CASE WHEN cover_image_id = $2 THEN NULL ELSE cover_image_id END, updated_at = now() WHERE id = $1",
+        )
+        .bind(album_id)
+        .bind(image_id)
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Rewrites the album positions following the order of `image_ids`, which must contain
+    /// exactly the images of the album.
+    pub async fn reorder_album_images(&self, album_id: &str, image_ids: &[Uuid]) -> DBResult<()> {
+        let album_id = parse_uuid(album_id)?;
+        let mut transaction = self.pool.begin().await?;
+
+        lock_album(&mut transaction, album_id).await?;
+
+        sqlx::query(
+            "UPDATE album_images SET position = (ids.ord - 1)::INTEGER
+            FROM unnest($2::UUID[]) WITH ORDINALITY AS ids(image_id, ord)
+            WHERE album_images.album_id = $1 AND album_images.image_id = ids.image_id",
+        )
+        .bind(album_id)
+        .bind(image_ids)
+        .execute(&mut *transaction)
+        .await?;
+
+        sqlx::query("UPDATE albums SET updated_at = now() WHERE id = $1")
+            .bind(album_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await
+    }
+
+    /// Lists the album images after the `after_position` cursor. Only public images are
+    /// returned, plus the ones owned by the `viewer` when there is one.
+    pub async fn get_album_images(
+        &self,
+        album_id: &str,
+        viewer: Option<&str>,
+        after_position: Option<i32>,
+        limit: i64,
+    ) -> DBResult<Vec<DBAlbumImage>> {
+        let images = sqlx::query_as::<_, DBAlbumImage>(
+            "SELECT images.*, album_images.position FROM album_images
+            JOIN images ON images.id = album_images.image_id
+            WHERE album_images.album_id = $1
+            AND album_images.position > $2
+            AND (images.visibility = 'public' OR images.user_address = $3)
+            ORDER BY album_images.position ASC LIMIT $4",
+        )
+        .bind(parse_uuid(album_id)?)
+        .bind(after_position.unwrap_or(-1))
+        .bind(viewer.map(|viewer| viewer.to_lowercase()))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(images)
+    }
+
+    /// Returns the album cover as seen by the `viewer`: the selected cover when it is visible
+    /// to them, otherwise the first visible image of the album.
+    pub async fn get_album_cover(
+        &self,
+        album: &DBAlbum,
+        viewer: Option<&str>,
+    ) -> DBResult<Option<DBImage>> {
+        let cover = sqlx::query_as::<_, DBImage>(
+            "SELECT images.* FROM album_images
+            JOIN images ON images.id = album_images.image_id
+            WHERE album_images.album_id = $1
+            AND (images.visibility = 'public' OR images.user_address = $2)
+            ORDER BY images.id = $3 DESC, album_images.position ASC LIMIT 1",
+        )
+        .bind(album.id)
+        .bind(viewer.map(|viewer| viewer.to_lowercase()))
+        .bind(album.cover_image_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(cover)
+    }
+}
+
+async fn insert_image_tags(
+    transaction: &mut Transaction<'_, Postgres>,
+    image_id: Uuid,
+    tags: &[String],
+    explicit_tags: &[String],
+) -> DBResult<()> {
+    if tags.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query(
+        "INSERT INTO image_tags (image_id, tag, is_explicit)
+        SELECT $1, tag, tag = ANY($3) FROM unnest($2::TEXT[]) AS tag
+        ON CONFLICT (image_id, tag) DO NOTHING",
+    )
+    .bind(image_id)
+    .bind(tags)
+    .bind(explicit_tags)
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Serializes the changes to the images of an album until the transaction ends, as their
+/// positions are derived from the current ones.
+async fn lock_album(transaction: &mut Transaction<'_, Postgres>, album_id: Uuid) -> DBResult<()> {
+    sqlx::query("SELECT 1 FROM albums WHERE id = $1 FOR UPDATE")
+        .bind(album_id)
+        .execute(&mut **transaction)
+        .await?;
+
+    Ok(())
+}
+
+/// Queues an event in the outbox, published by the outbox relay once the transaction is
+/// committed.
+async fn insert_outbox_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    event: &Event,
+) -> DBResult<()> {
+    sqlx::query("INSERT INTO outbox (id, event) VALUES ($1, $2)")
+        .bind(Uuid::new_v4())
+        .bind(sqlx::types::Json(event))
+        .execute(&mut **transaction)
+        .await?;
+
+    Ok(())
+}
+
+async fn notify_public_image(
+    transaction: &mut Transaction<'_, Postgres>,
+    image_id: Uuid,
+) -> DBResult<()> {
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(PUBLIC_IMAGES_CHANNEL)
+        .bind(image_id.to_string())
+        .execute(&mut **transaction)
+        .await?;
+
+    Ok(())
+}
+
+async fn insert_image_people(
+    transaction: &mut Transaction<'_, Postgres>,
+    image_id: Uuid,
+    addresses: &[String],
+) -> DBResult<()> {
+    if addresses.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query(
+        "INSERT INTO image_people (image_id, user_address)
+        SELECT $1, user_address FROM unnest($2::TEXT[]) AS user_address
+        ON CONFLICT (image_id, user_address) DO NOTHING",
+    )
+    .bind(image_id)
+    .bind(addresses)
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+async fn insert_image_wearables(
+    transaction: &mut Transaction<'_, Postgres>,
+    image_id: Uuid,
+    user_addresses: &[String],
+    urns: &[String],
+) -> DBResult<()> {
+    if urns.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query(
+        "INSERT INTO image_wearables (image_id, user_address, urn)
+        SELECT $1, user_address, urn FROM unnest($2::TEXT[], $3::TEXT[]) AS wearables (user_address, urn)
+        ON CONFLICT (image_id, user_address, urn) DO NOTHING",
+    )
+    .bind(image_id)
+    .bind(user_addresses)
+    .bind(urns)
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+fn parse_uuid(uuid: &str) -> Result<Uuid, DBError> {
+    Uuid::parse_str(uuid).map_err(|_| DBError::Protocol("Invalid UUID".to_string()))
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct DBImage {
+    pub id: Uuid,
+    pub user_address: String,
+    /// Storage key of the original, without the `private/` prefix.
+    pub image_key: String,
+    /// Storage key of the thumbnail. Images uploaded before thumbnails existed have none.
+    pub thumbnail_key: Option<String>,
+    pub visibility: Visibility,
+    pub created_at: chrono::NaiveDateTime,
+    pub metadata: sqlx::types::Json<Metadata>,
+    pub caption: Option<String>,
+    pub alt_text: Option<String>,
+    /// When the original was moved under the `archive/` prefix, `None` while it's hot.
+    pub archived_at: Option<chrono::NaiveDateTime>,
+    /// When the image was taken down by moderation. It stays private meanwhile.
+    pub moderated_at: Option<chrono::NaiveDateTime>,
+    pub moderation_reason: Option<String>,
+    /// Visibility the owner had chosen before the image was taken down.
+    pub owner_visibility: Option<Visibility>,
+    #[sqlx(default)]
+    pub tags: Vec<String>,
+}
+
+impl DBImage {
+    /// Returns the key of a file of the image, the original stands in for missing thumbnails.
+    pub fn key(&self, rendition: Rendition) -> &str {
+        match (rendition, &self.thumbnail_key) {
+            (Rendition::Thumbnail, Some(thumbnail_key)) => thumbnail_key,
+            _ => &self.image_key,
+        }
+    }
+
+    /// Returns the keys of all the files of the image.
+    pub fn keys(&self) -> Vec<&str> {
+        std::iter::once(self.image_key.as_str())
+            .chain(self.thumbnail_key.as_deref())
+            .collect()
+    }
+
+    /// Whether a file of the image is archived, and can't be read until it's restored.
+    pub fn is_archived(&self, rendition: Rendition) -> bool {
+        rendition == Rendition::Original && self.archived_at.is_some()
+    }
+
+    /// Returns the key a file of the image is stored at right now, which differs from its key
+    /// while archived.
+    pub fn stored_key(&self, rendition: Rendition) -> String {
+        let key = self.key(rendition);
+        if self.is_archived(rendition) {
+            archive_key(key)
+        } else {
+            key.to_string()
+        }
+    }
+
+    /// Returns the keys all the files of the image are stored at right now.
+    pub fn stored_keys(&self) -> Vec<String> {
+        std::iter::once(self.stored_key(Rendition::Original))
+            .chain(self.thumbnail_key.clone())
+            .collect()
+    }
+}
+
+/// Search terms and filters of a full-text image search.
+#[derive(Debug)]
+pub struct SearchFilter {
+    /// Search terms, in `websearch_to_tsquery` syntax.
+    pub query: String,
+    pub realm: Option<String>,
+    /// Inclusive lower bound of the upload time.
+    pub from: Option<chrono::NaiveDateTime>,
+    /// Exclusive upper bound of the upload time.
+    pub to: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct DBTagCount {
+    pub tag: String,
+    pub count: i64,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct DBWearableUsage {
+    pub urn: String,
+    pub images: i64,
+    pub people: i64,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct DBUserPreferences {
+    pub user_address: String,
+    pub allow_tagging: bool,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct DBShareLink {
+    pub token: String,
+    pub image_id: Uuid,
+    pub user_address: String,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub max_views: Option<i32>,
+    pub views: i32,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct DBNotification {
+    /// Id of the event the notification was stored from.
+    pub id: Uuid,
+    pub user_address: String,
+    pub image_id: Uuid,
+    /// Owner of the image.
+    pub actor_address: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub read_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct DBWebhookSubscription {
+    pub id: Uuid,
+    pub user_address: String,
+    /// A place ID, or a world name for every place of the world.
+    pub place_id: String,
+    /// Places of the world, empty for subscriptions to a place.
+    pub world_place_ids: Vec<String>,
+    pub world_resolved_at: Option<chrono::NaiveDateTime>,
+    pub url: String,
+    pub secret: String,
+    pub consecutive_failures: i32,
+    pub disabled_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct DBWebhookDelivery {
+    pub id: Uuid,
+    pub subscription_id: Uuid,
+    pub event_id: String,
+    pub event: sqlx::types::Json<Event>,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: i32,
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub next_attempt_at: chrono::NaiveDateTime,
+    pub completed_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Delivery claimed by the webhook worker, with where to send it.
+#[derive(sqlx::FromRow, Debug)]
+pub struct DBClaimedWebhookDelivery {
+    #[sqlx(flatten)]
+    pub delivery: DBWebhookDelivery,
+    pub url: String,
+    pub secret: String,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct DBImageRestore {
+    pub image_id: Uuid,
+    pub status: RestoreStatus,
+    pub error: Option<String>,
+    pub requested_at: chrono::NaiveDateTime,
+    pub completed_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Event queued in the outbox. It's kept as stored and decoded by the relay, so a row written
+/// by another version of the service can't fail the claim of the others.
+#[derive(sqlx::FromRow, Debug)]
+pub struct DBOutboxEvent {
+    pub id: Uuid,
+    pub event: sqlx::types::Json<serde_json::Value>,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub next_attempt_at: chrono::NaiveDateTime,
+    pub delivered_at: Option<chrono::NaiveDateTime>,
+    pub parked_at: Option<chrono::NaiveDateTime>,
+}
+
+impl DBOutboxEvent {
+    pub fn decode(&self) -> Result<Event, serde_json::Error> {
+        serde_json::from_value(self.event.0.clone())
+    }
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct DBAlbum {
+    pub id: Uuid,
+    pub user_address: String,
+    pub title: String,
+    pub description: String,
+    pub is_public: bool,
+    pub cover_image_id: Option<Uuid>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct DBAlbumImage {
+    #[sqlx(flatten)]
+    pub image: DBImage,
+    pub position: i32,
 }
@@ -0,0 +1,294 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono;
+
+mod log;
+mod memory;
+mod payloads;
+mod sns;
+mod sqs;
+mod webhook;
+
+pub use self::log::LogPublisher;
+pub use self::memory::MemoryPublisher;
+pub use self::payloads::{
+    event_schemas, EventPayload, EventUser, PhotoDeletedMetadata, PhotoPrivacyChangedMetadata,
+    PhotoTaggedMetadata, PhotoTakenMetadata, PhotoUntaggedMetadata, PhotoUpdatedMetadata,
+    SchemaVersion,
+};
+pub use self::sns::SNSPublisher;
+pub use self::sqs::{sqs_client, SQSPublisher};
+pub use self::webhook::WebhookPublisher;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EventType {
+    Camera,
+}
+
+impl std::fmt::Display for EventType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventType::Camera => write!(f, "camera"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventSubtype {
+    PhotoTaken,
+    /// One per person visible in a new photo, for the people notified when they appear in one.
+    PhotoTagged,
+    PhotoPrivacyChanged,
+    PhotoUpdated,
+    PhotoUntagged,
+    PhotoDeleted,
+}
+
+impl std::fmt::Display for EventSubtype {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventSubtype::PhotoTaken => write!(f, "photo-taken"),
+            EventSubtype::PhotoTagged => write!(f, "photo-tagged"),
+            EventSubtype::PhotoPrivacyChanged => write!(f, "photo-privacy-changed"),
+            EventSubtype::PhotoUpdated => write!(f, "photo-updated"),
+            EventSubtype::PhotoUntagged => write!(f, "photo-untagged"),
+            EventSubtype::PhotoDeleted => write!(f, "photo-deleted"),
+        }
+    }
+}
+
+/// Published message. The payload is sent as `subType` and `metadata`, whose shape is described
+/// by the schemas of [`event_schemas`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    #[serde(rename = "type")]
+    pub event_type: EventType,
+    #[serde(flatten)]
+    pub payload: EventPayload,
+    pub key: String,
+    pub timestamp: u64,
+}
+
+impl Event {
+    /// Camera event about the photo `key`, timestamped now.
+    pub fn new(key: &str, payload: EventPayload) -> Self {
+        Self {
+            event_type: EventType::Camera,
+            payload,
+            key: key.to_string(),
+            timestamp: chrono::Utc::now().timestamp() as u64,
+        }
+    }
+
+    pub fn sub_type(&self) -> EventSubtype {
+        self.payload.sub_type()
+    }
+}
+
+/// Where the events are published. The outbox relay is the only caller, handlers queue their
+/// events in the outbox instead.
+#[async_trait(?Send)]
+pub trait EventPublisher: Send + Sync {
+    /// Publishes an event. `event_id` stays the same when an event is published again after a
+    /// failure, so sinks and consumers can deduplicate on it.
+    async fn publish(
+        &self,
+        event_id: &str,
+        event: &Event,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// Lets a publisher be shared, like a `MemoryPublisher` checked by the tests while in a fan-out.
+#[async_trait(?Send)]
+impl<P: EventPublisher + ?Sized> EventPublisher for Arc<P> {
+    async fn publish(
+        &self,
+        event_id: &str,
+        event: &Event,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.as_ref().publish(event_id, event).await
+    }
+}
+
+/// Publishes every event to several sinks. An event fails when any sink fails, and is then
+/// published again to all of them, so the sinks that got it already get a copy with the same id.
+pub struct FanoutPublisher {
+    publishers: Vec<Box<dyn EventPublisher>>,
+}
+
+impl FanoutPublisher {
+    pub fn new(publishers: Vec<Box<dyn EventPublisher>>) -> Self {
+        Self { publishers }
+    }
+}
+
+#[async_trait(?Send)]
+impl EventPublisher for FanoutPublisher {
+    async fn publish(
+        &self,
+        event_id: &str,
+        event: &Event,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut errors = Vec::new();
+        for publisher in &self.publishers {
+            if let Err(error) = publisher.publish(event_id, event).await {
+                errors.push(error.to_string());
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors.join("; ").into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Kinds of event publishers, selected with `EVENT_SINKS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventSink {
+    Sns,
+    Sqs,
+    Webhook,
+    /// Only logs the events. Meant for development.
+    Log,
+}
+
+impl std::str::FromStr for EventSink {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "sns" => Ok(EventSink::Sns),
+            "sqs" => Ok(EventSink::Sqs),
+            "webhook" => Ok(EventSink::Webhook),
+            "log" => Ok(EventSink::Log),
+            _ => Err(format!(
+                "invalid event sink {value}, expected sns, sqs, webhook or log"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Visibility;
+
+    struct FailingPublisher;
+
+    #[async_trait(?Send)]
+    impl EventPublisher for FailingPublisher {
+        async fn publish(&self, _: &str, _: &Event) -> Result<(), Box<dyn std::error::Error>> {
+            Err("unavailable".into())
+        }
+    }
+
+    fn event() -> Event {
+        Event {
+            event_type: EventType::Camera,
+            payload: EventPayload::PhotoDeleted(PhotoDeletedMetadata {
+                schema_version: SchemaVersion,
+                photo_id: "image-id".to_string(),
+                user_address: "0xowner".to_string(),
+                realm: "main".to_string(),
+                place_id: "place-id".to_string(),
+                is_public: true,
+                visibility: Visibility::Public,
+                tags: vec![],
+                users: vec![EventUser {
+                    address: "0xfriend".to_string(),
+                    is_emoting: false,
+                }],
+            }),
+            key: "image-id".to_string(),
+            timestamp: 1_700_000_000,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fans_out_to_every_sink_even_when_one_fails() {
+        let first = Arc::new(MemoryPublisher::new());
+        let second = Arc::new(MemoryPublisher::new());
+        let publisher = FanoutPublisher::new(vec![
+            Box::new(first.clone()),
+            Box::new(FailingPublisher),
+            Box::new(second.clone()),
+        ]);
+
+        let error = publisher.publish("event-id", &event()).await.unwrap_err();
+        assert_eq!(error.to_string(), "unavailable");
+        for memory in [first, second] {
+            let events = memory.events();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].0, "event-id");
+            assert_eq!(events[0].1.key, "image-id");
+        }
+    }
+
+    #[test]
+    fn test_serializes_the_payload_as_sub_type_and_metadata() {
+        let message = serde_json::to_value(event()).unwrap();
+        assert_eq!(message["type"], "camera");
+        assert_eq!(message["subType"], "photo-deleted");
+        assert_eq!(message["metadata"]["schemaVersion"], 1);
+        assert_eq!(message["metadata"]["users"][0]["isEmoting"], false);
+
+        let event: Event = serde_json::from_value(message).unwrap();
+        assert_eq!(event.sub_type(), EventSubtype::PhotoDeleted);
+        assert_eq!(event.key, "image-id");
+    }
+
+    #[test]
+    fn test_reads_events_queued_without_schema_version_as_version_1() {
+        let mut message = serde_json::to_value(event()).unwrap();
+        message["metadata"]
+            .as_object_mut()
+            .unwrap()
+            .remove("schemaVersion");
+
+        let event: Event = serde_json::from_value(message).unwrap();
+        assert_eq!(event.sub_type(), EventSubtype::PhotoDeleted);
+    }
+
+    #[test]
+    fn test_rejects_unknown_schema_versions() {
+        let mut message = serde_json::to_value(event()).unwrap();
+        message["metadata"]["schemaVersion"] = 2.into();
+
+        let error = serde_json::from_value::<Event>(message).unwrap_err();
+        assert!(error.to_string().contains("unsupported schema version 2"));
+    }
+
+    #[test]
+    fn test_catalogs_a_schema_per_subtype() {
+        let schemas = event_schemas();
+        assert_eq!(
+            schemas.keys().collect::<Vec<_>>(),
+            [
+                "photo-deleted",
+                "photo-privacy-changed",
+                "photo-tagged",
+                "photo-taken",
+                "photo-untagged",
+                "photo-updated"
+            ]
+        );
+
+        let schema = serde_json::to_value(&schemas["photo-deleted"]).unwrap();
+        let schema = jsonschema::JSONSchema::compile(&schema).unwrap();
+        let mut message = serde_json::to_value(event()).unwrap();
+        assert!(schema.is_valid(&message));
+
+        message["metadata"]["visibility"] = "everyone".into();
+        assert!(!schema.is_valid(&message));
+        message["metadata"]["visibility"] = "public".into();
+        message["subType"] = "photo-taken".into();
+        assert!(!schema.is_valid(&message));
+    }
+}
@@ -0,0 +1,25 @@
+use async_trait::async_trait;
+
+use super::{Event, EventPublisher};
+
+/// Only logs the events, for running the service without any messaging infrastructure.
+pub struct LogPublisher;
+
+#[async_trait(?Send)]
+impl EventPublisher for LogPublisher {
+    async fn publish(
+        &self,
+        event_id: &str,
+        event: &Event,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        tracing::info!(
+            event_id,
+            sub_type = %event.sub_type(),
+            key = %event.key,
+            event = %serde_json::to_string(event)?,
+            "event published"
+        );
+
+        Ok(())
+    }
+}
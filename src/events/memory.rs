@@ -0,0 +1,38 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use super::{Event, EventPublisher};
+
+/// Keeps the published events in memory, so tests can check them without SNS.
+#[derive(Default)]
+pub struct MemoryPublisher {
+    events: Mutex<Vec<(String, Event)>>,
+}
+
+impl MemoryPublisher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The published events with their ids, in publication order.
+    pub fn events(&self) -> Vec<(String, Event)> {
+        self.events.lock().expect("events lock poisoned").clone()
+    }
+}
+
+#[async_trait(?Send)]
+impl EventPublisher for MemoryPublisher {
+    async fn publish(
+        &self,
+        event_id: &str,
+        event: &Event,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.events
+            .lock()
+            .expect("events lock poisoned")
+            .push((event_id.to_string(), event.clone()));
+
+        Ok(())
+    }
+}
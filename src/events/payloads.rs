@@ -0,0 +1,250 @@
+use std::collections::BTreeMap;
+
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, RootSchema, Schema, SchemaObject},
+    schema_for, JsonSchema,
+};
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{EventSubtype, EventType};
+use crate::api::Visibility;
+
+/// Version of an event payload, sent as `schemaVersion`. It is bumped when a change would
+/// break consumers, like removing or retyping a field, but not when a field is added.
+/// Payloads queued before the versioning have none, and are read as version 1.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SchemaVersion<const V: u32>;
+
+impl<const V: u32> Serialize for SchemaVersion<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(V)
+    }
+}
+
+impl<'de, const V: u32> Deserialize<'de> for SchemaVersion<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // A missing field is deserialized as `None`, like with any `Option`
+        match Option::<u32>::deserialize(deserializer)? {
+            None if V == 1 => Ok(Self),
+            Some(version) if version == V => Ok(Self),
+            version => Err(D::Error::custom(format!(
+                "unsupported schema version {}, expected {V}",
+                version.unwrap_or(1)
+            ))),
+        }
+    }
+}
+
+impl<const V: u32> JsonSchema for SchemaVersion<V> {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        format!("SchemaVersion{V}")
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::Integer.into()),
+            const_value: Some(V.into()),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+/// A person visible in a photo.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EventUser {
+    pub address: String,
+    pub is_emoting: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PhotoTakenMetadata {
+    pub schema_version: SchemaVersion<1>,
+    pub photo_id: String,
+    /// Owner of the photo, lowercased.
+    pub user_address: String,
+    pub realm: String,
+    /// Name of the scene the photo was taken in.
+    pub scene_id: String,
+    pub place_id: String,
+    pub is_public: bool,
+    pub visibility: Visibility,
+    pub tags: Vec<String>,
+    /// People visible in the photo, without the ones who don't allow tagging.
+    pub users: Vec<EventUser>,
+}
+
+/// Sent for each person visible in a new photo who is notified of it.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PhotoTaggedMetadata {
+    pub schema_version: SchemaVersion<1>,
+    pub photo_id: String,
+    /// Owner of the photo, lowercased.
+    pub user_address: String,
+    /// Person notified, lowercased.
+    pub tagged_address: String,
+    pub is_emoting: bool,
+    pub place_id: String,
+    pub is_public: bool,
+    pub visibility: Visibility,
+    pub thumbnail_url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PhotoPrivacyChangedMetadata {
+    pub schema_version: SchemaVersion<1>,
+    pub photo_id: String,
+    /// Owner of the photo, lowercased.
+    pub user_address: String,
+    pub is_public: bool,
+    pub visibility: Visibility,
+    pub previous_visibility: Visibility,
+    /// Addresses a `shared` photo is shared with.
+    pub shared_with: Vec<String>,
+    /// Moderation event that hid the photo, absent when its owner changed the visibility.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moderation_reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PhotoUpdatedMetadata {
+    pub schema_version: SchemaVersion<1>,
+    pub photo_id: String,
+    /// Owner of the photo, lowercased.
+    pub user_address: String,
+    pub is_public: bool,
+    pub visibility: Visibility,
+    pub caption: Option<String>,
+    /// Alternative text written by the owner, clients generate one when it's `null`.
+    pub alt_text: Option<String>,
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PhotoUntaggedMetadata {
+    pub schema_version: SchemaVersion<1>,
+    pub photo_id: String,
+    /// Owner of the photo, lowercased.
+    pub user_address: String,
+    /// Person removed from the photo, lowercased.
+    pub untagged_address: String,
+    pub is_public: bool,
+    pub visibility: Visibility,
+    pub flagged_for_review: bool,
+}
+
+/// Carries what consumers need to undo what they derived from the photo.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PhotoDeletedMetadata {
+    pub schema_version: SchemaVersion<1>,
+    pub photo_id: String,
+    /// Owner of the photo, lowercased.
+    pub user_address: String,
+    pub realm: String,
+    pub place_id: String,
+    pub is_public: bool,
+    pub visibility: Visibility,
+    pub tags: Vec<String>,
+    pub users: Vec<EventUser>,
+}
+
+/// Subtype of an event along with its metadata, sent as `subType` and `metadata`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "subType", content = "metadata", rename_all = "kebab-case")]
+pub enum EventPayload {
+    PhotoTaken(PhotoTakenMetadata),
+    PhotoTagged(PhotoTaggedMetadata),
+    PhotoPrivacyChanged(PhotoPrivacyChangedMetadata),
+    PhotoUpdated(PhotoUpdatedMetadata),
+    PhotoUntagged(PhotoUntaggedMetadata),
+    PhotoDeleted(PhotoDeletedMetadata),
+}
+
+impl EventPayload {
+    pub fn sub_type(&self) -> EventSubtype {
+        match self {
+            EventPayload::PhotoTaken(_) => EventSubtype::PhotoTaken,
+            EventPayload::PhotoTagged(_) => EventSubtype::PhotoTagged,
+            EventPayload::PhotoPrivacyChanged(_) => EventSubtype::PhotoPrivacyChanged,
+            EventPayload::PhotoUpdated(_) => EventSubtype::PhotoUpdated,
+            EventPayload::PhotoUntagged(_) => EventSubtype::PhotoUntagged,
+            EventPayload::PhotoDeleted(_) => EventSubtype::PhotoDeleted,
+        }
+    }
+}
+
+/// Shape of a message with the metadata of one subtype, only used for its schema.
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct EventMessage<M> {
+    #[serde(rename = "type")]
+    event_type: EventType,
+    sub_type: EventSubtype,
+    /// Id of the photo, also the message group of FIFO topics and queues.
+    key: String,
+    /// Unix timestamp, in seconds.
+    timestamp: u64,
+    metadata: M,
+}
+
+fn message_schema<M: JsonSchema>(sub_type: EventSubtype) -> RootSchema {
+    let mut schema = schema_for!(EventMessage<M>);
+    schema.schema.metadata().title = Some(sub_type.to_string());
+    schema.schema.object().properties.insert(
+        "subType".to_string(),
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            const_value: Some(sub_type.to_string().into()),
+            ..Default::default()
+        }
+        .into(),
+    );
+
+    schema
+}
+
+/// JSON Schemas of the published messages by subtype, as sent to SNS, SQS and webhooks.
+pub fn event_schemas() -> BTreeMap<String, RootSchema> {
+    [
+        (
+            EventSubtype::PhotoTaken,
+            message_schema::<PhotoTakenMetadata> as fn(EventSubtype) -> RootSchema,
+        ),
+        (
+            EventSubtype::PhotoTagged,
+            message_schema::<PhotoTaggedMetadata>,
+        ),
+        (
+            EventSubtype::PhotoPrivacyChanged,
+            message_schema::<PhotoPrivacyChangedMetadata>,
+        ),
+        (
+            EventSubtype::PhotoUpdated,
+            message_schema::<PhotoUpdatedMetadata>,
+        ),
+        (
+            EventSubtype::PhotoUntagged,
+            message_schema::<PhotoUntaggedMetadata>,
+        ),
+        (
+            EventSubtype::PhotoDeleted,
+            message_schema::<PhotoDeletedMetadata>,
+        ),
+    ]
+    .into_iter()
+    .map(|(sub_type, schema)| (sub_type.to_string(), schema(sub_type)))
+    .collect()
+}
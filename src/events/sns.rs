@@ -1,51 +1,12 @@
+use async_trait::async_trait;
 use aws_config::{BehaviorVersion, Region};
 use aws_sdk_sns as sns;
 use aws_sdk_sns::{Client, Config};
-use serde::{Deserialize, Serialize};
 use serde_json;
-use std::collections::HashMap;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum EventType {
-    Camera,
-}
-
-impl std::fmt::Display for EventType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            EventType::Camera => write!(f, "camera"),
-        }
-    }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
-pub enum EventSubtype {
-    PhotoTaken,
-    PhotoPrivacyChanged,
-}
-
-impl std::fmt::Display for EventSubtype {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            EventSubtype::PhotoTaken => write!(f, "photo-taken"),
-            EventSubtype::PhotoPrivacyChanged => write!(f, "photo-privacy-changed"),
-        }
-    }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Event {
-    #[serde(rename = "type")]
-    pub event_type: EventType,
-    #[serde(rename = "subType")]
-    pub sub_type: EventSubtype,
-    pub key: String,
-    pub timestamp: u64,
-    pub metadata: HashMap<String, serde_json::Value>,
-}
+use super::{Event, EventPublisher};
 
+/// Publishes the events to an SNS topic.
 pub struct SNSPublisher {
     client: Client,
     topic_arn: String,
@@ -114,18 +75,32 @@ impl SNSPublisher {
 
         Ok(SNSPublisher { client, topic_arn })
     }
+}
 
-    pub async fn publish(
+#[async_trait(?Send)]
+impl EventPublisher for SNSPublisher {
+    /// Publishes an event with its outbox id as `eventId` attribute, so consumers can drop the
+    /// copies of events delivered more than once. FIFO topics also deduplicate on it, and keep
+    /// the events of each photo in order.
+    async fn publish(
         &self,
+        event_id: &str,
         event: &Event,
-    ) -> Result<sns::operation::publish::PublishOutput, Box<dyn std::error::Error>> {
+    ) -> Result<(), Box<dyn std::error::Error>> {
         let message_json = serde_json::to_string(event)?;
 
-        let response = self
+        let mut request = self
             .client
             .publish()
             .topic_arn(&self.topic_arn)
             .message(&message_json)
+            .message_attributes(
+                "eventId",
+                sns::types::MessageAttributeValue::builder()
+                    .data_type("String")
+                    .string_value(event_id)
+                    .build()?,
+            )
             .message_attributes(
                 "type",
                 sns::types::MessageAttributeValue::builder()
@@ -137,12 +112,18 @@ impl SNSPublisher {
                 "subType",
                 sns::types::MessageAttributeValue::builder()
                     .data_type("String")
-                    .string_value(event.sub_type.to_string())
+                    .string_value(event.sub_type().to_string())
                     .build()?,
-            )
-            .send()
-            .await?;
+            );
+
+        if self.topic_arn.ends_with(".fifo") {
+            request = request
+                .message_deduplication_id(event_id)
+                .message_group_id(&event.key);
+        }
+
+        request.send().await?;
 
-        Ok(response)
+        Ok(())
     }
 }
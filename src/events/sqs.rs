@@ -0,0 +1,90 @@
+use async_trait::async_trait;
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_sqs::{types::MessageAttributeValue, Client, Config};
+
+use super::{Event, EventPublisher};
+
+/// Sends the events straight to an SQS queue, with the same body and attributes as the SNS
+/// messages, for consumers that don't need the fan-out of a topic.
+pub struct SQSPublisher {
+    client: Client,
+    queue_url: String,
+}
+
+impl SQSPublisher {
+    pub async fn new(
+        queue_url: String,
+        endpoint: Option<String>,
+        region: String,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let client = sqs_client(endpoint, region).await?;
+
+        Ok(SQSPublisher { client, queue_url })
+    }
+}
+
+/// Creates an SQS client with the default credentials, pointed at `endpoint` when given.
+pub async fn sqs_client(
+    endpoint: Option<String>,
+    region: String,
+) -> Result<Client, Box<dyn std::error::Error>> {
+    let config = aws_config::defaults(BehaviorVersion::latest())
+        .region(Region::new(region))
+        .load()
+        .await;
+
+    let Some(credentials_provider) = config.credentials_provider() else {
+        return Err("SQS: No credentials provider available".into());
+    };
+    let Some(region) = config.region() else {
+        return Err("SQS: No AWS region configured".into());
+    };
+
+    let mut sqs_config_builder = Config::builder()
+        .credentials_provider(credentials_provider)
+        .region(region.clone())
+        .behavior_version(BehaviorVersion::latest());
+
+    if let Some(endpoint_url) = endpoint {
+        println!("SQS: Using custom SQS endpoint: {}", endpoint_url);
+        sqs_config_builder = sqs_config_builder.endpoint_url(endpoint_url);
+    }
+
+    Ok(Client::from_conf(sqs_config_builder.build()))
+}
+
+fn string_attribute(value: &str) -> Result<MessageAttributeValue, Box<dyn std::error::Error>> {
+    Ok(MessageAttributeValue::builder()
+        .data_type("String")
+        .string_value(value)
+        .build()?)
+}
+
+#[async_trait(?Send)]
+impl EventPublisher for SQSPublisher {
+    /// FIFO queues deduplicate on the event id, and keep the events of each photo in order.
+    async fn publish(
+        &self,
+        event_id: &str,
+        event: &Event,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut request = self
+            .client
+            .send_message()
+            .queue_url(&self.queue_url)
+            .message_body(serde_json::to_string(event)?)
+            .message_attributes("eventId", string_attribute(event_id)?)
+            .message_attributes("type", string_attribute(&event.event_type.to_string())?)
+            .message_attributes("subType", string_attribute(&event.sub_type().to_string())?);
+
+        if self.queue_url.ends_with(".fifo") {
+            request = request
+                .message_deduplication_id(event_id)
+                .message_group_id(&event.key);
+        }
+
+        request.send().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use sqlx::types::chrono;
+
+use super::{Event, EventPublisher};
+use crate::webhooks::signature;
+
+/// Posts the events as JSON to an HTTP endpoint. Any response other than a 2xx is a failed
+/// delivery, retried by the outbox relay.
+pub struct WebhookPublisher {
+    client: reqwest::Client,
+    url: String,
+    /// Secret shared with the receiver to sign the bodies. They are left unsigned when missing.
+    signing_key: Option<String>,
+}
+
+impl WebhookPublisher {
+    pub fn new(url: String, signing_key: Option<String>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("failed to build reqwest client");
+
+        Self {
+            client,
+            url,
+            signing_key,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl EventPublisher for WebhookPublisher {
+    /// Sends the event id in `X-Event-Id`, its subtype in `X-Event-Type` and, with a signing
+    /// key, `X-Webhook-Timestamp` and `X-Webhook-Signature` like the place webhooks.
+    async fn publish(
+        &self,
+        event_id: &str,
+        event: &Event,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let body = serde_json::to_vec(event)?;
+
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Event-Id", event_id)
+            .header("X-Event-Type", event.sub_type().to_string());
+        if let Some(signing_key) = &self.signing_key {
+            let timestamp = chrono::Utc::now().timestamp();
+            request = request.header("X-Webhook-Timestamp", timestamp).header(
+                "X-Webhook-Signature",
+                signature(signing_key, timestamp, &body),
+            );
+        }
+
+        request.body(body).send().await?.error_for_status()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::api::Visibility;
+    use crate::events::{EventPayload, EventType, PhotoTakenMetadata, SchemaVersion};
+
+    fn event() -> Event {
+        Event {
+            event_type: EventType::Camera,
+            payload: EventPayload::PhotoTaken(PhotoTakenMetadata {
+                schema_version: SchemaVersion,
+                photo_id: "image-id".to_string(),
+                user_address: "0xowner".to_string(),
+                realm: "main".to_string(),
+                scene_id: "scene".to_string(),
+                place_id: "place-id".to_string(),
+                is_public: true,
+                visibility: Visibility::Public,
+                tags: vec![],
+                users: vec![],
+            }),
+            key: "image-id".to_string(),
+            timestamp: 1_700_000_000,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_posts_signed_events() {
+        let mock_server = MockServer::start().await;
+        let body = serde_json::to_vec(&event()).unwrap();
+
+        Mock::given(method("POST"))
+            .and(path("/events"))
+            .and(header("X-Event-Id", "event-id"))
+            .and(header("X-Event-Type", "photo-taken"))
+            .and(header_exists("X-Webhook-Timestamp"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let publisher = WebhookPublisher::new(
+            format!("{}/events", mock_server.uri()),
+            Some("secret".into()),
+        );
+        publisher.publish("event-id", &event()).await.unwrap();
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let header = |name: &'static str| {
+            requests[0].headers[&name.into()]
+                .last()
+                .as_str()
+                .to_string()
+        };
+        let timestamp = header("X-Webhook-Timestamp").parse().unwrap();
+        assert_eq!(requests[0].body, body);
+        assert_eq!(
+            header("X-Webhook-Signature"),
+            signature("secret", timestamp, &body)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fails_on_error_responses() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(header_exists("X-Event-Id"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+
+        let publisher = WebhookPublisher::new(mock_server.uri(), None);
+        assert!(publisher.publish("event-id", &event()).await.is_err());
+    }
+}
@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use actix_web::web::Bytes;
+use moka::future::Cache;
+
+/// Files bigger than this aren't cached, so a few large ones can't evict every thumbnail.
+const MAX_CACHED_FILE_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct CachedImage {
+    pub bytes: Bytes,
+    pub e_tag: Option<String>,
+    pub content_type: Option<String>,
+}
+
+/// In-process cache of the hot image files served in proxy mode, bounded by their total size
+/// in bytes.
+pub struct ImageCache {
+    cache: Cache<String, CachedImage>,
+}
+
+impl ImageCache {
+    pub fn new(ttl_seconds: u64, max_size: u64) -> Self {
+        let cache = Cache::builder()
+            .weigher(|_key: &String, image: &CachedImage| {
+                u32::try_from(image.bytes.len()).unwrap_or(u32::MAX)
+            })
+            .max_capacity(max_size)
+            .time_to_live(Duration::from_secs(ttl_seconds))
+            .build();
+
+        Self { cache }
+    }
+
+    pub async fn get(&self, key: &str) -> Option<CachedImage> {
+        self.cache.get(key).await
+    }
+
+    pub async fn insert(&self, key: String, image: CachedImage) {
+        if image.bytes.len() <= MAX_CACHED_FILE_SIZE {
+            self.cache.insert(key, image).await;
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::time::Duration;
+
+use actix_web::{rt::task::JoinHandle, web::Data};
+use tokio::sync::{broadcast, oneshot};
+
+use crate::{api::GalleryImageWithPlace, database::Database, urls::UrlBuilder};
+
+/// Images kept for the subscribers that fall behind, the slowest ones miss the older images.
+const CAPACITY: usize = 256;
+
+/// Wait before listening again after losing the connection to Postgres.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Fans out the images becoming public to the live streams of this replica.
+pub struct ImageFeed {
+    sender: broadcast::Sender<GalleryImageWithPlace>,
+}
+
+impl ImageFeed {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<GalleryImageWithPlace> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for ImageFeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Running image feed listener. It holds a Postgres connection, so it has to be stopped
+/// before the runtime shuts down: the connection can't be released without one.
+pub struct ImageFeedListener {
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl ImageFeedListener {
+    /// Stops listening and waits for the connection to be released.
+    pub async fn stop(self) {
+        _ = self.shutdown.send(());
+        if let Err(error) = self.task.await {
+            tracing::error!("image feed listener failed: {}", error);
+        }
+    }
+}
+
+/// Feeds the images made public on any replica, announced through Postgres `LISTEN/NOTIFY`,
+/// to the live streams of this replica.
+pub fn spawn_image_feed_listener(
+    feed: Data<ImageFeed>,
+    database: Data<Database>,
+    urls: Data<UrlBuilder>,
+) -> ImageFeedListener {
+    let (shutdown, mut stopped) = oneshot::channel();
+
+    let task = actix_web::rt::spawn(async move {
+        loop {
+            let listener = tokio::select! {
+                _ = &mut stopped => return,
+                listener = database.listen_public_images() => listener,
+            };
+            let mut listener = match listener {
+                Ok(listener) => listener,
+                Err(error) => {
+                    tracing::error!("failed to listen to public images: {}", error);
+                    if stopped_while_waiting(&mut stopped).await {
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            loop {
+                let notification = tokio::select! {
+                    _ = &mut stopped => return,
+                    notification = listener.recv() => notification,
+                };
+                let notification = match notification {
+                    Ok(notification) => notification,
+                    Err(error) => {
+                        tracing::error!("lost the public images notifications: {}", error);
+                        break;
+                    }
+                };
+
+                // Nobody is watching, no need to look the image up
+                if feed.sender.receiver_count() == 0 {
+                    continue;
+                }
+
+                match database.get_image(notification.payload()).await {
+                    // It could have been hidden again in the meantime
+                    Ok(image) if image.visibility.is_public() => {
+                        _ = feed
+                            .sender
+                            .send(GalleryImageWithPlace::from_db(image, &urls));
+                    }
+                    Ok(_) => {}
+                    Err(error) => tracing::error!(
+                        "failed to get public image {}: {}",
+                        notification.payload(),
+                        error
+                    ),
+                }
+            }
+
+            if stopped_while_waiting(&mut stopped).await {
+                return;
+            }
+        }
+    });
+
+    ImageFeedListener { shutdown, task }
+}
+
+/// Waits before reconnecting, returns `true` when the listener was stopped meanwhile.
+async fn stopped_while_waiting(stopped: &mut oneshot::Receiver<()>) -> bool {
+    tokio::select! {
+        _ = stopped => true,
+        _ = actix_web::rt::time::sleep(RECONNECT_DELAY) => false,
+    }
+}
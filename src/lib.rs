@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use actix_web::{
     get,
     web::{scope, Data},
@@ -5,7 +7,6 @@ use actix_web::{
 };
 use database::Database;
 use dcl_http_prom_metrics::HttpMetricsCollectorBuilder;
-use s3::Bucket;
 use tracing::subscriber::set_global_default;
 use tracing_actix_web::TracingLogger;
 use tracing_log::LogTracer;
@@ -13,13 +14,31 @@ use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 use tracing_tree::HierarchicalLayer;
 
 use crate::api::middlewares;
+use crate::archival::{spawn_archival_worker, ArchiveSettings};
+use crate::events::{EventPublisher, FanoutPublisher};
+use crate::image_cache::ImageCache;
+use crate::image_feed::{spawn_image_feed_listener, ImageFeed};
+use crate::moderation::{spawn_moderation_consumer, ModerationConsumer, ModerationMetrics};
+use crate::notifications::TagNotificationsPublisher;
+use crate::outbox::{spawn_outbox_relay, OutboxMetrics, OutboxSettings};
 use crate::places_client::PlacesClient;
-use crate::sns::SNSPublisher;
+use crate::storage::ObjectStorage;
+use crate::urls::{DeliverySettings, UrlBuilder};
+use crate::webhooks::{spawn_webhook_worker, PlaceWebhooksPublisher, WebhookSettings};
 
 pub mod api;
+pub mod archival;
 pub mod database;
+pub mod events;
+pub mod image_cache;
+pub mod image_feed;
+pub mod moderation;
+pub mod notifications;
+pub mod outbox;
 pub mod places_client;
-pub mod sns;
+pub mod storage;
+pub mod urls;
+pub mod webhooks;
 
 #[derive(Debug)]
 pub enum Environment {
@@ -27,11 +46,58 @@ pub enum Environment {
     Prod,
 }
 
+/// How `GET /api/images/{file}` hands out the image files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageDelivery {
+    /// Redirects to the bucket, or to a presigned URL for private and shared images.
+    Redirect,
+    /// Streams the files from the bucket through the service.
+    Proxy,
+}
+
+impl std::str::FromStr for ImageDelivery {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "redirect" => Ok(ImageDelivery::Redirect),
+            "proxy" => Ok(ImageDelivery::Proxy),
+            _ => Err(format!(
+                "invalid image delivery {value}, expected redirect or proxy"
+            )),
+        }
+    }
+}
+
+/// Where the image files are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    S3,
+    /// A local directory, served by the service under `/files`. Meant for development.
+    Local,
+}
+
+impl std::str::FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "s3" => Ok(StorageBackend::S3),
+            "local" => Ok(StorageBackend::Local),
+            _ => Err(format!(
+                "invalid storage backend {value}, expected s3 or local"
+            )),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Settings {
     pub port: u16,
     pub api_url: String,
+    /// Public URL of the stored files.
     pub bucket_url: String,
+    pub storage_backend: StorageBackend,
     pub max_images_per_user: u64,
     pub env: Environment,
     pub aws_sns_arn: String,
@@ -39,28 +105,86 @@ pub struct Settings {
     pub places_api_url: String,
     pub places_cache_ttl_seconds: u64,
     pub places_cache_max_size: u64,
+    pub image_delivery: ImageDelivery,
+    pub image_cache_ttl_seconds: u64,
+    pub image_cache_max_size: u64,
+    /// Secret used to sign the parameters of the image render URLs.
+    pub render_signing_key: String,
+    pub delivery: DeliverySettings,
+    pub archive: ArchiveSettings,
+    pub outbox: OutboxSettings,
+    pub webhooks: WebhookSettings,
+    /// Bearer token of the admin endpoints, which are disabled without one.
+    pub admin_bearer_token: Option<String>,
 }
 
 pub struct Context {
     pub settings: Settings,
     pub database: Database,
-    pub bucket: Bucket,
-    pub sns_publisher: SNSPublisher,
+    pub storage: Arc<dyn ObjectStorage>,
+    pub event_publisher: Arc<dyn EventPublisher>,
     pub places_client: PlacesClient,
+    pub image_cache: ImageCache,
+    /// Consumer of the moderation events, which aren't consumed without one.
+    pub moderation_consumer: Option<ModerationConsumer>,
 }
 
 pub async fn run(context: Context) -> std::io::Result<()> {
-    initialize_tracing();
-
     let port = context.settings.port;
 
+    let urls = Data::new(UrlBuilder::new(&context.settings));
     let settings = Data::new(context.settings);
-    let bucket = Data::new(context.bucket);
+    let storage: Data<dyn ObjectStorage> = Data::from(context.storage);
     let database = Data::new(context.database);
-    let sns_publisher = Data::new(context.sns_publisher);
     let places_client = Data::new(context.places_client);
-
-    let http_metrics_collector = Data::new(HttpMetricsCollectorBuilder::default().build());
+    let image_cache = Data::new(context.image_cache);
+
+    // The place webhooks and the notification inboxes get their events through the outbox as well
+    let event_publisher: Arc<dyn EventPublisher> = Arc::new(FanoutPublisher::new(vec![
+        Box::new(context.event_publisher),
+        Box::new(PlaceWebhooksPublisher::new(database.clone())),
+        Box::new(TagNotificationsPublisher::new(database.clone())),
+    ]));
+    let event_publisher: Data<dyn EventPublisher> = Data::from(event_publisher);
+
+    spawn_archival_worker(settings.archive.clone(), storage.clone(), database.clone());
+    spawn_webhook_worker(
+        settings.webhooks.clone(),
+        database.clone(),
+        places_client.clone(),
+    );
+
+    let image_feed = Data::new(ImageFeed::new());
+    let image_feed_listener =
+        spawn_image_feed_listener(image_feed.clone(), database.clone(), urls.clone());
+
+    let metrics_registry = prometheus::Registry::new();
+    let outbox_metrics =
+        OutboxMetrics::new(&metrics_registry).expect("failed to register outbox metrics");
+    spawn_outbox_relay(
+        settings.outbox.clone(),
+        database.clone(),
+        event_publisher,
+        outbox_metrics,
+    );
+
+    if let Some(consumer) = context.moderation_consumer {
+        let moderation_metrics = ModerationMetrics::new(&metrics_registry)
+            .expect("failed to register moderation metrics");
+        spawn_moderation_consumer(
+            consumer,
+            storage.clone(),
+            database.clone(),
+            places_client.clone(),
+            moderation_metrics,
+        );
+    }
+
+    let http_metrics_collector = Data::new(
+        HttpMetricsCollectorBuilder::default()
+            .registry(metrics_registry)
+            .build(),
+    );
     let metrics_token = std::env::var("WKC_METRICS_BEARER_TOKEN").unwrap_or("".to_string());
 
     let server = HttpServer::new(move || {
@@ -74,10 +198,12 @@ pub async fn run(context: Context) -> std::io::Result<()> {
 
         App::new()
             .app_data(settings.clone())
-            .app_data(bucket.clone())
+            .app_data(urls.clone())
+            .app_data(storage.clone())
             .app_data(database.clone())
-            .app_data(sns_publisher.clone())
             .app_data(places_client.clone())
+            .app_data(image_cache.clone())
+            .app_data(image_feed.clone())
             .app_data(http_metrics_collector.clone())
             .service(scope("/health").wrap(health_cors).service(live))
             .configure(api::services)
@@ -89,10 +215,14 @@ pub async fn run(context: Context) -> std::io::Result<()> {
 
     tracing::debug!("listening on port: {port}");
 
-    server.run().await
+    let result = server.run().await;
+    image_feed_listener.stop().await;
+
+    result
 }
 
-fn initialize_tracing() {
+/// Sets up the logs, before building anything that logs through `tracing`.
+pub fn initialize_tracing() {
     // Redirect all `log`'s events to our subscriber
     LogTracer::init().expect("Failed to set logger");
 
@@ -1,6 +1,21 @@
+use camera_reel_service::archival::ArchiveSettings;
+use camera_reel_service::image_cache::ImageCache;
+use camera_reel_service::outbox::OutboxSettings;
 use camera_reel_service::places_client::PlacesClient;
-use camera_reel_service::sns::SNSPublisher;
-use camera_reel_service::{database::Database, run, Context, Environment, Settings};
+use std::sync::Arc;
+
+use camera_reel_service::events::{
+    sqs_client, EventPublisher, EventSink, FanoutPublisher, LogPublisher, SNSPublisher,
+    SQSPublisher, WebhookPublisher,
+};
+use camera_reel_service::moderation::{ModerationConsumer, ModerationSettings};
+use camera_reel_service::storage::{LocalStorage, ObjectStorage, S3Storage};
+use camera_reel_service::urls::{DeliverySettings, DEFAULT_CDN_URL_TTL_SECS};
+use camera_reel_service::webhooks::WebhookSettings;
+use camera_reel_service::{
+    database::Database, initialize_tracing, run, Context, Environment, ImageDelivery, Settings,
+    StorageBackend,
+};
 use clap::Parser;
 use s3::{creds::Credentials, Bucket, Region};
 
@@ -25,6 +40,13 @@ pub struct Arguments {
     #[clap(long, env, default_value_t = String::from("camera-reel"))]
     s3_bucket_name: String,
 
+    /// `s3`, or `local` to keep the files in `local_storage_path` and run without a bucket
+    #[clap(long, env, default_value = "s3")]
+    storage_backend: StorageBackend,
+
+    #[clap(long, env, default_value_t = String::from("./data/images"))]
+    local_storage_path: String,
+
     #[clap(long, env, default_value_t = 500)]
     max_images_per_user: u64,
 
@@ -34,6 +56,25 @@ pub struct Arguments {
     #[clap(long, env)]
     aws_sns_endpoint: Option<String>,
 
+    /// Where the events are published, comma separated: `sns`, `sqs`, `webhook` or `log`
+    #[clap(long, env, value_delimiter = ',', default_value = "sns")]
+    event_sinks: Vec<EventSink>,
+
+    /// Queue the `sqs` event sink sends the events to
+    #[clap(long, env)]
+    aws_sqs_queue_url: Option<String>,
+
+    #[clap(long, env)]
+    aws_sqs_endpoint: Option<String>,
+
+    /// Endpoint the `webhook` event sink posts the events to
+    #[clap(long, env)]
+    event_webhook_url: Option<String>,
+
+    /// Secret to sign the events posted by the `webhook` event sink, left unsigned when missing
+    #[clap(long, env)]
+    event_webhook_signing_key: Option<String>,
+
     #[clap(long, env, default_value_t = String::from("test"))]
     s3_access_key_id: String,
 
@@ -48,17 +89,110 @@ pub struct Arguments {
 
     #[clap(long, env, default_value_t = 1000)]
     places_cache_max_size: u64,
+
+    /// `redirect` to the bucket, or `proxy` to stream the files through the service
+    #[clap(long, env, default_value = "redirect")]
+    image_delivery: ImageDelivery,
+
+    #[clap(long, env, default_value_t = 3600)]
+    image_cache_ttl_seconds: u64,
+
+    /// Size, in bytes, of the in-process cache of thumbnails used in proxy mode
+    #[clap(long, env, default_value_t = 64 * 1024 * 1024)]
+    image_cache_max_size: u64,
+
+    /// Secret shared with the services that build image render URLs. Required outside dev,
+    /// where it defaults to `local-render-signing-key`
+    #[clap(long, env)]
+    render_signing_key: Option<String>,
+
+    /// CDN serving the originals of the public images, with the bucket as origin
+    #[clap(long, env)]
+    originals_cdn_url: Option<String>,
+
+    /// CDN serving the thumbnails of the public images, with the bucket as origin
+    #[clap(long, env)]
+    thumbnails_cdn_url: Option<String>,
+
+    /// Secret shared with the CDN to sign its URLs, left unsigned when missing
+    #[clap(long, env)]
+    cdn_signing_key: Option<String>,
+
+    #[clap(long, env, default_value_t = DEFAULT_CDN_URL_TTL_SECS)]
+    cdn_url_ttl_seconds: u64,
+
+    /// Archive the originals uploaded more than this many months ago, never when missing
+    #[clap(long, env)]
+    archive_after_months: Option<u32>,
+
+    /// Seconds between the runs of the worker archiving originals and restoring them
+    #[clap(long, env, default_value_t = 60)]
+    archive_interval_seconds: u64,
+
+    /// Seconds between the runs of the relay publishing the outbox events to SNS
+    #[clap(long, env, default_value_t = 1)]
+    outbox_interval_seconds: u64,
+
+    /// Longest wait between two attempts to publish an outbox event
+    #[clap(long, env, default_value_t = 900)]
+    outbox_max_backoff_seconds: u64,
+
+    /// Outbox events still waiting after this many seconds are reported as stuck
+    #[clap(long, env, default_value_t = 300)]
+    outbox_stuck_after_seconds: u64,
+
+    /// Seconds between the runs of the worker delivering the place webhooks
+    #[clap(long, env, default_value_t = 5)]
+    webhook_interval_seconds: u64,
+
+    /// Lets place webhooks point at loopback and private addresses, for local development
+    #[clap(long, env, default_value_t = false)]
+    webhook_allow_private_networks: bool,
+
+    /// Longest wait between two attempts of a place webhook delivery
+    #[clap(long, env, default_value_t = 3600)]
+    webhook_max_backoff_seconds: u64,
+
+    /// Failed deliveries in a row after which a place webhook is disabled
+    #[clap(long, env, default_value_t = 20)]
+    webhook_max_consecutive_failures: i32,
+
+    /// Queue the moderation and account events are consumed from, not consumed when missing
+    #[clap(long, env)]
+    moderation_queue_url: Option<String>,
+
+    #[clap(long, env)]
+    moderation_sqs_endpoint: Option<String>,
+
+    /// Queue the moderation events that can't be handled are moved to, left to the redrive
+    /// policy of the queue when missing
+    #[clap(long, env)]
+    moderation_dlq_url: Option<String>,
+
+    /// Failed receives after which a moderation event is dead-lettered
+    #[clap(long, env, default_value_t = 5)]
+    moderation_max_receive_count: u32,
+
+    /// Seconds each receive of moderation events waits for messages, up to 20
+    #[clap(long, env, default_value_t = 20)]
+    moderation_wait_time_seconds: i32,
+
+    /// Bearer token of the admin endpoints, disabled when missing
+    #[clap(long, env)]
+    admin_bearer_token: Option<String>,
 }
 
 #[actix_web::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut args = Arguments::parse();
+    initialize_tracing();
 
     let database = Database::from_url(&args.database_url).await?;
 
     println!("Connected to the database");
 
-    let aws_region = args.aws_region.clone();
+    let event_publisher = create_event_publisher(&args).await?;
+    let moderation_consumer = create_moderation_consumer(&args).await?;
 
     // Use S3 credentials from arguments (defaults to "test" if not provided)
     let s3_access_key = &args.s3_access_key_id;
@@ -72,59 +206,99 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         args.s3_url = format!("{}/{}", args.s3_url, args.s3_bucket_name);
 
         let credentials =
-            Credentials::new(Some(&s3_access_key), Some(&s3_secret_key), None, None, None)?;
+            Credentials::new(Some(s3_access_key), Some(s3_secret_key), None, None, None)?;
 
         (region, credentials)
     } else {
         let region = args.aws_region.parse()?;
         let credentials =
-            Credentials::new(Some(&s3_access_key), Some(&s3_secret_key), None, None, None)?;
+            Credentials::new(Some(s3_access_key), Some(s3_secret_key), None, None, None)?;
 
         (region, credentials)
     };
 
-    let bucket = Bucket::new(&args.s3_bucket_name, region, s3_credentials)?.with_path_style();
+    let (bucket_url, storage): (String, Arc<dyn ObjectStorage>) = match args.storage_backend {
+        StorageBackend::S3 => {
+            let bucket =
+                Bucket::new(&args.s3_bucket_name, region, s3_credentials)?.with_path_style();
+            let s3_url = args.s3_url.trim_end_matches('/').to_string();
+            let storage = S3Storage::new(bucket, &s3_url);
+
+            (s3_url, Arc::new(storage))
+        }
+        StorageBackend::Local => {
+            let files_url = format!("{}/files", args.api_url.trim_end_matches('/'));
+            let storage = LocalStorage::new(&args.local_storage_path, &files_url);
+            println!("Storing images in {}", args.local_storage_path);
+
+            (files_url, Arc::new(storage))
+        }
+    };
 
-    let s3_url = if !args.s3_url.ends_with('/') {
-        args.s3_url.to_string()
-    } else {
-        let mut s3_url = args.s3_url.to_string();
-        s3_url.remove(args.s3_url.len() - 1);
-        s3_url
+    let env = read_env();
+    let render_signing_key = match (args.render_signing_key, &env) {
+        (Some(key), _) => key,
+        (None, Environment::Dev) => String::from("local-render-signing-key"),
+        (None, _) => return Err("RENDER_SIGNING_KEY is required outside dev".into()),
     };
 
     let settings = Settings {
         port: args.port,
-        bucket_url: s3_url.to_string(),
+        bucket_url,
+        storage_backend: args.storage_backend,
         api_url: args.api_url,
         max_images_per_user: args.max_images_per_user,
-        env: read_env(),
+        env,
         aws_sns_arn: args.aws_sns_arn.clone(),
         aws_sns_endpoint: args.aws_sns_endpoint.clone(),
         places_api_url: args.places_api_url.clone(),
         places_cache_ttl_seconds: args.places_cache_ttl_seconds,
         places_cache_max_size: args.places_cache_max_size,
+        image_delivery: args.image_delivery,
+        image_cache_ttl_seconds: args.image_cache_ttl_seconds,
+        image_cache_max_size: args.image_cache_max_size,
+        render_signing_key,
+        delivery: DeliverySettings {
+            originals_cdn_url: args.originals_cdn_url,
+            thumbnails_cdn_url: args.thumbnails_cdn_url,
+            cdn_signing_key: args.cdn_signing_key,
+            cdn_url_ttl_seconds: args.cdn_url_ttl_seconds,
+        },
+        archive: ArchiveSettings {
+            after_months: args.archive_after_months,
+            interval_seconds: args.archive_interval_seconds,
+        },
+        outbox: OutboxSettings {
+            interval_seconds: args.outbox_interval_seconds,
+            max_backoff_seconds: args.outbox_max_backoff_seconds,
+            stuck_after_seconds: args.outbox_stuck_after_seconds,
+        },
+        webhooks: WebhookSettings {
+            interval_seconds: args.webhook_interval_seconds,
+            max_backoff_seconds: args.webhook_max_backoff_seconds,
+            max_consecutive_failures: args.webhook_max_consecutive_failures,
+            allow_private_networks: args.webhook_allow_private_networks,
+        },
+        admin_bearer_token: args.admin_bearer_token.filter(|token| !token.is_empty()),
     };
     println!("Starting camera-reel-service");
 
-    // Create SNS Publisher
-    let sns_publisher =
-        SNSPublisher::new(args.aws_sns_arn, args.aws_sns_endpoint, aws_region).await?;
-
-    println!("SNS Publisher created");
-
     let places_client = PlacesClient::new(
         args.places_api_url,
         args.places_cache_ttl_seconds,
         args.places_cache_max_size,
     );
 
+    let image_cache = ImageCache::new(args.image_cache_ttl_seconds, args.image_cache_max_size);
+
     let context = Context {
         settings,
         database,
-        bucket,
-        sns_publisher,
+        storage,
+        event_publisher,
         places_client,
+        image_cache,
+        moderation_consumer,
     };
 
     Ok(run(context).await.map_err(|e| {
@@ -133,6 +307,93 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     })?)
 }
 
+/// Builds the publishers of the event sinks, fanning out to all of them when there are several.
+async fn create_event_publisher(
+    args: &Arguments,
+) -> Result<Arc<dyn EventPublisher>, Box<dyn std::error::Error>> {
+    // Without sinks the relay would mark every event as delivered without publishing it
+    if args.event_sinks.is_empty() {
+        return Err("EVENT_SINKS needs at least one sink".into());
+    }
+    tracing::info!("publishing events to {:?}", args.event_sinks);
+
+    let mut publishers: Vec<Box<dyn EventPublisher>> = Vec::new();
+    for sink in &args.event_sinks {
+        let publisher: Box<dyn EventPublisher> = match sink {
+            EventSink::Sns => Box::new(
+                SNSPublisher::new(
+                    args.aws_sns_arn.clone(),
+                    args.aws_sns_endpoint.clone(),
+                    args.aws_region.clone(),
+                )
+                .await?,
+            ),
+            EventSink::Sqs => {
+                let Some(queue_url) = &args.aws_sqs_queue_url else {
+                    return Err("the sqs event sink needs AWS_SQS_QUEUE_URL".into());
+                };
+                Box::new(
+                    SQSPublisher::new(
+                        queue_url.clone(),
+                        args.aws_sqs_endpoint.clone(),
+                        args.aws_region.clone(),
+                    )
+                    .await?,
+                )
+            }
+            EventSink::Webhook => {
+                let Some(url) = &args.event_webhook_url else {
+                    return Err("the webhook event sink needs EVENT_WEBHOOK_URL".into());
+                };
+                Box::new(WebhookPublisher::new(
+                    url.clone(),
+                    args.event_webhook_signing_key.clone(),
+                ))
+            }
+            EventSink::Log => Box::new(LogPublisher),
+        };
+        publishers.push(publisher);
+    }
+
+    if publishers.len() == 1 {
+        return Ok(Arc::from(publishers.remove(0)));
+    }
+
+    Ok(Arc::new(FanoutPublisher::new(publishers)))
+}
+
+async fn create_moderation_consumer(
+    args: &Arguments,
+) -> Result<Option<ModerationConsumer>, Box<dyn std::error::Error>> {
+    let Some(queue_url) = args
+        .moderation_queue_url
+        .as_ref()
+        .filter(|url| !url.is_empty())
+    else {
+        return Ok(None);
+    };
+
+    let client = sqs_client(
+        args.moderation_sqs_endpoint.clone(),
+        args.aws_region.clone(),
+    )
+    .await?;
+    println!("Consuming moderation events from {queue_url}");
+
+    Ok(Some(ModerationConsumer::new(
+        client,
+        ModerationSettings {
+            queue_url: queue_url.clone(),
+            dead_letter_queue_url: args
+                .moderation_dlq_url
+                .clone()
+                .filter(|url| !url.is_empty()),
+            max_receive_count: args.moderation_max_receive_count,
+            wait_time_seconds: args.moderation_wait_time_seconds,
+        },
+    )))
+}
+
 fn read_env() -> Environment {
     match std::env::var("ENV") {
         Ok(env) if env == "prd" => Environment::Prod,
@@ -0,0 +1,485 @@
+use actix_web::web::Data;
+use aws_sdk_sqs::types::{Message, MessageAttributeValue, MessageSystemAttributeName};
+use prometheus::{IntCounter, IntCounterVec, Opts, Registry};
+use serde::Deserialize;
+
+use crate::{
+    api::{delete::photo_deleted_event, update::VisibilityChangeError, Visibility},
+    database::{DBImage, Database},
+    events::{Event, EventPayload, PhotoPrivacyChangedMetadata, SchemaVersion},
+    places_client::PlacesClient,
+    storage::{delete_renders, move_image_objects, object_key, ObjectStorage, Rendition},
+};
+
+/// Images hidden or deleted per query while handling an event.
+const BATCH_SIZE: i64 = 100;
+
+/// Events from other services that take images down.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ModerationEvent {
+    /// Hides the images of the user and rejects their uploads.
+    UserBanned { address: String },
+    /// Deletes the images of the user.
+    AccountClosed { address: String },
+    /// Hides the images taken in the place.
+    #[serde(rename_all = "camelCase")]
+    PlaceDeleted { place_id: String },
+    /// Hides the images taken in the world. Its places are looked up in the places API when
+    /// the event doesn't list them, which only works until the API forgets the world.
+    #[serde(rename_all = "camelCase")]
+    WorldUnpublished {
+        world_name: String,
+        #[serde(default)]
+        place_ids: Vec<String>,
+    },
+}
+
+impl ModerationEvent {
+    fn event_type(&self) -> &'static str {
+        match self {
+            ModerationEvent::UserBanned { .. } => "user-banned",
+            ModerationEvent::AccountClosed { .. } => "account-closed",
+            ModerationEvent::PlaceDeleted { .. } => "place-deleted",
+            ModerationEvent::WorldUnpublished { .. } => "world-unpublished",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ModerationMessage {
+    /// Id of the event, deduplicated on. The id of the message is used when missing.
+    id: Option<String>,
+    #[serde(flatten)]
+    event: ModerationEvent,
+}
+
+/// SNS envelope of the messages of queues subscribed to a topic without raw delivery.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct SnsNotification {
+    message_id: String,
+    message: String,
+}
+
+/// Parses the body of a message, sent as is or through SNS. Returns the event along with its
+/// id, `message_id` unless the event or its SNS envelope has one.
+fn parse_message(
+    body: &str,
+    message_id: &str,
+) -> Result<(String, ModerationEvent), serde_json::Error> {
+    let (body, message_id) = match serde_json::from_str::<SnsNotification>(body) {
+        Ok(notification) => (notification.message, notification.message_id),
+        Err(_) => (body.to_string(), message_id.to_string()),
+    };
+
+    let message = serde_json::from_str::<ModerationMessage>(&body)?;
+
+    Ok((message.id.unwrap_or(message_id), message.event))
+}
+
+/// Where the moderation events are consumed from.
+pub struct ModerationSettings {
+    pub queue_url: String,
+    /// Queue the messages that can't be handled are moved to. Without one, they are left to
+    /// the redrive policy of the queue.
+    pub dead_letter_queue_url: Option<String>,
+    /// Failed receives after which a message is dead-lettered.
+    pub max_receive_count: u32,
+    /// Seconds each receive waits for messages, up to 20.
+    pub wait_time_seconds: i32,
+}
+
+/// Prometheus metrics of the moderation consumer, served by `/metrics` along with the HTTP ones.
+#[derive(Clone)]
+pub struct ModerationMetrics {
+    /// Events by type and outcome: `processed`, `duplicate`, `failed` or `dead-lettered`.
+    events: IntCounterVec,
+    hidden_images: IntCounter,
+    deleted_images: IntCounter,
+}
+
+impl ModerationMetrics {
+    pub fn new(registry: &Registry) -> prometheus::Result<Self> {
+        let metrics = Self {
+            events: IntCounterVec::new(
+                Opts::new("moderation_events_total", "Moderation events consumed"),
+                &["type", "outcome"],
+            )?,
+            hidden_images: IntCounter::new(
+                "moderation_hidden_images_total",
+                "Images hidden by moderation events",
+            )?,
+            deleted_images: IntCounter::new(
+                "moderation_deleted_images_total",
+                "Images deleted by moderation events",
+            )?,
+        };
+
+        registry.register(Box::new(metrics.events.clone()))?;
+        registry.register(Box::new(metrics.hidden_images.clone()))?;
+        registry.register(Box::new(metrics.deleted_images.clone()))?;
+
+        Ok(metrics)
+    }
+}
+
+/// Consumes the moderation and account events of other services from SQS, and hides or
+/// deletes the images they affect.
+pub struct ModerationConsumer {
+    client: aws_sdk_sqs::Client,
+    settings: ModerationSettings,
+}
+
+impl ModerationConsumer {
+    pub fn new(client: aws_sdk_sqs::Client, settings: ModerationSettings) -> Self {
+        Self { client, settings }
+    }
+
+    /// Receives a batch of messages and handles them. An event is recorded once handled, so a
+    /// message received again is only deleted. Failed messages are received again after their
+    /// visibility timeout, and dead-lettered once they failed `max_receive_count` times.
+    /// Returns how many messages were received.
+    pub async fn consume(
+        &self,
+        storage: &dyn ObjectStorage,
+        database: &Database,
+        places_client: &PlacesClient,
+        metrics: &ModerationMetrics,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let output = self
+            .client
+            .receive_message()
+            .queue_url(&self.settings.queue_url)
+            .max_number_of_messages(10)
+            .wait_time_seconds(self.settings.wait_time_seconds)
+            .message_system_attribute_names(MessageSystemAttributeName::ApproximateReceiveCount)
+            .send()
+            .await?;
+
+        let messages = output.messages();
+        for message in messages {
+            let body = message.body().unwrap_or_default();
+            let message_id = message.message_id().unwrap_or_default();
+
+            let (event_id, event) = match parse_message(body, message_id) {
+                Ok(parsed) => parsed,
+                Err(error) => {
+                    tracing::error!("invalid moderation message {}: {}", message_id, error);
+                    self.dead_letter(message, &error.to_string(), "unknown", metrics)
+                        .await?;
+                    continue;
+                }
+            };
+            let event_type = event.event_type();
+
+            if database.is_moderation_event_processed(&event_id).await? {
+                metrics
+                    .events
+                    .with_label_values(&[event_type, "duplicate"])
+                    .inc();
+                self.delete(message).await?;
+                continue;
+            }
+
+            match handle_event(storage, database, places_client, metrics, &event).await {
+                Ok(affected_images) => {
+                    tracing::info!(
+                        "handled {} event {}, {} images affected",
+                        event_type,
+                        event_id,
+                        affected_images
+                    );
+                    database
+                        .mark_moderation_event_processed(&event_id, event_type, affected_images)
+                        .await?;
+                    metrics
+                        .events
+                        .with_label_values(&[event_type, "processed"])
+                        .inc();
+                    self.delete(message).await?;
+                }
+                Err(error) => {
+                    tracing::error!(
+                        "failed to handle {} event {}: {}",
+                        event_type,
+                        event_id,
+                        error
+                    );
+                    metrics
+                        .events
+                        .with_label_values(&[event_type, "failed"])
+                        .inc();
+                    if receive_count(message) >= self.settings.max_receive_count {
+                        self.dead_letter(message, &error.to_string(), event_type, metrics)
+                            .await?;
+                    }
+                }
+            }
+        }
+
+        Ok(messages.len())
+    }
+
+    async fn delete(&self, message: &Message) -> Result<(), Box<dyn std::error::Error>> {
+        self.client
+            .delete_message()
+            .queue_url(&self.settings.queue_url)
+            .receipt_handle(message.receipt_handle().unwrap_or_default())
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    /// Moves a message to the dead-letter queue, with why it failed in its `error` attribute.
+    async fn dead_letter(
+        &self,
+        message: &Message,
+        error: &str,
+        event_type: &str,
+        metrics: &ModerationMetrics,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(dead_letter_queue_url) = &self.settings.dead_letter_queue_url else {
+            return Ok(());
+        };
+
+        self.client
+            .send_message()
+            .queue_url(dead_letter_queue_url)
+            .message_body(message.body().unwrap_or_default())
+            .message_attributes(
+                "error",
+                MessageAttributeValue::builder()
+                    .data_type("String")
+                    .string_value(error)
+                    .build()?,
+            )
+            .send()
+            .await?;
+        self.delete(message).await?;
+        metrics
+            .events
+            .with_label_values(&[event_type, "dead-lettered"])
+            .inc();
+
+        Ok(())
+    }
+}
+
+fn receive_count(message: &Message) -> u32 {
+    message
+        .attributes()
+        .and_then(|attributes| attributes.get(&MessageSystemAttributeName::ApproximateReceiveCount))
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(1)
+}
+
+/// Hides or deletes the images affected by an event. Images already handled are skipped, so
+/// handling an event again only catches up with the images it missed. Returns how many images
+/// were hidden or deleted.
+pub async fn handle_event(
+    storage: &dyn ObjectStorage,
+    database: &Database,
+    places_client: &PlacesClient,
+    metrics: &ModerationMetrics,
+    event: &ModerationEvent,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let reason = event.event_type();
+    if let ModerationEvent::UserBanned { address } = event {
+        database.ban_user(address, reason).await?;
+    }
+
+    let mut affected_images = 0;
+    loop {
+        let images = match event {
+            ModerationEvent::UserBanned { address } => {
+                database
+                    .get_unmoderated_user_images(address, BATCH_SIZE)
+                    .await?
+            }
+            ModerationEvent::AccountClosed { address } => {
+                database.get_oldest_user_images(address, BATCH_SIZE).await?
+            }
+            ModerationEvent::PlaceDeleted { place_id } => {
+                database
+                    .get_unmoderated_places_images(std::slice::from_ref(place_id), BATCH_SIZE)
+                    .await?
+            }
+            ModerationEvent::WorldUnpublished {
+                world_name,
+                place_ids,
+            } => {
+                let place_ids = if place_ids.is_empty() {
+                    places_client
+                        .get_world_place_ids(world_name)
+                        .await
+                        .map_err(|error| format!("failed to resolve world name: {error}"))?
+                } else {
+                    place_ids.clone()
+                };
+                database
+                    .get_unmoderated_places_images(&place_ids, BATCH_SIZE)
+                    .await?
+            }
+        };
+        if images.is_empty() {
+            return Ok(affected_images);
+        }
+
+        for image in images {
+            if matches!(event, ModerationEvent::AccountClosed { .. }) {
+                delete_image(storage, database, &image).await?;
+                metrics.deleted_images.inc();
+            } else {
+                hide_image(storage, database, &image, reason).await?;
+                metrics.hidden_images.inc();
+            }
+            affected_images += 1;
+        }
+    }
+}
+
+/// Takes an image down, making it private until moderation lets it go. Its files are moved
+/// first and moved back if the image can't be updated.
+async fn hide_image(
+    storage: &dyn ObjectStorage,
+    database: &Database,
+    image: &DBImage,
+    reason: &str,
+) -> Result<(), VisibilityChangeError> {
+    let image_id = image.id.to_string();
+
+    // Same event as when the owner changes the visibility, with why it was hidden. Private
+    // images don't change for anyone else, so there's nothing to tell.
+    let event = (image.visibility != Visibility::Private).then(|| {
+        Event::new(
+            &image_id,
+            EventPayload::PhotoPrivacyChanged(PhotoPrivacyChangedMetadata {
+                schema_version: SchemaVersion,
+                photo_id: image_id.clone(),
+                user_address: image.user_address.to_lowercase(),
+                is_public: false,
+                visibility: Visibility::Private,
+                previous_visibility: image.visibility,
+                shared_with: vec![],
+                moderation_reason: Some(reason.to_string()),
+            }),
+        )
+    });
+
+    let keys = image.stored_keys();
+    move_image_objects(storage, &keys, image.visibility, Visibility::Private)
+        .await
+        .map_err(VisibilityChangeError::Storage)?;
+
+    if let Err(error) = database
+        .moderate_image(image.id, reason, event.as_ref())
+        .await
+    {
+        if let Err(error) =
+            move_image_objects(storage, &keys, Visibility::Private, image.visibility).await
+        {
+            tracing::error!("failed to move image files back: {}", error);
+        }
+        return Err(VisibilityChangeError::Database(error));
+    }
+
+    // Rendered variants are dropped instead of moved, they are rendered again when requested
+    if image.visibility.is_public() {
+        if let Err(error) = delete_renders(storage, &image_id, image.visibility).await {
+            tracing::error!("failed to delete image renders: {}", error);
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes an image along with its files.
+async fn delete_image(
+    storage: &dyn ObjectStorage,
+    database: &Database,
+    image: &DBImage,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let image_id = image.id.to_string();
+    database
+        .delete_image(&image_id, &photo_deleted_event(image))
+        .await?;
+
+    // The image is gone already, leftover files are only logged
+    let mut keys = vec![image.stored_key(Rendition::Original)];
+    keys.extend(image.thumbnail_key.clone());
+    for key in keys {
+        if let Err(error) = storage.delete(&object_key(&key, image.visibility)).await {
+            tracing::error!("failed to delete {}: {}", key, error);
+        }
+    }
+    if let Err(error) = delete_renders(storage, &image_id, image.visibility).await {
+        tracing::error!("failed to delete image renders: {}", error);
+    }
+
+    Ok(())
+}
+
+/// Runs the moderation consumer in the background, long polling the queue.
+pub fn spawn_moderation_consumer(
+    consumer: ModerationConsumer,
+    storage: Data<dyn ObjectStorage>,
+    database: Data<Database>,
+    places_client: Data<PlacesClient>,
+    metrics: ModerationMetrics,
+) {
+    actix_web::rt::spawn(async move {
+        loop {
+            if let Err(error) = consumer
+                .consume(storage.get_ref(), &database, &places_client, &metrics)
+                .await
+            {
+                tracing::error!("failed to consume moderation events: {}", error);
+                actix_web::rt::time::sleep(std::time::Duration::from_secs(5)).await;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_raw_and_sns_messages() {
+        let body = r#"{"id":"ban-1","type":"user-banned","address":"0xabc"}"#;
+        assert_eq!(
+            parse_message(body, "message-id").unwrap(),
+            (
+                "ban-1".to_string(),
+                ModerationEvent::UserBanned {
+                    address: "0xabc".to_string()
+                }
+            )
+        );
+
+        let body = serde_json::json!({
+            "Type": "Notification",
+            "MessageId": "sns-message-id",
+            "Message": r#"{"type":"world-unpublished","worldName":"my-world.eth"}"#,
+        })
+        .to_string();
+        assert_eq!(
+            parse_message(&body, "message-id").unwrap(),
+            (
+                "sns-message-id".to_string(),
+                ModerationEvent::WorldUnpublished {
+                    world_name: "my-world.eth".to_string(),
+                    place_ids: vec![]
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_rejects_unknown_events() {
+        assert!(parse_message(r#"{"type":"user-unbanned","address":"0xabc"}"#, "id").is_err());
+        assert!(parse_message("not json", "id").is_err());
+    }
+}
@@ -0,0 +1,44 @@
+use actix_web::web::Data;
+use async_trait::async_trait;
+
+use crate::{
+    database::Database,
+    events::{Event, EventPayload, EventPublisher},
+};
+
+/// Stores the `photo-tagged` events in the inbox of the tagged people, for the clients that
+/// can't subscribe to the SNS topic. Sits in the fan-out behind the outbox, keyed by the event
+/// id, so an event published again is stored once.
+pub struct TagNotificationsPublisher {
+    database: Data<Database>,
+}
+
+impl TagNotificationsPublisher {
+    pub fn new(database: Data<Database>) -> Self {
+        Self { database }
+    }
+}
+
+#[async_trait(?Send)]
+impl EventPublisher for TagNotificationsPublisher {
+    async fn publish(
+        &self,
+        event_id: &str,
+        event: &Event,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let EventPayload::PhotoTagged(metadata) = &event.payload else {
+            return Ok(());
+        };
+
+        self.database
+            .insert_tag_notification(
+                event_id,
+                &metadata.tagged_address,
+                &event.key,
+                &metadata.user_address,
+            )
+            .await?;
+
+        Ok(())
+    }
+}
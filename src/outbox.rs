@@ -0,0 +1,211 @@
+use std::time::{Duration, Instant};
+
+use actix_web::web::Data;
+use prometheus::{Gauge, IntCounter, IntGauge, Registry};
+
+use crate::{database::Database, events::EventPublisher};
+
+/// Events claimed at once by each pass of the relay.
+const BATCH_SIZE: i64 = 100;
+
+/// Seconds a claimed event is held back from other relays while it is published.
+const LEASE_SECS: i64 = 60;
+
+/// How long delivered events are kept before being deleted.
+const DELIVERED_RETENTION_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// How often the delivered events past their retention are deleted.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How the events queued in the outbox are published.
+#[derive(Debug, Clone)]
+pub struct OutboxSettings {
+    /// Seconds between the passes of the relay.
+    pub interval_seconds: u64,
+    /// Longest wait between two attempts to publish an event. Waits start at a second and
+    /// double on each failed attempt.
+    pub max_backoff_seconds: u64,
+    /// Events still waiting after this many seconds are reported as stuck.
+    pub stuck_after_seconds: u64,
+}
+
+impl Default for OutboxSettings {
+    fn default() -> Self {
+        Self {
+            interval_seconds: 1,
+            max_backoff_seconds: 15 * 60,
+            stuck_after_seconds: 5 * 60,
+        }
+    }
+}
+
+/// Prometheus metrics of the outbox, served by `/metrics` along with the HTTP ones.
+#[derive(Clone)]
+pub struct OutboxMetrics {
+    pending: IntGauge,
+    lag_seconds: Gauge,
+    delivered: IntCounter,
+    failed: IntCounter,
+}
+
+impl OutboxMetrics {
+    pub fn new(registry: &Registry) -> prometheus::Result<Self> {
+        let metrics = Self {
+            pending: IntGauge::new("outbox_pending_events", "Events waiting to be published")?,
+            lag_seconds: Gauge::new(
+                "outbox_lag_seconds",
+                "Age of the oldest event waiting to be published",
+            )?,
+            delivered: IntCounter::new("outbox_delivered_events_total", "Events published")?,
+            failed: IntCounter::new(
+                "outbox_failed_deliveries_total",
+                "Failed attempts to publish events",
+            )?,
+        };
+
+        registry.register(Box::new(metrics.pending.clone()))?;
+        registry.register(Box::new(metrics.lag_seconds.clone()))?;
+        registry.register(Box::new(metrics.delivered.clone()))?;
+        registry.register(Box::new(metrics.failed.clone()))?;
+
+        Ok(metrics)
+    }
+}
+
+/// Seconds to wait before trying again an event that already failed `attempts` times.
+pub(crate) fn backoff_secs(attempts: i32, max_backoff_seconds: u64) -> i64 {
+    let backoff = 1_u64
+        .checked_shl(attempts.max(0) as u32)
+        .unwrap_or(u64::MAX)
+        .min(max_backoff_seconds.max(1));
+
+    backoff as i64
+}
+
+/// Formats an error along with its sources, AWS errors only say `service error` otherwise.
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(&format!(": {error}"));
+        source = error.source();
+    }
+
+    message
+}
+
+/// Publishes the events due for delivery, the oldest first, until none is left. Failed ones
+/// are scheduled again with an exponential backoff, and the ones that can't be decoded are
+/// parked. Returns how many were delivered and how many failed.
+pub async fn relay_outbox_events(
+    database: &Database,
+    event_publisher: &dyn EventPublisher,
+    settings: &OutboxSettings,
+) -> Result<(usize, usize), Box<dyn std::error::Error>> {
+    let (mut delivered, mut failed) = (0, 0);
+    loop {
+        let events = database.claim_outbox_events(BATCH_SIZE, LEASE_SECS).await?;
+        let claimed = events.len() as i64;
+
+        for event in events {
+            // Rows that can't be decoded would fail every time, they are set aside instead
+            let decoded = match event.decode() {
+                Ok(decoded) => decoded,
+                Err(error) => {
+                    tracing::error!("failed to decode outbox event {}: {}", event.id, error);
+                    database
+                        .park_outbox_event(event.id, &format!("invalid event: {error}"))
+                        .await?;
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            match event_publisher
+                .publish(&event.id.to_string(), &decoded)
+                .await
+            {
+                Ok(_) => {
+                    database.mark_outbox_event_delivered(event.id).await?;
+                    delivered += 1;
+                }
+                Err(error) => {
+                    let error = error_chain(error.as_ref());
+                    tracing::error!("failed to publish outbox event {}: {}", event.id, error);
+                    let retry_in = backoff_secs(event.attempts, settings.max_backoff_seconds);
+                    database
+                        .mark_outbox_event_failed(event.id, &error, retry_in)
+                        .await?;
+                    failed += 1;
+                }
+            }
+        }
+
+        if claimed < BATCH_SIZE {
+            return Ok((delivered, failed));
+        }
+    }
+}
+
+/// Runs the outbox relay in the background, publishing the queued events every
+/// `interval_seconds` and updating the outbox metrics.
+pub fn spawn_outbox_relay(
+    settings: OutboxSettings,
+    database: Data<Database>,
+    event_publisher: Data<dyn EventPublisher>,
+    metrics: OutboxMetrics,
+) {
+    actix_web::rt::spawn(async move {
+        let mut interval =
+            actix_web::rt::time::interval(Duration::from_secs(settings.interval_seconds.max(1)));
+        let mut last_purge: Option<Instant> = None;
+        loop {
+            interval.tick().await;
+
+            match relay_outbox_events(&database, event_publisher.get_ref(), &settings).await {
+                Ok((delivered, failed)) => {
+                    metrics.delivered.inc_by(delivered as u64);
+                    metrics.failed.inc_by(failed as u64);
+                }
+                Err(error) => tracing::error!("failed to relay outbox events: {}", error),
+            }
+
+            match database.get_outbox_lag().await {
+                Ok((pending, lag_seconds)) => {
+                    metrics.pending.set(pending);
+                    metrics.lag_seconds.set(lag_seconds);
+                }
+                Err(error) => tracing::error!("failed to get outbox lag: {}", error),
+            }
+
+            if last_purge.is_some_and(|last_purge| last_purge.elapsed() < PURGE_INTERVAL) {
+                continue;
+            }
+            last_purge = Some(Instant::now());
+            match database
+                .delete_delivered_outbox_events(DELIVERED_RETENTION_SECS)
+                .await
+            {
+                Ok(0) => {}
+                Ok(deleted) => tracing::info!("deleted {} delivered outbox events", deleted),
+                Err(error) => {
+                    tracing::error!("failed to delete delivered outbox events: {}", error)
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_doubles_the_backoff_up_to_the_maximum() {
+        assert_eq!(backoff_secs(0, 900), 1);
+        assert_eq!(backoff_secs(1, 900), 2);
+        assert_eq!(backoff_secs(5, 900), 32);
+        assert_eq!(backoff_secs(10, 900), 900);
+        assert_eq!(backoff_secs(100, 900), 900);
+    }
+}
@@ -0,0 +1,373 @@
+use std::pin::Pin;
+
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use futures_util::Stream;
+use serde::Deserialize;
+use sqlx::types::chrono::NaiveDateTime;
+
+use crate::{
+    api::{Image, Visibility},
+    database::{DBImage, Database},
+    urls::UrlBuilder,
+};
+
+mod local;
+mod s3;
+
+pub use self::local::LocalStorage;
+pub use self::s3::S3Storage;
+
+/// Prefix of the objects of the images that aren't public, unlisted ones included. The bucket
+/// policy must keep it out of public reads, so these objects are only reachable through
+/// presigned URLs.
+pub const PRIVATE_PREFIX: &str = "private/";
+
+/// Prefix of the rendered variants of the images, under the image id.
+pub const RENDERS_PREFIX: &str = "renders/";
+
+/// Prefix of the archived originals, under the `private/` prefix for non-public images. A bucket
+/// lifecycle rule moves these objects to a colder storage class. Only the classes read
+/// instantly, like Glacier Instant Retrieval, are supported: objects that need a restore first
+/// can't be moved back.
+pub const ARCHIVE_PREFIX: &str = "archive/";
+
+/// How long a presigned URL to a private object stays valid.
+pub const PRESIGNED_URL_EXPIRATION_SECS: u32 = 300;
+
+#[derive(Debug)]
+pub enum StorageError {
+    NotFound,
+    Backend(String),
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::NotFound => write!(f, "object not found"),
+            StorageError::Backend(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+/// An object read at once, with the headers needed to serve it.
+#[derive(Debug, Clone)]
+pub struct StoredObject {
+    pub bytes: Bytes,
+    pub e_tag: Option<String>,
+    pub content_type: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectInfo {
+    pub content_length: u64,
+    pub e_tag: Option<String>,
+    pub content_type: Option<String>,
+    /// Whether the object is in a class that must be restored before it's read, like Glacier
+    /// Flexible Retrieval or Deep Archive, and has no restored copy.
+    pub needs_restore: bool,
+}
+
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, StorageError>>>>;
+
+/// Where the image files are kept. Keys are the same whatever the backend, so the bucket
+/// layout (like the `private/` prefix) doesn't depend on it.
+#[async_trait(?Send)]
+pub trait ObjectStorage: Send + Sync {
+    async fn put(&self, key: &str, bytes: &[u8], content_type: &str) -> Result<(), StorageError>;
+
+    async fn get(&self, key: &str) -> Result<StoredObject, StorageError>;
+
+    /// Reads the bytes from `start` to `end`, both included.
+    async fn get_range(&self, key: &str, start: u64, end: u64) -> Result<Bytes, StorageError>;
+
+    async fn get_stream(&self, key: &str) -> Result<ByteStream, StorageError>;
+
+    async fn head(&self, key: &str) -> Result<ObjectInfo, StorageError>;
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+
+    /// Returns the keys of all the objects under a prefix.
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError>;
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError>;
+
+    /// Returns a URL to read an object for a while, even if it isn't publicly readable.
+    fn presign_get(&self, key: &str, expires_in_secs: u32) -> Result<String, StorageError>;
+
+    /// Returns the URL of a publicly readable object.
+    fn public_url(&self, key: &str) -> String;
+
+    /// Checks the signature of a URL made by `presign_get`, for backends whose files are served
+    /// by this service.
+    fn verify_presigned(&self, _key: &str, _expires: u64, _signature: &str) -> bool {
+        false
+    }
+}
+
+/// The files stored for every image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rendition {
+    Original,
+    Thumbnail,
+}
+
+impl std::fmt::Display for Rendition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rendition::Original => write!(f, "original"),
+            Rendition::Thumbnail => write!(f, "thumbnail"),
+        }
+    }
+}
+
+/// Returns the key of a file of an image, as `{address}/{yyyy}/{mm}/{id}/{rendition}.{ext}`.
+/// Partitioning by owner and upload month keeps listings and lifecycle rules cheap.
+pub fn image_key(
+    user_address: &str,
+    uploaded_at: NaiveDateTime,
+    image_id: &str,
+    rendition: Rendition,
+    extension: &str,
+) -> String {
+    format!(
+        "{}/{}/{image_id}/{rendition}.{extension}",
+        user_address.to_lowercase(),
+        uploaded_at.format("%Y/%m"),
+    )
+}
+
+/// Returns the storage key of an image file. Only the files of public images are publicly
+/// readable, even unlisted ones are served through presigned URLs so their keys can't leak.
+pub fn object_key(key: &str, visibility: Visibility) -> String {
+    if !visibility.is_public() {
+        format!("{PRIVATE_PREFIX}{key}")
+    } else {
+        key.to_string()
+    }
+}
+
+/// Returns the key an original is moved to when archived, without the `private/` prefix.
+pub fn archive_key(key: &str) -> String {
+    format!("{ARCHIVE_PREFIX}{key}")
+}
+
+/// Returns the bucket key of a rendered variant of an image.
+pub fn render_key(image_id: &str, variant: &str, visibility: Visibility) -> String {
+    object_key(&format!("{RENDERS_PREFIX}{image_id}/{variant}"), visibility)
+}
+
+/// Returns the file name at the end of a URL or key.
+pub fn file_name(url: &str) -> Option<&str> {
+    url.split('/').next_back().filter(|name| !name.is_empty())
+}
+
+/// Returns a short-lived URL to read an image file, valid even for private objects.
+pub fn presigned_url(
+    storage: &dyn ObjectStorage,
+    key: &str,
+    visibility: Visibility,
+) -> Result<String, StorageError> {
+    storage.presign_get(&object_key(key, visibility), PRESIGNED_URL_EXPIRATION_SECS)
+}
+
+/// Replaces the URLs of a non-public image with presigned ones, for viewers that were granted
+/// access some other way than authenticating, like a share link.
+pub fn presign_image(
+    storage: &dyn ObjectStorage,
+    urls: &UrlBuilder,
+    image: DBImage,
+) -> Result<Image, StorageError> {
+    if image.visibility.is_public() {
+        return Ok(Image::from_db(image, urls));
+    }
+
+    // Archived originals can't be read until restored, the service queues the restore
+    let url = if image.is_archived(Rendition::Original) {
+        urls.image_url(&image.id.to_string(), Rendition::Original)
+    } else {
+        presigned_url(storage, image.key(Rendition::Original), image.visibility)?
+    };
+    let thumbnail_url = presigned_url(storage, image.key(Rendition::Thumbnail), image.visibility)?;
+
+    Ok(Image {
+        url,
+        thumbnail_url,
+        ..Image::from_db(image, urls)
+    })
+}
+
+/// Moves an object within the storage, copying it and then deleting the original.
+pub async fn move_object(
+    storage: &dyn ObjectStorage,
+    from: &str,
+    to: &str,
+) -> Result<(), StorageError> {
+    storage.copy(from, to).await?;
+    storage.delete(from).await?;
+
+    Ok(())
+}
+
+/// Moves the image files to the keys of the new visibility. If a file fails to move, the ones
+/// already moved are moved back so the image stays consistent.
+pub async fn move_image_objects(
+    storage: &dyn ObjectStorage,
+    keys: &[String],
+    from: Visibility,
+    to: Visibility,
+) -> Result<(), StorageError> {
+    if object_key("", from) == object_key("", to) {
+        return Ok(());
+    }
+
+    for (index, key) in keys.iter().enumerate() {
+        let (from_key, to_key) = (object_key(key, from), object_key(key, to));
+        if let Err(error) = move_object(storage, &from_key, &to_key).await {
+            for moved in &keys[..index] {
+                let (from_key, to_key) = (object_key(moved, from), object_key(moved, to));
+                if let Err(error) = move_object(storage, &to_key, &from_key).await {
+                    tracing::error!("failed to move back {}: {}", to_key, error);
+                }
+            }
+            return Err(error);
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes the rendered variants of an image, they are rendered again when requested.
+pub async fn delete_renders(
+    storage: &dyn ObjectStorage,
+    image_id: &str,
+    visibility: Visibility,
+) -> Result<(), StorageError> {
+    let prefix = object_key(&format!("{RENDERS_PREFIX}{image_id}/"), visibility);
+    for key in storage.list(&prefix).await? {
+        storage.delete(&key).await?;
+    }
+
+    Ok(())
+}
+
+/// Moves the files of a non-public image still at the public keys, where unlisted images and
+/// the ones uploaded before the `private/` prefix existed were stored. Files already moved are
+/// skipped, so an interrupted run can be started again. Returns how many files were moved.
+pub async fn move_to_private_prefix(
+    storage: &dyn ObjectStorage,
+    image: &DBImage,
+) -> Result<usize, StorageError> {
+    if image.visibility.is_public() {
+        return Ok(0);
+    }
+
+    let mut moved = 0;
+    for key in image.stored_keys() {
+        match storage.head(&key).await {
+            Ok(_) => {}
+            Err(StorageError::NotFound) => continue,
+            Err(error) => return Err(error),
+        }
+
+        copy_checked(storage, &key, &object_key(&key, image.visibility)).await?;
+        storage.delete(&key).await?;
+        moved += 1;
+    }
+
+    // Publicly readable variants are dropped, they are rendered again under the private prefix
+    delete_renders(storage, &image.id.to_string(), Visibility::Public).await?;
+
+    Ok(moved)
+}
+
+/// Returns the partitioned key a file of an image uploaded before keys were partitioned moves to.
+fn migrated_key(image: &DBImage, legacy_key: &str, rendition: Rendition) -> String {
+    let extension = legacy_key
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .unwrap_or_else(|| "png".to_string());
+
+    image_key(
+        &image.user_address,
+        image.created_at,
+        &image.id.to_string(),
+        rendition,
+        &extension,
+    )
+}
+
+/// Deletes objects on a best effort basis, when undoing or finishing a migration.
+async fn delete_objects(storage: &dyn ObjectStorage, keys: &[String]) {
+    for key in keys {
+        if let Err(error) = storage.delete(key).await {
+            tracing::error!("failed to delete {}: {}", key, error);
+        }
+    }
+}
+
+/// Copies an object, checking the copy is complete.
+pub(crate) async fn copy_checked(
+    storage: &dyn ObjectStorage,
+    from: &str,
+    to: &str,
+) -> Result<(), StorageError> {
+    storage.copy(from, to).await?;
+    let (original, copy) = (storage.head(from).await?, storage.head(to).await?);
+    if original.content_length != copy.content_length {
+        return Err(StorageError::Backend(format!(
+            "the copy of {from} to {to} is incomplete"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Moves the files of an image from the root of the bucket to their partitioned keys. The
+/// copies are checked before the image points to them and the legacy files are only deleted
+/// afterwards, so an interrupted migration can be run again. Returns whether the image was
+/// migrated, which it isn't when it changed in the meantime.
+pub async fn migrate_legacy_image_keys(
+    storage: &dyn ObjectStorage,
+    database: &Database,
+    image: &DBImage,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let image_key = migrated_key(image, &image.image_key, Rendition::Original);
+    let thumbnail_key = image
+        .thumbnail_key
+        .as_deref()
+        .map(|legacy_key| migrated_key(image, legacy_key, Rendition::Thumbnail));
+
+    let moves: Vec<(String, String)> = std::iter::once((image.image_key.as_str(), &image_key))
+        .chain(image.thumbnail_key.as_deref().zip(thumbnail_key.as_ref()))
+        .map(|(from, to)| {
+            (
+                object_key(from, image.visibility),
+                object_key(to, image.visibility),
+            )
+        })
+        .collect();
+    let (legacy_keys, new_keys): (Vec<String>, Vec<String>) = moves.iter().cloned().unzip();
+
+    for (index, (from, to)) in moves.iter().enumerate() {
+        if let Err(error) = copy_checked(storage, from, to).await {
+            delete_objects(storage, &new_keys[..=index]).await;
+            return Err(error.into());
+        }
+    }
+
+    if !database
+        .update_image_keys(image, &image_key, thumbnail_key.as_deref())
+        .await?
+    {
+        delete_objects(storage, &new_keys).await;
+        return Ok(false);
+    }
+
+    delete_objects(storage, &legacy_keys).await;
+
+    Ok(true)
+}
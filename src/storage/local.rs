@@ -0,0 +1,322 @@
+use std::{
+    io::SeekFrom,
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::types::Uuid;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use super::{ByteStream, ObjectInfo, ObjectStorage, StorageError, StoredObject};
+
+/// Keeps the files in a local directory, so the service can run without a bucket. The files
+/// are served by the service itself under `base_url`.
+pub struct LocalStorage {
+    root: PathBuf,
+    /// URL the files are served from, without trailing slash.
+    base_url: String,
+    /// Signs the presigned URLs. It changes on every start, which only invalidates URLs that
+    /// were short-lived anyway.
+    signing_key: String,
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(error: std::io::Error) -> Self {
+        match error.kind() {
+            std::io::ErrorKind::NotFound => StorageError::NotFound,
+            _ => StorageError::Backend(error.to_string()),
+        }
+    }
+}
+
+/// Files don't keep their content type, it's guessed back from the extension.
+fn content_type(key: &str) -> Option<String> {
+    let extension = key.rsplit_once('.')?.1.to_ascii_lowercase();
+    let content_type = match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "webp" => "image/webp",
+        _ => return None,
+    };
+
+    Some(content_type.to_string())
+}
+
+/// Weak entity tag out of the size and modification time of a file.
+fn e_tag(metadata: &std::fs::Metadata) -> Option<String> {
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_nanos();
+
+    Some(format!("W/\"{:x}-{:x}\"", metadata.len(), modified))
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>, base_url: &str) -> Self {
+        Self {
+            root: root.into(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            signing_key: Uuid::new_v4().to_string(),
+        }
+    }
+
+    /// Returns the path of an object, refusing keys that would escape the root directory.
+    fn path(&self, key: &str) -> Result<PathBuf, StorageError> {
+        let relative = Path::new(key);
+        if key.is_empty()
+            || !relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(StorageError::Backend(format!("invalid key {key}")));
+        }
+
+        Ok(self.root.join(relative))
+    }
+
+    fn signature(&self, key: &str, expires: u64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.signing_key.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(format!("{key}:{expires}").as_bytes());
+        mac
+    }
+}
+
+#[async_trait(?Send)]
+impl ObjectStorage for LocalStorage {
+    async fn put(&self, key: &str, bytes: &[u8], _content_type: &str) -> Result<(), StorageError> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, bytes).await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<StoredObject, StorageError> {
+        let path = self.path(key)?;
+        let metadata = tokio::fs::metadata(&path).await?;
+        let bytes = tokio::fs::read(&path).await?;
+
+        Ok(StoredObject {
+            bytes: Bytes::from(bytes),
+            e_tag: e_tag(&metadata),
+            content_type: content_type(key),
+        })
+    }
+
+    async fn get_range(&self, key: &str, start: u64, end: u64) -> Result<Bytes, StorageError> {
+        let mut file = tokio::fs::File::open(self.path(key)?).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+
+        let mut bytes = vec![];
+        file.take(end - start + 1).read_to_end(&mut bytes).await?;
+
+        Ok(Bytes::from(bytes))
+    }
+
+    async fn get_stream(&self, key: &str) -> Result<ByteStream, StorageError> {
+        let file = tokio::fs::File::open(self.path(key)?).await?;
+
+        Ok(Box::pin(
+            ReaderStream::new(file).map(|chunk| chunk.map_err(StorageError::from)),
+        ))
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectInfo, StorageError> {
+        let metadata = tokio::fs::metadata(self.path(key)?).await?;
+        if !metadata.is_file() {
+            return Err(StorageError::NotFound);
+        }
+
+        Ok(ObjectInfo {
+            content_length: metadata.len(),
+            e_tag: e_tag(&metadata),
+            content_type: content_type(key),
+            needs_restore: false,
+        })
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        // Like buckets, deleting a missing object isn't an error
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError> {
+        // Prefixes aren't necessarily directories, so the closest directory is walked
+        let directory = match prefix.rsplit_once('/') {
+            Some((directory, _)) => self.path(directory)?,
+            None => self.root.clone(),
+        };
+
+        let mut keys = vec![];
+        let mut directories = vec![directory];
+        while let Some(directory) = directories.pop() {
+            let mut entries = match tokio::fs::read_dir(&directory).await {
+                Ok(entries) => entries,
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(error.into()),
+            };
+
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    directories.push(path);
+                    continue;
+                }
+
+                let key = path
+                    .strip_prefix(&self.root)
+                    .map_err(|error| StorageError::Backend(error.to_string()))?
+                    .to_string_lossy()
+                    .replace('\\', "/");
+                if key.starts_with(prefix) {
+                    keys.push(key);
+                }
+            }
+        }
+
+        Ok(keys)
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let to = self.path(to)?;
+        if let Some(parent) = to.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::copy(self.path(from)?, to).await?;
+
+        Ok(())
+    }
+
+    fn presign_get(&self, key: &str, expires_in_secs: u32) -> Result<String, StorageError> {
+        let expires = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|error| StorageError::Backend(error.to_string()))?
+            .as_secs()
+            + expires_in_secs as u64;
+        let signature = hex::encode(self.signature(key, expires).finalize().into_bytes());
+
+        Ok(format!(
+            "{}?expires={expires}&signature={signature}",
+            self.public_url(key)
+        ))
+    }
+
+    fn public_url(&self, key: &str) -> String {
+        format!("{}/{key}", self.base_url)
+    }
+
+    fn verify_presigned(&self, key: &str, expires: u64, signature: &str) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or(u64::MAX);
+        if expires < now {
+            return false;
+        }
+
+        hex::decode(signature).is_ok_and(|signature| {
+            self.signature(key, expires)
+                .verify_slice(&signature)
+                .is_ok()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage() -> LocalStorage {
+        let root = std::env::temp_dir().join(format!("camera-reel-{}", Uuid::new_v4()));
+        LocalStorage::new(root, "http://localhost:3000/files/")
+    }
+
+    #[actix_web::test]
+    async fn stores_lists_and_deletes_objects() {
+        let storage = storage();
+        storage
+            .put(
+                "private/renders/id/64x64-cover.webp",
+                b"render",
+                "image/webp",
+            )
+            .await
+            .unwrap();
+        storage
+            .put("id-image.png", b"image", "image/png")
+            .await
+            .unwrap();
+
+        let object = storage.get("id-image.png").await.unwrap();
+        assert_eq!(object.bytes, Bytes::from_static(b"image"));
+        assert_eq!(object.content_type.as_deref(), Some("image/png"));
+        assert_eq!(
+            storage.get_range("id-image.png", 1, 3).await.unwrap(),
+            Bytes::from_static(b"mag")
+        );
+        assert_eq!(
+            storage.head("id-image.png").await.unwrap().content_length,
+            5
+        );
+
+        assert_eq!(
+            storage.list("private/renders/id/").await.unwrap(),
+            vec!["private/renders/id/64x64-cover.webp"]
+        );
+        assert!(storage.list("renders/").await.unwrap().is_empty());
+
+        storage
+            .copy("id-image.png", "private/id-image.png")
+            .await
+            .unwrap();
+        storage.delete("id-image.png").await.unwrap();
+        assert!(matches!(
+            storage.get("id-image.png").await,
+            Err(StorageError::NotFound)
+        ));
+        assert!(storage.head("private/id-image.png").await.is_ok());
+        assert!(storage.delete("id-image.png").await.is_ok());
+
+        std::fs::remove_dir_all(&storage.root).unwrap();
+    }
+
+    #[test]
+    fn test_refuses_keys_outside_the_root() {
+        let storage = storage();
+        assert!(storage.path("../etc/passwd").is_err());
+        assert!(storage.path("/etc/passwd").is_err());
+        assert!(storage.path("private/id-image.png").is_ok());
+    }
+
+    #[test]
+    fn test_verifies_presigned_urls() {
+        let storage = storage();
+        let url = storage.presign_get("private/id-image.png", 300).unwrap();
+        assert!(url.starts_with("http://localhost:3000/files/private/id-image.png?expires="));
+
+        let query = url.split_once('?').unwrap().1;
+        let (expires, signature) = query.split_once('&').unwrap();
+        let expires = expires.trim_start_matches("expires=").parse().unwrap();
+        let signature = signature.trim_start_matches("signature=");
+
+        assert!(storage.verify_presigned("private/id-image.png", expires, signature));
+        assert!(!storage.verify_presigned("private/other-image.png", expires, signature));
+        assert!(!storage.verify_presigned("private/id-image.png", expires + 1, signature));
+        assert!(!storage.verify_presigned("private/id-image.png", 1, signature));
+    }
+}
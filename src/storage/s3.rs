@@ -0,0 +1,132 @@
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use s3::{error::S3Error, Bucket};
+
+use super::{ByteStream, ObjectInfo, ObjectStorage, StorageError, StoredObject};
+
+/// Keeps the files in an S3 bucket.
+pub struct S3Storage {
+    bucket: Bucket,
+    /// Public URL of the bucket, without trailing slash.
+    bucket_url: String,
+}
+
+impl S3Storage {
+    pub fn new(bucket: Bucket, bucket_url: &str) -> Self {
+        Self {
+            bucket,
+            bucket_url: bucket_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl From<S3Error> for StorageError {
+    fn from(error: S3Error) -> Self {
+        match error {
+            S3Error::Http(404, _) => StorageError::NotFound,
+            error => StorageError::Backend(error.to_string()),
+        }
+    }
+}
+
+/// Some bucket calls answer errors with a status code instead of failing.
+fn check_status(status: u16) -> Result<(), StorageError> {
+    match status {
+        200..=299 => Ok(()),
+        404 => Err(StorageError::NotFound),
+        status => Err(StorageError::Backend(format!(
+            "bucket answered with status {status}"
+        ))),
+    }
+}
+
+#[async_trait(?Send)]
+impl ObjectStorage for S3Storage {
+    async fn put(&self, key: &str, bytes: &[u8], content_type: &str) -> Result<(), StorageError> {
+        let response = self
+            .bucket
+            .put_object_with_content_type(key, bytes, content_type)
+            .await?;
+        check_status(response.status_code())
+    }
+
+    async fn get(&self, key: &str) -> Result<StoredObject, StorageError> {
+        let response = self.bucket.get_object(key).await?;
+        check_status(response.status_code())?;
+        let headers = response.headers();
+
+        Ok(StoredObject {
+            e_tag: headers.get("etag").cloned(),
+            content_type: headers.get("content-type").cloned(),
+            bytes: Bytes::copy_from_slice(response.bytes()),
+        })
+    }
+
+    async fn get_range(&self, key: &str, start: u64, end: u64) -> Result<Bytes, StorageError> {
+        // The bucket client can't request single-byte ranges, those read until the end
+        let response = self
+            .bucket
+            .get_object_range(key, start, (start < end).then_some(end))
+            .await?;
+        check_status(response.status_code())?;
+
+        let bytes = Bytes::copy_from_slice(response.bytes());
+        Ok(bytes.slice(..bytes.len().min((end - start + 1) as usize)))
+    }
+
+    async fn get_stream(&self, key: &str) -> Result<ByteStream, StorageError> {
+        let stream = self.bucket.get_object_stream(key).await?;
+        check_status(stream.status_code)?;
+
+        Ok(Box::pin(stream.bytes.map(Ok)))
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectInfo, StorageError> {
+        let (head, status) = self.bucket.head_object(key).await?;
+        check_status(status)?;
+
+        Ok(ObjectInfo {
+            content_length: head
+                .content_length
+                .ok_or(StorageError::Backend("missing content length".to_string()))?
+                as u64,
+            e_tag: head.e_tag,
+            content_type: head.content_type,
+            needs_restore: matches!(
+                head.storage_class.as_deref(),
+                Some("GLACIER" | "DEEP_ARCHIVE")
+            ) && !head
+                .restore
+                .is_some_and(|restore| restore.contains("ongoing-request=\"false\"")),
+        })
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        let response = self.bucket.delete_object(key).await?;
+        check_status(response.status_code())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError> {
+        let pages = self.bucket.list(prefix.to_string(), None).await?;
+
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .map(|object| object.key)
+            .collect())
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let status = self.bucket.copy_object_internal(from, to).await?;
+        check_status(status)
+    }
+
+    fn presign_get(&self, key: &str, expires_in_secs: u32) -> Result<String, StorageError> {
+        Ok(self.bucket.presign_get(key, expires_in_secs, None)?)
+    }
+
+    fn public_url(&self, key: &str) -> String {
+        format!("{}/{key}", self.bucket_url)
+    }
+}
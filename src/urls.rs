@@ -0,0 +1,187 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{api::Visibility, database::DBImage, storage::Rendition, Settings};
+
+/// How long the signed CDN URLs stay valid by default.
+pub const DEFAULT_CDN_URL_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// Where the files of the public images are delivered from. Without CDN URLs, the files are
+/// reached through the service, which redirects to the bucket or proxies them.
+#[derive(Debug, Clone)]
+pub struct DeliverySettings {
+    /// Base URL of the CDN serving the originals, with the bucket as origin.
+    pub originals_cdn_url: Option<String>,
+    /// Base URL of the CDN serving the thumbnails, with the bucket as origin.
+    pub thumbnails_cdn_url: Option<String>,
+    /// Secret shared with the CDN to sign its URLs. They are left unsigned when missing.
+    pub cdn_signing_key: Option<String>,
+    /// How long the signed CDN URLs stay valid, at least.
+    pub cdn_url_ttl_seconds: u64,
+}
+
+impl Default for DeliverySettings {
+    fn default() -> Self {
+        Self {
+            originals_cdn_url: None,
+            thumbnails_cdn_url: None,
+            cdn_signing_key: None,
+            cdn_url_ttl_seconds: DEFAULT_CDN_URL_TTL_SECS,
+        }
+    }
+}
+
+/// Builds the URLs of the image files when responding, rows only keep the storage keys.
+#[derive(Debug, Clone)]
+pub struct UrlBuilder {
+    /// Public URL of the service, without trailing slash.
+    api_url: String,
+    originals_cdn_url: Option<String>,
+    thumbnails_cdn_url: Option<String>,
+    cdn_signing_key: Option<String>,
+    cdn_url_ttl_seconds: u64,
+}
+
+impl UrlBuilder {
+    pub fn new(settings: &Settings) -> Self {
+        let base_url = |url: &Option<String>| {
+            url.as_deref()
+                .map(|url| url.trim_end_matches('/').to_string())
+        };
+        let delivery = &settings.delivery;
+
+        Self {
+            api_url: settings.api_url.trim_end_matches('/').to_string(),
+            originals_cdn_url: base_url(&delivery.originals_cdn_url),
+            thumbnails_cdn_url: base_url(&delivery.thumbnails_cdn_url),
+            cdn_signing_key: delivery.cdn_signing_key.clone(),
+            cdn_url_ttl_seconds: delivery.cdn_url_ttl_seconds.max(1),
+        }
+    }
+
+    /// URL of a file of an image, served by `GET /api/images/{image_id}/{rendition}`.
+    pub fn image_url(&self, image_id: &str, rendition: Rendition) -> String {
+        format!("{}/api/images/{image_id}/{rendition}", self.api_url)
+    }
+
+    /// URL clients should load a file of an image from. Files of public images come straight
+    /// from the CDN when one is set for the rendition, skipping the service. The others are
+    /// under the private prefix and go through the service, which presigns them.
+    pub fn file_url(
+        &self,
+        image_id: &str,
+        key: &str,
+        visibility: Visibility,
+        rendition: Rendition,
+    ) -> String {
+        let cdn_url = match rendition {
+            Rendition::Original => &self.originals_cdn_url,
+            Rendition::Thumbnail => &self.thumbnails_cdn_url,
+        };
+
+        match cdn_url {
+            Some(cdn_url) if visibility.is_public() => self.cdn_url(cdn_url, key, now()),
+            _ => self.image_url(image_id, rendition),
+        }
+    }
+
+    /// URL clients should load a file of a stored image from. Archived originals go through
+    /// the service, which queues their restore.
+    pub fn image_file_url(&self, image: &DBImage, rendition: Rendition) -> String {
+        if image.is_archived(rendition) {
+            return self.image_url(&image.id.to_string(), rendition);
+        }
+
+        self.file_url(
+            &image.id.to_string(),
+            image.key(rendition),
+            image.visibility,
+            rendition,
+        )
+    }
+
+    /// Signs the CDN URLs as `?expires={unix time}&signature={HMAC-SHA256 of "{key}:{expires}"}`.
+    /// Expirations are rounded up to the TTL, so the URL of a file stays the same for a while
+    /// and keeps hitting the caches.
+    fn cdn_url(&self, cdn_url: &str, key: &str, now: u64) -> String {
+        let Some(signing_key) = &self.cdn_signing_key else {
+            return format!("{cdn_url}/{key}");
+        };
+
+        let ttl = self.cdn_url_ttl_seconds;
+        let expires = (now / ttl + 2) * ttl;
+        let mut mac = Hmac::<Sha256>::new_from_slice(signing_key.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(format!("{key}:{expires}").as_bytes());
+        let signature = hex::encode(mac.finalize().into_bytes());
+
+        format!("{cdn_url}/{key}?expires={expires}&signature={signature}")
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "0xabc/2026/10/id/thumbnail.png";
+
+    fn urls(delivery: DeliverySettings) -> UrlBuilder {
+        UrlBuilder {
+            api_url: "https://camera-reel.decentraland.org".to_string(),
+            originals_cdn_url: delivery.originals_cdn_url,
+            thumbnails_cdn_url: delivery.thumbnails_cdn_url,
+            cdn_signing_key: delivery.cdn_signing_key,
+            cdn_url_ttl_seconds: delivery.cdn_url_ttl_seconds,
+        }
+    }
+
+    #[test]
+    fn test_serves_public_files_from_the_cdn_of_their_rendition() {
+        let urls = urls(DeliverySettings {
+            thumbnails_cdn_url: Some("https://thumbnails.cdn.example".to_string()),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            urls.file_url("id", KEY, Visibility::Public, Rendition::Thumbnail),
+            format!("https://thumbnails.cdn.example/{KEY}")
+        );
+        assert_eq!(
+            urls.file_url("id", KEY, Visibility::Unlisted, Rendition::Thumbnail),
+            "https://camera-reel.decentraland.org/api/images/id/thumbnail"
+        );
+        assert_eq!(
+            urls.file_url("id", KEY, Visibility::Public, Rendition::Original),
+            "https://camera-reel.decentraland.org/api/images/id/original"
+        );
+        assert_eq!(
+            urls.file_url("id", KEY, Visibility::Private, Rendition::Thumbnail),
+            "https://camera-reel.decentraland.org/api/images/id/thumbnail"
+        );
+    }
+
+    #[test]
+    fn test_signs_cdn_urls_with_stable_expirations() {
+        let urls = urls(DeliverySettings {
+            cdn_signing_key: Some("secret".to_string()),
+            cdn_url_ttl_seconds: 100,
+            ..Default::default()
+        });
+
+        let url = urls.cdn_url("https://cdn.example", KEY, 1_050);
+        assert!(url.starts_with(&format!(
+            "https://cdn.example/{KEY}?expires=1200&signature="
+        )));
+        assert_eq!(urls.cdn_url("https://cdn.example", KEY, 1_099), url);
+        assert_ne!(urls.cdn_url("https://cdn.example", KEY, 1_100), url);
+    }
+}
@@ -0,0 +1,422 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use actix_web::web::Data;
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::types::chrono;
+
+use crate::{
+    database::{DBClaimedWebhookDelivery, Database},
+    events::{Event, EventPayload, EventPublisher},
+    outbox::backoff_secs,
+    places_client::PlacesClient,
+};
+
+/// Deliveries claimed at once by each pass of the worker.
+const BATCH_SIZE: i64 = 50;
+
+/// Seconds a claimed delivery is held back from other workers while it is sent.
+const LEASE_SECS: i64 = 60;
+
+/// Attempts after which a delivery is given up.
+const MAX_ATTEMPTS: i32 = 10;
+
+/// How long finished deliveries stay in the delivery log.
+const DELIVERY_LOG_RETENTION_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// How often the deliveries past their retention are deleted.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long the places of a world are trusted before being resolved again.
+const WORLD_REFRESH_SECS: i64 = 10 * 60;
+
+/// How the events are delivered to the place webhooks.
+#[derive(Debug, Clone)]
+pub struct WebhookSettings {
+    /// Seconds between the passes of the delivery worker.
+    pub interval_seconds: u64,
+    /// Longest wait between two attempts of a delivery. Waits start at a second and double on
+    /// each failed attempt.
+    pub max_backoff_seconds: u64,
+    /// Failed deliveries in a row after which a subscription is disabled.
+    pub max_consecutive_failures: i32,
+    /// Lets webhooks point at loopback and private addresses, for local development.
+    pub allow_private_networks: bool,
+}
+
+impl Default for WebhookSettings {
+    fn default() -> Self {
+        Self {
+            interval_seconds: 5,
+            max_backoff_seconds: 60 * 60,
+            max_consecutive_failures: 20,
+            allow_private_networks: false,
+        }
+    }
+}
+
+/// Queues the `photo-taken` and `photo-deleted` events of public photos for the webhooks
+/// subscribed to their place, directly or through its world. Sits in the fan-out behind the
+/// outbox, so an event fails and is published again when it couldn't be queued. Worlds are
+/// matched with the places cached on their subscriptions, the places API isn't called here.
+pub struct PlaceWebhooksPublisher {
+    database: Data<Database>,
+}
+
+impl PlaceWebhooksPublisher {
+    pub fn new(database: Data<Database>) -> Self {
+        Self { database }
+    }
+}
+
+#[async_trait(?Send)]
+impl EventPublisher for PlaceWebhooksPublisher {
+    async fn publish(
+        &self,
+        event_id: &str,
+        event: &Event,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (is_public, place_id) = match &event.payload {
+            EventPayload::PhotoTaken(metadata) => (metadata.is_public, metadata.place_id.as_str()),
+            EventPayload::PhotoDeleted(metadata) => {
+                (metadata.is_public, metadata.place_id.as_str())
+            }
+            _ => return Ok(()),
+        };
+        if !is_public || place_id.is_empty() {
+            return Ok(());
+        }
+
+        let subscription_ids = self
+            .database
+            .get_place_webhook_subscriptions(place_id)
+            .await?
+            .into_iter()
+            .map(|subscription| subscription.id)
+            .collect::<Vec<_>>();
+
+        if !subscription_ids.is_empty() {
+            self.database
+                .queue_webhook_deliveries(&subscription_ids, event_id, event)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Signature of a delivery, as `sha256={HMAC-SHA256 of "{timestamp}.{body}"}`. Signing the
+/// timestamp along with the body lets receivers reject replayed deliveries. The `webhook` event
+/// sink signs its posts the same way.
+pub(crate) fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(body);
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Whether an address is reachable from the internet. Loopback, private, link-local (the cloud
+/// metadata endpoints included) and other reserved addresses would let webhooks reach the
+/// network of the service.
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || first == 0
+                // Shared address space, used by carrier-grade NATs
+                || (first == 100 && second & 0b1100_0000 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_address(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    // Unique local and link-local
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Resolves the host of a webhook URL to the address the webhook is sent to. Fails when any of
+/// the addresses of the host isn't public, unless `allow_private_networks`.
+pub(crate) async fn resolve_webhook_url(
+    url: &reqwest::Url,
+    allow_private_networks: bool,
+) -> Result<SocketAddr, String> {
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return Err("invalid url".to_string());
+    };
+    let addresses = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|error| format!("failed to resolve {host}: {error}"))?
+            .collect(),
+    };
+
+    if !allow_private_networks
+        && addresses
+            .iter()
+            .any(|address| !is_public_address(address.ip()))
+    {
+        return Err("url must not point at a private address".to_string());
+    }
+
+    addresses
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("{host} has no addresses"))
+}
+
+/// Posts a delivery to its webhook. Returns the status of a 2xx response, or the error along
+/// with the status of any other response.
+async fn send_delivery(
+    claimed: &DBClaimedWebhookDelivery,
+    allow_private_networks: bool,
+) -> Result<u16, (Option<u16>, String)> {
+    let delivery = &claimed.delivery;
+    let body = serde_json::to_vec(&delivery.event.0).map_err(|error| (None, error.to_string()))?;
+    let timestamp = chrono::Utc::now().timestamp();
+
+    // The host is resolved and checked again, as it may point elsewhere since the subscription
+    // was created, and the request is pinned to the checked address
+    let url = reqwest::Url::parse(&claimed.url).map_err(|error| (None, error.to_string()))?;
+    let address = resolve_webhook_url(&url, allow_private_networks)
+        .await
+        .map_err(|error| (None, error))?;
+    let mut client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .redirect(reqwest::redirect::Policy::none())
+        .no_proxy();
+    if let Some(host) = url.host_str() {
+        client = client.resolve(host, address);
+    }
+    let client = client.build().map_err(|error| (None, error.to_string()))?;
+
+    let response = client
+        .post(&claimed.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Webhook-Id", delivery.subscription_id.to_string())
+        .header("X-Webhook-Delivery", delivery.id.to_string())
+        .header("X-Event-Id", &delivery.event_id)
+        .header("X-Event-Type", delivery.event.sub_type().to_string())
+        .header("X-Webhook-Timestamp", timestamp)
+        .header(
+            "X-Webhook-Signature",
+            signature(&claimed.secret, timestamp, &body),
+        )
+        .body(body)
+        .send()
+        .await;
+
+    match response {
+        Ok(response) if response.status().is_success() => Ok(response.status().as_u16()),
+        Ok(response) => Err((
+            Some(response.status().as_u16()),
+            format!("webhook responded with status {}", response.status()),
+        )),
+        Err(error) => Err((None, error.to_string())),
+    }
+}
+
+/// Resolves again the places of the worlds subscribed to, when they are older than
+/// `WORLD_REFRESH_SECS`. A world that fails to resolve keeps its places until the next run.
+/// Returns how many were refreshed.
+pub async fn refresh_world_place_ids(
+    database: &Database,
+    places_client: &PlacesClient,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut refreshed = 0;
+    for subscription in database
+        .get_stale_world_webhook_subscriptions(WORLD_REFRESH_SECS, BATCH_SIZE)
+        .await?
+    {
+        match places_client
+            .get_world_place_ids(&subscription.place_id)
+            .await
+        {
+            Ok(world_place_ids) => {
+                database
+                    .set_webhook_world_place_ids(subscription.id, &world_place_ids)
+                    .await?;
+                refreshed += 1;
+            }
+            Err(error) => tracing::warn!(
+                "failed to resolve world name '{}': {}",
+                subscription.place_id,
+                error
+            ),
+        }
+    }
+
+    Ok(refreshed)
+}
+
+/// Sends the deliveries due, the oldest first, until none is left. Failed ones are tried again
+/// with an exponential backoff, up to `MAX_ATTEMPTS` times. Returns how many were delivered
+/// and how many failed.
+pub async fn deliver_webhooks(
+    database: &Database,
+    settings: &WebhookSettings,
+) -> Result<(usize, usize), Box<dyn std::error::Error>> {
+    let (mut delivered, mut failed) = (0, 0);
+    loop {
+        let deliveries = database
+            .claim_webhook_deliveries(BATCH_SIZE, LEASE_SECS)
+            .await?;
+        let claimed = deliveries.len() as i64;
+
+        for claimed in deliveries {
+            let delivery = &claimed.delivery;
+            match send_delivery(&claimed, settings.allow_private_networks).await {
+                Ok(status) => {
+                    database
+                        .complete_webhook_delivery(delivery, status as i32)
+                        .await?;
+                    delivered += 1;
+                }
+                Err((status, error)) => {
+                    tracing::warn!("failed to deliver webhook {}: {}", delivery.id, error);
+                    let retry_in = (delivery.attempts + 1 < MAX_ATTEMPTS)
+                        .then(|| backoff_secs(delivery.attempts, settings.max_backoff_seconds));
+                    let disabled = database
+                        .fail_webhook_delivery(
+                            delivery,
+                            status.map(i32::from),
+                            &error,
+                            retry_in,
+                            settings.max_consecutive_failures,
+                        )
+                        .await?;
+                    if disabled {
+                        tracing::warn!(
+                            "disabled webhook subscription {} after repeated failures",
+                            delivery.subscription_id
+                        );
+                    }
+                    failed += 1;
+                }
+            }
+        }
+
+        if claimed < BATCH_SIZE {
+            return Ok((delivered, failed));
+        }
+    }
+}
+
+/// Runs the webhook deliveries in the background every `interval_seconds`, refreshing the
+/// places of the worlds subscribed to, and deletes the deliveries past their retention from
+/// the delivery log.
+pub fn spawn_webhook_worker(
+    settings: WebhookSettings,
+    database: Data<Database>,
+    places_client: Data<PlacesClient>,
+) {
+    actix_web::rt::spawn(async move {
+        let mut interval =
+            actix_web::rt::time::interval(Duration::from_secs(settings.interval_seconds.max(1)));
+        let mut last_purge: Option<Instant> = None;
+        loop {
+            interval.tick().await;
+
+            if let Err(error) = refresh_world_place_ids(&database, &places_client).await {
+                tracing::error!("failed to refresh the places of the worlds: {}", error);
+            }
+
+            if let Err(error) = deliver_webhooks(&database, &settings).await {
+                tracing::error!("failed to deliver webhooks: {}", error);
+            }
+
+            if last_purge.is_some_and(|last_purge| last_purge.elapsed() < PURGE_INTERVAL) {
+                continue;
+            }
+            last_purge = Some(Instant::now());
+            match database
+                .delete_completed_webhook_deliveries(DELIVERY_LOG_RETENTION_SECS)
+                .await
+            {
+                Ok(0) => {}
+                Ok(deleted) => tracing::info!("deleted {} webhook deliveries", deleted),
+                Err(error) => {
+                    tracing::error!("failed to delete webhook deliveries: {}", error)
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signs_the_timestamp_with_the_body() {
+        let signed = signature("secret", 1_700_000_000, b"{}");
+        assert!(signed.starts_with("sha256="));
+        assert_eq!(signed.len(), "sha256=".len() + 64);
+        assert_eq!(signed, signature("secret", 1_700_000_000, b"{}"));
+        assert_ne!(signed, signature("secret", 1_700_000_001, b"{}"));
+        assert_ne!(signed, signature("other", 1_700_000_000, b"{}"));
+    }
+
+    #[test]
+    fn test_only_public_addresses_are_public() {
+        for address in ["93.184.216.34", "2606:2800:220:1::1", "100.128.0.1"] {
+            assert!(is_public_address(address.parse().unwrap()), "{address}");
+        }
+        for address in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::ffff:127.0.0.1",
+            "fd00:ec2::254",
+            "fe80::1",
+        ] {
+            assert!(!is_public_address(address.parse().unwrap()), "{address}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejects_urls_resolving_to_private_addresses() {
+        let url = |url: &str| reqwest::Url::parse(url).unwrap();
+
+        assert!(
+            resolve_webhook_url(&url("http://169.254.169.254/latest"), false)
+                .await
+                .is_err()
+        );
+        assert!(resolve_webhook_url(&url("http://[::1]:3000/hook"), false)
+            .await
+            .is_err());
+        assert!(
+            resolve_webhook_url(&url("http://localhost:3000/hook"), false)
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            resolve_webhook_url(&url("http://127.0.0.1:3000/hook"), true).await,
+            Ok("127.0.0.1:3000".parse().unwrap())
+        );
+    }
+}
@@ -1,21 +1,45 @@
 use actix_web_lab::__reexports::serde_json;
+use camera_reel_service::api::admin::OutboxStatus;
+use camera_reel_service::api::restore::{ImageRestore, RestoreStatus};
 use camera_reel_service::api::{
+    albums::{Album, GetAlbumImagesResponse, GetAlbumsResponse},
+    embed::OEmbedResponse,
     get::{
-        GetGalleryImagesResponse, GetImagesResponse, GetMultiplePlacesImagesResponse,
-        GetPlaceImagesResponse, UserDataResponse,
+        GetAppearancesResponse, GetGalleryAppearancesResponse, GetGalleryImagesResponse,
+        GetImagesResponse, GetMultiplePlacesImagesResponse, GetPlaceImagesResponse,
+        UserDataResponse,
     },
-    Image, ResponseError,
+    notifications::{GetNotificationsResponse, MarkNotificationsReadResponse},
+    people::{GetBlockedUsersResponse, UserPreferences},
+    render::{sign_render_params, Fit, RenderFormat, RenderParams},
+    search::SearchImagesResponse,
+    share_links::{GetShareLinksResponse, ShareLink, SharedImageResponse},
+    tags::{GetTagImagesResponse, GetTrendingTagsResponse},
+    wearables::{GetWearableImagesResponse, GetWearablesUsageResponse},
+    webhooks::{CreatedWebhookSubscription, GetWebhookDeliveriesResponse, WebhookDeliveryStatus},
+    GalleryImageWithPlace, Image, ResponseError, Visibility,
 };
+use camera_reel_service::archival::{archive_old_originals, process_restores};
+use camera_reel_service::events::EventPayload;
+use camera_reel_service::moderation::{ModerationConsumer, ModerationMetrics, ModerationSettings};
+use camera_reel_service::storage::migrate_legacy_image_keys;
 use common::upload_test_failing_image;
 use common::upload_test_image;
-use common::{get_place_id, upload_public_test_image};
-use sqlx::types::Uuid;
+use common::{
+    get_place_id, upload_public_test_image, upload_test_image_with_people,
+    upload_test_image_with_wearables,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::types::{chrono, Uuid};
 use wiremock::matchers::{method, path, query_param};
 use wiremock::{Mock, MockServer, ResponseTemplate};
 
 use crate::common::{
-    create_other_identity, create_test_identity, create_test_server,
+    assert_matches_schema, create_local_storage_test_server, create_other_identity,
+    create_proxy_test_server, create_stream_test_server, create_test_identity, create_test_server,
     create_test_server_with_places_url, get_signed_headers, poll_sqs_for_message_with_filter,
+    ADMIN_BEARER_TOKEN,
 };
 
 mod common;
@@ -195,11 +219,19 @@ async fn test_get_multiple_images_compact() {
 
 #[actix_web::test]
 async fn test_delete_image() {
-    let (server, _) = create_test_server().await;
+    let (server, test_context) = create_test_server().await;
     let address = server.addr();
     let place_id = Uuid::new_v4().to_string();
-
-    let id = upload_test_image("image.png", &address.to_string(), &place_id).await;
+    let friend = "0x1111111111111111111111111111111111111111";
+
+    let id = upload_test_image_with_people(
+        "image.png",
+        &address.to_string(),
+        true,
+        &place_id,
+        &[friend],
+    )
+    .await;
 
     // Metadata is public for every image, so no auth is required to read it.
     let response = reqwest::Client::new()
@@ -229,6 +261,26 @@ async fn test_delete_image() {
     let response = response.json::<UserDataResponse>().await;
     assert!(response.is_ok());
 
+    let sns_message = poll_sqs_for_message_with_filter(
+        &test_context.sqs_client,
+        &test_context.queue_url,
+        10,
+        Some("photo-deleted"),
+    )
+    .await
+    .expect("SNS message should have been received");
+    assert_eq!(sns_message["type"], "camera");
+    assert_eq!(sns_message["key"], id);
+    let metadata = &sns_message["metadata"];
+    assert_eq!(metadata["photoId"], id);
+    assert_eq!(
+        metadata["userAddress"],
+        "0x7949f9f239d1a0816ce5eb364a1f588ae9cc1bf5"
+    );
+    assert_eq!(metadata["placeId"], place_id);
+    assert_eq!(metadata["isPublic"], true);
+    assert_eq!(metadata["users"][0]["address"], friend);
+
     let response = reqwest::Client::new()
         .get(&format!("http://{}/api/images/{}/metadata", address, id))
         .send()
@@ -275,16 +327,31 @@ async fn test_update_image_visibility() {
         .unwrap();
     assert!(response.status().is_success());
 
-    // Check if visibility was updated. The image is now private, but its metadata stays
-    // publicly readable (privacy only hides it from the user's public gallery listing).
+    // Check if visibility was updated. The image is now private, so only its owner can read
+    // its metadata.
+    let path = format!("/api/images/{}/metadata", id);
     let response = reqwest::Client::new()
-        .get(&format!("http://{}/api/images/{}/metadata", address, id))
+        .get(&format!("http://{address}{path}"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+
+    let headers = get_signed_headers(create_test_identity(), "get", &path, "");
+    let response = reqwest::Client::new()
+        .get(&format!("http://{address}{path}"))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
         .send()
         .await
         .unwrap();
     assert!(response.status().is_success());
     let image = response.json::<Image>().await.unwrap();
     assert_eq!(image.is_public, false);
+    assert_eq!(image.visibility, Visibility::Private);
 
     // Verify SNS event was published correctly (filter for photo-privacy-changed events)
     let sns_message = poll_sqs_for_message_with_filter(
@@ -348,25 +415,22 @@ async fn test_get_public_image_metadata_without_auth_succeeds() {
 }
 
 #[actix_web::test]
-async fn test_get_private_image_metadata_without_auth_succeeds() {
+async fn test_get_private_image_metadata_without_auth_is_not_found() {
     let (server, _) = create_test_server().await;
     let address = server.addr();
     let place_id = get_place_id();
 
+    // `isPublic: false` uploads are private, only their owner can see them. Sharing a photo
+    // by link takes the `unlisted` visibility.
     let id = upload_test_image("private-noauth.png", &address.to_string(), &place_id).await;
 
-    // A private image is still viewable by anyone with the link (privacy only hides it
-    // from the user's public gallery), so its metadata is served without auth. This is
-    // what powers sharing a photo to social networks.
     let response = reqwest::Client::new()
         .get(&format!("http://{}/api/images/{}/metadata", address, id))
         .send()
         .await
         .unwrap();
 
-    assert!(response.status().is_success());
-    let image = response.json::<Image>().await.unwrap();
-    assert_eq!(image.is_public, false);
+    assert_eq!(response.status(), 404);
 }
 
 #[actix_web::test]
@@ -397,15 +461,14 @@ async fn test_get_private_image_metadata_as_owner_succeeds() {
 }
 
 #[actix_web::test]
-async fn test_get_private_image_metadata_as_non_owner_succeeds() {
+async fn test_get_private_image_metadata_as_non_owner_is_not_found() {
     let (server, _) = create_test_server().await;
     let address = server.addr();
     let place_id = get_place_id();
 
     let id = upload_test_image("private-other.png", &address.to_string(), &place_id).await;
 
-    // A different authenticated user can also read the metadata: a photo is shareable
-    // regardless of who requests it. Authentication never changes the outcome here.
+    // Other users can't read the metadata of a private image, even when authenticated
     let path = format!("/api/images/{id}/metadata");
     let headers = get_signed_headers(create_other_identity(), "get", &path, "");
     let response = reqwest::Client::new()
@@ -419,9 +482,7 @@ async fn test_get_private_image_metadata_as_non_owner_succeeds() {
         .await
         .unwrap();
 
-    assert!(response.status().is_success());
-    let image = response.json::<Image>().await.unwrap();
-    assert_eq!(image.is_public, false);
+    assert_eq!(response.status(), 404);
 }
 
 #[actix_web::test]
@@ -865,3 +926,2464 @@ async fn test_post_multiple_places_images_eth_api_error() {
     let body = response.json::<ResponseError>().await.unwrap();
     assert!(body.get_message().contains("failed to resolve world name"));
 }
+
+async fn create_test_album(address: &str, is_public: bool) -> Album {
+    let path = "/api/albums";
+    let headers = get_signed_headers(create_test_identity(), "post", path, "");
+    let response = reqwest::Client::new()
+        .post(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .json(&serde_json::json!({ "title": "My trip", "isPublic": is_public }))
+        .send()
+        .await
+        .unwrap();
+
+    assert!(response.status().is_success());
+    response.json::<Album>().await.unwrap()
+}
+
+async fn add_test_album_images(address: &str, album_id: &str, image_ids: &[&str]) {
+    let path = format!("/api/albums/{album_id}/images");
+    let headers = get_signed_headers(create_test_identity(), "post", &path, "");
+    let response = reqwest::Client::new()
+        .post(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .json(&serde_json::json!({ "imageIds": image_ids }))
+        .send()
+        .await
+        .unwrap();
+
+    assert!(response.status().is_success());
+}
+
+#[actix_web::test]
+async fn test_public_album_only_lists_public_images() {
+    let (server, _) = create_test_server().await;
+    let address = server.addr();
+    let place_id = get_place_id();
+
+    let public_id = upload_public_test_image("album-pu.png", &address.to_string(), &place_id).await;
+    let private_id = upload_test_image("album-pr.png", &address.to_string(), &place_id).await;
+
+    let album = create_test_album(&address.to_string(), true).await;
+    add_test_album_images(
+        &address.to_string(),
+        &album.id,
+        &[private_id.as_str(), public_id.as_str()],
+    )
+    .await;
+
+    // Anyone can read a public album, but private images are left out
+    let response = reqwest::Client::new()
+        .get(&format!(
+            "http://{}/api/albums/{}/images",
+            address, album.id
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json::<GetAlbumImagesResponse>()
+        .await
+        .unwrap();
+
+    assert_eq!(response.images.len(), 1);
+    assert_eq!(response.images[0].id, public_id);
+    assert!(response.next_cursor.is_none());
+
+    // The owner sees every image, in album order
+    let path = format!("/api/albums/{}/images", album.id);
+    let headers = get_signed_headers(create_test_identity(), "get", &path, "");
+    let response = reqwest::Client::new()
+        .get(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<GetAlbumImagesResponse>()
+        .await
+        .unwrap();
+
+    assert_eq!(response.images.len(), 2);
+    assert_eq!(response.images[0].id, private_id);
+    assert_eq!(response.images[1].id, public_id);
+
+    // The cover falls back to the first image visible to the requester
+    let response = reqwest::Client::new()
+        .get(&format!("http://{}/api/albums/{}", address, album.id))
+        .send()
+        .await
+        .unwrap()
+        .json::<Album>()
+        .await
+        .unwrap();
+
+    assert_eq!(response.cover_image.unwrap().id, public_id);
+}
+
+#[actix_web::test]
+async fn test_private_album_is_not_visible_to_others() {
+    let (server, _) = create_test_server().await;
+    let address = server.addr();
+
+    let album = create_test_album(&address.to_string(), false).await;
+
+    let response = reqwest::Client::new()
+        .get(&format!("http://{}/api/albums/{}", address, album.id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+
+    let response = reqwest::Client::new()
+        .get(&format!(
+            "http://{}/api/albums/{}/images",
+            address, album.id
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+
+    let user_address = "0x7949f9f239d1a0816ce5eb364a1f588ae9cc1bf5";
+    let response = reqwest::Client::new()
+        .get(&format!(
+            "http://{}/api/users/{}/albums",
+            address, user_address
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json::<GetAlbumsResponse>()
+        .await
+        .unwrap();
+    assert_eq!(response.total, 0);
+}
+
+#[actix_web::test]
+async fn test_album_order_cover_and_cursor_pagination() {
+    let (server, _) = create_test_server().await;
+    let address = server.addr();
+    let place_id = get_place_id();
+
+    let mut image_ids = vec![];
+    for i in 0..3 {
+        image_ids.push(
+            upload_public_test_image(&format!("album-{i}.png"), &address.to_string(), &place_id)
+                .await,
+        );
+    }
+
+    let album = create_test_album(&address.to_string(), true).await;
+    add_test_album_images(
+        &address.to_string(),
+        &album.id,
+        &image_ids.iter().map(String::as_str).collect::<Vec<_>>(),
+    )
+    .await;
+
+    // Reverse the album order
+    let reversed = image_ids.iter().rev().cloned().collect::<Vec<_>>();
+    let path = format!("/api/albums/{}/order", album.id);
+    let headers = get_signed_headers(create_test_identity(), "put", &path, "");
+    let response = reqwest::Client::new()
+        .put(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .json(&serde_json::json!({ "imageIds": reversed }))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    // Pick the middle image as cover
+    let path = format!("/api/albums/{}", album.id);
+    let headers = get_signed_headers(create_test_identity(), "patch", &path, "");
+    let response = reqwest::Client::new()
+        .patch(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .json(&serde_json::json!({ "coverImageId": image_ids[1] }))
+        .send()
+        .await
+        .unwrap()
+        .json::<Album>()
+        .await
+        .unwrap();
+    assert_eq!(response.cover_image.unwrap().id, image_ids[1]);
+
+    // Walk the album two images at a time
+    let first_page = reqwest::Client::new()
+        .get(&format!(
+            "http://{}/api/albums/{}/images?limit=2",
+            address, album.id
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json::<GetAlbumImagesResponse>()
+        .await
+        .unwrap();
+
+    assert_eq!(first_page.images.len(), 2);
+    assert_eq!(first_page.images[0].id, reversed[0]);
+    assert_eq!(first_page.images[1].id, reversed[1]);
+
+    let second_page = reqwest::Client::new()
+        .get(&format!(
+            "http://{}/api/albums/{}/images?limit=2&cursor={}",
+            address,
+            album.id,
+            first_page.next_cursor.unwrap()
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json::<GetAlbumImagesResponse>()
+        .await
+        .unwrap();
+
+    assert_eq!(second_page.images.len(), 1);
+    assert_eq!(second_page.images[0].id, reversed[2]);
+    assert!(second_page.next_cursor.is_none());
+
+    // Removing an image twice, or one that isn't an image id
+    let address = address.to_string();
+    let path = format!("/api/albums/{}/images/{}", album.id, image_ids[0]);
+    for status in [200, 404] {
+        let response =
+            send_test_share_links_request(&address, "delete", &path, create_test_identity(), None)
+                .await;
+        assert_eq!(response.status(), status);
+    }
+    let path = format!("/api/albums/{}/images/not-an-id", album.id);
+    let response =
+        send_test_share_links_request(&address, "delete", &path, create_test_identity(), None)
+            .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[actix_web::test]
+async fn test_update_image_caption_and_alt_text() {
+    let (server, test_context) = create_test_server().await;
+    let address = server.addr();
+    let place_id = get_place_id();
+
+    let id = upload_public_test_image("caption.png", &address.to_string(), &place_id).await;
+
+    // Images without alt text get one generated from their metadata
+    let image = reqwest::Client::new()
+        .get(&format!("http://{}/api/images/{}/metadata", address, id))
+        .send()
+        .await
+        .unwrap()
+        .json::<Image>()
+        .await
+        .unwrap();
+    assert!(image.caption.is_none());
+    assert_eq!(image.alt_text.unwrap(), "Photo taken in Decentraland");
+
+    let path = format!("/api/images/{id}");
+    let headers = get_signed_headers(create_test_identity(), "patch", &path, "");
+    let response = reqwest::Client::new()
+        .patch(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .json(&serde_json::json!({ "caption": "Sunset party", "altText": "Two avatars dancing" }))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let image = response.json::<Image>().await.unwrap();
+    assert_eq!(image.caption.unwrap(), "Sunset party");
+    assert_eq!(image.alt_text.unwrap(), "Two avatars dancing");
+
+    let sns_message = poll_sqs_for_message_with_filter(
+        &test_context.sqs_client,
+        &test_context.queue_url,
+        10,
+        Some("photo-updated"),
+    )
+    .await;
+    assert!(
+        sns_message.is_some(),
+        "SNS message should have been received"
+    );
+
+    let message = sns_message.unwrap();
+    assert_eq!(message["key"], id);
+    assert_eq!(message["metadata"]["caption"], "Sunset party");
+    assert_eq!(message["metadata"]["altText"], "Two avatars dancing");
+
+    // Clearing the alt text goes back to the generated one and keeps the caption
+    let headers = get_signed_headers(create_test_identity(), "patch", &path, "");
+    let image = reqwest::Client::new()
+        .patch(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .json(&serde_json::json!({ "altText": "" }))
+        .send()
+        .await
+        .unwrap()
+        .json::<Image>()
+        .await
+        .unwrap();
+    assert_eq!(image.caption.unwrap(), "Sunset party");
+    assert_eq!(image.alt_text.unwrap(), "Photo taken in Decentraland");
+
+    // The event carries the stored alt text, not the generated one
+    let message = poll_sqs_for_message_with_filter(
+        &test_context.sqs_client,
+        &test_context.queue_url,
+        10,
+        Some("photo-updated"),
+    )
+    .await
+    .expect("SNS message should have been received");
+    assert_eq!(message["metadata"]["caption"], "Sunset party");
+    assert!(message["metadata"]["altText"].is_null());
+
+    // Saving the same details doesn't publish anything, the next event is the caption change
+    let address = address.to_string();
+    for caption in ["Sunset party", "Sunrise party"] {
+        let response = send_test_share_links_request(
+            &address,
+            "patch",
+            &path,
+            create_test_identity(),
+            Some(serde_json::json!({ "caption": caption })),
+        )
+        .await;
+        assert!(response.status().is_success());
+    }
+    let message = poll_sqs_for_message_with_filter(
+        &test_context.sqs_client,
+        &test_context.queue_url,
+        10,
+        Some("photo-updated"),
+    )
+    .await
+    .expect("SNS message should have been received");
+    assert_eq!(message["metadata"]["caption"], "Sunrise party");
+    let updates = test_context
+        .events
+        .events()
+        .into_iter()
+        .filter(|(_, event)| {
+            event.key == id && matches!(event.payload, EventPayload::PhotoUpdated(_))
+        })
+        .count();
+    assert_eq!(updates, 3);
+}
+
+#[actix_web::test]
+async fn test_update_image_caption_too_long() {
+    let (server, _) = create_test_server().await;
+    let address = server.addr();
+    let place_id = get_place_id();
+
+    let id = upload_test_image("long-caption.png", &address.to_string(), &place_id).await;
+
+    let path = format!("/api/images/{id}");
+    let headers = get_signed_headers(create_test_identity(), "patch", &path, "");
+    let response = reqwest::Client::new()
+        .patch(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .json(&serde_json::json!({ "caption": "a".repeat(501) }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 400);
+    let body = response.json::<ResponseError>().await.unwrap();
+    assert!(body.get_message().contains("caption is too long"));
+}
+
+#[actix_web::test]
+async fn test_update_image_caption_as_non_owner_is_forbidden() {
+    let (server, _) = create_test_server().await;
+    let address = server.addr();
+    let place_id = get_place_id();
+
+    let id = upload_public_test_image("other-caption.png", &address.to_string(), &place_id).await;
+
+    let path = format!("/api/images/{id}");
+    let headers = get_signed_headers(create_other_identity(), "patch", &path, "");
+    let response = reqwest::Client::new()
+        .patch(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .json(&serde_json::json!({ "caption": "not mine" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 403);
+}
+
+async fn update_test_image(address: &str, image_id: &str, body: serde_json::Value) -> Image {
+    let path = format!("/api/images/{image_id}");
+    let headers = get_signed_headers(create_test_identity(), "patch", &path, "");
+    reqwest::Client::new()
+        .patch(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+        .json::<Image>()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn test_tag_images_across_places_only_lists_public_images() {
+    let (server, _) = create_test_server().await;
+    let address = server.addr().to_string();
+
+    let first_place = get_place_id();
+    let second_place = get_place_id();
+    let first = upload_public_test_image("tag-1.png", &address, &first_place).await;
+    let second = upload_public_test_image("tag-2.png", &address, &second_place).await;
+    let private = upload_test_image("tag-3.png", &address, &first_place).await;
+
+    let image = update_test_image(
+        &address,
+        &first,
+        serde_json::json!({ "caption": "Dancing at #MusicFestival2026" }),
+    )
+    .await;
+    assert_eq!(image.tags, vec!["musicfestival2026"]);
+
+    let image = update_test_image(
+        &address,
+        &second,
+        serde_json::json!({ "tags": ["#MusicFestival2026", "party"] }),
+    )
+    .await;
+    assert_eq!(image.tags, vec!["musicfestival2026", "party"]);
+
+    // Editing the caption keeps the explicit tags
+    let image = update_test_image(
+        &address,
+        &second,
+        serde_json::json!({ "caption": "Last song #encore" }),
+    )
+    .await;
+    assert_eq!(image.tags, vec!["encore", "musicfestival2026", "party"]);
+
+    update_test_image(
+        &address,
+        &private,
+        serde_json::json!({ "tags": ["musicfestival2026"] }),
+    )
+    .await;
+
+    let response = reqwest::Client::new()
+        .get(&format!(
+            "http://{}/api/tags/%23MusicFestival2026/images",
+            address
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json::<GetTagImagesResponse>()
+        .await
+        .unwrap();
+
+    assert_eq!(response.tag_data.max_images, 2);
+    let mut place_ids = response
+        .images
+        .iter()
+        .map(|image| image.place_id.clone())
+        .collect::<Vec<_>>();
+    place_ids.sort();
+    let mut expected_place_ids = vec![first_place, second_place];
+    expected_place_ids.sort();
+    assert_eq!(place_ids, expected_place_ids);
+    assert!(response.images.iter().all(|image| image.id != private));
+
+    let response = reqwest::Client::new()
+        .get(&format!("http://{}/api/tags/not-a-tag/images", address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+}
+
+#[actix_web::test]
+async fn test_trending_tags() {
+    let (server, _) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+
+    let first = upload_public_test_image("trending-1.png", &address, &place_id).await;
+    let second = upload_public_test_image("trending-2.png", &address, &place_id).await;
+    let private = upload_test_image("trending-3.png", &address, &place_id).await;
+
+    update_test_image(
+        &address,
+        &first,
+        serde_json::json!({ "caption": "#party #sunset" }),
+    )
+    .await;
+    update_test_image(&address, &second, serde_json::json!({ "tags": ["party"] })).await;
+    update_test_image(
+        &address,
+        &private,
+        serde_json::json!({ "tags": ["sunset", "secret"] }),
+    )
+    .await;
+
+    let response = reqwest::Client::new()
+        .get(&format!("http://{}/api/tags/trending?hours=1", address))
+        .send()
+        .await
+        .unwrap()
+        .json::<GetTrendingTagsResponse>()
+        .await
+        .unwrap();
+
+    let tags = response
+        .tags
+        .iter()
+        .map(|tag| (tag.tag.as_str(), tag.count))
+        .collect::<Vec<_>>();
+    assert_eq!(tags, vec![("party", 2), ("sunset", 1)]);
+}
+
+#[actix_web::test]
+async fn test_get_user_appearances() {
+    let (server, _) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+
+    let owner = "0x7949f9f239d1a0816ce5eb364a1f588ae9cc1bf5";
+    let friend = "0x1234567890AbCdEf1234567890aBcDeF12345678";
+
+    let public_with_friend =
+        upload_test_image_with_people("appear-1.png", &address, true, &place_id, &[friend]).await;
+    // A private image of someone else is never listed in the appearances of the friend
+    upload_test_image_with_people("appear-2.png", &address, false, &place_id, &[friend]).await;
+    let private_selfie =
+        upload_test_image_with_people("appear-3.png", &address, false, &place_id, &[owner]).await;
+    upload_test_image("appear-4.png", &address, &place_id).await;
+
+    let response = reqwest::Client::new()
+        .get(&format!(
+            "http://{}/api/users/{}/appearances",
+            address,
+            friend.to_lowercase()
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json::<GetAppearancesResponse>()
+        .await
+        .unwrap();
+    assert_eq!(response.appearances_data.max_images, 1);
+    assert_eq!(response.images[0].id, public_with_friend);
+
+    let response = reqwest::Client::new()
+        .get(&format!(
+            "http://{}/api/users/{}/appearances",
+            address, owner
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json::<GetAppearancesResponse>()
+        .await
+        .unwrap();
+    assert_eq!(response.appearances_data.max_images, 0);
+    assert!(response.images.is_empty());
+
+    let path = format!("/api/users/{owner}/appearances");
+    let headers = get_signed_headers(create_test_identity(), "get", &path, "");
+    let response = reqwest::Client::new()
+        .get(&format!("http://{}{}?compact=true", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<GetGalleryAppearancesResponse>()
+        .await
+        .unwrap();
+    assert_eq!(response.appearances_data.max_images, 1);
+    assert_eq!(response.images[0].id, private_selfie);
+}
+
+#[actix_web::test]
+async fn test_untag_image_removes_user_and_flags_it() {
+    let (server, test_context) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+
+    let owner = "0x7949f9f239d1a0816ce5eb364a1f588ae9cc1bf5";
+    let friend = "0x1234567890abcdef1234567890abcdef12345678";
+    let id =
+        upload_test_image_with_people("untag.png", &address, true, &place_id, &[owner, friend])
+            .await;
+
+    let path = format!("/api/images/{id}/untag");
+    let headers = get_signed_headers(create_test_identity(), "post", &path, "");
+    let response = reqwest::Client::new()
+        .post(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .json(&serde_json::json!({ "flagForReview": true, "reason": "I didn't agree to this" }))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let image = reqwest::Client::new()
+        .get(&format!("http://{}/api/images/{}/metadata", address, id))
+        .send()
+        .await
+        .unwrap()
+        .json::<Image>()
+        .await
+        .unwrap();
+    let people = image
+        .metadata
+        .visible_people
+        .iter()
+        .map(|user| user.user_address.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(people, vec![friend]);
+
+    let appearances = reqwest::Client::new()
+        .get(&format!(
+            "http://{}/api/users/{}/appearances",
+            address, owner
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json::<GetAppearancesResponse>()
+        .await
+        .unwrap();
+    assert_eq!(appearances.appearances_data.max_images, 0);
+
+    let sns_message = poll_sqs_for_message_with_filter(
+        &test_context.sqs_client,
+        &test_context.queue_url,
+        10,
+        Some("photo-untagged"),
+    )
+    .await;
+    assert!(
+        sns_message.is_some(),
+        "SNS message should have been received"
+    );
+
+    let message = sns_message.unwrap();
+    assert_eq!(message["key"], id);
+    assert_eq!(message["metadata"]["untaggedAddress"], owner);
+    assert_eq!(message["metadata"]["flaggedForReview"], true);
+
+    // The user is no longer in the image
+    let headers = get_signed_headers(create_test_identity(), "post", &path, "");
+    let response = reqwest::Client::new()
+        .post(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+}
+
+#[actix_web::test]
+async fn test_untaggable_user_is_stripped_from_uploads() {
+    let (server, _) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+
+    let other_identity = create_other_identity();
+    let other = other_identity.sign_payload("").owner().unwrap().to_string();
+    let friend = "0x1234567890abcdef1234567890abcdef12345678";
+
+    let path = format!("/api/users/{other}/preferences");
+
+    // Users can't change the preferences of someone else
+    let headers = get_signed_headers(create_test_identity(), "put", &path, "");
+    let response = reqwest::Client::new()
+        .put(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .json(&serde_json::json!({ "allowTagging": false }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+
+    let headers = get_signed_headers(other_identity.clone(), "put", &path, "");
+    let preferences = reqwest::Client::new()
+        .put(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .json(&serde_json::json!({ "allowTagging": false }))
+        .send()
+        .await
+        .unwrap()
+        .json::<UserPreferences>()
+        .await
+        .unwrap();
+    assert!(!preferences.allow_tagging);
+
+    let headers = get_signed_headers(other_identity, "get", &path, "");
+    let preferences = reqwest::Client::new()
+        .get(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<UserPreferences>()
+        .await
+        .unwrap();
+    assert!(!preferences.allow_tagging);
+
+    let id = upload_test_image_with_people(
+        "untaggable.png",
+        &address,
+        true,
+        &place_id,
+        &[&other, friend],
+    )
+    .await;
+
+    let image = reqwest::Client::new()
+        .get(&format!("http://{}/api/images/{}/metadata", address, id))
+        .send()
+        .await
+        .unwrap()
+        .json::<Image>()
+        .await
+        .unwrap();
+    let people = image
+        .metadata
+        .visible_people
+        .iter()
+        .map(|user| user.user_address.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(people, vec![friend]);
+}
+
+#[actix_web::test]
+async fn test_get_wearable_images_and_usage() {
+    let (server, _) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+
+    let hat = "urn:decentraland:matic:collections-v2:0xabc:0";
+    let shoes = "urn:decentraland:matic:collections-v2:0xabc:1";
+    let unused = "urn:decentraland:matic:collections-v2:0xabc:2";
+
+    let first = upload_test_image_with_wearables(
+        "wearable-1.png",
+        &address,
+        &place_id,
+        "0x1234567890abcdef1234567890abcdef12345678",
+        &[hat, shoes],
+    )
+    .await;
+    let second = upload_test_image_with_wearables(
+        "wearable-2.png",
+        &address,
+        &place_id,
+        "0x7949f9f239d1a0816ce5eb364a1f588ae9cc1bf5",
+        &[&hat.to_uppercase()],
+    )
+    .await;
+
+    let response = reqwest::Client::new()
+        .get(&format!("http://{}/api/wearables/{}/images", address, hat))
+        .send()
+        .await
+        .unwrap()
+        .json::<GetWearableImagesResponse>()
+        .await
+        .unwrap();
+    assert_eq!(response.wearable_data.max_images, 2);
+    let mut ids = response
+        .images
+        .iter()
+        .map(|image| image.id.clone())
+        .collect::<Vec<_>>();
+    ids.sort();
+    let mut expected_ids = vec![first, second];
+    expected_ids.sort();
+    assert_eq!(ids, expected_ids);
+
+    let response = reqwest::Client::new()
+        .get(&format!(
+            "http://{}/api/wearables/{}/images?limit=1",
+            address, shoes
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json::<GetWearableImagesResponse>()
+        .await
+        .unwrap();
+    assert_eq!(response.wearable_data.max_images, 1);
+    assert_eq!(response.images.len(), 1);
+
+    let usage = reqwest::Client::new()
+        .post(&format!("http://{}/api/wearables/usage", address))
+        .json(&serde_json::json!({ "urns": [hat, shoes, unused] }))
+        .send()
+        .await
+        .unwrap()
+        .json::<GetWearablesUsageResponse>()
+        .await
+        .unwrap();
+    let usage = usage
+        .wearables
+        .iter()
+        .map(|wearable| (wearable.urn.as_str(), wearable.images, wearable.people))
+        .collect::<Vec<_>>();
+    assert_eq!(usage, vec![(hat, 2, 2), (shoes, 1, 1), (unused, 0, 0)]);
+}
+
+#[actix_web::test]
+async fn test_search_images() {
+    let (server, _) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+
+    let sunset = upload_public_test_image("search-1.png", &address, &place_id).await;
+    let volleyball = upload_public_test_image("search-2.png", &address, &place_id).await;
+    let private = upload_test_image("search-3.png", &address, &place_id).await;
+
+    update_test_image(
+        &address,
+        &sunset,
+        serde_json::json!({ "caption": "Sunset party at the beach" }),
+    )
+    .await;
+    update_test_image(
+        &address,
+        &volleyball,
+        serde_json::json!({ "caption": "Beach volleyball" }),
+    )
+    .await;
+    update_test_image(
+        &address,
+        &private,
+        serde_json::json!({ "caption": "Secret beach" }),
+    )
+    .await;
+
+    let search = |query: &str| {
+        let url = format!("http://{}/api/search/images?{}", address, query);
+        async move { reqwest::Client::new().get(&url).send().await.unwrap() }
+    };
+
+    let response = search("q=beach")
+        .await
+        .json::<SearchImagesResponse>()
+        .await
+        .unwrap();
+    assert_eq!(response.search_data.max_images, 2);
+    assert!(response.images.iter().all(|image| image.id != private));
+
+    let response = search("q=beach%20-volleyball")
+        .await
+        .json::<SearchImagesResponse>()
+        .await
+        .unwrap();
+    assert_eq!(response.images.len(), 1);
+    assert_eq!(response.images[0].id, sunset);
+
+    let response = search("q=beach&realm=https://realm.org/v1&from=2020-01-01")
+        .await
+        .json::<SearchImagesResponse>()
+        .await
+        .unwrap();
+    assert_eq!(response.search_data.max_images, 2);
+
+    let response = search("q=beach&realm=other-realm")
+        .await
+        .json::<SearchImagesResponse>()
+        .await
+        .unwrap();
+    assert_eq!(response.search_data.max_images, 0);
+
+    let response = search("q=beach&to=2020-01-01")
+        .await
+        .json::<SearchImagesResponse>()
+        .await
+        .unwrap();
+    assert_eq!(response.search_data.max_images, 0);
+
+    assert_eq!(search("q=beach&from=yesterday").await.status(), 400);
+    assert_eq!(search("q=%20").await.status(), 400);
+}
+
+async fn update_test_image_visibility(
+    address: &str,
+    image_id: &str,
+    body: serde_json::Value,
+) -> reqwest::Response {
+    let path = format!("/api/images/{image_id}/visibility");
+    let headers = get_signed_headers(create_test_identity(), "patch", &path, "");
+    reqwest::Client::new()
+        .patch(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn get_test_image_metadata(
+    address: &str,
+    image_id: &str,
+    identity: Option<dcl_crypto::Identity>,
+) -> reqwest::Response {
+    let path = format!("/api/images/{image_id}/metadata");
+    let request = reqwest::Client::new().get(&format!("http://{}{}", address, path));
+    let request = match identity {
+        Some(identity) => {
+            let headers = get_signed_headers(identity, "get", &path, "");
+            request
+                .header(headers[0].0.clone(), headers[0].1.clone())
+                .header(headers[1].0.clone(), headers[1].1.clone())
+                .header(headers[2].0.clone(), headers[2].1.clone())
+                .header(headers[3].0.clone(), headers[3].1.clone())
+                .header(headers[4].0.clone(), headers[4].1.clone())
+        }
+        None => request,
+    };
+    request.send().await.unwrap()
+}
+
+#[actix_web::test]
+async fn test_private_image_is_only_visible_to_its_owner() {
+    let (server, _) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+
+    let id = upload_public_test_image("private.png", &address, &place_id).await;
+
+    let response = update_test_image_visibility(
+        &address,
+        &id,
+        serde_json::json!({ "visibility": "private" }),
+    )
+    .await;
+    assert!(response.status().is_success());
+
+    let response = get_test_image_metadata(&address, &id, None).await;
+    assert_eq!(response.status(), 404);
+
+    let response = get_test_image_metadata(&address, &id, Some(create_other_identity())).await;
+    assert_eq!(response.status(), 404);
+
+    let response = get_test_image_metadata(&address, &id, Some(create_test_identity())).await;
+    assert!(response.status().is_success());
+    let image = response.json::<Image>().await.unwrap();
+    assert_eq!(image.visibility, Visibility::Private);
+    assert!(!image.is_public);
+
+    // Legacy clients keep working
+    let response =
+        update_test_image_visibility(&address, &id, serde_json::json!({ "isPublic": true })).await;
+    assert!(response.status().is_success());
+    let image = get_test_image_metadata(&address, &id, None)
+        .await
+        .json::<Image>()
+        .await
+        .unwrap();
+    assert_eq!(image.visibility, Visibility::Public);
+    assert!(image.is_public);
+}
+
+#[actix_web::test]
+async fn test_unlisted_image_is_viewable_but_not_listed() {
+    let (server, test_context) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+
+    let id = upload_public_test_image("unlisted.png", &address, &place_id).await;
+
+    let response = update_test_image_visibility(
+        &address,
+        &id,
+        serde_json::json!({ "visibility": "unlisted" }),
+    )
+    .await;
+    assert!(response.status().is_success());
+
+    let image = get_test_image_metadata(&address, &id, None)
+        .await
+        .json::<Image>()
+        .await
+        .unwrap();
+    assert_eq!(image.visibility, Visibility::Unlisted);
+
+    // Unlisted files are kept under the private prefix too, and presigned for anyone
+    let stored_image = test_context.database.get_image(&id).await.unwrap();
+    assert!(test_context
+        .storage
+        .head(&format!("private/{}", stored_image.image_key))
+        .await
+        .is_ok());
+    assert!(test_context
+        .storage
+        .head(&stored_image.image_key)
+        .await
+        .is_err());
+
+    let response = get_test_image_file(&address, &format!("{id}/original"), None).await;
+    assert_eq!(response.status(), 307);
+    let location = response.headers()["location"].to_str().unwrap().to_string();
+    assert!(location.contains(&format!("private/{}", stored_image.image_key)));
+
+    let response = reqwest::Client::new()
+        .get(&format!(
+            "http://{}/api/places/{}/images",
+            address, place_id
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json::<GetPlaceImagesResponse>()
+        .await
+        .unwrap();
+    assert!(response.images.iter().all(|image| image.id != id));
+}
+
+#[actix_web::test]
+async fn test_shared_image_is_visible_to_the_allowlist() {
+    let (server, test_context) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+
+    let id = upload_test_image("shared.png", &address, &place_id).await;
+
+    let friend_identity = create_other_identity();
+    let friend = friend_identity
+        .sign_payload("")
+        .owner()
+        .unwrap()
+        .to_string();
+
+    let response = update_test_image_visibility(
+        &address,
+        &id,
+        serde_json::json!({ "visibility": "shared", "sharedWith": ["not-an-address"] }),
+    )
+    .await;
+    assert_eq!(response.status(), 400);
+
+    let response = update_test_image_visibility(
+        &address,
+        &id,
+        serde_json::json!({ "visibility": "shared", "sharedWith": [friend.clone()] }),
+    )
+    .await;
+    assert!(response.status().is_success());
+
+    let response = get_test_image_metadata(&address, &id, Some(friend_identity)).await;
+    assert!(response.status().is_success());
+    let image = response.json::<Image>().await.unwrap();
+    assert_eq!(image.visibility, Visibility::Shared);
+
+    let response = get_test_image_metadata(&address, &id, Some(create_other_identity())).await;
+    assert_eq!(response.status(), 404);
+
+    let response = get_test_image_metadata(&address, &id, None).await;
+    assert_eq!(response.status(), 404);
+
+    let sns_message = poll_sqs_for_message_with_filter(
+        &test_context.sqs_client,
+        &test_context.queue_url,
+        10,
+        Some("photo-privacy-changed"),
+    )
+    .await;
+    assert!(
+        sns_message.is_some(),
+        "SNS message should have been received"
+    );
+
+    let metadata = &sns_message.unwrap()["metadata"];
+    assert_eq!(metadata["visibility"], "shared");
+    assert_eq!(metadata["previousVisibility"], "private");
+    assert_eq!(metadata["isPublic"], false);
+    assert_eq!(metadata["sharedWith"], serde_json::json!([friend]));
+}
+
+async fn get_test_image_file(
+    address: &str,
+    file_name: &str,
+    identity: Option<dcl_crypto::Identity>,
+) -> reqwest::Response {
+    let path = format!("/api/images/{file_name}");
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let request = client.get(&format!("http://{}{}", address, path));
+    let request = match identity {
+        Some(identity) => {
+            let headers = get_signed_headers(identity, "get", &path, "");
+            request
+                .header(headers[0].0.clone(), headers[0].1.clone())
+                .header(headers[1].0.clone(), headers[1].1.clone())
+                .header(headers[2].0.clone(), headers[2].1.clone())
+                .header(headers[3].0.clone(), headers[3].1.clone())
+                .header(headers[4].0.clone(), headers[4].1.clone())
+        }
+        None => request,
+    };
+    request.send().await.unwrap()
+}
+
+#[actix_web::test]
+async fn test_private_image_files_are_served_through_presigned_urls() {
+    let (server, test_context) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+
+    let id = upload_public_test_image("presigned.png", &address, &place_id).await;
+    let image = get_test_image_metadata(&address, &id, None)
+        .await
+        .json::<Image>()
+        .await
+        .unwrap();
+    let image_path = image.url.split("/api/images/").nth(1).unwrap().to_string();
+    let stored_image = test_context.database.get_image(&id).await.unwrap();
+    let image_key = stored_image.image_key.clone();
+    let thumbnail_key = stored_image.thumbnail_key.clone().unwrap();
+    assert_eq!(image_path, format!("{id}/original"));
+    assert!(image_key.ends_with(&format!("/{id}/original.png")));
+
+    let response = get_test_image_file(&address, &image_path, None).await;
+    assert_eq!(response.status(), 307);
+    assert_eq!(
+        response.headers()["location"],
+        format!("{}/{}", test_context.settings.bucket_url, image_key).as_str()
+    );
+
+    let response = update_test_image_visibility(
+        &address,
+        &id,
+        serde_json::json!({ "visibility": "private" }),
+    )
+    .await;
+    assert!(response.status().is_success());
+
+    // The files are moved under the private prefix
+    for name in [&image_key, &thumbnail_key] {
+        assert!(test_context
+            .storage
+            .head(&format!("private/{name}"))
+            .await
+            .is_ok());
+        assert!(test_context.storage.head(name).await.is_err());
+    }
+
+    let response = get_test_image_file(&address, &image_path, None).await;
+    assert_eq!(response.status(), 404);
+
+    let response = get_test_image_file(&address, &image_path, Some(create_other_identity())).await;
+    assert_eq!(response.status(), 404);
+
+    let response = get_test_image_file(&address, &image_path, Some(create_test_identity())).await;
+    assert_eq!(response.status(), 307);
+    assert_eq!(response.headers()["cache-control"], "private, no-store");
+    let location = response.headers()["location"].to_str().unwrap().to_string();
+    assert!(location.contains(&format!("private/{image_key}")));
+    assert!(location.contains("X-Amz-Signature"));
+
+    let response = reqwest::get(&location).await.unwrap();
+    assert!(response.status().is_success());
+
+    // Making the image public again moves the files back
+    let response =
+        update_test_image_visibility(&address, &id, serde_json::json!({ "visibility": "public" }))
+            .await;
+    assert!(response.status().is_success());
+
+    for name in [&image_key, &thumbnail_key] {
+        assert!(test_context.storage.head(name).await.is_ok());
+        assert!(test_context
+            .storage
+            .head(&format!("private/{name}"))
+            .await
+            .is_err());
+    }
+}
+
+async fn send_test_share_links_request(
+    address: &str,
+    method: &str,
+    path: &str,
+    identity: dcl_crypto::Identity,
+    body: Option<serde_json::Value>,
+) -> reqwest::Response {
+    let headers = get_signed_headers(identity, method, path, "");
+    let request = match method {
+        "post" => reqwest::Client::new().post(&format!("http://{}{}", address, path)),
+        "put" => reqwest::Client::new().put(&format!("http://{}{}", address, path)),
+        "delete" => reqwest::Client::new().delete(&format!("http://{}{}", address, path)),
+        "patch" => reqwest::Client::new().patch(&format!("http://{}{}", address, path)),
+        _ => reqwest::Client::new().get(&format!("http://{}{}", address, path)),
+    };
+    let request = request
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone());
+    let request = match body {
+        Some(body) => request.json(&body),
+        None => request,
+    };
+    request.send().await.unwrap()
+}
+
+#[actix_web::test]
+async fn test_share_links_expose_private_images_until_used_up_or_revoked() {
+    let (server, _) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+
+    let id = upload_test_image("share-link.png", &address, &place_id).await;
+    let response = update_test_image_visibility(
+        &address,
+        &id,
+        serde_json::json!({ "visibility": "private" }),
+    )
+    .await;
+    assert!(response.status().is_success());
+
+    let path = format!("/api/images/{id}/share-links");
+
+    let response =
+        send_test_share_links_request(&address, "post", &path, create_other_identity(), None).await;
+    assert_eq!(response.status(), 403);
+
+    let response = send_test_share_links_request(
+        &address,
+        "post",
+        &path,
+        create_test_identity(),
+        Some(serde_json::json!({ "maxViews": 0 })),
+    )
+    .await;
+    assert_eq!(response.status(), 400);
+
+    let share_link = send_test_share_links_request(
+        &address,
+        "post",
+        &path,
+        create_test_identity(),
+        Some(serde_json::json!({ "maxViews": 2, "expiresIn": 3600 })),
+    )
+    .await
+    .json::<ShareLink>()
+    .await
+    .unwrap();
+    assert_eq!(share_link.image_id, id);
+    assert_eq!(share_link.max_views, Some(2));
+    assert!(share_link.expires_at.is_some());
+    assert!(share_link
+        .url
+        .ends_with(&format!("/api/s/{}", share_link.token)));
+
+    let shared_image_path = format!("http://{}/api/s/{}", address, share_link.token);
+    let response = reqwest::get(&shared_image_path).await.unwrap();
+    assert!(response.status().is_success());
+    let shared_image = response.json::<SharedImageResponse>().await.unwrap();
+    assert_eq!(shared_image.image.id, id);
+    assert_eq!(shared_image.remaining_views, Some(1));
+    assert!(shared_image.image.url.contains("X-Amz-Signature"));
+    let response = reqwest::get(&shared_image.image.url).await.unwrap();
+    assert!(response.status().is_success());
+
+    let response = reqwest::get(&shared_image_path).await.unwrap();
+    assert!(response.status().is_success());
+    let response = reqwest::get(&shared_image_path).await.unwrap();
+    assert_eq!(response.status(), 404);
+
+    // Revoked links stop working right away
+    let share_link =
+        send_test_share_links_request(&address, "post", &path, create_test_identity(), None)
+            .await
+            .json::<ShareLink>()
+            .await
+            .unwrap();
+    let shared_image_path = format!("http://{}/api/s/{}", address, share_link.token);
+    assert!(reqwest::get(&shared_image_path)
+        .await
+        .unwrap()
+        .status()
+        .is_success());
+
+    let revoke_path = format!("{path}/{}", share_link.token);
+    let response = send_test_share_links_request(
+        &address,
+        "delete",
+        &revoke_path,
+        create_test_identity(),
+        None,
+    )
+    .await;
+    assert!(response.status().is_success());
+    assert!(response.json::<ShareLink>().await.unwrap().revoked);
+    assert_eq!(
+        reqwest::get(&shared_image_path).await.unwrap().status(),
+        404
+    );
+
+    let share_links =
+        send_test_share_links_request(&address, "get", &path, create_test_identity(), None)
+            .await
+            .json::<GetShareLinksResponse>()
+            .await
+            .unwrap();
+    assert_eq!(share_links.share_links.len(), 2);
+    assert_eq!(share_links.share_links[0].token, share_link.token);
+    assert_eq!(share_links.share_links[1].views, 2);
+
+    let response =
+        send_test_share_links_request(&address, "get", &path, create_other_identity(), None).await;
+    assert_eq!(response.status(), 403);
+}
+
+#[actix_web::test]
+async fn test_share_page_and_oembed_respect_visibility() {
+    let (server, test_context) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+    let api_url = &test_context.settings.api_url;
+
+    let id = upload_public_test_image("embed.png", &address, &place_id).await;
+    update_test_image(
+        &address,
+        &id,
+        serde_json::json!({ "caption": "Sunset <3 at the plaza" }),
+    )
+    .await;
+
+    let response = reqwest::get(&format!("http://{}/share/{}", address, id))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"<meta name="twitter:card" content="summary_large_image">"#));
+    assert!(page.contains(&format!(
+        r#"<meta property="og:image" content="{api_url}/api/images/{id}/original">"#
+    )));
+    assert!(page.contains("Sunset &lt;3 at the plaza"));
+    assert!(page.contains("application/json+oembed"));
+
+    let oembed_path = |share_url: &str| {
+        reqwest::Url::parse_with_params(
+            &format!("http://{}/oembed", address),
+            &[("url", share_url), ("maxwidth", "320")],
+        )
+        .unwrap()
+    };
+
+    let embed = reqwest::get(oembed_path(&format!("{api_url}/share/{id}")))
+        .await
+        .unwrap()
+        .json::<OEmbedResponse>()
+        .await
+        .unwrap();
+    assert_eq!(embed.embed_type, "photo");
+    assert_eq!((embed.width, embed.height), (Some(320), Some(180)));
+    assert_eq!(
+        embed.url,
+        Some(format!("{api_url}/api/images/{id}/thumbnail"))
+    );
+
+    let response = reqwest::get(oembed_path("https://example.com/share/whatever"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+
+    // Private images only get a generic card, unless a share token is given
+    let response = update_test_image_visibility(
+        &address,
+        &id,
+        serde_json::json!({ "visibility": "private" }),
+    )
+    .await;
+    assert!(response.status().is_success());
+
+    let page = reqwest::get(&format!("http://{}/share/{}", address, id))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains("This photo is private."));
+    assert!(!page.contains(&id));
+
+    let embed = reqwest::get(oembed_path(&format!("{api_url}/share/{id}")))
+        .await
+        .unwrap()
+        .json::<OEmbedResponse>()
+        .await
+        .unwrap();
+    assert_eq!(embed.embed_type, "link");
+    assert!(embed.url.is_none());
+
+    let share_link = send_test_share_links_request(
+        &address,
+        "post",
+        &format!("/api/images/{id}/share-links"),
+        create_test_identity(),
+        Some(serde_json::json!({ "maxViews": 1 })),
+    )
+    .await
+    .json::<ShareLink>()
+    .await
+    .unwrap();
+
+    let page = reqwest::get(&format!(
+        "http://{}/share/{}?token={}",
+        address, id, share_link.token
+    ))
+    .await
+    .unwrap()
+    .text()
+    .await
+    .unwrap();
+    assert!(page.contains("X-Amz-Signature"));
+
+    let embed = reqwest::get(oembed_path(&format!(
+        "{api_url}/share/{id}?token={}",
+        share_link.token
+    )))
+    .await
+    .unwrap()
+    .json::<OEmbedResponse>()
+    .await
+    .unwrap();
+    assert_eq!(embed.embed_type, "photo");
+
+    // Previews don't use up the views of the link
+    let response = reqwest::get(&format!("http://{}/api/s/{}", address, share_link.token))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+}
+
+#[actix_web::test]
+async fn test_proxy_mode_streams_images_with_caching_headers() {
+    let (server, test_context) = create_proxy_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+    let image_bytes = include_bytes!("resources/image.png");
+
+    let id = upload_public_test_image("proxy.png", &address, &place_id).await;
+    let image = get_test_image_metadata(&address, &id, None)
+        .await
+        .json::<Image>()
+        .await
+        .unwrap();
+    let image_path = image.url.split("/api/images/").nth(1).unwrap().to_string();
+    let thumbnail_path = image
+        .thumbnail_url
+        .split("/api/images/")
+        .nth(1)
+        .unwrap()
+        .to_string();
+    let thumbnail_key = test_context
+        .database
+        .get_image(&id)
+        .await
+        .unwrap()
+        .thumbnail_key
+        .unwrap();
+
+    let response = get_test_image_file(&address, &image_path, None).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["cache-control"], "public, max-age=3600");
+    assert_eq!(response.headers()["accept-ranges"], "bytes");
+    assert_eq!(response.headers()["content-type"], "image/png");
+    assert_eq!(
+        response.headers()["content-disposition"],
+        "inline; filename=\"original.png\""
+    );
+    let e_tag = response.headers()["etag"].to_str().unwrap().to_string();
+    assert_eq!(response.bytes().await.unwrap().as_ref(), image_bytes);
+
+    let client = reqwest::Client::new();
+    let url = format!("http://{}/api/images/{}", address, image_path);
+
+    let response = client
+        .get(&url)
+        .header("If-None-Match", &e_tag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 304);
+
+    let response = client
+        .get(&url)
+        .header("Range", "bytes=0-9")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 206);
+    assert_eq!(
+        response.headers()["content-range"],
+        format!("bytes 0-9/{}", image_bytes.len()).as_str()
+    );
+    assert_eq!(response.bytes().await.unwrap().as_ref(), &image_bytes[..10]);
+
+    let response = client
+        .get(&format!("{url}?download=true"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers()["content-disposition"],
+        "attachment; filename=\"original.png\""
+    );
+
+    let response =
+        get_test_image_file(&address, &format!("{}/original", Uuid::new_v4()), None).await;
+    assert_eq!(response.status(), 404);
+
+    // Private images are only proxied to the viewers allowed to see them
+    let response = update_test_image_visibility(
+        &address,
+        &id,
+        serde_json::json!({ "visibility": "private" }),
+    )
+    .await;
+    assert!(response.status().is_success());
+
+    let response = get_test_image_file(&address, &image_path, None).await;
+    assert_eq!(response.status(), 404);
+
+    let response = get_test_image_file(&address, &image_path, Some(create_test_identity())).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["cache-control"], "private, max-age=300");
+    assert_eq!(response.bytes().await.unwrap().as_ref(), image_bytes);
+
+    // Thumbnails are served from memory once they were requested
+    let response =
+        get_test_image_file(&address, &thumbnail_path, Some(create_test_identity())).await;
+    assert_eq!(response.status(), 200);
+    let thumbnail = response.bytes().await.unwrap();
+    test_context
+        .storage
+        .delete(&format!("private/{thumbnail_key}"))
+        .await
+        .unwrap();
+    let response =
+        get_test_image_file(&address, &thumbnail_path, Some(create_test_identity())).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.bytes().await.unwrap(), thumbnail);
+}
+
+#[actix_web::test]
+async fn test_render_image_variants_with_signed_params() {
+    let (server, test_context) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+    let signing_key = &test_context.settings.render_signing_key;
+
+    let id = upload_public_test_image("render.png", &address, &place_id).await;
+
+    let params = RenderParams {
+        w: Some(64),
+        h: Some(48),
+        fit: Some(Fit::Cover),
+        format: Some(RenderFormat::Webp),
+    };
+    let sig = sign_render_params(signing_key, &id, &params);
+    let render_url = |query: &str| format!("http://{}/api/images/{}/render?{}", address, id, query);
+
+    let response = reqwest::get(render_url(&format!(
+        "w=64&h=48&fit=cover&format=webp&sig={sig}"
+    )))
+    .await
+    .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "image/webp");
+    let rendered = image::load_from_memory(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!((rendered.width(), rendered.height()), (64, 48));
+
+    // The variant is cached in the bucket under a deterministic key
+    let variant_key = format!("renders/{id}/64x48-cover.webp");
+    assert!(test_context.storage.head(&variant_key).await.is_ok());
+
+    let response = reqwest::get(render_url(&format!(
+        "w=64&h=48&fit=cover&format=webp&sig={sig}"
+    )))
+    .await
+    .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let response = reqwest::get(render_url("w=64&h=48&fit=cover&format=webp"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+
+    let response = reqwest::get(render_url(&format!(
+        "w=2048&h=48&fit=cover&format=webp&sig={sig}"
+    )))
+    .await
+    .unwrap();
+    assert_eq!(response.status(), 403);
+
+    let response = reqwest::get(render_url(&format!("fit=cover&sig={sig}")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    // Hidden images aren't rendered, and their public variants are dropped
+    let response = update_test_image_visibility(
+        &address,
+        &id,
+        serde_json::json!({ "visibility": "private" }),
+    )
+    .await;
+    assert!(response.status().is_success());
+    assert!(test_context.storage.head(&variant_key).await.is_err());
+
+    let response = reqwest::get(render_url(&format!(
+        "w=64&h=48&fit=cover&format=webp&sig={sig}"
+    )))
+    .await
+    .unwrap();
+    assert_eq!(response.status(), 404);
+}
+
+#[actix_web::test]
+async fn test_local_storage_serves_uploaded_images() {
+    let (server, test_context) = create_local_storage_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+
+    let id = upload_public_test_image("local.png", &address, &place_id).await;
+    let image = get_test_image_metadata(&address, &id, None)
+        .await
+        .json::<Image>()
+        .await
+        .unwrap();
+    let image_path = image.url.split("/api/images/").nth(1).unwrap().to_string();
+    let image_key = test_context
+        .database
+        .get_image(&id)
+        .await
+        .unwrap()
+        .image_key;
+
+    let response = get_test_image_file(&address, &image_path, None).await;
+    assert_eq!(response.status(), 307);
+    assert_eq!(
+        response.headers()["location"],
+        format!("{}/{}", test_context.settings.bucket_url, image_key).as_str()
+    );
+
+    let response = reqwest::get(format!("http://{address}/files/{image_key}"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "image/png");
+    assert_eq!(
+        response.bytes().await.unwrap(),
+        include_bytes!("resources/image.png").as_slice()
+    );
+
+    let response = update_test_image_visibility(
+        &address,
+        &id,
+        serde_json::json!({ "visibility": "private" }),
+    )
+    .await;
+    assert!(response.status().is_success());
+    assert!(test_context
+        .storage
+        .head(&format!("private/{image_key}"))
+        .await
+        .is_ok());
+
+    // Private files need a presigned URL, like in the bucket
+    let response = reqwest::get(format!("http://{address}/files/private/{image_key}"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+
+    let response = get_test_image_file(&address, &image_path, Some(create_test_identity())).await;
+    assert_eq!(response.status(), 307);
+    let location = response.headers()["location"].to_str().unwrap().to_string();
+    let presigned_path = location
+        .strip_prefix(&test_context.settings.api_url)
+        .unwrap();
+    assert!(presigned_path.starts_with(&format!("/files/private/{image_key}?expires=")));
+
+    let response = reqwest::get(format!("http://{address}{presigned_path}"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let path = format!("/api/images/{id}");
+    let headers = get_signed_headers(create_test_identity(), "delete", &path, "{}");
+    let response = reqwest::Client::new()
+        .delete(&format!("http://{}{}", address, path))
+        .header(headers[0].0.clone(), headers[0].1.clone())
+        .header(headers[1].0.clone(), headers[1].1.clone())
+        .header(headers[2].0.clone(), headers[2].1.clone())
+        .header(headers[3].0.clone(), headers[3].1.clone())
+        .header(headers[4].0.clone(), headers[4].1.clone())
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    assert!(test_context
+        .storage
+        .head(&format!("private/{image_key}"))
+        .await
+        .is_err());
+}
+
+#[actix_web::test]
+async fn test_legacy_image_files_are_migrated_to_partitioned_keys() {
+    let (server, test_context) = create_local_storage_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+
+    // Puts an uploaded image back in the layout used before keys were partitioned
+    let id = upload_public_test_image("legacy.png", &address, &place_id).await;
+    let image = test_context.database.get_image(&id).await.unwrap();
+    let legacy_keys = [
+        format!("{id}-legacy.png"),
+        format!("{id}-thumbnail-legacy.png"),
+    ];
+    for (key, legacy_key) in image.keys().into_iter().zip(&legacy_keys) {
+        test_context.storage.copy(key, legacy_key).await.unwrap();
+        test_context.storage.delete(key).await.unwrap();
+    }
+    assert!(test_context
+        .database
+        .update_image_keys(&image, &legacy_keys[0], Some(&legacy_keys[1]))
+        .await
+        .unwrap());
+
+    let response = get_test_image_file(&address, &legacy_keys[0], None).await;
+    assert_eq!(response.status(), 307);
+    assert!(response.headers()["location"]
+        .to_str()
+        .unwrap()
+        .ends_with(&format!("/files/{}", legacy_keys[0])));
+
+    let legacy_images = test_context
+        .database
+        .get_legacy_key_images(None, 10)
+        .await
+        .unwrap();
+    assert_eq!(legacy_images.len(), 1);
+    assert!(migrate_legacy_image_keys(
+        test_context.storage.get_ref(),
+        &test_context.database,
+        &legacy_images[0]
+    )
+    .await
+    .unwrap());
+
+    let migrated = test_context.database.get_image(&id).await.unwrap();
+    assert_eq!(migrated.keys(), image.keys());
+    for (key, legacy_key) in image.keys().into_iter().zip(&legacy_keys) {
+        assert!(test_context.storage.head(key).await.is_ok());
+        assert!(test_context.storage.head(legacy_key).await.is_err());
+    }
+    assert!(test_context
+        .database
+        .get_legacy_key_images(None, 10)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Links shared before the migration keep working
+    let response = get_test_image_file(&address, &legacy_keys[0], None).await;
+    assert_eq!(response.status(), 307);
+    assert!(response.headers()["location"]
+        .to_str()
+        .unwrap()
+        .ends_with(&format!("/files/{}", image.image_key)));
+}
+
+#[actix_web::test]
+async fn test_archived_originals_are_restored_on_demand() {
+    let (server, test_context) = create_local_storage_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+
+    let id = upload_public_test_image("archive.png", &address, &place_id).await;
+    let image = test_context.database.get_image(&id).await.unwrap();
+    let storage = test_context.storage.get_ref();
+    assert_eq!(
+        archive_old_originals(storage, &test_context.database, 0)
+            .await
+            .unwrap(),
+        1
+    );
+    assert!(storage
+        .head(&format!("archive/{}", image.image_key))
+        .await
+        .is_ok());
+    assert!(storage.head(&image.image_key).await.is_err());
+
+    // Thumbnails stay hot
+    let response = get_test_image_file(&address, &format!("{id}/thumbnail"), None).await;
+    assert_eq!(response.status(), 307);
+
+    let response = get_test_image_file(&address, &format!("{id}/original"), None).await;
+    assert_eq!(response.status(), 202);
+    assert_eq!(response.headers()["retry-after"], "60");
+    let restore = response.json::<ImageRestore>().await.unwrap();
+    assert_eq!(restore.status, RestoreStatus::Pending);
+    assert_eq!(restore.retry_after, Some(60));
+
+    let restore_url = format!("http://{address}/api/images/{id}/restore");
+    let restore = reqwest::get(&restore_url)
+        .await
+        .unwrap()
+        .json::<ImageRestore>()
+        .await
+        .unwrap();
+    assert_eq!(restore.status, RestoreStatus::Pending);
+
+    assert_eq!(
+        process_restores(storage, &test_context.database)
+            .await
+            .unwrap(),
+        1
+    );
+    assert!(storage.head(&image.image_key).await.is_ok());
+
+    let response = get_test_image_file(&address, &format!("{id}/original"), None).await;
+    assert_eq!(response.status(), 307);
+    let restore = reqwest::get(&restore_url)
+        .await
+        .unwrap()
+        .json::<ImageRestore>()
+        .await
+        .unwrap();
+    assert_eq!(restore.status, RestoreStatus::Completed);
+    assert!(restore.completed_at.is_some());
+
+    let response = reqwest::Client::new()
+        .post(&restore_url)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 409);
+}
+
+#[actix_web::test]
+async fn test_outbox_events_are_delivered_and_stuck_ones_reported() {
+    let (server, test_context) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+    let outbox_url = format!("http://{address}/api/admin/outbox");
+
+    let response = reqwest::get(&outbox_url).await.unwrap();
+    assert_eq!(response.status(), 401);
+
+    let image_id = upload_test_image("outbox.png", &address, &place_id).await;
+    let message = poll_sqs_for_message_with_filter(
+        &test_context.sqs_client,
+        &test_context.queue_url,
+        10,
+        Some("photo-taken"),
+    )
+    .await
+    .expect("SNS message should have been received");
+    assert_eq!(message["key"], image_id);
+
+    // Published with the id of its outbox entry, which is marked delivered right after
+    for _ in 0..50 {
+        if test_context.database.get_outbox_lag().await.unwrap().0 == 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    let mut connection = test_context.database.get_connection().await.unwrap();
+    let events = test_context.events.events();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].1.key, image_id);
+    let delivered_at = sqlx::query_scalar::<_, Option<chrono::NaiveDateTime>>(
+        "SELECT delivered_at FROM outbox WHERE id = $1",
+    )
+    .bind(Uuid::parse_str(&events[0].0).unwrap())
+    .fetch_one(&mut *connection)
+    .await
+    .unwrap();
+    assert!(delivered_at.is_some());
+
+    // An event whose deliveries keep failing
+    sqlx::query(
+        "INSERT INTO outbox (id, event, attempts, last_error, created_at, next_attempt_at)
+         VALUES ($1, $2, 3, 'topic not found', now() - INTERVAL '1 hour', now() + INTERVAL '1 hour')",
+    )
+    .bind(Uuid::new_v4())
+    .bind(serde_json::json!({
+        "type": "camera",
+        "subType": "photo-updated",
+        "key": image_id,
+        "timestamp": 0,
+        "metadata": {}
+    }))
+    .execute(&mut *connection)
+    .await
+    .unwrap();
+
+    let status = reqwest::Client::new()
+        .get(&outbox_url)
+        .bearer_auth(ADMIN_BEARER_TOKEN)
+        .send()
+        .await
+        .unwrap()
+        .json::<OutboxStatus>()
+        .await
+        .unwrap();
+    assert_eq!(status.pending, 1);
+    assert!(status.lag_seconds >= 3600.0);
+    assert_eq!(status.stuck.len(), 1);
+    assert_eq!(status.stuck[0].sub_type, "photo-updated");
+    assert_eq!(status.stuck[0].key, image_id);
+    assert_eq!(status.stuck[0].attempts, 3);
+    assert_eq!(
+        status.stuck[0].last_error.as_deref(),
+        Some("topic not found")
+    );
+
+    // An event that can't be decoded is parked, without holding back the others
+    let parked_id = Uuid::new_v4();
+    sqlx::query("INSERT INTO outbox (id, event) VALUES ($1, $2)")
+        .bind(parked_id)
+        .bind(serde_json::json!({ "type": "camera", "subType": "photo-burned" }))
+        .execute(&mut *connection)
+        .await
+        .unwrap();
+    let other_image_id = upload_test_image("outbox-other.png", &address, &place_id).await;
+
+    let mut parked = None;
+    for _ in 0..50 {
+        parked = sqlx::query_as::<_, (Option<chrono::NaiveDateTime>, Option<String>)>(
+            "SELECT parked_at, last_error FROM outbox WHERE id = $1",
+        )
+        .bind(parked_id)
+        .fetch_one(&mut *connection)
+        .await
+        .ok()
+        .filter(|(parked_at, _)| parked_at.is_some());
+        if parked.is_some() && test_context.events.events().len() == 2 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    let (_, last_error) = parked.expect("the invalid event should have been parked");
+    assert!(last_error.unwrap().starts_with("invalid event"));
+    assert_eq!(test_context.events.events()[1].1.key, other_image_id);
+}
+
+#[actix_web::test]
+async fn test_place_webhooks_receive_signed_public_photos() {
+    let (server, _) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+
+    let webhook_server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&webhook_server)
+        .await;
+
+    let response = send_test_share_links_request(
+        &address,
+        "post",
+        "/api/webhooks",
+        create_test_identity(),
+        Some(serde_json::json!({ "placeId": place_id, "url": "not a url" })),
+    )
+    .await;
+    assert_eq!(response.status(), 400);
+
+    let subscription = send_test_share_links_request(
+        &address,
+        "post",
+        "/api/webhooks",
+        create_test_identity(),
+        Some(serde_json::json!({
+            "placeId": place_id,
+            "url": format!("{}/hook", webhook_server.uri())
+        })),
+    )
+    .await
+    .json::<CreatedWebhookSubscription>()
+    .await
+    .unwrap();
+    assert_eq!(subscription.subscription.place_id, place_id);
+    assert_eq!(subscription.secret.len(), 64);
+
+    // Private photos aren't delivered
+    upload_test_image("webhook-private.png", &address, &place_id).await;
+    let image_id = upload_public_test_image("webhook.png", &address, &place_id).await;
+
+    let mut requests = vec![];
+    for _ in 0..50 {
+        requests = webhook_server.received_requests().await.unwrap();
+        if !requests.is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(requests.len(), 1);
+    let request = &requests[0];
+    let header = |name: &'static str| request.headers[&name.into()].last().as_str().to_string();
+    let body = serde_json::from_slice::<serde_json::Value>(&request.body).unwrap();
+    assert_eq!(body["subType"], "photo-taken");
+    assert_eq!(body["key"], image_id);
+    assert_eq!(header("X-Event-Type"), "photo-taken");
+    assert_eq!(header("X-Webhook-Id"), subscription.subscription.id);
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(subscription.secret.as_bytes()).unwrap();
+    mac.update(format!("{}.", header("X-Webhook-Timestamp")).as_bytes());
+    mac.update(&request.body);
+    assert_eq!(
+        header("X-Webhook-Signature"),
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    );
+
+    let deliveries_path = format!("/api/webhooks/{}/deliveries", subscription.subscription.id);
+    let response = send_test_share_links_request(
+        &address,
+        "get",
+        &deliveries_path,
+        create_other_identity(),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), 404);
+
+    let deliveries = send_test_share_links_request(
+        &address,
+        "get",
+        &deliveries_path,
+        create_test_identity(),
+        None,
+    )
+    .await
+    .json::<GetWebhookDeliveriesResponse>()
+    .await
+    .unwrap()
+    .deliveries;
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0].key, image_id);
+    assert_eq!(deliveries[0].status, WebhookDeliveryStatus::Delivered);
+    assert_eq!(deliveries[0].response_status, Some(200));
+
+    let response = send_test_share_links_request(
+        &address,
+        "delete",
+        &format!("/api/webhooks/{}", subscription.subscription.id),
+        create_test_identity(),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), 204);
+}
+
+#[actix_web::test]
+async fn test_place_images_stream_pushes_new_public_images() {
+    let (server, _, listener) = create_stream_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+
+    let mut response = reqwest::get(&format!(
+        "http://{address}/api/places/{place_id}/images/stream"
+    ))
+    .await
+    .unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(
+        response.headers()["content-type"].to_str().unwrap(),
+        "text/event-stream"
+    );
+
+    // Let the feed start listening before the uploads
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    upload_test_image("stream-private.png", &address, &place_id).await;
+    let other_place_id = Uuid::new_v4().to_string();
+    upload_public_test_image("stream-other-place.png", &address, &other_place_id).await;
+    let image_id = upload_public_test_image("stream.png", &address, &place_id).await;
+
+    let mut received = String::new();
+    while !received.contains("\n\n") {
+        let chunk = tokio::time::timeout(std::time::Duration::from_secs(10), response.chunk())
+            .await
+            .expect("an image should have been streamed")
+            .unwrap()
+            .unwrap();
+        received.push_str(&String::from_utf8_lossy(&chunk));
+        // Keep-alive comments start with a colon
+        if received.starts_with(':') {
+            received.clear();
+        }
+    }
+
+    assert!(received.contains("event: image"));
+    assert!(received.contains(&format!("id: {image_id}")));
+    let data = received
+        .lines()
+        .find_map(|line| line.strip_prefix("data: "))
+        .unwrap();
+    let image = serde_json::from_str::<GalleryImageWithPlace>(data).unwrap();
+    assert_eq!(image.id, image_id);
+    assert_eq!(image.place_id, place_id);
+    assert!(image.is_public);
+
+    listener.stop().await;
+}
+
+#[actix_web::test]
+async fn test_moderation_events_hide_the_images_of_banned_users() {
+    let (server, test_context) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+    let image_id = upload_public_test_image("moderation.png", &address, &place_id).await;
+    let image = test_context.database.get_image(&image_id).await.unwrap();
+    assert!(image.visibility.is_public());
+
+    let queue_url = test_context
+        .sqs_client
+        .create_queue()
+        .queue_name(format!("test-moderation-{}", Uuid::new_v4().simple()))
+        .send()
+        .await
+        .unwrap()
+        .queue_url()
+        .unwrap()
+        .to_string();
+    let consumer = ModerationConsumer::new(
+        test_context.sqs_client.clone(),
+        ModerationSettings {
+            queue_url: queue_url.clone(),
+            dead_letter_queue_url: None,
+            max_receive_count: 5,
+            wait_time_seconds: 1,
+        },
+    );
+    let metrics = ModerationMetrics::new(&prometheus::Registry::new()).unwrap();
+    let body = serde_json::json!({
+        "id": format!("ban-{image_id}"),
+        "type": "user-banned",
+        "address": image.user_address,
+    })
+    .to_string();
+
+    // Delivered twice, only hidden once
+    for _ in 0..2 {
+        test_context
+            .sqs_client
+            .send_message()
+            .queue_url(&queue_url)
+            .message_body(&body)
+            .send()
+            .await
+            .unwrap();
+        let received = consumer
+            .consume(
+                test_context.storage.get_ref(),
+                &test_context.database,
+                &test_context.places_client,
+                &metrics,
+            )
+            .await
+            .unwrap();
+        assert_eq!(received, 1);
+
+        let image = test_context.database.get_image(&image_id).await.unwrap();
+        assert_eq!(image.visibility, Visibility::Private);
+    }
+
+    // The event is published by the outbox relay
+    for _ in 0..50 {
+        if test_context.database.get_outbox_lag().await.unwrap().0 == 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    let hidden = test_context
+        .events
+        .events()
+        .into_iter()
+        .filter(|(_, event)| match &event.payload {
+            EventPayload::PhotoPrivacyChanged(metadata) => {
+                event.key == image_id && metadata.moderation_reason.is_some()
+            }
+            _ => false,
+        })
+        .count();
+    assert_eq!(hidden, 1);
+
+    // Both messages were deleted from the queue
+    let remaining = test_context
+        .sqs_client
+        .receive_message()
+        .queue_url(&queue_url)
+        .send()
+        .await
+        .unwrap();
+    assert!(remaining.messages().is_empty());
+
+    // The owner's choice is kept, and can't be changed back while the image is taken down
+    let image = test_context.database.get_image(&image_id).await.unwrap();
+    assert!(image.moderated_at.is_some());
+    assert_eq!(image.moderation_reason.as_deref(), Some("user-banned"));
+    assert_eq!(image.owner_visibility, Some(Visibility::Public));
+    let response = update_test_image_visibility(
+        &address,
+        &image_id,
+        serde_json::json!({ "visibility": "public" }),
+    )
+    .await;
+    assert_eq!(response.status(), 403);
+
+    // Nor can the banned user upload new images
+    let response = upload_test_failing_image("after-ban.png", &address).await;
+    assert_eq!(response, "user is banned");
+}
+
+#[actix_web::test]
+async fn test_tagged_people_are_notified_unless_they_blocked_the_owner() {
+    let (server, test_context) = create_test_server().await;
+    let address = server.addr().to_string();
+    let place_id = get_place_id();
+    let owner = "0x7949f9f239d1a0816ce5eb364a1f588ae9cc1bf5";
+
+    let tagged_identity = create_other_identity();
+    let tagged = tagged_identity
+        .sign_payload("")
+        .owner()
+        .unwrap()
+        .to_string();
+    let blocker_identity = create_other_identity();
+    let blocker = blocker_identity
+        .sign_payload("")
+        .owner()
+        .unwrap()
+        .to_string();
+
+    let path = format!("/api/users/{blocker}/blocks/{owner}");
+    let response =
+        send_test_share_links_request(&address, "put", &path, blocker_identity.clone(), None).await;
+    assert_eq!(response.status(), 204);
+    let path = format!("/api/users/{blocker}/blocks");
+    let blocks = send_test_share_links_request(&address, "get", &path, blocker_identity, None)
+        .await
+        .json::<GetBlockedUsersResponse>()
+        .await
+        .unwrap();
+    assert_eq!(blocks.blocked_addresses, vec![owner.to_string()]);
+
+    let image_id = upload_test_image_with_people(
+        "tagged.png",
+        &address,
+        true,
+        &place_id,
+        &[&tagged, &blocker, owner],
+    )
+    .await;
+    // Private photos don't notify anyone
+    upload_test_image_with_people("private.png", &address, false, &place_id, &[&tagged]).await;
+    let tagged_events = |events: Vec<(String, camera_reel_service::events::Event)>| {
+        events
+            .into_iter()
+            .filter_map(|(_, event)| match event.payload {
+                EventPayload::PhotoTagged(metadata) => Some((event.key, metadata.tagged_address)),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Published by the outbox relay, which stores them in the inbox as well
+    let notifications_path = format!("/api/users/{tagged}/notifications");
+    let mut notifications = None;
+    for _ in 0..50 {
+        let response = send_test_share_links_request(
+            &address,
+            "get",
+            &notifications_path,
+            tagged_identity.clone(),
+            None,
+        )
+        .await
+        .json::<GetNotificationsResponse>()
+        .await
+        .unwrap();
+        if response.total > 0 {
+            notifications = Some(response);
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    let notifications = notifications.expect("the tagged user should have been notified");
+    assert_eq!(
+        tagged_events(test_context.events.events()),
+        vec![(image_id.clone(), tagged.to_lowercase())]
+    );
+    assert_eq!(notifications.total, 1);
+    assert_eq!(notifications.unread, 1);
+    assert_eq!(notifications.notifications[0].image_id, image_id);
+    assert_eq!(notifications.notifications[0].user_address, owner);
+    assert!(!notifications.notifications[0].read);
+
+    // Nobody else can read the inbox
+    let response = send_test_share_links_request(
+        &address,
+        "get",
+        &notifications_path,
+        create_test_identity(),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), 403);
+
+    let read_path = format!("{notifications_path}/read");
+    let read =
+        send_test_share_links_request(&address, "post", &read_path, tagged_identity.clone(), None)
+            .await
+            .json::<MarkNotificationsReadResponse>()
+            .await
+            .unwrap();
+    assert_eq!(read.updated, 1);
+
+    let notifications =
+        send_test_share_links_request(&address, "get", &notifications_path, tagged_identity, None)
+            .await
+            .json::<GetNotificationsResponse>()
+            .await
+            .unwrap();
+    assert_eq!(notifications.total, 1);
+    assert_eq!(notifications.unread, 0);
+    assert!(notifications.notifications[0].read);
+}
+
+#[actix_web::test]
+async fn test_published_events_match_the_served_schemas() {
+    let (server, test_context) = create_test_server().await;
+    let address = server.addr();
+    let place_id = get_place_id();
+
+    let schemas = reqwest::Client::new()
+        .get(format!("http://{}/api/docs/events.json", address))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let sub_types = schemas.as_object().unwrap().keys().collect::<Vec<_>>();
+    assert_eq!(
+        sub_types,
+        [
+            "photo-deleted",
+            "photo-privacy-changed",
+            "photo-tagged",
+            "photo-taken",
+            "photo-untagged",
+            "photo-updated"
+        ]
+    );
+
+    let image_id = upload_test_image("image.png", &address.to_string(), &place_id).await;
+    let message = poll_sqs_for_message_with_filter(
+        &test_context.sqs_client,
+        &test_context.queue_url,
+        10,
+        Some("photo-taken"),
+    )
+    .await
+    .expect("photo-taken message should have been received");
+    assert_eq!(message["key"], image_id);
+    assert_eq!(message["metadata"]["schemaVersion"], 1);
+    assert_matches_schema(&schemas["photo-taken"], &message);
+
+    // A message of another subtype doesn't match
+    let mut message = message;
+    message["subType"] = "photo-deleted".into();
+    let compiled = jsonschema::JSONSchema::compile(&schemas["photo-taken"]).unwrap();
+    assert!(!compiled.is_valid(&message));
+}
@@ -1,4 +1,7 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use actix_test::TestServer;
 use actix_web::{
@@ -9,12 +12,22 @@ use actix_web_lab::__reexports::serde_json;
 use aws_config::{BehaviorVersion, Region};
 use aws_sdk_sqs::{Client as SqsClient, Config as SqsConfig};
 use camera_reel_service::{
-    api::{self, upload::UploadResponse, Metadata, ResponseError},
+    api::{self, upload::UploadResponse, Metadata, ResponseError, User},
+    archival::ArchiveSettings,
     database::{Database, DatabaseOptions},
+    events::{
+        event_schemas, Event, EventPublisher, FanoutPublisher, MemoryPublisher, SNSPublisher,
+    },
+    image_cache::ImageCache,
+    image_feed::{spawn_image_feed_listener, ImageFeed, ImageFeedListener},
     live,
+    notifications::TagNotificationsPublisher,
+    outbox::{spawn_outbox_relay, OutboxMetrics, OutboxSettings},
     places_client::PlacesClient,
-    sns::SNSPublisher,
-    Environment, Settings,
+    storage::{LocalStorage, ObjectStorage, S3Storage},
+    urls::{DeliverySettings, UrlBuilder},
+    webhooks::{spawn_webhook_worker, PlaceWebhooksPublisher, WebhookSettings},
+    Environment, ImageDelivery, Settings, StorageBackend,
 };
 use dcl_crypto::{Account, Expiration, Identity};
 use rand::{distributions::Alphanumeric, Rng};
@@ -239,10 +252,14 @@ async fn subscribe_queue_to_sns(sqs_client: &SqsClient, queue_url: &String, topi
         .unwrap();
 }
 
+/// Bearer token of the admin endpoints in the test servers.
+pub const ADMIN_BEARER_TOKEN: &str = "test-admin-token";
+
 fn create_settings(bucket_name: &str, topic_arn: &str) -> Settings {
     Settings {
         port: 5000,
         bucket_url: format!("http://localhost:4566/{bucket_name}"),
+        storage_backend: StorageBackend::S3,
         api_url: "http://localhost:5000".to_owned(),
         max_images_per_user: 1000,
         aws_sns_arn: topic_arn.to_owned(),
@@ -251,15 +268,32 @@ fn create_settings(bucket_name: &str, topic_arn: &str) -> Settings {
         places_api_url: "https://places.decentraland.org".to_owned(),
         places_cache_ttl_seconds: 300,
         places_cache_max_size: 1000,
+        image_delivery: ImageDelivery::Redirect,
+        image_cache_ttl_seconds: 300,
+        image_cache_max_size: 16 * 1024 * 1024,
+        render_signing_key: "test-render-signing-key".to_owned(),
+        delivery: DeliverySettings::default(),
+        archive: ArchiveSettings::default(),
+        outbox: OutboxSettings::default(),
+        webhooks: WebhookSettings {
+            interval_seconds: 1,
+            // The webhooks of the tests are served locally
+            allow_private_networks: true,
+            ..WebhookSettings::default()
+        },
+        admin_bearer_token: Some(ADMIN_BEARER_TOKEN.to_owned()),
     }
 }
 
 pub struct TestContext {
     pub settings: Data<Settings>,
     pub database: Data<Database>,
-    pub bucket: Data<Bucket>,
-    pub sns_publisher: Data<SNSPublisher>,
+    pub storage: Data<dyn ObjectStorage>,
+    pub event_publisher: Data<dyn EventPublisher>,
+    /// Every event published during the test, also sent to the SNS topic.
+    pub events: Arc<MemoryPublisher>,
     pub places_client: Data<PlacesClient>,
+    pub image_cache: Data<ImageCache>,
     pub sqs_client: SqsClient,
     pub queue_url: String,
     pub topic_arn: String,
@@ -270,6 +304,14 @@ pub async fn create_context() -> TestContext {
 }
 
 pub async fn create_context_with_places_url(places_url: &str) -> TestContext {
+    create_context_with_settings(places_url, ImageDelivery::Redirect, StorageBackend::S3).await
+}
+
+async fn create_context_with_settings(
+    places_url: &str,
+    image_delivery: ImageDelivery,
+    storage_backend: StorageBackend,
+) -> TestContext {
     std::env::set_var("AWS_ACCESS_KEY_ID", "test");
     std::env::set_var("AWS_SECRET_ACCESS_KEY", "test");
 
@@ -278,7 +320,7 @@ pub async fn create_context_with_places_url(places_url: &str) -> TestContext {
     // Create unique SNS topic for this test
     let topic_arn = create_sns_topic().await;
 
-    // Create SNS publisher for tests
+    // Create SNS publisher for tests, recording the events as well
     let sns_publisher = SNSPublisher::new(
         topic_arn.clone(),
         Some("http://localhost:4566".to_string()),
@@ -292,19 +334,51 @@ pub async fn create_context_with_places_url(places_url: &str) -> TestContext {
 
     let mut settings = create_settings(&test_bucket, &topic_arn);
     settings.places_api_url = places_url.to_string();
+    settings.image_delivery = image_delivery;
+    settings.storage_backend = storage_backend;
 
-    let places_client = PlacesClient::new(
+    let database = Data::new(create_db(&test_bucket).await);
+    let places_client = Data::new(PlacesClient::new(
         settings.places_api_url.clone(),
         settings.places_cache_ttl_seconds,
         settings.places_cache_max_size,
+    ));
+
+    let events = Arc::new(MemoryPublisher::new());
+    let event_publisher: Arc<dyn EventPublisher> = Arc::new(FanoutPublisher::new(vec![
+        Box::new(sns_publisher),
+        Box::new(events.clone()),
+        Box::new(PlaceWebhooksPublisher::new(database.clone())),
+        Box::new(TagNotificationsPublisher::new(database.clone())),
+    ]));
+
+    let storage: Arc<dyn ObjectStorage> = match storage_backend {
+        StorageBackend::S3 => Arc::new(S3Storage::new(
+            create_bucket(&test_bucket).await,
+            &settings.bucket_url,
+        )),
+        StorageBackend::Local => {
+            settings.bucket_url = format!("{}/files", settings.api_url);
+            Arc::new(LocalStorage::new(
+                std::env::temp_dir().join(&test_bucket),
+                &settings.bucket_url,
+            ))
+        }
+    };
+
+    let image_cache = ImageCache::new(
+        settings.image_cache_ttl_seconds,
+        settings.image_cache_max_size,
     );
 
     TestContext {
         settings: Data::new(settings),
-        database: Data::new(create_db(&test_bucket).await),
-        bucket: Data::new(create_bucket(&test_bucket).await),
-        sns_publisher: Data::new(sns_publisher),
-        places_client: Data::new(places_client),
+        database,
+        storage: Data::from(storage),
+        event_publisher: Data::from(event_publisher),
+        events,
+        places_client,
+        image_cache: Data::new(image_cache),
         sqs_client,
         queue_url,
         topic_arn,
@@ -320,33 +394,54 @@ fn initialize_tracing() {
         .try_init();
 }
 
-pub async fn create_test_server_with_places_url(places_url: &str) -> (TestServer, TestContext) {
-    initialize_tracing();
-    let context = create_context_with_places_url(places_url).await;
-
-    let server = actix_test::start({
+/// Starts the service. The image feed only gets images when a listener was spawned for it.
+fn start_test_server(context: &TestContext, image_feed: Data<ImageFeed>) -> TestServer {
+    actix_test::start({
         let context_clone = TestContext {
             settings: context.settings.clone(),
             database: context.database.clone(),
-            bucket: context.bucket.clone(),
-            sns_publisher: context.sns_publisher.clone(),
+            storage: context.storage.clone(),
+            event_publisher: context.event_publisher.clone(),
+            events: context.events.clone(),
             places_client: context.places_client.clone(),
+            image_cache: context.image_cache.clone(),
             sqs_client: context.sqs_client.clone(),
             queue_url: context.queue_url.clone(),
             topic_arn: context.topic_arn.clone(),
         };
-
+        let urls = Data::new(UrlBuilder::new(&context.settings));
+
+        // Each test has its own database, so its relay only publishes the events of the test
+        spawn_outbox_relay(
+            context.settings.outbox.clone(),
+            context.database.clone(),
+            context.event_publisher.clone(),
+            OutboxMetrics::new(&prometheus::Registry::new()).unwrap(),
+        );
+        spawn_webhook_worker(
+            context.settings.webhooks.clone(),
+            context.database.clone(),
+            context.places_client.clone(),
+        );
         move || {
             App::new()
                 .app_data(context_clone.settings.clone())
-                .app_data(context_clone.bucket.clone())
+                .app_data(context_clone.storage.clone())
                 .app_data(context_clone.database.clone())
-                .app_data(context_clone.sns_publisher.clone())
                 .app_data(context_clone.places_client.clone())
+                .app_data(context_clone.image_cache.clone())
+                .app_data(image_feed.clone())
+                .app_data(urls.clone())
                 .service(scope("/health").service(live))
                 .configure(api::services)
         }
-    });
+    })
+}
+
+pub async fn create_test_server_with_places_url(places_url: &str) -> (TestServer, TestContext) {
+    initialize_tracing();
+    let context = create_context_with_places_url(places_url).await;
+    let server = start_test_server(&context, Data::new(ImageFeed::new()));
 
     (server, context)
 }
@@ -354,35 +449,66 @@ pub async fn create_test_server_with_places_url(places_url: &str) -> (TestServer
 pub async fn create_test_server() -> (TestServer, TestContext) {
     initialize_tracing();
     let context = create_context().await;
+    let server = start_test_server(&context, Data::new(ImageFeed::new()));
 
-    let server = actix_test::start({
-        let context_clone = TestContext {
-            settings: context.settings.clone(),
-            database: context.database.clone(),
-            bucket: context.bucket.clone(),
-            sns_publisher: context.sns_publisher.clone(),
-            places_client: context.places_client.clone(),
-            sqs_client: context.sqs_client.clone(),
-            queue_url: context.queue_url.clone(),
-            topic_arn: context.topic_arn.clone(),
-        };
+    (server, context)
+}
 
-        move || {
-            App::new()
-                .app_data(context_clone.settings.clone())
-                .app_data(context_clone.bucket.clone())
-                .app_data(context_clone.database.clone())
-                .app_data(context_clone.sns_publisher.clone())
-                .app_data(context_clone.places_client.clone())
-                .service(scope("/health").service(live))
-                .configure(api::services)
-        }
-    });
+/// Test server listening to the images made public, for the live streams. The listener must be
+/// stopped before the test ends, the runtime can't release its connection while shutting down.
+pub async fn create_stream_test_server() -> (TestServer, TestContext, ImageFeedListener) {
+    initialize_tracing();
+    let context = create_context().await;
+    let image_feed = Data::new(ImageFeed::new());
+    let listener = spawn_image_feed_listener(
+        image_feed.clone(),
+        context.database.clone(),
+        Data::new(UrlBuilder::new(&context.settings)),
+    );
+    let server = start_test_server(&context, image_feed);
+
+    (server, context, listener)
+}
+
+/// Test server that streams the image files instead of redirecting to the bucket.
+pub async fn create_proxy_test_server() -> (TestServer, TestContext) {
+    initialize_tracing();
+    let context = create_context_with_settings(
+        "https://places.decentraland.org",
+        ImageDelivery::Proxy,
+        StorageBackend::S3,
+    )
+    .await;
+    let server = start_test_server(&context, Data::new(ImageFeed::new()));
+
+    (server, context)
+}
+
+/// Test server that keeps the image files in a temporary directory instead of a bucket.
+pub async fn create_local_storage_test_server() -> (TestServer, TestContext) {
+    initialize_tracing();
+    let context = create_context_with_settings(
+        "https://places.decentraland.org",
+        ImageDelivery::Redirect,
+        StorageBackend::Local,
+    )
+    .await;
+    let server = start_test_server(&context, Data::new(ImageFeed::new()));
 
     (server, context)
 }
 
 async fn upload_image(file_name: &str, address: &str, is_public: bool, place_id: &str) -> String {
+    upload_image_with_people(file_name, address, is_public, place_id, vec![]).await
+}
+
+async fn upload_image_with_people(
+    file_name: &str,
+    address: &str,
+    is_public: bool,
+    place_id: &str,
+    visible_people: Vec<User>,
+) -> String {
     let identity = create_test_identity();
     // prepare image
     let image_bytes = include_bytes!("../resources/image.png").to_vec();
@@ -396,6 +522,7 @@ async fn upload_image(file_name: &str, address: &str, is_public: bool, place_id:
         user_address: "0x7949f9f239d1a0816ce5eb364a1f588ae9cc1bf5".to_string(),
         place_id: place_id.to_string(),
         realm: "https://realm.org/v1".to_string(),
+        visible_people,
         ..Default::default()
     };
     let metadata_json = serde_json::to_vec(&metadata).unwrap();
@@ -443,6 +570,43 @@ pub async fn upload_public_test_image(file_name: &str, address: &str, place_id:
     upload_image(file_name, address, true, place_id).await
 }
 
+pub async fn upload_test_image_with_people(
+    file_name: &str,
+    address: &str,
+    is_public: bool,
+    place_id: &str,
+    visible_people: &[&str],
+) -> String {
+    let visible_people = visible_people
+        .iter()
+        .map(|user_address| User {
+            user_name: "someone".to_string(),
+            user_address: user_address.to_string(),
+            wearables: vec![],
+            is_guest: false,
+            is_emoting: None,
+        })
+        .collect();
+    upload_image_with_people(file_name, address, is_public, place_id, visible_people).await
+}
+
+pub async fn upload_test_image_with_wearables(
+    file_name: &str,
+    address: &str,
+    place_id: &str,
+    wearer: &str,
+    wearables: &[&str],
+) -> String {
+    let visible_people = vec![User {
+        user_name: "someone".to_string(),
+        user_address: wearer.to_string(),
+        wearables: wearables.iter().map(|urn| urn.to_string()).collect(),
+        is_guest: false,
+        is_emoting: None,
+    }];
+    upload_image_with_people(file_name, address, true, place_id, visible_people).await
+}
+
 pub async fn upload_test_failing_image(file_name: &str, address: &str) -> String {
     let identity = create_test_identity();
     // prepare image
@@ -529,6 +693,37 @@ pub fn get_place_id() -> String {
     "f888b899-c509-44d1-af21-717a4cef654e".to_string()
 }
 
+/// Checks a published message against the schema of its `subType` in the event catalog, and
+/// that the service reads it back.
+pub fn assert_matches_event_schema(message: &serde_json::Value) {
+    let sub_type = message["subType"]
+        .as_str()
+        .expect("published message without subType");
+    let schemas = event_schemas();
+    let schema = serde_json::to_value(&schemas[sub_type]).unwrap();
+    assert_matches_schema(&schema, message);
+
+    if let Err(error) = serde_json::from_value::<Event>(message.clone()) {
+        panic!("{sub_type} message doesn't deserialize: {error}\n{message}");
+    }
+}
+
+/// Validates a value against a JSON Schema, listing every violation on failure.
+pub fn assert_matches_schema(schema: &serde_json::Value, value: &serde_json::Value) {
+    let compiled = jsonschema::JSONSchema::compile(schema).expect("invalid JSON Schema");
+    let errors = match compiled.validate(value) {
+        Ok(()) => return,
+        Err(errors) => errors
+            .map(|error| format!("{}: {}", error.instance_path, error))
+            .collect::<Vec<_>>(),
+    };
+    panic!(
+        "message doesn't match its schema:\n{}\n{}",
+        errors.join("\n"),
+        value
+    );
+}
+
 pub async fn poll_sqs_for_message_with_filter(
     sqs_client: &SqsClient,
     queue_url: &str,
@@ -563,6 +758,8 @@ pub async fn poll_sqs_for_message_with_filter(
                                 if let Ok(actual_message) =
                                     serde_json::from_str::<serde_json::Value>(message_text)
                                 {
+                                    assert_matches_event_schema(&actual_message);
+
                                     // Check if we need to filter by subtype
                                     if let Some(expected_subtype) = filter_subtype {
                                         if let Some(subtype) = actual_message.get("subType") {